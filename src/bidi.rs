@@ -0,0 +1,115 @@
+//! Direction hints for rendering snippets that mix right to left text with left to right text like code.
+
+use std::fmt;
+use crate::{Snippet, Segment, Field};
+
+/// First strong isolate: starts text laid out in the direction of its first strong character.
+pub const FIRST_STRONG_ISOLATE: char = '\u{2068}';
+/// Pop directional isolate: ends isolated text.
+pub const POP_DIRECTIONAL_ISOLATE: char = '\u{2069}';
+
+/// Direction of text, told by its first strong (directional) character.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+	LeftToRight,
+	RightToLeft,
+	/// No strong characters, such as digits, punctuation and white space only.
+	Neutral
+}
+
+fn is_right_to_left(c: char) -> bool {
+	matches!(c, '\u{0590}'..='\u{08FF}' | '\u{FB1D}'..='\u{FDFF}' | '\u{FE70}'..='\u{FEFF}' | '\u{10800}'..='\u{10FFF}' | '\u{1E800}'..='\u{1EFFF}')
+}
+
+impl Direction {
+	/// Direction of the first strong character of the text.
+	/// Letters of the Hebrew, Arabic, Syriac, Thaana, NKo and other right to left blocks are right to left, other letters left to right.
+	pub fn of(text: &str) -> Self {
+		text.chars()
+			.find_map(|c| if is_right_to_left(c) {
+				Some(Direction::RightToLeft)
+			} else if c.is_alphabetic() {
+				Some(Direction::LeftToRight)
+			} else {
+				None
+			})
+			.unwrap_or(Direction::Neutral)
+	}
+}
+
+impl Segment {
+	/// Direction of the segment's rendered text.
+	pub fn direction(&self) -> Direction {
+		Direction::of(&self.to_string())
+	}
+}
+
+impl Snippet {
+	/// Direction of the snippet's rendered text, to lay it out in when the host does not know better.
+	pub fn direction(&self) -> Direction {
+		Direction::of(&self.to_string())
+	}
+
+	/// Renders the snippet like Display does, but with the text of each field, variable, code block,
+	/// transformation and nested snippet wrapped in Unicode isolates ([`FIRST_STRONG_ISOLATE`] ... [`POP_DIRECTIONAL_ISOLATE`]),
+	/// so filled in text of one direction does not reorder the surrounding text of the other.
+	pub fn render_isolated_to<W: fmt::Write + ?Sized>(&self, w: &mut W) -> fmt::Result {
+		isolated(&self.body, w)
+	}
+
+	/// Renders the snippet with isolates as [`Snippet::render_isolated_to`] does.
+	pub fn render_isolated(&self) -> String {
+		let mut rendered = String::new();
+		// Writing into a String can not fail.
+		let _ = self.render_isolated_to(&mut rendered);
+		rendered
+	}
+}
+
+fn isolated<W: fmt::Write + ?Sized>(segments: &[Segment], w: &mut W) -> fmt::Result {
+	for segment in segments {
+		if let Segment::Text(text) = segment {
+			w.write_str(text)?;
+			continue
+		}
+		w.write_char(FIRST_STRONG_ISOLATE)?;
+		match segment {
+			Segment::Field(field) => match &**field {
+				Field::Placeholder(child_body) => isolated(child_body, w)?,
+				Field::Choice(choice, child_body, _) => if let Some(child_body) = child_body.get(*choice) {
+					isolated(child_body, w)?
+				},
+				Field::Number(_) => field.render_to(w)?,
+				Field::Toggle(on, when_on, when_off) => isolated(if *on { when_on } else { when_off }, w)?,
+				Field::Repeat(repeat) => for (i, body) in repeat.repetitions.iter().enumerate() {
+					if i > 0 {
+						w.write_str(&repeat.separator)?;
+					}
+					isolated(body, w)?
+				}
+			},
+			Segment::Snippet(nested) => isolated(&nested.body, w)?,
+			_ => segment.render_to(w)?
+		}
+		w.write_char(POP_DIRECTIONAL_ISOLATE)?;
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mustache::from_template;
+
+	#[test]
+	fn isolate_filled_in_text() {
+		assert_eq!(Direction::of("  42 שלום abc"), Direction::RightToLeft);
+		assert_eq!(Direction::of("(مرحبا)"), Direction::RightToLeft);
+		assert_eq!(Direction::of("1. fn"), Direction::LeftToRight);
+		assert_eq!(Direction::of("12 - !"), Direction::Neutral);
+		let snippet = from_template("שם: {{name}}!").output;
+		assert_eq!(snippet.direction(), Direction::RightToLeft);
+		assert_eq!(snippet.body()[1].direction(), Direction::LeftToRight);
+		assert_eq!(snippet.render_isolated(), "שם: \u{2068}name\u{2069}!");
+	}
+}
@@ -0,0 +1,17 @@
+//! Reads a request on standard input and writes the snippet filled in to standard output, see the oneshot module.
+//! Failures are written to standard error as JSON, exiting with status 1 (status 2 when the output could not be written).
+
+use std::io;
+use std::process::ExitCode;
+use snippet_parse::oneshot;
+
+fn main() -> ExitCode {
+	match oneshot::run(&mut io::stdin().lock(), &mut io::stdout().lock(), &mut io::stderr().lock()) {
+		Ok(true) => ExitCode::SUCCESS,
+		Ok(false) => ExitCode::from(1),
+		Err(error) => {
+			eprintln!("could not write output: {}", error);
+			ExitCode::from(2)
+		}
+	}
+}
@@ -0,0 +1,221 @@
+//! Putting snippets together piece by piece rather than parsing them, with the references between their parts made along the way.
+
+use std::fmt;
+use crate::shared::{Rc, Weak};
+use crate::{Snippet, Segment, Field, Tab, Variable, VariableSource, Code, Transformation};
+use crate::handle::{FieldRef, ExpansionRef};
+use crate::compose::Replacer;
+use crate::parse::variable_source;
+use crate::transform::{self, TransformError};
+use crate::verify::Inconsistency;
+
+/// Why the pieces given to a [`SnippetBuilder`] do not make a snippet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BuildError {
+	/// A tab with the number was added more than once. Use [`SnippetBuilder::mirror`] to repeat a tab.
+	DuplicateTab(u8),
+	/// A mirror of a tab with the number comes before any tab with it.
+	UnknownTab(u8),
+	/// Tabs are numbered from 1 without gaps, but the tab with the number is missing while a higher one was added.
+	MissingTab(u8),
+	/// A transformation of the tab with the number is not valid, such as by inserting a group its pattern does not have.
+	Transformation(u8, TransformError),
+	/// The pieces do not make a snippet [`Snippet::verify`] accepts.
+	Inconsistent(Inconsistency)
+}
+
+impl fmt::Display for BuildError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			BuildError::DuplicateTab(num) => write!(f, "tab {} is added more than once", num),
+			BuildError::UnknownTab(num) => write!(f, "mirror of tab {} comes before the tab; add the tab first", num),
+			BuildError::MissingTab(num) => write!(f, "tab {} is missing; number tabs from 1 without gaps", num),
+			BuildError::Transformation(num, error) => write!(f, "transformation of tab {} is not valid: {}", num, error),
+			BuildError::Inconsistent(inconsistency) => write!(f, "pieces do not make a consistent snippet: {}", inconsistency)
+		}
+	}
+}
+
+impl std::error::Error for BuildError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			BuildError::Transformation(_, error) => Some(error),
+			BuildError::Inconsistent(inconsistency) => Some(inconsistency),
+			_ => None
+		}
+	}
+}
+
+/// Builds a snippet from its segments in order, such as
+/// `SnippetBuilder::new().text("fn ").tabstop(1, "name").text("() {").tabstop(0, "").text("}").build()`.
+#[derive(Debug, Default)]
+pub struct SnippetBuilder {
+	body: Vec<Segment>,
+	fields: Vec<(u8, Rc<Field>)>,
+	variables: Vec<Rc<Variable>>,
+	codes: Vec<Rc<Code>>,
+	/// Transformations of each tab.
+	transformations: Vec<(u8, Weak<Transformation>)>,
+	/// First problem with the pieces, reported by [`SnippetBuilder::build`].
+	error: Option<BuildError>
+}
+
+impl SnippetBuilder {
+	pub fn new() -> Self {
+		SnippetBuilder::default()
+	}
+
+	/// Normal text.
+	pub fn text(mut self, text: &str) -> Self {
+		match self.body.last_mut() {
+			Some(Segment::Text(previous)) => previous.push_str(text),
+			_ => self.body.push(Segment::Text(text.to_string()))
+		}
+		self
+	}
+
+	fn field(mut self, num: u8, field: Field) -> Self {
+		if self.fields.iter().any(|(known, _)| *known == num) {
+			self.error.get_or_insert(BuildError::DuplicateTab(num));
+			return self
+		}
+		let field = Rc::new(field);
+		self.fields.push((num, field.clone()));
+		self.body.push(Segment::Field(field));
+		self
+	}
+
+	/// Tab with a placeholder holding the text initially (empty for a plain tab stop).
+	pub fn tabstop(self, num: u8, default: &str) -> Self {
+		let body = if default.is_empty() { Vec::new() } else { vec![Segment::Text(default.to_string())] };
+		self.field(num, Field::Placeholder(body))
+	}
+
+	/// Tab with a menu of the choices, the first of them chosen.
+	pub fn choice<'a>(self, num: u8, choices: impl IntoIterator<Item = &'a str>) -> Self {
+		let choices = choices.into_iter().map(|choice| vec![Segment::Text(choice.to_string())]).collect();
+		self.field(num, Field::Choice(0, choices, Vec::new()))
+	}
+
+	/// Another occurrence of the field of an earlier tab, showing the same text.
+	pub fn mirror(mut self, num: u8) -> Self {
+		match self.fields.iter().find(|(known, _)| *known == num) {
+			Some((_, field)) => self.body.push(Segment::Field(field.clone())),
+			None => {
+				self.error.get_or_insert(BuildError::UnknownTab(num));
+			}
+		}
+		self
+	}
+
+	/// Transformation of the field of an earlier tab (see [`crate::transform`]), showing its result.
+	pub fn transformation(mut self, num: u8, section: &str, format: &str, flags: &str) -> Self {
+		if !self.fields.iter().any(|(known, _)| *known == num) {
+			self.error.get_or_insert(BuildError::UnknownTab(num));
+			return self
+		}
+		if let Err(error) = transform::check(section, format, flags) {
+			self.error.get_or_insert(BuildError::Transformation(num, error));
+			return self
+		}
+		let transformation = Rc::new(Transformation::new(section, format, flags));
+		self.transformations.push((num, Rc::downgrade(&transformation)));
+		self.body.push(Segment::Transformation(transformation));
+		self
+	}
+
+	/// Variable with no default, coming from the editor or the program using this library as [`variable_source`] tells,
+	/// which starts out as its name or, for the editor's, empty until it is resolved (as parsed variables do).
+	/// Variables with the same name are shared.
+	pub fn variable(mut self, name: &str) -> Self {
+		let variable = match self.variables.iter().find(|variable| variable.name == name) {
+			Some(variable) => variable.clone(),
+			None => {
+				let source = variable_source(name);
+				let value = if matches!(source, VariableSource::Daemon) { name } else { "" };
+				let variable = Rc::new(Variable::new(name, value, source));
+				self.variables.push(variable.clone());
+				variable
+			}
+		};
+		self.body.push(Segment::Variable(variable));
+		self
+	}
+
+	/// Code run by the program of the shebang (`#!/bin/sh`, or a bare path such as `/bin/sh`), with no output until it is run.
+	pub fn code(mut self, code: &str, shebang: &str) -> Self {
+		let shebang = if shebang.is_empty() || shebang.starts_with("#!") { shebang.to_string() } else { format!("#!{}", shebang) };
+		let code = Rc::new(Code::new(code, &shebang));
+		self.codes.push(code.clone());
+		self.body.push(Segment::Code(code));
+		self
+	}
+
+	/// The snippet of the pieces added, or the first problem with them.
+	/// Transformations are applied to the text their tab starts out with.
+	pub fn build(self) -> Result<Snippet, BuildError> {
+		if let Some(error) = self.error {
+			return Err(error)
+		}
+		let highest = self.fields.iter().map(|(num, _)| *num).max().unwrap_or(0);
+		if let Some(missing) = (1..highest).find(|num| !self.fields.iter().any(|(known, _)| known == num)) {
+			return Err(BuildError::MissingTab(missing))
+		}
+		let mut snippet = Snippet {
+			tabs: self.fields.iter().map(|(num, field)| Tab {
+				transformations: self.transformations.iter().filter(|(of, _)| of == num).map(|(_, transformation)| transformation.clone()).collect(),
+				..FieldRef(field.clone()).tab(*num)
+			}).collect(),
+			variables: self.variables.iter().map(|variable| ExpansionRef(variable.clone()).expansion()).collect(),
+			code_expansions: self.codes.iter().map(|code| ExpansionRef(code.clone()).expansion()).collect(),
+			named_segments: Vec::new(),
+			current_tab: None,
+			choice_sources: Vec::new(),
+			baseline: Default::default(),
+			body: self.body
+		};
+		let mut replacer = Replacer::default();
+		for (tab, (num, field)) in snippet.tabs.iter().zip(&self.fields) {
+			if let Some(error) = replacer.reapply(&tab.transformations, &field.to_string()).into_iter().next() {
+				return Err(BuildError::Transformation(*num, error))
+			}
+		}
+		replacer.snippet(&mut snippet);
+		snippet.verify().map_err(BuildError::Inconsistent)?;
+		Ok(snippet)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn build_snippet() {
+		let snippet = SnippetBuilder::new()
+			.text("// ").variable("TM_FILENAME").text("\n")
+			.text("let ").tabstop(1, "name").text(": ").choice(2, ["i32", "u8"]).text(" = ").code("echo 1", "/bin/sh")
+			.text("; ").mirror(1).tabstop(0, "").variable("TM_FILENAME").variable("USER")
+			.build()
+			.unwrap();
+		assert_eq!(snippet.to_string(), "// \nlet name: i32 = ; nameUSER");
+		assert_eq!(snippet.tabs().iter().map(|tab| tab.num).collect::<Vec<_>>(), [1, 2, 0]);
+		let variables: Vec<_> = snippet.variables().iter().map(|variable| variable.expansion.upgrade().unwrap()).collect();
+		assert!(matches!(variables[..], [ref editor, ref user] if matches!(editor.source, VariableSource::Client) && matches!(user.source, VariableSource::Daemon)));
+		assert_eq!(snippet.code_expansions()[0].expansion.upgrade().unwrap().shebang, "#!/bin/sh");
+		assert_eq!(SnippetBuilder::new().tabstop(1, "a").tabstop(1, "b").build().unwrap_err(), BuildError::DuplicateTab(1));
+		assert_eq!(SnippetBuilder::new().mirror(2).build().unwrap_err(), BuildError::UnknownTab(2));
+	}
+
+	#[test]
+	fn validate_pieces() {
+		let snippet = SnippetBuilder::new().tabstop(1, "name").text(" ").transformation(1, "(.*)", "${1:/upcase}", "").build().unwrap();
+		assert_eq!(snippet.to_string(), "name NAME");
+		assert_eq!(snippet.tabs()[0].transformations.len(), 1);
+		assert_eq!(SnippetBuilder::new().transformation(1, "a", "b", "").build().unwrap_err(), BuildError::UnknownTab(1));
+		let error = SnippetBuilder::new().tabstop(1, "a").transformation(1, "(a)", "$2", "").build().unwrap_err();
+		assert_eq!(error, BuildError::Transformation(1, TransformError::UnknownGroup(2, 1)));
+		assert_eq!(error.to_string(), "transformation of tab 1 is not valid: format inserts group 2, which the pattern does not have (it has 1)");
+		assert_eq!(SnippetBuilder::new().tabstop(1, "").tabstop(3, "").tabstop(0, "").build().unwrap_err(), BuildError::MissingTab(2));
+	}
+}
@@ -0,0 +1,881 @@
+use crate::shared::{Rc, Weak};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use std::{fmt, fs, io};
+use crate::{Snippet, Segment, Field, Transformation, Variable, VariableSource, Code, Conditional, Condition, Tab, Expansion, NamedSegment, NumberField, RepeatField};
+use crate::regex::Regex;
+use crate::parse::MAX_NESTING;
+use crate::library::{SnippetLibrary, SnippetDefinition, SnippetKind, SourceLocation, GlobalCode, CodeSettings};
+
+const MAGIC: &[u8; 4] = b"SNPC";
+/// Starts snippet states rather than library caches.
+const STATE_MAGIC: &[u8; 4] = b"SNPS";
+const VERSION: u8 = 6;
+/// Id written for a reference whose target is not part of the snippet.
+const DANGLING: u32 = u32::MAX;
+
+/// Reasons a cache could not be read.
+#[derive(Debug)]
+pub enum CacheError {
+	Io(io::Error),
+	/// The data is not a cache written by this version of the library. Carries what was wrong with it.
+	Format(&'static str)
+}
+
+impl fmt::Display for CacheError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			CacheError::Io(error) => write!(f, "{}", error),
+			CacheError::Format(message) => write!(f, "invalid snippet cache: {}", message)
+		}
+	}
+}
+
+impl std::error::Error for CacheError {}
+
+impl From<io::Error> for CacheError {
+	fn from(error: io::Error) -> Self {
+		if error.kind() == io::ErrorKind::UnexpectedEof {
+			CacheError::Format("truncated")
+		} else {
+			CacheError::Io(error)
+		}
+	}
+}
+
+impl SnippetLibrary {
+	/// Writes the library in a compact binary form,
+	/// stamped with the modification times of the files its definitions were loaded from.
+	pub fn write_cache(&self, writer: impl io::Write) -> io::Result<()> {
+		let mut encoder = Encoder { writer, nodes: Vec::new() };
+		encoder.writer.write_all(MAGIC)?;
+		encoder.u8(VERSION)?;
+		let mut paths: Vec<&Path> = Vec::new();
+		let sources = self.definitions().iter().map(|definition| &definition.source).chain(self.globals().iter().map(|global| &global.source));
+		for path in sources.filter_map(|source| source.as_ref()?.path.as_deref()) {
+			if !paths.contains(&path) {
+				paths.push(path);
+			}
+		}
+		encoder.len(paths.len())?;
+		for path in paths {
+			encoder.str(&path.to_string_lossy())?;
+			let (secs, nanos) = modified(path).unwrap_or((0, 0));
+			encoder.u64(secs)?;
+			encoder.u32(nanos)?;
+		}
+		encoder.len(self.definitions().len())?;
+		for definition in self.definitions() {
+			encoder.nodes.clear();
+			encoder.definition(definition, true)?;
+		}
+		encoder.len(self.globals().len())?;
+		for global in self.globals() {
+			encoder.str(&global.code)?;
+			encoder.str(&global.shebang)?;
+			encoder.source(global.source.as_ref())?;
+		}
+		encoder.len(self.code_settings().len())?;
+		for settings in self.code_settings() {
+			encoder.option_str(settings.path.as_ref().map(|path| path.to_string_lossy()).as_deref())?;
+			encoder.option_str(settings.shell.as_deref())?;
+			encoder.pairs(&settings.interpreters)?;
+			encoder.pairs(&settings.environment)?;
+		}
+		Ok(())
+	}
+
+	/// Reads a library written by [`SnippetLibrary::write_cache`].
+	/// Gives nothing when any file the library was loaded from has been modified (or removed) since, as the cache is then stale.
+	pub fn read_cache(reader: impl io::Read) -> Result<Option<SnippetLibrary>, CacheError> {
+		let mut decoder = Decoder { reader, nodes: Vec::new(), depth: 0 };
+		let mut magic = [0; 4];
+		decoder.reader.read_exact(&mut magic)?;
+		if &magic != MAGIC {
+			return Err(CacheError::Format("not a snippet cache"))
+		}
+		if decoder.u8()? != VERSION {
+			return Err(CacheError::Format("unsupported version"))
+		}
+		let mut stale = false;
+		for _ in 0..decoder.len()? {
+			let path = PathBuf::from(decoder.str()?);
+			let stamp = (decoder.u64()?, decoder.u32()?);
+			stale |= stamp == (0, 0) || modified(&path).ok() != Some(stamp);
+		}
+		if stale {
+			return Ok(None)
+		}
+		let mut library = SnippetLibrary::new();
+		for _ in 0..decoder.len()? {
+			decoder.nodes.clear();
+			library.add(decoder.definition()?);
+		}
+		for _ in 0..decoder.len()? {
+			library.add_global(GlobalCode {
+				code: decoder.str()?,
+				shebang: decoder.str()?,
+				source: decoder.source()?
+			});
+		}
+		for _ in 0..decoder.len()? {
+			library.add_code_settings(CodeSettings {
+				path: decoder.option_str()?.map(PathBuf::from),
+				shell: decoder.option_str()?,
+				interpreters: decoder.pairs()?,
+				environment: decoder.pairs()?
+			});
+		}
+		Ok(Some(library))
+	}
+}
+
+impl Snippet {
+	/// Writes the full state of the snippet (including text filled into fields, resolved variables and the selected tab)
+	/// in the binary form of [`SnippetLibrary::write_cache`], keeping which segments are shared.
+	pub fn write_state(&self, writer: impl io::Write) -> io::Result<()> {
+		let mut encoder = Encoder { writer, nodes: Vec::new() };
+		encoder.writer.write_all(STATE_MAGIC)?;
+		encoder.u8(VERSION)?;
+		encoder.snippet(self)?;
+		encoder.option_i64(self.current_tab.map(i64::from))
+	}
+
+	/// Reads a snippet written by [`Snippet::write_state`].
+	pub fn read_state(reader: impl io::Read) -> Result<Snippet, CacheError> {
+		let mut decoder = Decoder { reader, nodes: Vec::new(), depth: 0 };
+		let mut magic = [0; 4];
+		decoder.reader.read_exact(&mut magic)?;
+		if &magic != STATE_MAGIC {
+			return Err(CacheError::Format("not a snippet state"))
+		}
+		if decoder.u8()? != VERSION {
+			return Err(CacheError::Format("unsupported version"))
+		}
+		let mut snippet = decoder.snippet()?;
+		snippet.current_tab = match decoder.option_i64()? {
+			Some(num) => Some(u8::try_from(num).map_err(|_| CacheError::Format("invalid tab number"))?),
+			None => None
+		};
+		snippet.verify().map_err(|_| CacheError::Format("inconsistent snippet"))?;
+		Ok(snippet)
+	}
+
+	/// Hash of what the snippet is made of, the same however its source was formatted or wherever it was loaded from.
+	/// Covers everything [`Snippet::write_state`] writes apart from the selected tab, so it is stable across runs and platforms
+	/// (though not across versions of this library changing that form), for telling when a stored snippet has changed.
+	pub fn content_hash(&self) -> u64 {
+		let mut encoder = Encoder { writer: Fnv(FNV_OFFSET), nodes: Vec::new() };
+		// Writing to a hash can not fail, and snippets too large to write are hashed as far as they could be.
+		let _ = encoder.snippet(self);
+		encoder.writer.0
+	}
+}
+
+impl SnippetDefinition {
+	/// Hash of the triggers, description and what the definition expands into (see [`Snippet::content_hash`]),
+	/// leaving out where it was loaded from.
+	pub fn content_hash(&self) -> u64 {
+		let mut encoder = Encoder { writer: Fnv(FNV_OFFSET), nodes: Vec::new() };
+		let _ = encoder.definition(self, false);
+		encoder.writer.0
+	}
+}
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x100_0000_01b3;
+
+/// 64 bit FNV-1a hash of the bytes written.
+struct Fnv(u64);
+
+impl io::Write for Fnv {
+	fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+		for &byte in bytes {
+			self.0 = (self.0 ^ u64::from(byte)).wrapping_mul(FNV_PRIME);
+		}
+		Ok(bytes.len())
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+fn modified(path: &Path) -> io::Result<(u64, u32)> {
+	let since = fs::metadata(path)?.modified()?.duration_since(UNIX_EPOCH).unwrap_or_default();
+	Ok((since.as_secs(), since.subsec_nanos()))
+}
+
+/// Writes definitions, giving every reference counted segment an id (in the order their content is finished being written)
+/// so that shared segments and weak references can be written as ids.
+struct Encoder<W> {
+	writer: W,
+	nodes: Vec<*const ()>
+}
+
+impl<W: io::Write> Encoder<W> {
+	fn u8(&mut self, value: u8) -> io::Result<()> {
+		self.writer.write_all(&[value])
+	}
+
+	fn u32(&mut self, value: u32) -> io::Result<()> {
+		self.writer.write_all(&value.to_le_bytes())
+	}
+
+	fn u64(&mut self, value: u64) -> io::Result<()> {
+		self.writer.write_all(&value.to_le_bytes())
+	}
+
+	fn len(&mut self, len: usize) -> io::Result<()> {
+		let len = u32::try_from(len).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "too many items for a snippet cache"))?;
+		self.u32(len)
+	}
+
+	fn str(&mut self, text: &str) -> io::Result<()> {
+		self.len(text.len())?;
+		self.writer.write_all(text.as_bytes())
+	}
+
+	fn option_str(&mut self, text: Option<&str>) -> io::Result<()> {
+		match text {
+			Some(text) => {
+				self.u8(1)?;
+				self.str(text)
+			},
+			None => self.u8(0)
+		}
+	}
+
+	fn option_i64(&mut self, value: Option<i64>) -> io::Result<()> {
+		match value {
+			Some(value) => {
+				self.u8(1)?;
+				self.u64(value as u64)
+			},
+			None => self.u8(0)
+		}
+	}
+
+	fn pairs(&mut self, pairs: &[(String, String)]) -> io::Result<()> {
+		self.len(pairs.len())?;
+		for (first, second) in pairs {
+			self.str(first)?;
+			self.str(second)?;
+		}
+		Ok(())
+	}
+
+	fn source(&mut self, source: Option<&SourceLocation>) -> io::Result<()> {
+		match source {
+			Some(source) => {
+				self.u8(1)?;
+				self.option_str(source.path.as_ref().map(|path| path.to_string_lossy()).as_deref())?;
+				self.u64(source.start_line as u64)?;
+				self.u64(source.end_line as u64)
+			},
+			None => self.u8(0)
+		}
+	}
+
+	/// Writes the definition, leaving out its source (for hashing what it is made of) unless asked for.
+	fn definition(&mut self, definition: &SnippetDefinition, source: bool) -> io::Result<()> {
+		self.len(definition.triggers.len())?;
+		for trigger in &definition.triggers {
+			self.str(trigger)?;
+		}
+		self.option_str(definition.description.as_deref())?;
+		if source {
+			self.source(definition.source.as_ref())?;
+		}
+		match &definition.kind {
+			SnippetKind::Static(text) => {
+				self.u8(0)?;
+				self.str(text)
+			},
+			SnippetKind::Dynamic(snippet) => {
+				self.u8(1)?;
+				self.snippet(snippet)
+			}
+		}
+	}
+
+	/// Writes the segment's id if it was already written, or marks it as new.
+	/// Tells whether its content still needs to be written, after which it must be given an id with [`Encoder::written`].
+	fn node<T>(&mut self, rc: &Rc<T>) -> io::Result<bool> {
+		let ptr = Rc::as_ptr(rc) as *const ();
+		match self.nodes.iter().position(|node| *node == ptr) {
+			Some(id) => {
+				self.u8(1)?;
+				self.u32(id as u32)?;
+				Ok(false)
+			},
+			None => {
+				self.u8(0)?;
+				Ok(true)
+			}
+		}
+	}
+
+	/// Gives the segment whose content was just written the next id, just as reading it back does.
+	fn written<T>(&mut self, rc: &Rc<T>) {
+		self.nodes.push(Rc::as_ptr(rc) as *const ());
+	}
+
+	fn reference<T>(&mut self, weak: &Weak<T>) -> io::Result<()> {
+		let ptr = weak.as_ptr() as *const ();
+		let id = if weak.strong_count() == 0 {
+			None
+		} else {
+			self.nodes.iter().position(|node| *node == ptr)
+		};
+		self.u32(id.map_or(DANGLING, |id| id as u32))
+	}
+
+	fn references<T>(&mut self, weaks: &[Weak<T>]) -> io::Result<()> {
+		self.len(weaks.len())?;
+		for weak in weaks {
+			self.reference(weak)?;
+		}
+		Ok(())
+	}
+
+	fn field(&mut self, field: &Rc<Field>) -> io::Result<()> {
+		if self.node(field)? {
+			match &**field {
+				Field::Placeholder(body) => {
+					self.u8(0)?;
+					self.segments(body)?;
+				},
+				Field::Choice(choice, choices, labels) => {
+					self.u8(1)?;
+					self.u64(*choice as u64)?;
+					self.len(choices.len())?;
+					for body in choices {
+						self.segments(body)?;
+					}
+					self.len(labels.len())?;
+					for label in labels {
+						self.str(label)?;
+					}
+				},
+				Field::Number(number) => {
+					self.u8(2)?;
+					self.u64(number.value as u64)?;
+					self.option_i64(number.min)?;
+					self.option_i64(number.max)?;
+					self.u64(number.step as u64)?;
+				},
+				Field::Toggle(on, when_on, when_off) => {
+					self.u8(3)?;
+					self.u8(*on as u8)?;
+					self.segments(when_on)?;
+					self.segments(when_off)?;
+				},
+				Field::Repeat(repeat) => {
+					self.u8(4)?;
+					if self.node(&repeat.template)? {
+						self.snippet(&repeat.template)?;
+						self.written(&repeat.template);
+					}
+					self.str(&repeat.separator)?;
+					self.len(repeat.repetitions.len())?;
+					for body in &repeat.repetitions {
+						self.segments(body)?;
+					}
+				}
+			}
+			self.written(field);
+		}
+		Ok(())
+	}
+
+	fn segments(&mut self, segments: &[Segment]) -> io::Result<()> {
+		self.len(segments.len())?;
+		for segment in segments {
+			match segment {
+				Segment::Text(text) => {
+					self.u8(0)?;
+					self.str(text)?;
+				},
+				Segment::Field(field) => {
+					self.u8(1)?;
+					self.field(field)?;
+				},
+				Segment::Transformation(transformation) => {
+					self.u8(2)?;
+					if self.node(transformation)? {
+						self.str(&transformation.section)?;
+						self.str(&transformation.format)?;
+						self.str(&transformation.flags)?;
+						self.str(&transformation.result)?;
+						self.written(transformation);
+					}
+				},
+				Segment::Variable(variable) => {
+					self.u8(3)?;
+					if self.node(variable)? {
+						self.str(&variable.name)?;
+						self.str(&variable.value)?;
+						self.u8(match variable.source { VariableSource::Daemon => 0, VariableSource::Client => 1 })?;
+						match &variable.default {
+							Some(default) => {
+								self.u8(1)?;
+								self.segments(default)?;
+							},
+							None => self.u8(0)?
+						}
+						self.written(variable);
+					}
+				},
+				Segment::Code(code) => {
+					self.u8(4)?;
+					if self.node(code)? {
+						self.str(&code.code)?;
+						self.str(&code.output)?;
+						self.str(&code.shebang)?;
+						self.written(code);
+					}
+				},
+				Segment::Conditional(conditional) => {
+					self.u8(6)?;
+					if self.node(conditional)? {
+						// The tested field is written here when it was not written before, so it can be referred to.
+						match conditional.field.upgrade() {
+							Some(field) => {
+								self.u8(1)?;
+								self.field(&field)?;
+							},
+							None => self.u8(0)?
+						}
+						match &conditional.condition {
+							Condition::Empty => self.u8(0)?,
+							Condition::NonEmpty => self.u8(1)?,
+							Condition::Matches(regex) => {
+								self.u8(2)?;
+								self.str(regex.as_str())?;
+								self.str(regex.flags())?;
+							}
+						}
+						self.segments(&conditional.then)?;
+						self.segments(&conditional.otherwise)?;
+						self.written(conditional);
+					}
+				},
+				Segment::Snippet(snippet) => {
+					self.u8(5)?;
+					if self.node(snippet)? {
+						self.snippet(snippet)?;
+						self.written(snippet);
+					}
+				}
+			}
+		}
+		Ok(())
+	}
+
+	fn snippet(&mut self, snippet: &Snippet) -> io::Result<()> {
+		self.segments(&snippet.body)?;
+		self.len(snippet.tabs.len())?;
+		for tab in &snippet.tabs {
+			self.u8(tab.num)?;
+			self.reference(&tab.field)?;
+			self.references(&tab.transformations)?;
+			self.option_str(tab.label.as_deref())?;
+		}
+		self.len(snippet.variables.len())?;
+		for expansion in &snippet.variables {
+			self.reference(&expansion.expansion)?;
+			self.references(&expansion.transformations)?;
+		}
+		self.len(snippet.code_expansions.len())?;
+		for expansion in &snippet.code_expansions {
+			self.reference(&expansion.expansion)?;
+			self.references(&expansion.transformations)?;
+		}
+		self.len(snippet.named_segments.len())?;
+		for named in &snippet.named_segments {
+			match named {
+				NamedSegment::Transformation(name, transformation) => {
+					self.u8(0)?;
+					self.str(name)?;
+					self.reference(transformation)?;
+				},
+				NamedSegment::Code(name, code) => {
+					self.u8(1)?;
+					self.str(name)?;
+					self.reference(code)?;
+				}
+			}
+		}
+		Ok(())
+	}
+}
+
+/// A reference counted segment read back, identified by its position.
+enum Node {
+	Field(Rc<Field>),
+	Transformation(Rc<Transformation>),
+	Variable(Rc<Variable>),
+	Code(Rc<Code>),
+	Conditional(Rc<Conditional>),
+	Snippet(Rc<Snippet>)
+}
+
+struct Decoder<R> {
+	reader: R,
+	nodes: Vec<Node>,
+	/// Number of segment lists being read, each within the one before.
+	depth: usize
+}
+
+/// Reads a weak reference of the given node kind, dangling ones becoming empty weak references.
+macro_rules! reference {
+	($decoder:expr, $kind:ident) => {{
+		let id = $decoder.u32()?;
+		if id == DANGLING {
+			Weak::new()
+		} else {
+			match $decoder.nodes.get(id as usize) {
+				Some(Node::$kind(rc)) => Rc::downgrade(rc),
+				_ => return Err(CacheError::Format("reference to a missing segment"))
+			}
+		}
+	}};
+}
+
+/// Reads a reference counted segment of the given node kind, either new (read with the given expression) or already read.
+macro_rules! node {
+	($decoder:expr, $kind:ident, $read:expr) => {{
+		if $decoder.u8()? == 0 {
+			let rc = Rc::new($read);
+			$decoder.nodes.push(Node::$kind(rc.clone()));
+			rc
+		} else {
+			let id = $decoder.u32()? as usize;
+			match $decoder.nodes.get(id) {
+				Some(Node::$kind(rc)) => rc.clone(),
+				_ => return Err(CacheError::Format("reference to a missing segment"))
+			}
+		}
+	}};
+}
+
+impl<R: io::Read> Decoder<R> {
+	fn u8(&mut self) -> Result<u8, CacheError> {
+		let mut bytes = [0; 1];
+		self.reader.read_exact(&mut bytes)?;
+		Ok(bytes[0])
+	}
+
+	fn u32(&mut self) -> Result<u32, CacheError> {
+		let mut bytes = [0; 4];
+		self.reader.read_exact(&mut bytes)?;
+		Ok(u32::from_le_bytes(bytes))
+	}
+
+	fn u64(&mut self) -> Result<u64, CacheError> {
+		let mut bytes = [0; 8];
+		self.reader.read_exact(&mut bytes)?;
+		Ok(u64::from_le_bytes(bytes))
+	}
+
+	fn len(&mut self) -> Result<usize, CacheError> {
+		Ok(self.u32()? as usize)
+	}
+
+	fn str(&mut self) -> Result<String, CacheError> {
+		let len = self.len()?;
+		// Read as far as there is text rather than allocating the length up front, as a corrupt length may be huge.
+		let mut bytes = Vec::new();
+		io::Read::read_to_end(&mut io::Read::take(&mut self.reader, len as u64), &mut bytes)?;
+		if bytes.len() != len {
+			return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into())
+		}
+		String::from_utf8(bytes).map_err(|_| CacheError::Format("text is not UTF-8"))
+	}
+
+	fn option_str(&mut self) -> Result<Option<String>, CacheError> {
+		Ok(if self.u8()? == 0 { None } else { Some(self.str()?) })
+	}
+
+	fn option_i64(&mut self) -> Result<Option<i64>, CacheError> {
+		Ok(if self.u8()? == 0 { None } else { Some(self.u64()? as i64) })
+	}
+
+	fn pairs(&mut self) -> Result<Vec<(String, String)>, CacheError> {
+		(0..self.len()?).map(|_| Ok((self.str()?, self.str()?))).collect()
+	}
+
+	fn source(&mut self) -> Result<Option<SourceLocation>, CacheError> {
+		Ok(if self.u8()? == 0 {
+			None
+		} else {
+			Some(SourceLocation {
+				path: self.option_str()?.map(PathBuf::from),
+				start_line: self.u64()? as usize,
+				end_line: self.u64()? as usize
+			})
+		})
+	}
+
+	fn definition(&mut self) -> Result<SnippetDefinition, CacheError> {
+		let triggers = (0..self.len()?).map(|_| self.str()).collect::<Result<_, _>>()?;
+		let description = self.option_str()?;
+		let source = self.source()?;
+		let kind = match self.u8()? {
+			0 => SnippetKind::Static(self.str()?),
+			1 => SnippetKind::Dynamic(self.consistent_snippet()?),
+			_ => return Err(CacheError::Format("unknown definition kind"))
+		};
+		Ok(SnippetDefinition { triggers, description, kind, source })
+	}
+
+	fn field(&mut self) -> Result<Rc<Field>, CacheError> {
+		Ok(node!(self, Field, match self.u8()? {
+			0 => Field::Placeholder(self.segments()?),
+			1 => {
+				let choice = self.u64()? as usize;
+				let choices = (0..self.len()?).map(|_| self.segments()).collect::<Result<_, _>>()?;
+				let labels = (0..self.len()?).map(|_| self.str()).collect::<Result<_, _>>()?;
+				Field::Choice(choice, choices, labels)
+			},
+			2 => Field::Number(NumberField {
+				value: self.u64()? as i64,
+				min: self.option_i64()?,
+				max: self.option_i64()?,
+				step: self.u64()? as i64
+			}),
+			3 => Field::Toggle(self.u8()? != 0, self.segments()?, self.segments()?),
+			4 => Field::Repeat(RepeatField {
+				template: node!(self, Snippet, self.snippet()?),
+				separator: self.str()?,
+				repetitions: (0..self.len()?).map(|_| self.segments()).collect::<Result<_, _>>()?
+			}),
+			_ => return Err(CacheError::Format("unknown field kind"))
+		}))
+	}
+
+	fn segments(&mut self) -> Result<Vec<Segment>, CacheError> {
+		if self.depth == MAX_NESTING {
+			return Err(CacheError::Format("segments are nested too deeply"))
+		}
+		self.depth += 1;
+		let segments = self.segments_within();
+		self.depth -= 1;
+		segments
+	}
+
+	fn segments_within(&mut self) -> Result<Vec<Segment>, CacheError> {
+		let len = self.len()?;
+		let mut segments = Vec::new();
+		for _ in 0..len {
+			segments.push(match self.u8()? {
+				0 => Segment::Text(self.str()?),
+				1 => Segment::Field(self.field()?),
+				2 => Segment::Transformation(node!(self, Transformation, Transformation {
+					section: self.str()?,
+					format: self.str()?,
+					flags: self.str()?,
+					result: self.str()?,
+					compiled: Default::default()
+				})),
+				3 => Segment::Variable(node!(self, Variable, Variable {
+					name: self.str()?,
+					value: self.str()?,
+					source: match self.u8()? {
+						0 => VariableSource::Daemon,
+						1 => VariableSource::Client,
+						_ => return Err(CacheError::Format("unknown variable source"))
+					},
+					default: if self.u8()? == 0 { None } else { Some(self.segments()?) }
+				})),
+				4 => Segment::Code(node!(self, Code, Code {
+					code: self.str()?,
+					output: self.str()?,
+					shebang: self.str()?
+				})),
+				5 => Segment::Snippet(node!(self, Snippet, self.snippet()?)),
+				6 => Segment::Conditional(node!(self, Conditional, {
+					let field = if self.u8()? == 0 { Weak::new() } else { Rc::downgrade(&self.field()?) };
+					let condition = match self.u8()? {
+						0 => Condition::Empty,
+						1 => Condition::NonEmpty,
+						2 => {
+							let pattern = self.str()?;
+							let flags = self.str()?;
+							Condition::Matches(Regex::with_flags(&pattern, &flags).map_err(|_| CacheError::Format("invalid pattern"))?)
+						},
+						_ => return Err(CacheError::Format("unknown condition"))
+					};
+					Conditional {
+						field,
+						condition,
+						then: self.segments()?,
+						otherwise: self.segments()?
+					}
+				})),
+				_ => return Err(CacheError::Format("unknown segment kind"))
+			});
+		}
+		Ok(segments)
+	}
+
+	fn transformations(&mut self) -> Result<Vec<Weak<Transformation>>, CacheError> {
+		let len = self.len()?;
+		let mut transformations = Vec::new();
+		for _ in 0..len {
+			transformations.push(reference!(self, Transformation));
+		}
+		Ok(transformations)
+	}
+
+	/// Reads a snippet that is then checked (see [`Snippet::verify`]), so that corrupt data does not make a snippet that misbehaves.
+	fn consistent_snippet(&mut self) -> Result<Snippet, CacheError> {
+		let snippet = self.snippet()?;
+		snippet.verify().map_err(|_| CacheError::Format("inconsistent snippet"))?;
+		Ok(snippet)
+	}
+
+	fn snippet(&mut self) -> Result<Snippet, CacheError> {
+		let body = self.segments()?;
+		let mut tabs = Vec::new();
+		for _ in 0..self.len()? {
+			tabs.push(Tab {
+				num: self.u8()?,
+				field: reference!(self, Field),
+				transformations: self.transformations()?,
+				label: self.option_str()?
+			});
+		}
+		let mut variables = Vec::new();
+		for _ in 0..self.len()? {
+			variables.push(Expansion {
+				expansion: reference!(self, Variable),
+				transformations: self.transformations()?
+			});
+		}
+		let mut code_expansions = Vec::new();
+		for _ in 0..self.len()? {
+			code_expansions.push(Expansion {
+				expansion: reference!(self, Code),
+				transformations: self.transformations()?
+			});
+		}
+		let mut named_segments = Vec::new();
+		for _ in 0..self.len()? {
+			named_segments.push(match self.u8()? {
+				0 => NamedSegment::Transformation(self.str()?, reference!(self, Transformation)),
+				1 => NamedSegment::Code(self.str()?, reference!(self, Code)),
+				_ => return Err(CacheError::Format("unknown named segment kind"))
+			});
+		}
+		Ok(Snippet { body, tabs, variables, code_expansions, named_segments, current_tab: None, choice_sources: Vec::new(), baseline: Default::default() })
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	#[cfg(all(feature = "formats-jetbrains", feature = "formats-espanso"))]
+	fn round_trip() {
+		let import = crate::jetbrains::import(r#"<templateSet>
+<template name="for" value="for ($I$ = 0; $I$ &lt; $N$; $I$++) $END$ by $U$"><variable name="I" expression="&quot;i&quot;" /><variable name="U" expression="user()" /></template>
+<template name="hi" value="hello" />
+</templateSet>"#).unwrap();
+		let mut library: SnippetLibrary = import.definitions.into_iter().collect();
+		library.add_global(GlobalCode { code: String::from("import os"), shebang: String::from("#!/usr/bin/env python3"), source: None });
+		library.add_code_settings(CodeSettings { shell: Some(String::from("#!/bin/bash")), environment: vec![(String::from("A"), String::from("b"))], ..CodeSettings::default() });
+		let mut cache = Vec::new();
+		library.write_cache(&mut cache).unwrap();
+		let read = SnippetLibrary::read_cache(&cache[..]).unwrap().unwrap();
+		assert_eq!(read.definitions().len(), 2);
+		assert_eq!(read.definitions()[1].static_text(), Some("hello"));
+		assert_eq!(read.globals(), library.globals());
+		assert_eq!(read.code_settings(), library.code_settings());
+		let snippet = read.definitions()[0].snippet().unwrap();
+		assert_eq!(snippet.to_string(), "for (i = 0; i < ; i++)  by ");
+		assert_eq!(read.definitions()[0].source, library.definitions()[0].source);
+		let mirrored = match (&snippet.body()[1], &snippet.body()[3]) {
+			(Segment::Field(a), Segment::Field(b)) => Rc::ptr_eq(a, b),
+			_ => false
+		};
+		assert!(mirrored);
+		assert!(snippet.tabs().iter().all(|tab| tab.field.upgrade().is_some()));
+		assert_eq!(snippet.tabs()[0].label.as_deref(), Some("I"));
+		assert_eq!(snippet.variables()[0].expansion.upgrade().unwrap().name, "USER");
+
+		let choice: SnippetLibrary = crate::espanso::import("matches:\n  - trigger: c\n    replace: \"{{c}}\"\n    vars:\n      - name: c\n        type: choice\n        params:\n          values: [{label: Long, id: long text}, short]\n").unwrap().definitions.into_iter().collect();
+		let mut cache = Vec::new();
+		choice.write_cache(&mut cache).unwrap();
+		let read = SnippetLibrary::read_cache(&cache[..]).unwrap().unwrap();
+		let field = read.definitions()[0].snippet().unwrap().tabs()[0].field.upgrade().unwrap();
+		assert_eq!(field.choice_labels(), ["Long", "short"]);
+
+		let filter = Rc::new(Field::Placeholder(vec![Segment::Text(String::from("id"))]));
+		let where_clause = Rc::new(Conditional {
+			field: Rc::downgrade(&filter),
+			condition: Condition::Matches(Regex::with_flags("^ID$", "gi").unwrap()),
+			then: vec![Segment::Text(String::from(" WHERE"))],
+			otherwise: Vec::new()
+		});
+		let snippet = Snippet {
+			body: vec![Segment::Conditional(where_clause), Segment::Text(String::from(" ")), Segment::Field(filter)],
+			tabs: Vec::new(),
+			variables: Vec::new(),
+			code_expansions: Vec::new(),
+			named_segments: Vec::new(),
+			current_tab: None,
+			choice_sources: Vec::new(),
+			baseline: Default::default()
+		};
+		let conditional: SnippetLibrary = [SnippetDefinition::new(vec![String::from("w")], None, snippet)].into_iter().collect();
+		let mut cache = Vec::new();
+		conditional.write_cache(&mut cache).unwrap();
+		let read = SnippetLibrary::read_cache(&cache[..]).unwrap().unwrap();
+		let snippet = read.definitions()[0].snippet().unwrap();
+		assert_eq!(snippet.to_string(), " WHERE id");
+		assert!(matches!(&snippet.body()[0], Segment::Conditional(conditional) if conditional.field.upgrade().is_some()));
+	}
+
+	#[test]
+	#[cfg(feature = "formats-jetbrains")]
+	fn invalidate_and_reject() {
+		let path = std::env::temp_dir().join(format!("snippet-parse-cache-{}.xml", std::process::id()));
+		fs::write(&path, "<templateSet><template name=\"a\" value=\"b\" /></templateSet>").unwrap();
+		let library: SnippetLibrary = crate::jetbrains::import_file(&path).unwrap().definitions.into_iter().collect();
+		let mut cache = Vec::new();
+		library.write_cache(&mut cache).unwrap();
+		assert!(SnippetLibrary::read_cache(&cache[..]).unwrap().is_some());
+		assert!(matches!(SnippetLibrary::read_cache(&cache[..cache.len() - 1]), Err(CacheError::Format("truncated"))));
+		assert!(matches!(SnippetLibrary::read_cache(&b"nope"[..]), Err(CacheError::Format(_))));
+		fs::remove_file(&path).unwrap();
+		assert!(SnippetLibrary::read_cache(&cache[..]).unwrap().is_none());
+	}
+
+	#[test]
+	fn persist_state() {
+		let mut snippet = Snippet::parse("${1:a} $1 ${2|x,y|} ${USER:me}").unwrap();
+		snippet.set_field_text(1, "typed");
+		snippet.set_choice(2, 1);
+		snippet.next_tab();
+		let mut state = Vec::new();
+		snippet.write_state(&mut state).unwrap();
+		let mut read = Snippet::read_state(&state[..]).unwrap();
+		assert_eq!(read.to_string(), "typed typed y me");
+		assert_eq!(read.current_tab().unwrap().tab.num(), 1);
+		read.set_field_text(1, "again");
+		assert_eq!(read.to_string(), "again again y me");
+		assert!(matches!(Snippet::read_state(&b"SNPC"[..]), Err(CacheError::Format("not a snippet state"))));
+	}
+
+	#[test]
+	fn hash_content() {
+		let snippet = Snippet::parse("fn ${1:name}($2) {$0}").unwrap();
+		assert_eq!(snippet.content_hash(), Snippet::parse("fn ${1:name}(${2}) {${0}}").unwrap().content_hash());
+		assert_ne!(snippet.content_hash(), Snippet::parse("fn ${1:name}($3) {$0}").unwrap().content_hash());
+		// Pinned so changes to the hashed form are noticed.
+		assert_eq!(snippet.content_hash(), 14793100232745757423);
+	}
+}
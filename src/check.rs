@@ -0,0 +1,443 @@
+use std::fmt;
+use crate::{Snippet, Segment, Field, NamedSegment, Transformation};
+use crate::shared::{Rc, Weak};
+use crate::library::{SnippetLibrary, SourceLocation};
+use crate::collection::SnippetCollection;
+use crate::warning;
+
+/// Consolidated outcome of checking a library.
+#[derive(Debug, Default)]
+pub struct Report {
+	/// Every issue found, in the order of the definitions they were found in.
+	pub issues: Vec<Issue>
+}
+
+/// A problem found in a definition of a library.
+#[derive(Debug)]
+pub struct Issue {
+	/// Machine readable kind of the problem.
+	pub code: IssueCode,
+	/// How much the problem matters.
+	pub severity: Severity,
+	/// First trigger of the definition the problem was found in. Empty for problems of a scope (see [`check_scopes`]).
+	pub trigger: String,
+	/// Scope the problem was found in, for problems with the scopes a collection extends rather than with a definition.
+	pub scope: Option<String>,
+	/// Where the definition was loaded from, if it was loaded.
+	pub source: Option<SourceLocation>,
+	/// Human readable description of the problem.
+	pub message: String
+}
+
+/// Kinds of problems found by checking.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IssueCode {
+	/// Several tabs (that are not mirrors of the same field) share a number.
+	DuplicateTabNumber,
+	/// Tab numbers skip over a number.
+	TabNumberGap,
+	/// A tab, expansion, named segment or conditional segment refers to a segment that no longer exists.
+	DanglingReference,
+	/// A choice field has nothing to choose from.
+	EmptyChoice,
+	/// A choice field's selected choice does not exist.
+	ChoiceOutOfRange,
+	/// Several definitions expand from the same trigger.
+	DuplicateTrigger,
+	/// A scope extends a scope that is not among those checked.
+	UnresolvedExtends,
+	/// A scope extends itself, directly or through the scopes it extends.
+	ExtendsCycle
+}
+
+/// How much an issue matters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+	/// The snippet works but probably not as intended.
+	Warning,
+	/// The snippet will misbehave when expanded.
+	Error
+}
+
+impl IssueCode {
+	/// Stable identifier of the code, suitable for filtering and configuration files.
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			IssueCode::DuplicateTabNumber => "duplicate-tab-number",
+			IssueCode::TabNumberGap => "tab-number-gap",
+			IssueCode::DanglingReference => "dangling-reference",
+			IssueCode::EmptyChoice => "empty-choice",
+			IssueCode::ChoiceOutOfRange => "choice-out-of-range",
+			IssueCode::DuplicateTrigger => "duplicate-trigger",
+			IssueCode::UnresolvedExtends => "unresolved-extends",
+			IssueCode::ExtendsCycle => "extends-cycle"
+		}
+	}
+
+	pub fn severity(&self) -> Severity {
+		match self {
+			IssueCode::TabNumberGap | IssueCode::DuplicateTrigger | IssueCode::UnresolvedExtends => Severity::Warning,
+			_ => Severity::Error
+		}
+	}
+}
+
+impl fmt::Display for IssueCode {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.as_str())
+	}
+}
+
+impl Report {
+	/// Whether nothing at all was found.
+	pub fn is_clean(&self) -> bool {
+		self.issues.is_empty()
+	}
+
+	/// Issues that make snippets misbehave.
+	pub fn errors(&self) -> impl Iterator<Item = &Issue> {
+		self.issues.iter().filter(|issue| issue.severity == Severity::Error)
+	}
+}
+
+impl SnippetLibrary {
+	/// Checks every definition of the library, and the definitions against each other.
+	/// The scopes that collections extend are checked by [`check_scopes`].
+	pub fn check(&self) -> Report {
+		let mut report = Report::default();
+		for (i, definition) in self.definitions().iter().enumerate() {
+			let trigger = definition.triggers.first().cloned().unwrap_or_default();
+			let mut found: Vec<(IssueCode, String)> = Vec::new();
+			if let Some(snippet) = definition.snippet() {
+				lint(snippet, &mut found);
+			}
+			for trigger in &definition.triggers {
+				let earlier = self.definitions()[..i].iter().any(|earlier| earlier.triggers.contains(trigger));
+				if earlier {
+					found.push((IssueCode::DuplicateTrigger, format!("trigger {} is already used by an earlier definition", trigger)));
+				}
+			}
+			report.issues.extend(found.into_iter().map(|(code, message)| Issue {
+				code,
+				severity: code.severity(),
+				trigger: trigger.clone(),
+				scope: None,
+				source: definition.source.clone(),
+				message
+			}));
+		}
+		report
+	}
+}
+
+/// Checks the scopes that the collections of the scopes extend, each collection given along with the name of its scope:
+/// that every scope extended is among those given, and that no scope extends itself. Each cycle is reported once,
+/// in the first scope of the cycle given.
+pub fn check_scopes(scopes: &[(&str, &SnippetCollection)]) -> Report {
+	let mut report = Report::default();
+	let issue = |code: IssueCode, scope: &str, message: String| Issue {
+		code,
+		severity: code.severity(),
+		trigger: String::new(),
+		scope: Some(scope.to_string()),
+		source: None,
+		message
+	};
+	let index = |name: &str| scopes.iter().position(|(scope, _)| *scope == name);
+	let mut cycles: Vec<Vec<usize>> = Vec::new();
+	for (i, (scope, collection)) in scopes.iter().enumerate() {
+		for extended in collection.extends() {
+			if index(extended).is_none() {
+				report.issues.push(issue(IssueCode::UnresolvedExtends, scope, format!("scope {} extends {}, which is not known", scope, extended)));
+			}
+		}
+		// Walks the scopes reachable from this one, finding a path back to it.
+		let mut path = vec![i];
+		let mut pending: Vec<Vec<usize>> = vec![extended_indices(scopes, i, &index)];
+		let mut visited = vec![false; scopes.len()];
+		while let Some(next) = pending.last_mut() {
+			let Some(j) = next.pop() else {
+				pending.pop();
+				path.pop();
+				continue
+			};
+			if j == i {
+				let mut members = path.clone();
+				members.sort_unstable();
+				if !cycles.contains(&members) {
+					let names: Vec<&str> = path.iter().skip(1).map(|&k| scopes[k].0).collect();
+					let through = if names.is_empty() { String::new() } else { format!(" through {}", names.join(", ")) };
+					report.issues.push(issue(IssueCode::ExtendsCycle, scope, format!("scope {} extends itself{}", scope, through)));
+					cycles.push(members);
+				}
+			} else if !visited[j] {
+				visited[j] = true;
+				path.push(j);
+				pending.push(extended_indices(scopes, j, &index));
+			}
+		}
+	}
+	report
+}
+
+/// Positions of the scopes that the collection at the position extends, leaving out those not given.
+fn extended_indices(scopes: &[(&str, &SnippetCollection)], at: usize, index: &impl Fn(&str) -> Option<usize>) -> Vec<usize> {
+	scopes[at].1.extends().iter().filter_map(|name| index(name)).collect()
+}
+
+/// Finds the problems within a single snippet, those [`Snippet::validate`] finds that checking looks for.
+fn lint(snippet: &Snippet, found: &mut Vec<(IssueCode, String)>) {
+	let mut issues = Vec::new();
+	validate(snippet, &mut issues);
+	found.extend(issues.into_iter().filter_map(|issue| Some((issue.code()?, issue.to_string()))));
+}
+
+/// A problem found in a single snippet by [`Snippet::validate`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValidationIssue {
+	/// Several tabs (that are not mirrors of the same field) share the number.
+	DuplicateTabNumber(u8),
+	/// The tab with the number refers to a field that no longer exists.
+	DeadTab(u8),
+	/// Tab numbers skip over the number.
+	TabNumberGap(u8),
+	/// A transformation acts upon a field that does not exist: that of the tab with the number once its field no longer exists,
+	/// or (None) a transformation of the body that no tab, variable or code lists, so nothing it transforms is known.
+	OrphanTransformation(Option<u8>),
+	/// The number of variables, code expansions and named segments the snippet lists that no longer exist.
+	DeadExpansions(usize, usize, usize),
+	/// A conditional segment tests a field that no longer exists.
+	DeadConditional,
+	/// A choice field has nothing to choose from.
+	EmptyChoice,
+	/// A choice field's selected choice (the first number) is not among its choices (as many as the second number).
+	ChoiceOutOfRange(usize, usize),
+	/// No tab has number 0, so where the cursor ends up after the last tab is left to the editor.
+	MissingFinalTab
+}
+
+impl ValidationIssue {
+	/// The code [`SnippetLibrary::check`] reports the same kind of problem under. None for those it does not look for.
+	pub fn code(&self) -> Option<IssueCode> {
+		match self {
+			ValidationIssue::DuplicateTabNumber(_) => Some(IssueCode::DuplicateTabNumber),
+			ValidationIssue::DeadTab(_) | ValidationIssue::DeadExpansions(..) | ValidationIssue::DeadConditional => Some(IssueCode::DanglingReference),
+			ValidationIssue::TabNumberGap(_) => Some(IssueCode::TabNumberGap),
+			ValidationIssue::EmptyChoice => Some(IssueCode::EmptyChoice),
+			ValidationIssue::ChoiceOutOfRange(..) => Some(IssueCode::ChoiceOutOfRange),
+			ValidationIssue::OrphanTransformation(_) | ValidationIssue::MissingFinalTab => None
+		}
+	}
+}
+
+impl fmt::Display for ValidationIssue {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			ValidationIssue::DuplicateTabNumber(num) => write!(f, "tab number {} is used more than once", num),
+			ValidationIssue::DeadTab(num) => write!(f, "tab {} refers to a field that no longer exists", num),
+			ValidationIssue::TabNumberGap(num) => write!(f, "tab number {} is skipped", num),
+			ValidationIssue::OrphanTransformation(Some(num)) => write!(f, "transformation of tab {} acts upon a field that no longer exists", num),
+			ValidationIssue::OrphanTransformation(None) => write!(f, "transformation acts upon nothing the snippet lists"),
+			ValidationIssue::DeadExpansions(variables, code, named) => {
+				let dead: Vec<String> = [(variables, "variable"), (code, "code"), (named, "named segment")].into_iter()
+					.filter(|(count, _)| **count > 0)
+					.map(|(count, what)| format!("{} {}", count, what))
+					.collect();
+				write!(f, "{} reference(s) refer to segments that no longer exist", dead.join(", "))
+			},
+			ValidationIssue::DeadConditional => write!(f, "conditional segment tests a field that no longer exists"),
+			ValidationIssue::EmptyChoice => write!(f, "choice field has no choices"),
+			ValidationIssue::ChoiceOutOfRange(choice, choices) => write!(f, "choice {} is selected but there are only {} choices", choice, choices),
+			ValidationIssue::MissingFinalTab => write!(f, "there is no tab 0 for the cursor to end at")
+		}
+	}
+}
+
+impl Snippet {
+	/// Finds the problems that make the snippet misbehave once expanded rather than failing outright:
+	/// duplicate or skipped tab numbers, tabs, expansions and conditional segments referring to what no longer exists,
+	/// transformations acting upon fields that do not exist, choice fields with no choices or a selected choice they lack
+	/// and the lack of a `$0`. Nested snippets are validated as well, except for their `$0`. Empty when nothing was found.
+	pub fn validate(&self) -> Vec<ValidationIssue> {
+		let mut issues = Vec::new();
+		validate(self, &mut issues);
+		if !self.tabs.iter().any(|tab| tab.num == 0) {
+			issues.push(ValidationIssue::MissingFinalTab);
+		}
+		issues
+	}
+}
+
+fn validate(snippet: &Snippet, issues: &mut Vec<ValidationIssue>) {
+	for (i, tab) in snippet.tabs.iter().enumerate() {
+		let duplicate = snippet.tabs[..i].iter().any(|earlier| earlier.num == tab.num && !earlier.field.ptr_eq(&tab.field));
+		let reported = snippet.tabs[..i].iter().any(|earlier| earlier.num == tab.num && earlier.field.upgrade().is_none());
+		if duplicate && !issues.contains(&ValidationIssue::DuplicateTabNumber(tab.num)) {
+			issues.push(ValidationIssue::DuplicateTabNumber(tab.num));
+		}
+		if tab.field.upgrade().is_none() && !reported {
+			issues.push(ValidationIssue::DeadTab(tab.num));
+			if !tab.transformations.is_empty() {
+				issues.push(ValidationIssue::OrphanTransformation(Some(tab.num)));
+			}
+		}
+	}
+	if let Some(num) = warning::tab_number_gap(snippet) {
+		issues.push(ValidationIssue::TabNumberGap(num));
+	}
+	let dead_variables = snippet.variables.iter().filter(|variable| variable.expansion.upgrade().is_none()).count();
+	let dead_code = snippet.code_expansions.iter().filter(|code| code.expansion.upgrade().is_none()).count();
+	let dead_named = snippet.named_segments.iter().filter(|named| match named {
+		NamedSegment::Transformation(_, transformation) => transformation.upgrade().is_none(),
+		NamedSegment::Code(_, code) => code.upgrade().is_none()
+	}).count();
+	if dead_variables + dead_code + dead_named > 0 {
+		issues.push(ValidationIssue::DeadExpansions(dead_variables, dead_code, dead_named));
+	}
+	let listed: Vec<&Weak<Transformation>> = snippet.tabs.iter().flat_map(|tab| &tab.transformations)
+		.chain(snippet.variables.iter().flat_map(|variable| &variable.transformations))
+		.chain(snippet.code_expansions.iter().flat_map(|code| &code.transformations))
+		.chain(snippet.named_segments.iter().filter_map(|named| match named {
+			NamedSegment::Transformation(_, transformation) => Some(transformation),
+			NamedSegment::Code(..) => None
+		}))
+		.collect();
+	validate_segments(&snippet.body, &listed, issues);
+}
+
+fn validate_segments(segments: &[Segment], listed: &[&Weak<Transformation>], issues: &mut Vec<ValidationIssue>) {
+	for segment in segments {
+		match segment {
+			Segment::Transformation(transformation) if !listed.iter().any(|weak| weak.as_ptr() == Rc::as_ptr(transformation)) => {
+				issues.push(ValidationIssue::OrphanTransformation(None));
+			},
+			Segment::Field(field) => match &**field {
+				Field::Placeholder(body) => validate_segments(body, listed, issues),
+				Field::Choice(choice, choices, _) => {
+					if choices.is_empty() {
+						issues.push(ValidationIssue::EmptyChoice);
+					} else if *choice >= choices.len() {
+						issues.push(ValidationIssue::ChoiceOutOfRange(*choice, choices.len()));
+					}
+					for body in choices {
+						validate_segments(body, listed, issues);
+					}
+				},
+				Field::Number(_) => {},
+				Field::Toggle(_, on, off) => {
+					validate_segments(on, listed, issues);
+					validate_segments(off, listed, issues);
+				},
+				Field::Repeat(repeat) => {
+					validate(&repeat.template, issues);
+					for body in &repeat.repetitions {
+						validate_segments(body, listed, issues);
+					}
+				}
+			},
+			Segment::Conditional(conditional) => {
+				if conditional.field.upgrade().is_none() {
+					issues.push(ValidationIssue::DeadConditional);
+				}
+				validate_segments(&conditional.then, listed, issues);
+				validate_segments(&conditional.otherwise, listed, issues);
+			},
+			Segment::Snippet(snippet) => validate(snippet, issues),
+			_ => {}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::Tab;
+	use crate::library::SnippetDefinition;
+
+	fn definition(trigger: &str, body: Vec<Segment>, tabs: Vec<Tab>) -> SnippetDefinition {
+		SnippetDefinition::new(vec![trigger.to_string()], None, Snippet {
+			body,
+			tabs,
+			variables: Vec::new(),
+			code_expansions: Vec::new(),
+			named_segments: Vec::new(),
+			current_tab: None,
+			choice_sources: Vec::new(),
+			baseline: Default::default()
+		})
+	}
+
+	#[test]
+	fn check_library() {
+		let first = Rc::new(Field::Placeholder(Vec::new()));
+		let second = Rc::new(Field::Choice(3, vec![vec![Segment::Text(String::from("a"))]], Vec::new()));
+		let tabs = vec![
+			Tab { num: 2, field: Rc::downgrade(&first), transformations: Vec::new(), label: None },
+			Tab { num: 2, field: Rc::downgrade(&second), transformations: Vec::new(), label: None },
+			Tab { num: 3, field: Rc::downgrade(&Rc::new(Field::Placeholder(Vec::new()))), transformations: Vec::new(), label: None }
+		];
+		let library: SnippetLibrary = [
+			definition("x", vec![Segment::Field(first), Segment::Field(second)], tabs),
+			definition("x", vec![Segment::Text(String::from("static"))], Vec::new())
+		].into_iter().collect();
+		let report = library.check();
+		let codes: Vec<_> = report.issues.iter().map(|issue| issue.code.as_str()).collect();
+		assert_eq!(codes, ["duplicate-tab-number", "dangling-reference", "tab-number-gap", "choice-out-of-range", "duplicate-trigger"]);
+		assert_eq!(report.errors().count(), 3);
+		assert!(!report.is_clean());
+	}
+
+	#[test]
+	#[cfg(feature = "formats-ultisnips")]
+	fn check_extended_scopes() {
+		let load = |text: &str| SnippetCollection::from_snippets(text.as_bytes()).unwrap().collection;
+		let (rust, c, cpp, lone) = (load("extends c, nope\n"), load("extends cpp\n"), load("extends rust, c\n"), load("extends lone\n"));
+		let report = check_scopes(&[("rust", &rust), ("c", &c), ("cpp", &cpp), ("lone", &lone)]);
+		let issues: Vec<_> = report.issues.iter().map(|issue| (issue.code, issue.scope.as_deref().unwrap(), issue.message.as_str())).collect();
+		assert_eq!(issues, [
+			(IssueCode::UnresolvedExtends, "rust", "scope rust extends nope, which is not known"),
+			(IssueCode::ExtendsCycle, "rust", "scope rust extends itself through c, cpp"),
+			(IssueCode::ExtendsCycle, "c", "scope c extends itself through cpp"),
+			(IssueCode::ExtendsCycle, "lone", "scope lone extends itself")
+		]);
+		assert_eq!(report.errors().count(), 3);
+		assert!(check_scopes(&[("c", &load("extends cpp\n")), ("cpp", &load(""))]).is_clean());
+	}
+
+	#[test]
+	fn validate_snippet() {
+		assert_eq!(Snippet::parse("${1:a} $1 ${1/(.*)/${1:/upcase}/} ${USER/a/b/} $0").unwrap().validate(), []);
+		assert_eq!(Snippet::parse("${1|a,b|} ${2:b}").unwrap().validate(), [ValidationIssue::MissingFinalTab]);
+		assert_eq!(Snippet::parse("${1:a} ${3:b} $0").unwrap().validate(), [ValidationIssue::TabNumberGap(2)]);
+
+		let first = Rc::new(Field::Choice(0, Vec::new(), Vec::new()));
+		let transformation = Rc::new(Transformation::new("a", "b", ""));
+		let orphan = Rc::new(Transformation::new("c", "d", ""));
+		let tabs = vec![
+			Tab { num: 1, field: Rc::downgrade(&first), transformations: Vec::new(), label: None },
+			Tab { num: 1, field: Rc::downgrade(&Rc::new(Field::Placeholder(Vec::new()))), transformations: vec![Rc::downgrade(&transformation)], label: None },
+			Tab { num: 0, field: Rc::downgrade(&Rc::new(Field::Placeholder(Vec::new()))), transformations: Vec::new(), label: None }
+		];
+		let snippet = Snippet {
+			body: vec![Segment::Field(first), Segment::Transformation(transformation), Segment::Transformation(orphan)],
+			tabs,
+			variables: Vec::new(),
+			code_expansions: Vec::new(),
+			named_segments: Vec::new(),
+			current_tab: None,
+			choice_sources: Vec::new(),
+			baseline: Default::default()
+		};
+		let issues = snippet.validate();
+		assert_eq!(issues, [
+			ValidationIssue::DuplicateTabNumber(1),
+			ValidationIssue::DeadTab(1),
+			ValidationIssue::OrphanTransformation(Some(1)),
+			ValidationIssue::DeadTab(0),
+			ValidationIssue::EmptyChoice,
+			ValidationIssue::OrphanTransformation(None)
+		]);
+		assert_eq!(issues[0].code(), Some(IssueCode::DuplicateTabNumber));
+		assert_eq!(issues[2].to_string(), "transformation of tab 1 acts upon a field that no longer exists");
+	}
+}
@@ -0,0 +1,95 @@
+//! Choice fields whose options are produced when the snippet is expanded, such as the branches of a git repository,
+//! rather than written in the snippet.
+
+use crate::{Snippet, Segment, Field};
+use crate::transform::TransformError;
+
+/// What produces the options of a choice field, each line of the text produced being an option.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChoiceSource {
+	/// The output of code, run as [`crate::Code`] is.
+	Code {
+		code: String,
+		/// The program that runs the code, as a shebang.
+		shebang: String
+	},
+	/// The value of a variable, resolved as variables of the body are.
+	Variable(String)
+}
+
+impl Snippet {
+	/// Has the options of the choice field selected by the tab produced by the source when the snippet is expanded
+	/// (see [`Snippet::resolve_choices`]), in place of those written. False when the tab's field is not a choice.
+	pub fn set_choice_source(&mut self, num: u8, source: ChoiceSource) -> bool {
+		let Some(tab) = self.tabs.iter().find(|tab| tab.num == num) else {
+			return false
+		};
+		if !matches!(tab.field.upgrade().as_deref(), Some(Field::Choice(..))) {
+			return false
+		}
+		let field = tab.field.clone();
+		self.choice_sources.retain(|(known, _)| known.as_ptr() != field.as_ptr());
+		self.choice_sources.push((field, source));
+		true
+	}
+
+	/// Sources of the choice fields not resolved yet, along with the number of their tab.
+	pub fn choice_sources(&self) -> Vec<(u8, &ChoiceSource)> {
+		self.choice_sources.iter()
+			.filter_map(|(field, source)| Some((self.tabs.iter().find(|tab| tab.field.as_ptr() == field.as_ptr())?.num, source)))
+			.collect()
+	}
+
+	/// Replaces the options of each choice field with a source with the lines of the text produced for the source, choosing the first.
+	/// Meant to be called once the snippet is expanded, before a tab is selected. Fields whose source produces nothing
+	/// (None or no lines) keep the options written. Sources are resolved once, so are not resolved by later calls.
+	/// Returns why transformations acting upon the fields could not be applied, as [`Snippet::set_choice`] does.
+	pub fn resolve_choices(&mut self, mut produce: impl FnMut(&ChoiceSource) -> Option<String>) -> Vec<TransformError> {
+		let mut errors = Vec::new();
+		for (field, source) in std::mem::take(&mut self.choice_sources) {
+			let Some(num) = self.tabs.iter().find(|tab| tab.field.as_ptr() == field.as_ptr()).map(|tab| tab.num) else {
+				continue
+			};
+			let options: Vec<Vec<Segment>> = produce(&source).iter()
+				.flat_map(|text| text.lines())
+				.filter(|line| !line.is_empty())
+				.map(|line| vec![Segment::Text(line.to_string())])
+				.collect();
+			if options.is_empty() {
+				continue
+			}
+			let resolved = self.replace_field(num, |_| Some(Field::Choice(0, options, Vec::new())));
+			errors.extend(resolved.into_iter().flatten());
+		}
+		errors
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn resolve_choice_sources() {
+		let mut snippet = Snippet::parse("git checkout ${1|main|} ${1/(.*)/${1:/upcase}/} ${2|x|} ${3:y}").unwrap();
+		assert!(snippet.set_choice_source(1, ChoiceSource::Code { code: String::from("git branch"), shebang: String::from("#!/bin/sh") }));
+		assert!(snippet.set_choice_source(2, ChoiceSource::Variable(String::from("NONE"))));
+		assert!(!snippet.set_choice_source(3, ChoiceSource::Variable(String::from("Y"))));
+		let mut copy = snippet.deep_clone();
+		assert_eq!(copy.choice_sources().len(), 2);
+
+		let errors = snippet.resolve_choices(|source| match source {
+			ChoiceSource::Code { code, .. } if code == "git branch" => Some(String::from("dev\n\nfix\n")),
+			_ => None
+		});
+		assert!(errors.is_empty() && snippet.choice_sources().is_empty());
+		assert_eq!(snippet.to_string(), "git checkout dev DEV x y");
+		assert!(snippet.set_choice(1, 1).unwrap().is_empty());
+		assert_eq!(snippet.to_string(), "git checkout fix FIX x y");
+		assert!(snippet.set_choice(1, 2).is_none());
+
+		copy.resolve_choices(|_| Some(String::from("z")));
+		assert_eq!(copy.to_string(), "git checkout z Z z y");
+		assert_eq!(snippet.to_string(), "git checkout fix FIX x y");
+	}
+}
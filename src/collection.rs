@@ -0,0 +1,707 @@
+//! Snippets loaded from editor snippet files, looked up by the prefixes that expand them as completion engines do.
+//! Bodies can be left unparsed until their snippet is first asked for, for collections too large to parse every body of up front.
+
+use std::{fmt, io};
+use std::sync::OnceLock;
+use crate::Snippet;
+use crate::parse::{ParseError, SnippetSyntax};
+use crate::regex::{Regex, Captures};
+use crate::json;
+#[cfg(feature = "formats-vscode")]
+use crate::json::{Node, Value};
+
+/// A snippet of a collection along with what its file says about it.
+#[derive(Debug)]
+pub struct CollectionEntry {
+	/// Name of the snippet within its file.
+	pub name: String,
+	/// Texts that, when typed, are expanded into the snippet. Empty for snippets only inserted by name, such as file templates.
+	pub prefixes: Vec<String>,
+	pub description: Option<String>,
+	/// Languages the snippet is for. Empty when it is for every language.
+	pub scopes: Vec<String>,
+	/// Line (starting at 1) of the file the snippet is defined on.
+	pub line: usize,
+	/// Snippets with a higher priority hide those with the same prefix, as in UltiSnips. 0 unless the file says otherwise.
+	pub priority: i64,
+	/// Regular expression matching the text before the cursor that expands the snippet (an UltiSnips `r` trigger),
+	/// ending in `$` when loaded so that the match ends at the cursor. Groups of the match fill in the tabs of the same number,
+	/// see [`CollectionEntry::instantiate`].
+	pub pattern: Option<Regex>,
+	pub snippet: LazySnippet,
+	/// Comments of the file belonging to the snippet: the comment lines before it (after the snippet before it),
+	/// and the comment after the `priority` line before it, in the order they are in the file.
+	pub comments: Vec<Comment>
+}
+
+impl CollectionEntry {
+	/// Match of the entry's regular expression trigger within the text before the cursor.
+	/// None when there is no match or the entry has no such trigger.
+	pub fn match_trigger<'t>(&self, before: &'t str) -> Option<Captures<'t>> {
+		self.pattern.as_ref()?.captures(before)
+	}
+
+	/// A copy of the entry's snippet with the field of each tab filled in with the group of the trigger match of the same number,
+	/// re-running the transformations acting upon it (so `(\w+)\.new` expanding into `let $1 = ${1/.*/\u$0/}::new();` gives `let x = X::new();` for `x.new`).
+	/// Groups that did not take part in the match and those without a tab are left out.
+	/// Transformations that can not be applied to a group keep their result, as with [`Snippet::set_field_text`].
+	pub fn instantiate(&self, captures: &Captures) -> Result<Snippet, &ParseError> {
+		let mut snippet = self.snippet.get()?.deep_clone();
+		for group in 1..captures.len() {
+			let (Ok(num), Some(text)) = (u8::try_from(group), captures.get(group)) else {
+				continue
+			};
+			snippet.set_field_text(num, text);
+		}
+		Ok(snippet)
+	}
+}
+
+/// A snippet, or the body it is parsed from when first asked for.
+#[derive(Debug)]
+pub struct LazySnippet {
+	syntax: SnippetSyntax,
+	body: String,
+	parsed: OnceLock<Result<Snippet, ParseError>>
+}
+
+impl LazySnippet {
+	/// The snippet of the body written in the syntax, parsed when first asked for.
+	pub fn new(syntax: SnippetSyntax, body: String) -> Self {
+		LazySnippet { syntax, body, parsed: OnceLock::new() }
+	}
+
+	/// The snippet, parsing the body unless it already was. Why it could not be parsed when it can not.
+	pub fn get(&self) -> Result<&Snippet, &ParseError> {
+		self.parsed.get_or_init(|| Snippet::parse_with(self.syntax, &self.body)).as_ref()
+	}
+
+	/// Whether the body has been parsed.
+	pub fn is_parsed(&self) -> bool {
+		self.parsed.get().is_some()
+	}
+
+	/// The body the snippet is parsed from. Empty for snippets that were given parsed.
+	pub fn body(&self) -> &str {
+		&self.body
+	}
+}
+
+impl From<Snippet> for LazySnippet {
+	fn from(snippet: Snippet) -> Self {
+		LazySnippet { syntax: SnippetSyntax::Lsp, body: String::new(), parsed: OnceLock::from(Ok(snippet)) }
+	}
+}
+
+/// Snippets keyed by prefix, in the order they were loaded.
+#[derive(Debug, Default)]
+pub struct SnippetCollection {
+	entries: Vec<CollectionEntry>,
+	extends: Vec<String>,
+	comments: Vec<Comment>
+}
+
+/// A `#` comment of a `.snippets` file, kept so that the file can be edited and written back out with its comments.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Comment {
+	/// Line (starting at 1) of the file the comment is on.
+	pub line: usize,
+	/// Text after the `#`.
+	pub text: String,
+	/// Whether the comment follows a directive on its line (such as `extends c # for C`) rather than being a line of its own.
+	pub trailing: bool
+}
+
+/// The text before a trailing `#` comment, which starts after whitespace, and the comment.
+#[cfg(feature = "formats-ultisnips")]
+fn trailing_comment(text: &str) -> (&str, Option<&str>) {
+	let start = text.char_indices().zip(text.chars().skip(1)).find(|&((_, c), next)| c.is_whitespace() && next == '#');
+	match start {
+		Some(((index, c), _)) => (text[..index].trim_end(), Some(&text[index + c.len_utf8() + 1..])),
+		None => (text, None)
+	}
+}
+
+/// A collection loaded from a snippet file, along with why the snippets that were left out of it could not be loaded.
+#[derive(Debug)]
+pub struct PartialLoad {
+	pub collection: SnippetCollection,
+	pub errors: Vec<CollectionError>
+}
+
+/// Reasons snippets could not be loaded into a collection.
+#[derive(Debug)]
+pub enum CollectionError {
+	/// The file could not be read.
+	Io(io::Error),
+	/// The file is not valid JSON or `.snippets` syntax. Carries the line (starting at 1) and a description of the problem.
+	Syntax(usize, &'static str),
+	/// The JSON at this line does not have the shape of a snippet file.
+	Structure(usize, &'static str),
+	/// The body of the snippet with the name, defined on the line, could not be parsed.
+	/// The position of the error is within the body, its lines joined by line breaks.
+	Snippet(usize, String, ParseError)
+}
+
+impl fmt::Display for CollectionError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			CollectionError::Io(error) => write!(f, "{}", error),
+			CollectionError::Syntax(line, message) => write!(f, "line {}: {}", line, message),
+			CollectionError::Structure(line, message) => write!(f, "line {}: {}", line, message),
+			CollectionError::Snippet(line, name, error) => write!(f, "line {}: snippet {}: {}", line, name, error)
+		}
+	}
+}
+
+impl std::error::Error for CollectionError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			CollectionError::Io(error) => Some(error),
+			CollectionError::Snippet(_, _, error) => Some(error),
+			_ => None
+		}
+	}
+}
+
+impl From<io::Error> for CollectionError {
+	fn from(error: io::Error) -> Self {
+		CollectionError::Io(error)
+	}
+}
+
+impl From<json::Error> for CollectionError {
+	fn from(error: json::Error) -> Self {
+		CollectionError::Syntax(error.line, error.message)
+	}
+}
+
+/// Trigger, description and options of an UltiSnips `snippet` line, from what follows `snippet`.
+#[cfg(feature = "formats-ultisnips")]
+fn ultisnips_header(arguments: &str) -> Result<(String, Option<String>, &str), &'static str> {
+	let mut rest = arguments;
+	let mut flags = "";
+	// Options are a word after the description.
+	if let Some((before, options)) = rest.rsplit_once(char::is_whitespace) {
+		if !options.contains('"') && before.trim_end().ends_with('"') {
+			flags = options;
+			rest = before.trim_end();
+		}
+	}
+	let mut description = None;
+	if let Some(before) = rest.strip_suffix('"') {
+		if let Some((trigger, quoted)) = before.rsplit_once('"') {
+			if trigger.is_empty() || trigger.ends_with(char::is_whitespace) {
+				description = Some(quoted.to_string());
+				rest = trigger.trim_end();
+			}
+		}
+	}
+	// Triggers with spaces and regular expression triggers are enclosed in a character that is not part of them.
+	let trigger = match rest.chars().next() {
+		None => return Err("snippet has no trigger"),
+		Some(delimiter) if rest.contains(char::is_whitespace) || flags.contains('r') => match rest[delimiter.len_utf8()..].strip_suffix(delimiter) {
+			Some(trigger) => trigger,
+			None => return Err("trigger with spaces is not enclosed in delimiters")
+		},
+		Some(_) => rest
+	};
+	Ok((trigger.to_string(), description, flags))
+}
+
+/// Trigger and description of a SnipMate `snippet` line, from what follows `snippet`.
+#[cfg(feature = "formats-ultisnips")]
+fn snipmate_header(arguments: &str) -> Result<(String, Option<String>, &str), &'static str> {
+	let (trigger, description) = arguments.split_once(char::is_whitespace).unwrap_or((arguments, ""));
+	if trigger.is_empty() {
+		return Err("snippet has no trigger")
+	}
+	let description = description.trim();
+	Ok((trigger.to_string(), (!description.is_empty()).then(|| description.to_string()), ""))
+}
+
+/// Strings of a node that is either a string or an array of them.
+#[cfg(feature = "formats-vscode")]
+fn strings<'a>(node: &'a Node, message: &'static str) -> Result<Vec<&'a str>, CollectionError> {
+	match &node.value {
+		Value::String(string) => Ok(vec![string]),
+		Value::Array(items) => items.iter()
+			.map(|item| item.as_str().ok_or(CollectionError::Structure(item.line, message)))
+			.collect(),
+		_ => Err(CollectionError::Structure(node.line, message))
+	}
+}
+
+impl SnippetCollection {
+	pub fn new() -> Self {
+		SnippetCollection::default()
+	}
+
+	/// Loads a VSCode snippet file (`*.code-snippets` or a language's `snippets.json`), an object of snippets by name, each with:
+	/// - `body`: the snippet, as a string or an array of lines.
+	/// - `prefix`: a prefix or an array of them. Optional.
+	/// - `description`: a string or an array of lines. Optional.
+	/// - `scope`: comma separated languages. Optional.
+	///
+	/// Other keys are ignored. Comments and trailing commas are permitted, as VSCode does.
+	/// Snippets that can not be loaded are left out, failing only when the file is not an object of snippets.
+	#[cfg(feature = "formats-vscode")]
+	pub fn from_vscode_json(reader: impl io::Read) -> Result<PartialLoad, CollectionError> {
+		SnippetCollection::load_vscode_json(reader, false)
+	}
+
+	/// Loads a VSCode snippet file as [`SnippetCollection::from_vscode_json`] does, leaving bodies to be parsed when their snippet is first asked for,
+	/// so snippets whose body can not be parsed are not left out.
+	#[cfg(feature = "formats-vscode")]
+	pub fn lazy_vscode_json(reader: impl io::Read) -> Result<PartialLoad, CollectionError> {
+		SnippetCollection::load_vscode_json(reader, true)
+	}
+
+	#[cfg(feature = "formats-vscode")]
+	fn load_vscode_json(mut reader: impl io::Read, lazy: bool) -> Result<PartialLoad, CollectionError> {
+		let mut text = String::new();
+		reader.read_to_string(&mut text)?;
+		let root = json::parse(&text)?;
+		let Value::Object(snippets) = &root.value else {
+			return Err(CollectionError::Structure(root.line, "expected an object of snippets by name"))
+		};
+		let mut collection = SnippetCollection::new();
+		let mut errors = Vec::new();
+		for (name, node) in snippets {
+			match SnippetCollection::vscode_entry(name, node, lazy) {
+				Ok(entry) => collection.add(entry),
+				Err(error) => errors.push(error)
+			}
+		}
+		Ok(PartialLoad { collection, errors })
+	}
+
+	/// The entry defined by the node of a VSCode snippet file.
+	#[cfg(feature = "formats-vscode")]
+	fn vscode_entry(name: &str, node: &Node, lazy: bool) -> Result<CollectionEntry, CollectionError> {
+		if !matches!(node.value, Value::Object(_)) {
+			return Err(CollectionError::Structure(node.line, "expected a snippet object"))
+		}
+		let Some(body) = node.get("body") else {
+			return Err(CollectionError::Structure(node.line, "snippet has no body"))
+		};
+		let body = strings(body, "body must be a string or an array of strings")?.join("\n");
+		let snippet = LazySnippet::new(SnippetSyntax::Lsp, body);
+		if !lazy {
+			if let Err(error) = snippet.get() {
+				return Err(CollectionError::Snippet(node.line, name.to_string(), error.clone()))
+			}
+		}
+		let prefixes = match node.get("prefix") {
+			Some(prefix) => strings(prefix, "prefix must be a string or an array of strings")?,
+			None => Vec::new()
+		};
+		let description = match node.get("description") {
+			Some(description) => Some(strings(description, "description must be a string or an array of strings")?.join("\n")),
+			None => None
+		};
+		let scopes = match node.get("scope") {
+			Some(scope) => scope.as_str()
+				.ok_or(CollectionError::Structure(scope.line, "scope must be a string"))?
+				.split(',')
+				.map(str::trim)
+				.filter(|scope| !scope.is_empty())
+				.map(str::to_string)
+				.collect(),
+			None => Vec::new()
+		};
+		Ok(CollectionEntry {
+			name: name.to_string(),
+			prefixes: prefixes.into_iter().map(str::to_string).collect(),
+			description,
+			scopes,
+			line: node.line,
+			priority: 0,
+			pattern: None,
+			snippet,
+			comments: Vec::new()
+		})
+	}
+
+	/// Loads a SnipMate or UltiSnips `.snippets` file, made of (besides lines that are `#` comments):
+	/// - `snippet trigger "description" options` lines followed by the body and an `endsnippet` line (UltiSnips),
+	///   or `snippet trigger description` lines followed by the body indented by a tab (SnipMate).
+	/// - `extends` lines naming other scopes (comma separated) whose snippets are available with these, see [`SnippetCollection::extends`].
+	/// - `priority` lines giving the priority of the snippets after them.
+	///
+	/// `extends` and `priority` lines may end in a comment, starting with a `#` after whitespace.
+	/// Within bodies, `#` is text. Comments are kept with the snippet after them (see [`CollectionEntry::comments`]),
+	/// those of `extends` lines and those after the last snippet with the collection (see [`SnippetCollection::comments`]).
+	///
+	/// Bodies are parsed as UltiSnips bodies. `global`, `context`, `pre_expand` and other UltiSnips directives are skipped,
+	/// see [`crate::ultisnips::globals`] for reading global code. Snippets that can not be loaded are left out.
+	/// Regular expression triggers (the `r` option) are kept as the [`CollectionEntry::pattern`] of entries without a prefix.
+	#[cfg(feature = "formats-ultisnips")]
+	pub fn from_snippets(reader: impl io::Read) -> Result<PartialLoad, CollectionError> {
+		SnippetCollection::load_snippets(reader, false)
+	}
+
+	/// Loads a `.snippets` file as [`SnippetCollection::from_snippets`] does, leaving bodies to be parsed when their snippet is first asked for,
+	/// so snippets whose body can not be parsed are not left out.
+	#[cfg(feature = "formats-ultisnips")]
+	pub fn lazy_snippets(reader: impl io::Read) -> Result<PartialLoad, CollectionError> {
+		SnippetCollection::load_snippets(reader, true)
+	}
+
+	#[cfg(feature = "formats-ultisnips")]
+	fn load_snippets(mut reader: impl io::Read, lazy: bool) -> Result<PartialLoad, CollectionError> {
+		let mut text = String::new();
+		reader.read_to_string(&mut text)?;
+		let mut collection = SnippetCollection::new();
+		let mut errors = Vec::new();
+		let mut priority = 0;
+		// Comments until the next snippet, which they belong to.
+		let mut pending = Vec::new();
+		let lines: Vec<(usize, &str)> = text.lines().enumerate().map(|(index, line)| (index + 1, line.trim_end_matches('\r'))).collect();
+		let mut rest = &lines[..];
+		while let Some((&(line, text), after)) = rest.split_first() {
+			rest = after;
+			if let Some(comment) = text.trim_start().strip_prefix('#') {
+				pending.push(Comment { line, text: comment.to_string(), trailing: false });
+				continue
+			}
+			let (keyword, arguments) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+			let mut arguments = arguments.trim();
+			if matches!(keyword, "extends" | "priority") {
+				let (before, comment) = trailing_comment(arguments);
+				arguments = before;
+				let comment = comment.map(|comment| Comment { line, text: comment.to_string(), trailing: true });
+				match keyword {
+					"extends" => collection.comments.extend(comment),
+					_ => pending.extend(comment)
+				}
+			}
+			match keyword {
+				"extends" => collection.extends.extend(arguments.split(',').map(str::trim).filter(|scope| !scope.is_empty()).map(str::to_string)),
+				"priority" => match arguments.parse() {
+					Ok(number) => priority = number,
+					Err(_) => errors.push(CollectionError::Syntax(line, "priority is not a number"))
+				},
+				"global" => match rest.iter().position(|&(_, text)| text.trim_end() == "endglobal") {
+					Some(end) => rest = &rest[end + 1..],
+					None => {
+						errors.push(CollectionError::Syntax(line, "global block is not closed by endglobal"));
+						rest = &[]
+					}
+				},
+				"snippet" => {
+					// UltiSnips bodies end at endsnippet, which comes before any other snippet.
+					let end = rest.iter().position(|&(_, text)| text.trim_end() == "endsnippet" || text.starts_with("snippet "));
+					let (header, body) = match end {
+						Some(end) if rest[end].1.trim_end() == "endsnippet" => {
+							let body = &rest[..end];
+							rest = &rest[end + 1..];
+							(ultisnips_header(arguments), body.iter().map(|&(_, text)| text).collect::<Vec<_>>().join("\n"))
+						},
+						_ => {
+							let end = rest.iter().position(|&(_, text)| !(text.is_empty() || text.starts_with('\t'))).unwrap_or(rest.len());
+							let mut body: Vec<&str> = rest[..end].iter().map(|&(_, text)| text.strip_prefix('\t').unwrap_or(text)).collect();
+							rest = &rest[end..];
+							while body.last() == Some(&"") {
+								body.pop();
+							}
+							(snipmate_header(arguments), body.join("\n"))
+						}
+					};
+					// Comments of snippets left out are kept with the collection.
+					let comments = std::mem::take(&mut pending);
+					let (trigger, description, options) = match header {
+						Ok(header) => header,
+						Err(message) => {
+							errors.push(CollectionError::Syntax(line, message));
+							collection.comments.extend(comments);
+							continue
+						}
+					};
+					let pattern = match options.contains('r') {
+						// Anchored at the end, as the trigger is matched against the text before the cursor.
+						true => match Regex::new(&format!("(?:{})$", trigger)) {
+							Ok(pattern) => Some(pattern),
+							Err(error) => {
+								errors.push(CollectionError::Syntax(line, error.message));
+								collection.comments.extend(comments);
+								continue
+							}
+						},
+						false => None
+					};
+					let snippet = LazySnippet::new(SnippetSyntax::UltiSnips, body);
+					if !lazy {
+						if let Err(error) = snippet.get() {
+							errors.push(CollectionError::Snippet(line, trigger, error.clone()));
+							collection.comments.extend(comments);
+							continue
+						}
+					}
+					collection.add(CollectionEntry {
+						name: trigger.clone(),
+						prefixes: if pattern.is_some() { Vec::new() } else { vec![trigger] },
+						description,
+						scopes: Vec::new(),
+						line,
+						priority,
+						pattern,
+						snippet,
+						comments
+					});
+				},
+				"endsnippet" => errors.push(CollectionError::Syntax(line, "endsnippet without a snippet")),
+				_ => {}
+			}
+		}
+		collection.comments.append(&mut pending);
+		collection.comments.sort_by_key(|comment| comment.line);
+		Ok(PartialLoad { collection, errors })
+	}
+
+	/// Writes the collection as an UltiSnips `.snippets` file, which [`SnippetCollection::from_snippets`] loads back:
+	/// an `extends` line with the scopes the collection extends, then every snippet with the comments belonging to it
+	/// (and a `priority` line when its priority differs from that of the snippet before it), then the other comments.
+	/// Comments of several `extends` lines are written on their own lines after the one `extends` line.
+	/// Bodies loaded from `.snippets` files are written as they were, and other snippets in UltiSnips syntax (see [`Snippet::to_source_with`]).
+	/// Fails for snippets that can not be parsed or written in UltiSnips syntax, and for bodies with an `endsnippet` line.
+	#[cfg(feature = "formats-ultisnips")]
+	pub fn write_snippets(&self, mut writer: impl io::Write) -> io::Result<()> {
+		let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+		let comment = |writer: &mut dyn io::Write, comment: &Comment| match comment.trailing {
+			true => write!(writer, " #{}", comment.text),
+			false => writeln!(writer, "#{}", comment.text)
+		};
+		let (trailing, others): (Vec<&Comment>, Vec<&Comment>) = self.comments.iter().partition(|comment| comment.trailing);
+		let mut trailing = trailing.into_iter();
+		if !self.extends.is_empty() {
+			write!(writer, "extends {}", self.extends.join(", "))?;
+			if let Some(first) = trailing.next() {
+				comment(&mut writer, first)?;
+			}
+			writeln!(writer)?;
+		}
+		for rest in trailing {
+			writeln!(writer, "#{}", rest.text)?;
+		}
+		let mut priority = 0;
+		for entry in &self.entries {
+			let priority_comment = entry.comments.iter().find(|comment| comment.trailing);
+			if entry.priority != priority || priority_comment.is_some() {
+				write!(writer, "priority {}", entry.priority)?;
+				if let Some(priority_comment) = priority_comment {
+					comment(&mut writer, priority_comment)?;
+				}
+				writeln!(writer)?;
+				priority = entry.priority;
+			}
+			for own_line in entry.comments.iter().filter(|comment| !comment.trailing) {
+				comment(&mut writer, own_line)?;
+			}
+			let (trigger, options) = match &entry.pattern {
+				Some(pattern) => (pattern.as_str().strip_prefix("(?:").and_then(|pattern| pattern.strip_suffix(")$")).unwrap_or(pattern.as_str()), " r"),
+				None => (entry.prefixes.first().unwrap_or(&entry.name).as_str(), "")
+			};
+			// Triggers with spaces and regular expression triggers are enclosed in a character that is not part of them.
+			let plain = options.is_empty() && !trigger.contains(char::is_whitespace);
+			let delimiter = match plain {
+				true => None,
+				false => Some(['|', '!', '/', '%', '#', '"'].into_iter().find(|c| !trigger.contains(*c)).ok_or_else(|| invalid(format!("no delimiter can enclose the trigger {}", trigger)))?)
+			};
+			match delimiter {
+				Some(delimiter) => write!(writer, "snippet {0}{1}{0}", delimiter, trigger)?,
+				None => write!(writer, "snippet {}", trigger)?
+			}
+			match &entry.description {
+				Some(description) => write!(writer, " \"{}\"", description)?,
+				// Options come after a description.
+				None if !options.is_empty() => write!(writer, " \"\"")?,
+				None => {}
+			}
+			writeln!(writer, "{}", options)?;
+			let body = match entry.snippet.syntax {
+				SnippetSyntax::UltiSnips if !entry.snippet.body.is_empty() => entry.snippet.body.clone(),
+				_ => {
+					let snippet = entry.snippet.get().map_err(|error| invalid(format!("snippet {} can not be parsed: {}", entry.name, error)))?;
+					snippet.to_source_with(SnippetSyntax::UltiSnips).map_err(|error| invalid(format!("snippet {} can not be written: {}", entry.name, error)))?
+				}
+			};
+			if body.lines().any(|line| line.trim_end() == "endsnippet") {
+				return Err(invalid(format!("the body of snippet {} has an endsnippet line", entry.name)))
+			}
+			if !body.is_empty() {
+				writeln!(writer, "{}", body)?;
+			}
+			writeln!(writer, "endsnippet")?;
+		}
+		for own_line in others {
+			comment(&mut writer, own_line)?;
+		}
+		Ok(())
+	}
+
+	pub fn add(&mut self, entry: CollectionEntry) {
+		self.entries.push(entry);
+	}
+
+	pub fn entries(&self) -> &[CollectionEntry] {
+		&self.entries
+	}
+
+	/// Comments of the file the collection was loaded from, in the order they are in the file.
+	pub fn comments(&self) -> &[Comment] {
+		&self.comments
+	}
+
+	/// Scopes whose snippets are available along with those of this collection, as named by `extends` lines.
+	pub fn extends(&self) -> &[String] {
+		&self.extends
+	}
+
+	/// Entries with the prefix, leaving out those hidden by entries with the prefix and a higher priority.
+	pub fn get<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = &'a CollectionEntry> {
+		let with_prefix = move |entry: &&CollectionEntry| entry.prefixes.iter().any(|p| p == prefix);
+		let priority = self.entries.iter().filter(with_prefix).map(|entry| entry.priority).max();
+		self.entries.iter().filter(with_prefix).filter(move |entry| Some(entry.priority) == priority)
+	}
+
+	/// The entry with a regular expression trigger matched by the end of the text before the cursor, along with the match.
+	/// Of the entries matched, the first of those with the highest priority.
+	pub fn match_trigger<'a, 't>(&'a self, before: &'t str) -> Option<(&'a CollectionEntry, Captures<'t>)> {
+		let mut found: Option<(&CollectionEntry, Captures)> = None;
+		for entry in &self.entries {
+			if found.as_ref().is_some_and(|(best, _)| best.priority >= entry.priority) {
+				continue
+			}
+			if let Some(captures) = entry.match_trigger(before) {
+				found = Some((entry, captures));
+			}
+		}
+		found
+	}
+
+	/// Entries with a prefix starting with what was typed, for completing it.
+	pub fn completions<'a>(&'a self, typed: &'a str) -> impl Iterator<Item = &'a CollectionEntry> {
+		self.entries.iter().filter(move |entry| entry.prefixes.iter().any(|p| p.starts_with(typed)))
+	}
+
+	/// Entries for the language: those scoped to it and those for every language.
+	pub fn for_scope<'a>(&'a self, scope: &'a str) -> impl Iterator<Item = &'a CollectionEntry> {
+		self.entries.iter().filter(move |entry| entry.scopes.is_empty() || entry.scopes.iter().any(|s| s == scope))
+	}
+}
+
+#[cfg(all(test, any(feature = "formats-vscode", feature = "formats-ultisnips")))]
+mod tests {
+	use super::*;
+
+	#[test]
+	#[cfg(feature = "formats-vscode")]
+	fn load_vscode_json() {
+		let json = r#"{
+			// Comments are permitted.
+			"For Loop": {
+				"prefix": ["for", "fori"],
+				"body": ["for (${1:i} = 0; $1 < ${2:n}; $1++) {", "\t$0", "}"],
+				"description": "For loop",
+				"scope": "javascript, typescript"
+			},
+			"Header": {"body": "// $TM_FILENAME", "isFileTemplate": true},
+		}"#;
+		let PartialLoad { collection, errors } = SnippetCollection::from_vscode_json(json.as_bytes()).unwrap();
+		assert!(errors.is_empty());
+		let entry = collection.get("fori").next().unwrap();
+		assert_eq!((entry.name.as_str(), entry.line, entry.description.as_deref()), ("For Loop", 3, Some("For loop")));
+		assert_eq!(entry.scopes, ["javascript", "typescript"]);
+		assert_eq!(entry.snippet.get().unwrap().to_string(), "for (i = 0; i < n; i++) {\n\t\n}");
+		assert_eq!(collection.completions("fo").count(), 1);
+		assert_eq!(collection.for_scope("rust").map(|entry| entry.name.as_str()).collect::<Vec<_>>(), ["Header"]);
+		let load = SnippetCollection::from_vscode_json(r#"{"x": {"prefix": "x", "body": "${1"}, "y": {"body": 1},
+			"z": {"prefix": "z", "body": "$1"}}"#.as_bytes()).unwrap();
+		assert!(matches!(&load.errors[..], [CollectionError::Snippet(1, name, ParseError::UnterminatedPlaceholder(_)), CollectionError::Structure(1, _)] if name == "x"));
+		assert_eq!(load.collection.entries().iter().map(|entry| (entry.name.as_str(), entry.line)).collect::<Vec<_>>(), [("z", 2)]);
+		assert!(matches!(SnippetCollection::from_vscode_json("[]".as_bytes()), Err(CollectionError::Structure(1, _))));
+		let lazy = SnippetCollection::lazy_vscode_json(r#"{"x": {"prefix": "x", "body": "${1"}, "y": {"prefix": "y", "body": "$1"}}"#.as_bytes()).unwrap().collection;
+		assert!(!lazy.entries()[1].snippet.is_parsed());
+		assert!(lazy.get("x").next().unwrap().snippet.get().is_err());
+		assert!(lazy.entries()[0].snippet.is_parsed() && !lazy.entries()[1].snippet.is_parsed());
+	}
+
+	#[test]
+	#[cfg(feature = "formats-ultisnips")]
+	fn load_snippets_files() {
+		let ultisnips = "extends c, cpp # and C++\n# comment\nsnippet fn \"function\" b\nfn ${1:name}() {\n\t$0\n}\nendsnippet\n\npriority 1 # higher\nsnippet \"a b\" \"spaced\"\n`date`\nendsnippet\nsnippet fn\nfn $1\nendsnippet\nsnippet bad\n${1\nendsnippet\nsnippet |re| \"regex\" r\nx\nendsnippet\n";
+		let load = SnippetCollection::from_snippets(ultisnips.as_bytes()).unwrap();
+		assert_eq!(load.collection.extends(), ["c", "cpp"]);
+		assert_eq!(load.collection.comments(), [Comment { line: 1, text: String::from(" and C++"), trailing: true }]);
+		assert_eq!(load.collection.entries()[0].comments, [Comment { line: 2, text: String::from(" comment"), trailing: false }]);
+		let spaced = load.collection.get("a b").next().unwrap();
+		assert_eq!((spaced.line, spaced.priority, spaced.description.as_deref()), (10, 1, Some("spaced")));
+		assert_eq!(spaced.comments, [Comment { line: 9, text: String::from(" higher"), trailing: true }]);
+		assert_eq!(spaced.snippet.get().unwrap().code_expansions().len(), 1);
+		// The later function snippet has a higher priority.
+		assert_eq!(load.collection.get("fn").map(|entry| entry.line).collect::<Vec<_>>(), [13]);
+		assert_eq!(load.collection.entries()[0].snippet.get().unwrap().to_string(), "fn name() {\n\t\n}");
+		assert!(matches!(&load.errors[..], [CollectionError::Snippet(16, name, _)] if name == "bad"));
+		assert!(load.collection.get("re").next().is_none() && load.collection.entries().last().unwrap().pattern.is_some());
+		let lazy = SnippetCollection::lazy_snippets(ultisnips.as_bytes()).unwrap();
+		assert!(lazy.errors.is_empty());
+		assert!(lazy.collection.get("bad").next().unwrap().snippet.get().is_err());
+
+		let load = SnippetCollection::from_snippets("snippet c\n# text\nendsnippet\n".as_bytes()).unwrap();
+		assert!(load.collection.comments().is_empty());
+		assert_eq!(load.collection.entries()[0].snippet.get().unwrap().to_string(), "# text");
+
+		let snipmate = "snippet if if statement\n\tif (${1:cond}) {\n\t\t$0 # not a comment\n\t}\n# comment\nsnippet el\n\telse\nversion 1\n";
+		let load = SnippetCollection::from_snippets(snipmate.as_bytes()).unwrap();
+		assert!(load.errors.is_empty());
+		let entries = load.collection.entries();
+		assert_eq!(entries.iter().map(|entry| (entry.name.as_str(), entry.description.as_deref())).collect::<Vec<_>>(), [("if", Some("if statement")), ("el", None)]);
+		assert_eq!(entries[0].snippet.get().unwrap().to_string(), "if (cond) {\n\t # not a comment\n}");
+		assert!(load.collection.comments().is_empty());
+		assert_eq!(entries[1].comments.iter().map(|comment| comment.line).collect::<Vec<_>>(), [5]);
+	}
+
+	#[test]
+	#[cfg(feature = "formats-ultisnips")]
+	fn write_snippets_files() {
+		let source = "# C snippets\nextends c # for C\n\n# functions\n# of C\nsnippet fn \"function\"\nfn ${1:name}() {\n\t$0 # not a comment\n}\nendsnippet\npriority -1 # lower\nsnippet \"a b\" \"spaced\"\n`date`\nendsnippet\nsnippet |(\\w+)\\.new| \"new\" r\nlet $1 = new();\nendsnippet\n# end\n";
+		let load = SnippetCollection::from_snippets(source.as_bytes()).unwrap();
+		let mut written = Vec::new();
+		load.collection.write_snippets(&mut written).unwrap();
+		let reload = SnippetCollection::from_snippets(&written[..]).unwrap();
+		assert!(load.errors.is_empty() && reload.errors.is_empty());
+		let comments = |comments: &[Comment]| comments.iter().map(|comment| (comment.text.clone(), comment.trailing)).collect::<Vec<_>>();
+		let entries = |collection: &SnippetCollection| collection.entries().iter().map(|entry| (
+			entry.name.clone(),
+			entry.prefixes.clone(),
+			entry.description.clone(),
+			entry.priority,
+			entry.pattern.as_ref().map(|pattern| pattern.as_str().to_string()),
+			entry.snippet.body().to_string(),
+			comments(&entry.comments)
+		)).collect::<Vec<_>>();
+		assert_eq!(entries(&reload.collection), entries(&load.collection));
+		assert_eq!(comments(reload.collection.comments()), [(String::from(" for C"), true), (String::from(" end"), false)]);
+		assert_eq!(reload.collection.extends(), ["c"]);
+		assert_eq!(comments(&reload.collection.entries()[0].comments), [(String::from(" C snippets"), false), (String::from(" functions"), false), (String::from(" of C"), false)]);
+		// Written again, the file is the same.
+		let mut rewritten = Vec::new();
+		reload.collection.write_snippets(&mut rewritten).unwrap();
+		assert_eq!(rewritten, written);
+	}
+
+	#[test]
+	#[cfg(feature = "formats-ultisnips")]
+	fn expand_regex_triggers() {
+		let snippets = "snippet \"(\\w+)\\.new\" \"constructor\" r\nlet $1 = ${1/.*/\\u$0/}::new();\nendsnippet\npriority 1\nsnippet \"(\\w+)\\.ret\" \"return\" r\nreturn $1;\nendsnippet\nsnippet \"(\" \"bad\" r\nx\nendsnippet\n";
+		let load = SnippetCollection::from_snippets(snippets.as_bytes()).unwrap();
+		assert!(matches!(&load.errors[..], [CollectionError::Syntax(8, _)]));
+		let (entry, captures) = load.collection.match_trigger("\tvalue.new").unwrap();
+		assert_eq!((entry.line, captures.range(0)), (1, Some(1..10)));
+		assert_eq!(entry.instantiate(&captures).unwrap().to_string(), "let value = Value::new();");
+		let (entry, captures) = load.collection.match_trigger("x.ret").unwrap();
+		assert_eq!(entry.instantiate(&captures).unwrap().to_string(), "return x;");
+		assert!(load.collection.match_trigger("x.ret ").is_none());
+	}
+}
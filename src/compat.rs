@@ -0,0 +1,115 @@
+//! Keeping code written against earlier versions of this crate building as the API grows.
+//!
+//! Items that are renamed or replaced stay for at least one minor release as `#[deprecated]` shims delegating to what replaced them,
+//! their deprecation note naming the replacement, and are only removed in a later minor release. Renamed Cargo features are kept
+//! the same way, as aliases of the features replacing them.
+//!
+//! Structs with public fields gain fields as the API grows, which breaks code building them as struct literals.
+//! The constructors here are the stable way of making the parts of snippets by hand, giving fields added later their defaults,
+//! along with the handles of [`crate::handle`] that tabs and expansions are made from.
+//! The parts as first released (before choice labels, variable defaults, tab labels and computed transformations) can still be made
+//! from what they were made of then, through the deprecated `Field::choice`, `Variable::without_default`, `Tab::unlabelled`
+//! and `Transformation::with_result`, as can tabs and expansions from shared pointers through `Tab::new` and `Expansion::new`.
+
+use crate::shared::{Rc, Weak};
+use crate::{Field, Segment, Tab, Variable, VariableSource, Code, Transformation, Expansion};
+use crate::handle::{FieldRef, ExpansionRef};
+
+impl Field {
+	/// Choice field as `Field::Choice` was first released, without labels.
+	#[deprecated(note = "`Field::Choice` also takes the labels shown in the menu, use `Field::Choice(choice, choices, Vec::new())`")]
+	pub fn choice(choice: usize, choices: Vec<Vec<Segment>>) -> Self {
+		Field::Choice(choice, choices, Vec::new())
+	}
+}
+
+impl Tab {
+	/// Tab selecting the field, with no transformations or label.
+	#[deprecated(note = "refers to the field by its shared pointer, use `FieldRef::tab`")]
+	pub fn new(num: u8, field: &Rc<Field>) -> Self {
+		FieldRef(field.clone()).tab(num)
+	}
+
+	/// Tab made of what it was first released with, without a label.
+	#[deprecated(note = "tabs also have a label, use `Tab::new` and set the transformations")]
+	pub fn unlabelled(num: u8, field: Weak<Field>, transformations: Vec<Weak<Transformation>>) -> Self {
+		Tab { num, field, transformations, label: None }
+	}
+}
+
+impl Variable {
+	pub fn new(name: &str, value: &str, source: VariableSource) -> Self {
+		Variable { name: name.to_string(), value: value.to_string(), source, default: None }
+	}
+
+	/// Variable showing its default until resolved, as `${NAME:default}` is parsed.
+	pub fn with_default(name: &str, default: Vec<Segment>, source: VariableSource) -> Self {
+		let value: String = default.iter().map(Segment::to_string).collect();
+		Variable { default: Some(default), ..Variable::new(name, &value, source) }
+	}
+
+	/// Variable made of what it was first released with, without a default to fall back to.
+	#[deprecated(note = "variables also have a default, use `Variable::new` (without one) or `Variable::with_default`")]
+	pub fn without_default(name: String, value: String, source: VariableSource) -> Self {
+		Variable::new(&name, &value, source)
+	}
+}
+
+impl Code {
+	/// Code run by the program of the shebang, with no output until it is run.
+	pub fn new(code: &str, shebang: &str) -> Self {
+		Code { code: code.to_string(), output: String::new(), shebang: shebang.to_string() }
+	}
+}
+
+impl Transformation {
+	/// Transformation with no result until it is applied.
+	pub fn new(section: &str, format: &str, flags: &str) -> Self {
+		Transformation { section: section.to_string(), format: format.to_string(), flags: flags.to_string(), result: String::new(), compiled: Default::default() }
+	}
+
+	/// Transformation made of what it was first released with, with the result given rather than computed.
+	#[deprecated(note = "transformations compute their result, use `Transformation::new` and `Transformation::apply`")]
+	pub fn with_result(section: String, format: String, flags: String, result: String) -> Self {
+		Transformation { result, ..Transformation::new(&section, &format, &flags) }
+	}
+}
+
+impl<E> Expansion<E> {
+	/// Expansion of the variable or code, with no transformations.
+	#[deprecated(note = "refers to the variable or code by its shared pointer, use `ExpansionRef::expansion`")]
+	pub fn new(expansion: &Rc<E>) -> Self {
+		ExpansionRef(expansion.clone()).expansion()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn construct_parts() {
+		let mut transformation = Transformation::new("(.*)", "${1:/upcase}", "");
+		assert_eq!(transformation.apply("a").unwrap(), "A");
+		assert!(Code::new("echo 1", "#!/bin/sh").output.is_empty());
+		assert_eq!(Variable::new("USER", "me", VariableSource::Daemon).value, "me");
+		let variable = Variable::with_default("TITLE", vec![Segment::Text(String::from("Untitled"))], VariableSource::Daemon);
+		assert_eq!((variable.value.as_str(), variable.default.map(|default| default.len())), ("Untitled", Some(1)));
+	}
+
+	#[test]
+	#[allow(deprecated)]
+	fn construct_former_parts() {
+		let field = Rc::new(Field::choice(1, vec![vec![Segment::Text(String::from("a"))], vec![Segment::Text(String::from("b"))]]));
+		assert!(matches!(&*field, Field::Choice(1, choices, labels) if choices.len() == 2 && labels.is_empty()));
+		let tab = Tab::unlabelled(2, Rc::downgrade(&field), Vec::new());
+		assert!(tab.num == 2 && tab.field.upgrade().is_some() && tab.label.is_none());
+		let tab = Tab::new(1, &field);
+		assert!(tab.field.upgrade().is_some() && tab.transformations.is_empty() && tab.label.is_none());
+		let code = Rc::new(Code::new("echo 1", "#!/bin/sh"));
+		assert!(Expansion::new(&code).expansion.upgrade().unwrap().output.is_empty());
+		let variable = Variable::without_default(String::from("USER"), String::from("me"), VariableSource::Client);
+		assert!(variable.value == "me" && variable.default.is_none());
+		assert_eq!(Transformation::with_result(String::from("a"), String::from("b"), String::new(), String::from("b")).result, "b");
+	}
+}
@@ -0,0 +1,703 @@
+use std::ops::Range;
+use crate::shared::{Rc, Weak};
+use crate::{Snippet, Segment, Field, RepeatField, Transformation, Variable, VariableSource, Code, Conditional, NamedSegment, Tab, Expansion};
+use crate::numbering::DuplicateTabs;
+use crate::transform::TransformError;
+use crate::sanitize::Removed;
+
+impl Snippet {
+	/// Appends the other snippet, its tabs numbered after this snippet's tabs.
+	/// The merged snippet keeps this snippet's final tab (tab 0), the other's only being kept when this snippet has none.
+	/// Named segments of the other snippet whose name is already taken are renamed with a numeric suffix (`name_2`).
+	pub fn concat(&mut self, other: Snippet) {
+		let end = self.body.len();
+		self.splice(end..end, other);
+	}
+
+	/// Replaces the segments in the range of the body with the other snippet's segments,
+	/// merging its tabs, variables and named segments as [`Snippet::concat`] does.
+	/// References to the removed segments are dropped and tabs are renumbered to leave no gaps,
+	/// in order of their old numbers with the other snippet's tabs coming after this snippet's.
+	/// Tabs of the other snippet left without a number once all 255 are taken are dropped, their fields no longer being tabbed.
+	///
+	/// # Panics
+	/// When the range is out of bounds of the body, see [`Snippet::try_splice`].
+	pub fn splice(&mut self, range: Range<usize>, mut other: Snippet) {
+		// Compacted first, so numbers only run out when there are more tabs than numbers.
+		self.renumber_tabs(DuplicateTabs::Keep);
+		other.renumber_tabs(DuplicateTabs::Keep);
+		let Snippet { body, tabs, variables, code_expansions, named_segments, .. } = other;
+		drop(self.body.splice(range, body));
+		let offset = self.tabs.iter().map(|tab| tab.num).max().unwrap_or(0);
+		let has_final = self.tabs.iter().any(|tab| tab.num == 0);
+		for mut tab in tabs {
+			if tab.num != 0 {
+				let Some(num) = tab.num.checked_add(offset) else {
+					continue
+				};
+				tab.num = num;
+			} else if has_final {
+				continue
+			}
+			self.tabs.push(tab);
+		}
+		self.variables.extend(variables);
+		self.code_expansions.extend(code_expansions);
+		self.add_named(named_segments);
+		self.prune();
+		self.renumber_tabs(DuplicateTabs::Keep);
+		self.debug_verify();
+	}
+
+	/// Splices the other snippet in as [`Snippet::splice`] does. False (leaving the snippet as it was) when the range is out of bounds
+	/// of the body or the tabs of both snippets together would need more than 255 numbers.
+	pub fn try_splice(&mut self, range: Range<usize>, other: Snippet) -> bool {
+		let numbered = |snippet: &Snippet| {
+			let mut nums: Vec<u8> = snippet.tabs.iter().map(|tab| tab.num).filter(|&num| num != 0).collect();
+			nums.sort_unstable();
+			nums.dedup();
+			nums.len()
+		};
+		if range.start > range.end || range.end > self.body.len() || numbered(self) + numbered(&other) > usize::from(u8::MAX) {
+			return false
+		}
+		self.splice(range, other);
+		true
+	}
+
+	/// Adds named segments of another snippet, renaming those whose name is already taken.
+	fn add_named(&mut self, named_segments: Vec<NamedSegment>) {
+		for mut named in named_segments {
+			let name = match &mut named {
+				NamedSegment::Transformation(name, _) | NamedSegment::Code(name, _) => name
+			};
+			if self.named(name) {
+				let mut suffix = 2;
+				while self.named(&format!("{}_{}", name, suffix)) {
+					suffix += 1;
+				}
+				*name = format!("{}_{}", name, suffix);
+			}
+			self.named_segments.push(named);
+		}
+	}
+
+	fn named(&self, name: &str) -> bool {
+		self.named_segments.iter().any(|named| match named {
+			NamedSegment::Transformation(taken, _) | NamedSegment::Code(taken, _) => taken == name
+		})
+	}
+
+	/// Drops references to segments no longer part of the snippet.
+	pub(crate) fn prune(&mut self) {
+		self.tabs.retain_mut(|tab| {
+			tab.transformations.retain(|transformation| transformation.strong_count() > 0);
+			tab.field.strong_count() > 0
+		});
+		self.variables.retain_mut(|variable| {
+			variable.transformations.retain(|transformation| transformation.strong_count() > 0);
+			variable.expansion.strong_count() > 0
+		});
+		self.code_expansions.retain_mut(|code| {
+			code.transformations.retain(|transformation| transformation.strong_count() > 0);
+			code.expansion.strong_count() > 0
+		});
+		self.named_segments.retain(|named| match named {
+			NamedSegment::Transformation(_, transformation) => transformation.strong_count() > 0,
+			NamedSegment::Code(_, code) => code.strong_count() > 0
+		});
+	}
+}
+
+impl Snippet {
+	/// Adds a copy of the template to the end of the repeated group selected by the tab with this number.
+	/// The tabs of the copy are numbered after the tabs of the group (and its earlier repetitions), the tabs after those
+	/// being moved up to make room, and its variables and named segments are merged as [`Snippet::concat`] does.
+	/// Everything holding the group is rebuilt around the new group, as fields can not change once shared.
+	/// False (leaving the snippet as it was) when the tab does not select a repeated group or tab numbers would run out.
+	pub fn add_repetition(&mut self, num: u8) -> bool {
+		let Some(group) = self.tabs.iter().find(|tab| tab.num == num).and_then(|tab| tab.field.upgrade()) else {
+			return false
+		};
+		let Field::Repeat(repeat) = &*group else {
+			return false
+		};
+		let Snippet { body, tabs, variables, code_expansions, named_segments, .. } = Copier::default().snippet(&repeat.template, &repeat.template.body);
+		let tabs: Vec<Tab> = tabs.into_iter().filter(|tab| tab.num != 0).collect();
+		let added = tabs.iter().map(|tab| tab.num).max().unwrap_or(0);
+		let mut within = Vec::new();
+		for body in &repeat.repetitions {
+			fields_within(body, &mut within);
+		}
+		let after = self.tabs.iter()
+			.filter(|tab| within.contains(&tab.field.as_ptr()))
+			.map(|tab| tab.num)
+			.fold(num, u8::max);
+		let highest = self.tabs.iter().map(|tab| tab.num).max().unwrap_or(0);
+		if highest.checked_add(added).is_none() {
+			return false
+		}
+		for tab in &mut self.tabs {
+			if tab.num > after {
+				tab.num += added;
+			}
+		}
+		if let Some(current) = &mut self.current_tab {
+			if *current > after {
+				*current += added;
+			}
+		}
+		self.tabs.extend(tabs.into_iter().map(|tab| Tab { num: after + tab.num, ..tab }));
+		self.variables.extend(variables);
+		self.code_expansions.extend(code_expansions);
+		self.add_named(named_segments);
+		let mut repetitions: Vec<Vec<Segment>> = repeat.repetitions.iter().map(|body| shared_body(body)).collect();
+		repetitions.push(body);
+		let repeated = Rc::new(Field::Repeat(RepeatField {
+			template: repeat.template.clone(),
+			separator: repeat.separator.clone(),
+			repetitions
+		}));
+		let mut replacer = Replacer { fields: vec![(Rc::as_ptr(&group), repeated)], ..Replacer::default() };
+		replacer.snippet(self);
+		true
+	}
+}
+
+/// Another occurrence of the segment, sharing its Rc.
+pub(crate) fn shared(segment: &Segment) -> Segment {
+	match segment {
+		Segment::Text(text) => Segment::Text(text.clone()),
+		Segment::Field(field) => Segment::Field(field.clone()),
+		Segment::Transformation(transformation) => Segment::Transformation(transformation.clone()),
+		Segment::Variable(variable) => Segment::Variable(variable.clone()),
+		Segment::Code(code) => Segment::Code(code.clone()),
+		Segment::Conditional(conditional) => Segment::Conditional(conditional.clone()),
+		Segment::Snippet(snippet) => Segment::Snippet(snippet.clone())
+	}
+}
+
+/// Adds the fields within the segments (not those of nested snippets).
+fn fields_within(segments: &[Segment], found: &mut Vec<*const Field>) {
+	for segment in segments {
+		match segment {
+			Segment::Field(field) => {
+				found.push(Rc::as_ptr(field));
+				match &**field {
+					Field::Placeholder(body) => fields_within(body, found),
+					Field::Choice(_, choices, _) => for body in choices {
+						fields_within(body, found);
+					},
+					Field::Number(_) => {},
+					Field::Toggle(_, on, off) => {
+						fields_within(on, found);
+						fields_within(off, found);
+					},
+					Field::Repeat(repeat) => for body in &repeat.repetitions {
+						fields_within(body, found);
+					}
+				}
+			},
+			Segment::Conditional(conditional) => {
+				fields_within(&conditional.then, found);
+				fields_within(&conditional.otherwise, found);
+			},
+			_ => {}
+		}
+	}
+}
+
+/// Rebuilds segments around replaced fields, variables, code and transformations,
+/// sharing everything that does not hold (or test) a replaced segment. Nested snippets are left as they are.
+#[derive(Default)]
+pub(crate) struct Replacer {
+	/// Fields replaced so far, starting with those to replace.
+	pub(crate) fields: Vec<(*const Field, Rc<Field>)>,
+	pub(crate) variables: Vec<(*const Variable, Rc<Variable>)>,
+	pub(crate) codes: Vec<(*const Code, Rc<Code>)>,
+	pub(crate) transformations: Vec<(*const Transformation, Rc<Transformation>)>,
+	conditionals: Vec<(*const Conditional, Rc<Conditional>)>,
+	/// Fields and conditionals found to need no rebuilding.
+	unchanged: Vec<*const ()>
+}
+
+fn replace_weak<T>(copies: &[(*const T, Rc<T>)], weak: &mut Weak<T>) {
+	if let Some(copy) = copied_weak(copies, weak) {
+		*weak = copy;
+	}
+}
+
+impl Replacer {
+	/// Rebuilds the snippet's body, pointing its tabs, expansions and named segments at the replacements.
+	pub(crate) fn snippet(&mut self, snippet: &mut Snippet) {
+		if let Some(body) = self.segments(&snippet.body) {
+			snippet.body = body;
+		}
+		let transformations = |transformations: &mut Vec<Weak<Transformation>>| for transformation in transformations {
+			replace_weak(&self.transformations, transformation);
+		};
+		for tab in &mut snippet.tabs {
+			replace_weak(&self.fields, &mut tab.field);
+			transformations(&mut tab.transformations);
+		}
+		for variable in &mut snippet.variables {
+			replace_weak(&self.variables, &mut variable.expansion);
+			transformations(&mut variable.transformations);
+		}
+		for code in &mut snippet.code_expansions {
+			replace_weak(&self.codes, &mut code.expansion);
+			transformations(&mut code.transformations);
+		}
+		for (field, _) in &mut snippet.choice_sources {
+			replace_weak(&self.fields, field);
+		}
+		for named in &mut snippet.named_segments {
+			match named {
+				NamedSegment::Transformation(_, transformation) => replace_weak(&self.transformations, transformation),
+				NamedSegment::Code(_, code) => replace_weak(&self.codes, code)
+			}
+		}
+		snippet.debug_verify();
+	}
+
+	/// Replaces the transformations with copies applied to the input, returning why any of them could not be applied
+	/// (those copies keeping their previous result).
+	pub(crate) fn reapply(&mut self, transformations: &[Weak<Transformation>], input: &str) -> Vec<TransformError> {
+		let mut errors = Vec::new();
+		for transformation in transformations.iter().filter_map(Weak::upgrade) {
+			let mut applied = Transformation {
+				section: transformation.section.clone(),
+				format: transformation.format.clone(),
+				flags: transformation.flags.clone(),
+				result: transformation.result.clone(),
+				compiled: transformation.compiled.clone()
+			};
+			if let Err(error) = applied.apply(input) {
+				errors.push(error);
+			}
+			self.transformations.push((Rc::as_ptr(&transformation), Rc::new(applied)));
+		}
+		errors
+	}
+
+	/// Rebuilt segments, or nothing when none of them changed.
+	fn segments(&mut self, segments: &[Segment]) -> Option<Vec<Segment>> {
+		let replaced: Vec<Option<Segment>> = segments.iter().map(|segment| match segment {
+			Segment::Field(field) => self.field(field).map(Segment::Field),
+			Segment::Conditional(conditional) => self.conditional(conditional).map(Segment::Conditional),
+			Segment::Variable(variable) => copied(&self.variables, variable).map(Segment::Variable),
+			Segment::Code(code) => copied(&self.codes, code).map(Segment::Code),
+			Segment::Transformation(transformation) => copied(&self.transformations, transformation).map(Segment::Transformation),
+			_ => None
+		}).collect();
+		if replaced.iter().all(Option::is_none) {
+			return None
+		}
+		Some(replaced.into_iter().zip(segments).map(|(replaced, segment)| replaced.unwrap_or_else(|| shared(segment))).collect())
+	}
+
+	fn bodies(&mut self, bodies: &[Vec<Segment>]) -> Option<Vec<Vec<Segment>>> {
+		let replaced: Vec<Option<Vec<Segment>>> = bodies.iter().map(|body| self.segments(body)).collect();
+		if replaced.iter().all(Option::is_none) {
+			return None
+		}
+		Some(replaced.into_iter().zip(bodies).map(|(replaced, body)| replaced.unwrap_or_else(|| shared_body(body))).collect())
+	}
+
+	fn field(&mut self, field: &Rc<Field>) -> Option<Rc<Field>> {
+		if let Some(replaced) = copied(&self.fields, field) {
+			return Some(replaced)
+		}
+		if self.unchanged.contains(&(Rc::as_ptr(field) as *const ())) {
+			return None
+		}
+		let rebuilt = match &**field {
+			Field::Placeholder(body) => self.segments(body).map(Field::Placeholder),
+			Field::Choice(choice, choices, labels) => self.bodies(choices).map(|choices| Field::Choice(*choice, choices, labels.clone())),
+			Field::Number(_) => None,
+			Field::Toggle(on, when_on, when_off) => match (self.segments(when_on), self.segments(when_off)) {
+				(None, None) => None,
+				(on_body, off_body) => Some(Field::Toggle(*on, on_body.unwrap_or_else(|| shared_body(when_on)), off_body.unwrap_or_else(|| shared_body(when_off))))
+			},
+			Field::Repeat(repeat) => self.bodies(&repeat.repetitions).map(|repetitions| Field::Repeat(RepeatField {
+				template: repeat.template.clone(),
+				separator: repeat.separator.clone(),
+				repetitions
+			}))
+		};
+		match rebuilt {
+			Some(rebuilt) => {
+				let rebuilt = Rc::new(rebuilt);
+				self.fields.push((Rc::as_ptr(field), rebuilt.clone()));
+				Some(rebuilt)
+			},
+			None => {
+				self.unchanged.push(Rc::as_ptr(field) as *const ());
+				None
+			}
+		}
+	}
+
+	fn conditional(&mut self, conditional: &Rc<Conditional>) -> Option<Rc<Conditional>> {
+		if let Some(replaced) = copied(&self.conditionals, conditional) {
+			return Some(replaced)
+		}
+		if self.unchanged.contains(&(Rc::as_ptr(conditional) as *const ())) {
+			return None
+		}
+		let field = conditional.field.upgrade().and_then(|field| self.field(&field));
+		let then = self.segments(&conditional.then);
+		let otherwise = self.segments(&conditional.otherwise);
+		if field.is_none() && then.is_none() && otherwise.is_none() {
+			self.unchanged.push(Rc::as_ptr(conditional) as *const ());
+			return None
+		}
+		let rebuilt = Rc::new(Conditional {
+			field: field.map_or_else(|| conditional.field.clone(), |field| Rc::downgrade(&field)),
+			condition: conditional.condition.clone(),
+			then: then.unwrap_or_else(|| shared_body(&conditional.then)),
+			otherwise: otherwise.unwrap_or_else(|| shared_body(&conditional.otherwise))
+		});
+		self.conditionals.push((Rc::as_ptr(conditional), rebuilt.clone()));
+		Some(rebuilt)
+	}
+}
+
+pub(crate) fn shared_body(segments: &[Segment]) -> Vec<Segment> {
+	segments.iter().map(shared).collect()
+}
+
+/// Part of a snippet to extract.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Selection {
+	/// Segments of the body by index.
+	Segments(Range<usize>),
+	/// Contents of the field selected by the tab with this number (the selected option of a choice).
+	Tab(u8)
+}
+
+impl From<Range<usize>> for Selection {
+	fn from(range: Range<usize>) -> Self {
+		Selection::Segments(range)
+	}
+}
+
+impl From<u8> for Selection {
+	fn from(num: u8) -> Self {
+		Selection::Tab(num)
+	}
+}
+
+/// Deep copies of segments, copying each Rc once so segments shared within the copied part stay shared.
+#[derive(Default)]
+pub(crate) struct Copier {
+	fields: Vec<(*const Field, Rc<Field>)>,
+	transformations: Vec<(*const Transformation, Rc<Transformation>)>,
+	variables: Vec<(*const Variable, Rc<Variable>)>,
+	codes: Vec<(*const Code, Rc<Code>)>,
+	conditionals: Vec<(*const Conditional, Rc<Conditional>)>,
+	snippets: Vec<(*const Snippet, Rc<Snippet>)>,
+	/// Whether code and client variables are left out of the copy, see [`Snippet::sanitize`].
+	strip: bool,
+	/// What was left out, each once.
+	pub(crate) stripped: Vec<Removed>,
+	stripped_ptrs: Vec<*const ()>,
+	/// Whether tabs keep their numbers rather than being renumbered from 1.
+	keep_numbers: bool
+}
+
+fn copied<T>(copies: &[(*const T, Rc<T>)], original: &Rc<T>) -> Option<Rc<T>> {
+	copies.iter().find(|(ptr, _)| *ptr == Rc::as_ptr(original)).map(|(_, copy)| copy.clone())
+}
+
+fn copied_weak<T>(copies: &[(*const T, Rc<T>)], original: &Weak<T>) -> Option<Weak<T>> {
+	copies.iter().find(|(ptr, _)| *ptr == original.as_ptr()).map(|(_, copy)| Rc::downgrade(copy))
+}
+
+impl Copier {
+	/// A copier leaving out code and client variables, keeping the numbers of tabs.
+	pub(crate) fn stripping() -> Self {
+		Copier { strip: true, keep_numbers: true, ..Copier::default() }
+	}
+
+	/// A copier keeping everything as it is, the numbers of tabs included.
+	pub(crate) fn exact() -> Self {
+		Copier { keep_numbers: true, ..Copier::default() }
+	}
+
+	fn segments(&mut self, segments: &[Segment]) -> Vec<Segment> {
+		let mut copies = Vec::new();
+		for segment in segments {
+			if !self.strips(segment) {
+				copies.push(self.segment(segment));
+			}
+		}
+		copies
+	}
+
+	/// Whether the segment is left out of the copy, recording it the first time it is.
+	fn strips(&mut self, segment: &Segment) -> bool {
+		if !self.strip {
+			return false
+		}
+		let (ptr, removed) = match segment {
+			Segment::Code(code) => (Rc::as_ptr(code) as *const (), Removed::Code(code.shebang.clone(), code.code.clone())),
+			Segment::Variable(variable) if matches!(variable.source, VariableSource::Client) => {
+				(Rc::as_ptr(variable) as *const (), Removed::Variable(variable.name.clone()))
+			},
+			_ => return false
+		};
+		if !self.stripped_ptrs.contains(&ptr) {
+			self.stripped_ptrs.push(ptr);
+			self.stripped.push(removed);
+		}
+		true
+	}
+
+	fn field(&mut self, field: &Rc<Field>) -> Rc<Field> {
+		if let Some(copy) = copied(&self.fields, field) {
+			return copy
+		}
+		let copy = Rc::new(match &**field {
+			Field::Placeholder(body) => Field::Placeholder(self.segments(body)),
+			Field::Choice(choice, choices, labels) => Field::Choice(*choice, choices.iter().map(|body| self.segments(body)).collect(), labels.clone()),
+			Field::Number(number) => Field::Number(number.clone()),
+			Field::Toggle(on, when_on, when_off) => Field::Toggle(*on, self.segments(when_on), self.segments(when_off)),
+			Field::Repeat(repeat) => Field::Repeat(RepeatField {
+				template: repeat.template.clone(),
+				separator: repeat.separator.clone(),
+				repetitions: repeat.repetitions.iter().map(|body| self.segments(body)).collect()
+			})
+		});
+		self.fields.push((Rc::as_ptr(field), copy.clone()));
+		copy
+	}
+
+	fn segment(&mut self, segment: &Segment) -> Segment {
+		match segment {
+			Segment::Text(text) => Segment::Text(text.clone()),
+			Segment::Field(field) => Segment::Field(self.field(field)),
+			Segment::Transformation(transformation) => Segment::Transformation(copied(&self.transformations, transformation).unwrap_or_else(|| {
+				let copy = Rc::new(Transformation {
+					section: transformation.section.clone(),
+					format: transformation.format.clone(),
+					flags: transformation.flags.clone(),
+					result: transformation.result.clone(),
+					compiled: transformation.compiled.clone()
+				});
+				self.transformations.push((Rc::as_ptr(transformation), copy.clone()));
+				copy
+			})),
+			Segment::Variable(variable) => Segment::Variable(copied(&self.variables, variable).unwrap_or_else(|| {
+				let copy = Rc::new(Variable {
+					name: variable.name.clone(),
+					value: variable.value.clone(),
+					source: match variable.source {
+						VariableSource::Daemon => VariableSource::Daemon,
+						VariableSource::Client => VariableSource::Client
+					},
+					default: variable.default.as_deref().map(|default| self.segments(default))
+				});
+				self.variables.push((Rc::as_ptr(variable), copy.clone()));
+				copy
+			})),
+			Segment::Code(code) => Segment::Code(copied(&self.codes, code).unwrap_or_else(|| {
+				let copy = Rc::new(Code {
+					code: code.code.clone(),
+					output: code.output.clone(),
+					shebang: code.shebang.clone()
+				});
+				self.codes.push((Rc::as_ptr(code), copy.clone()));
+				copy
+			})),
+			Segment::Conditional(conditional) => Segment::Conditional(match copied(&self.conditionals, conditional) {
+				Some(copy) => copy,
+				None => {
+					// The tested field is copied even when outside of the copied part, leaving the copy dangling unless it is within it.
+					let field = conditional.field.upgrade().map_or_else(Weak::new, |field| Rc::downgrade(&self.field(&field)));
+					let copy = Rc::new(Conditional {
+						field,
+						condition: conditional.condition.clone(),
+						then: self.segments(&conditional.then),
+						otherwise: self.segments(&conditional.otherwise)
+					});
+					self.conditionals.push((Rc::as_ptr(conditional), copy.clone()));
+					copy
+				}
+			}),
+			Segment::Snippet(nested) => Segment::Snippet(copied(&self.snippets, nested).unwrap_or_else(|| {
+				let mut copier = Copier { strip: self.strip, keep_numbers: self.keep_numbers, ..Copier::default() };
+				let copy = Rc::new(copier.snippet(nested, &nested.body));
+				self.stripped.extend(copier.stripped);
+				self.snippets.push((Rc::as_ptr(nested), copy.clone()));
+				copy
+			}))
+		}
+	}
+
+	/// Copies the segments into a snippet of their own, along with the references of the original snippet to them.
+	pub(crate) fn snippet(&mut self, original: &Snippet, segments: &[Segment]) -> Snippet {
+		let body = self.segments(segments);
+		let transformations = |transformations: &[Weak<Transformation>]| transformations.iter()
+			.filter_map(|transformation| copied_weak(&self.transformations, transformation))
+			.collect::<Vec<_>>();
+		let mut snippet = Snippet {
+			body,
+			tabs: original.tabs.iter().filter_map(|tab| Some(Tab {
+				num: tab.num,
+				field: copied_weak(&self.fields, &tab.field)?,
+				transformations: transformations(&tab.transformations),
+				label: tab.label.clone()
+			})).collect(),
+			variables: original.variables.iter().filter_map(|variable| Some(Expansion {
+				expansion: copied_weak(&self.variables, &variable.expansion)?,
+				transformations: transformations(&variable.transformations)
+			})).collect(),
+			code_expansions: original.code_expansions.iter().filter_map(|code| Some(Expansion {
+				expansion: copied_weak(&self.codes, &code.expansion)?,
+				transformations: transformations(&code.transformations)
+			})).collect(),
+			named_segments: original.named_segments.iter().filter_map(|named| Some(match named {
+				NamedSegment::Transformation(name, transformation) => NamedSegment::Transformation(name.clone(), copied_weak(&self.transformations, transformation)?),
+				NamedSegment::Code(name, code) => NamedSegment::Code(name.clone(), copied_weak(&self.codes, code)?)
+			})).collect(),
+			current_tab: None,
+			choice_sources: original.choice_sources.iter()
+				.filter_map(|(field, source)| Some((copied_weak(&self.fields, field)?, source.clone())))
+				.collect(),
+			baseline: Default::default()
+		};
+		if !self.keep_numbers {
+			snippet.renumber_tabs(DuplicateTabs::Keep);
+		}
+		snippet
+	}
+}
+
+impl Snippet {
+	/// Copies part of the snippet into an independent snippet, keeping the tabs, variables and named segments within that part
+	/// with the tabs renumbered from 1. None when the range is out of bounds or there is no tab with the number.
+	pub fn extract(&self, selection: impl Into<Selection>) -> Option<Snippet> {
+		let segments = match selection.into() {
+			Selection::Segments(range) => self.body.get(range)?,
+			Selection::Tab(num) => {
+				let field = self.tabs.iter().find(|tab| tab.num == num)?.field.upgrade()?;
+				let mut copier = Copier::default();
+				return Some(match &*field {
+					Field::Placeholder(body) => copier.snippet(self, body),
+					Field::Choice(choice, choices, _) => copier.snippet(self, choices.get(*choice).map_or(&[][..], Vec::as_slice)),
+					Field::Number(number) => {
+						let mut snippet = copier.snippet(self, &[]);
+						snippet.body.push(Segment::Text(number.value.to_string()));
+						snippet
+					},
+					Field::Toggle(on, when_on, when_off) => copier.snippet(self, if *on { when_on } else { when_off }),
+					// The group is kept whole so it can still be repeated.
+					Field::Repeat(_) => copier.snippet(self, &[Segment::Field(field.clone())])
+				})
+			}
+		};
+		Some(Copier::default().snippet(self, segments))
+	}
+
+	/// A copy of the snippet sharing nothing with it, so that filling in one copy leaves the other as it was,
+	/// such as for expanding the snippet more than once. Parts shared within the snippet, such as mirrored fields, are shared within the copy.
+	pub fn deep_clone(&self) -> Snippet {
+		let mut copy = Copier::exact().snippet(self, &self.body);
+		copy.current_tab = self.current_tab;
+		copy
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mustache::from_template;
+
+	#[test]
+	fn concat_and_splice() {
+		let mut snippet = from_template("{{a}} {{b}} {{a}}").output;
+		snippet.concat(from_template(" {{c}}").output);
+		let nums: Vec<u8> = snippet.tabs().iter().map(|tab| tab.num).collect();
+		assert_eq!(nums, [1, 2, 3]);
+		assert_eq!(snippet.to_string(), "a b a c");
+		assert_eq!(snippet.shared_segments().len(), 1);
+
+		snippet.splice(2..3, from_template("{{d}}{{e}}").output);
+		assert_eq!(snippet.to_string(), "a de a c");
+		let tabs: Vec<(u8, String)> = snippet.tabs().iter().map(|tab| (tab.num, tab.field.upgrade().unwrap().to_string())).collect();
+		assert_eq!(tabs, [(1, String::from("a")), (2, String::from("c")), (3, String::from("d")), (4, String::from("e"))]);
+	}
+
+	#[test]
+	fn extract_parts() {
+		let snippet = from_template("{{a}} {{b}} {{a}}").output;
+		let part = snippet.extract(2..5).unwrap();
+		assert_eq!(part.to_string(), "b a");
+		let nums: Vec<u8> = part.tabs().iter().map(|tab| tab.num).collect();
+		assert_eq!(nums, [1, 2]);
+		assert!(Rc::ptr_eq(&part.tabs()[0].field.upgrade().unwrap(), match &part.body()[2] {
+			Segment::Field(field) => field,
+			_ => unreachable!()
+		}));
+		assert!(snippet.extract(4..9).is_none());
+		assert_eq!(snippet.extract(2).unwrap().to_string(), "b");
+		assert!(snippet.extract(3).is_none());
+	}
+
+	#[test]
+	fn independent_copies() {
+		let snippet = Snippet::parse("${2:a} $2 ${2/(.*)/${1:/upcase}/} $TM_FILENAME $1").unwrap();
+		let mut copy = snippet.deep_clone();
+		copy.set_field_text(2, "b").unwrap();
+		assert_eq!(copy.to_string(), "b b B  ");
+		assert_eq!(snippet.to_string(), "a a   ");
+		assert_eq!(copy.tabs().iter().map(|tab| tab.num).collect::<Vec<_>>(), [2, 1]);
+		assert!(!Rc::ptr_eq(&copy.variables()[0].expansion.upgrade().unwrap(), &snippet.variables()[0].expansion.upgrade().unwrap()));
+		assert_eq!(snippet.deep_clone().to_test_fixture(), snippet.to_test_fixture());
+	}
+
+	#[test]
+	fn repeat_groups() {
+		let group = Rc::new(Field::Repeat(RepeatField {
+			template: Rc::new(Snippet::parse("${1:type} ${2:name}").unwrap()),
+			separator: String::from(", "),
+			repetitions: Vec::new()
+		}));
+		let after = Rc::new(Field::Placeholder(vec![Segment::Text(String::from("body"))]));
+		let mut snippet = Snippet {
+			body: vec![
+				Segment::Text(String::from("fn f(")),
+				Segment::Field(group.clone()),
+				Segment::Text(String::from(") ")),
+				Segment::Conditional(Rc::new(Conditional {
+					field: Rc::downgrade(&group),
+					condition: crate::Condition::Empty,
+					then: Vec::new(),
+					otherwise: vec![Segment::Text(String::from("-> "))]
+				})),
+				Segment::Field(after.clone())
+			],
+			tabs: vec![
+				Tab { num: 1, field: Rc::downgrade(&group), transformations: Vec::new(), label: None },
+				Tab { num: 2, field: Rc::downgrade(&after), transformations: Vec::new(), label: None }
+			],
+			variables: Vec::new(),
+			code_expansions: Vec::new(),
+			named_segments: Vec::new(),
+			current_tab: None,
+			choice_sources: Vec::new(),
+			baseline: Default::default()
+		};
+		drop(group);
+		assert_eq!(snippet.to_string(), "fn f() body");
+		assert!(snippet.add_repetition(1));
+		assert!(snippet.add_repetition(1));
+		assert_eq!(snippet.to_string(), "fn f(type name, type name) -> body");
+		let mut tabs: Vec<(u8, String)> = snippet.tabs().iter().map(|tab| (tab.num, tab.field.upgrade().unwrap().to_string())).collect();
+		tabs.sort();
+		assert_eq!(tabs[1..], [(2, String::from("type")), (3, String::from("name")), (4, String::from("type")), (5, String::from("name")), (6, String::from("body"))]);
+		assert!(!snippet.add_repetition(2));
+		assert!(!snippet.add_repetition(9));
+	}
+}
@@ -0,0 +1,154 @@
+//! Loading of snippet bodies embedded in YAML and TOML configuration files (such as editor plugin configurations),
+//! rather than written in snippet files of their own.
+
+use std::path::Path;
+use std::{fmt, fs, io};
+use crate::library::SourceLocation;
+use crate::{yaml, toml};
+use crate::yaml::{Node, Value};
+
+/// Kinds of configuration files bodies can be embedded in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+	Yaml,
+	Toml
+}
+
+impl ConfigFormat {
+	/// Format of the file judging by its extension (`yaml`, `yml` or `toml`).
+	pub fn from_path(path: impl AsRef<Path>) -> Option<Self> {
+		match path.as_ref().extension()?.to_str()? {
+			"yaml" | "yml" => Some(ConfigFormat::Yaml),
+			"toml" => Some(ConfigFormat::Toml),
+			_ => None
+		}
+	}
+}
+
+/// A snippet body found in a configuration file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmbeddedBody {
+	/// Dotted path of the key holding the body, with `[index]` for entries of lists (`snippets[2].body`).
+	pub path: String,
+	/// Where the body's key and value are written.
+	pub source: SourceLocation,
+	/// The body with the file's quoting and the indentation of multi-line strings removed.
+	pub body: String
+}
+
+/// Why bodies could not be loaded from a configuration file.
+#[derive(Debug)]
+pub enum ConfigError {
+	/// The file could not be read.
+	Io(io::Error),
+	/// The file's format could not be told from its extension.
+	UnknownFormat,
+	/// The file is not valid YAML or TOML. Carries the line (starting at 1) and a description of the problem.
+	Syntax(usize, &'static str)
+}
+
+impl fmt::Display for ConfigError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			ConfigError::Io(error) => write!(f, "{}", error),
+			ConfigError::UnknownFormat => write!(f, "not a yaml or toml file"),
+			ConfigError::Syntax(line, message) => write!(f, "line {}: {}", line, message)
+		}
+	}
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<io::Error> for ConfigError {
+	fn from(error: io::Error) -> Self {
+		ConfigError::Io(error)
+	}
+}
+
+impl From<yaml::Error> for ConfigError {
+	fn from(error: yaml::Error) -> Self {
+		ConfigError::Syntax(error.line, error.message)
+	}
+}
+
+impl From<toml::Error> for ConfigError {
+	fn from(error: toml::Error) -> Self {
+		ConfigError::Syntax(error.line, error.message)
+	}
+}
+
+/// Loads the string values of every key named `key` (at any depth) from the configuration file,
+/// recording the path in every body's source location.
+pub fn embedded_bodies_file(path: impl AsRef<Path>, key: &str) -> Result<Vec<EmbeddedBody>, ConfigError> {
+	let path = path.as_ref();
+	let format = ConfigFormat::from_path(path).ok_or(ConfigError::UnknownFormat)?;
+	let mut bodies = embedded_bodies(&fs::read_to_string(path)?, format, key)?;
+	for body in &mut bodies {
+		body.source.path = Some(path.to_path_buf());
+	}
+	Ok(bodies)
+}
+
+/// Loads the string values of every key named `key` (at any depth) from the configuration text, in document order.
+pub fn embedded_bodies(text: &str, format: ConfigFormat, key: &str) -> Result<Vec<EmbeddedBody>, ConfigError> {
+	let mut bodies = Vec::new();
+	match format {
+		ConfigFormat::Yaml => collect(&yaml::parse(text)?, key, String::new(), &mut bodies),
+		ConfigFormat::Toml => for entry in toml::strings(text)? {
+			if entry.key == key {
+				bodies.push(EmbeddedBody {
+					path: entry.path,
+					source: SourceLocation { path: None, start_line: entry.line, end_line: entry.end },
+					body: entry.value
+				});
+			}
+		}
+	}
+	Ok(bodies)
+}
+
+fn collect(node: &Node, key: &str, path: String, bodies: &mut Vec<EmbeddedBody>) {
+	match &node.value {
+		Value::Scalar(_) => {},
+		Value::Sequence(items) => for (i, item) in items.iter().enumerate() {
+			collect(item, key, format!("{}[{}]", path, i), bodies);
+		},
+		Value::Mapping(entries) => for (name, value) in entries {
+			let path = if path.is_empty() { name.clone() } else { format!("{}.{}", path, name) };
+			match &value.value {
+				Value::Scalar(body) if name == key => bodies.push(EmbeddedBody {
+					path,
+					source: SourceLocation { path: None, start_line: value.line, end_line: value.end },
+					body: body.clone()
+				}),
+				_ => collect(value, key, path, bodies)
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn load_yaml_and_toml_bodies() {
+		let yaml = "snippets:\n  - name: fn\n    body: |\n      fn ${1:name}() {\n          $0\n      }\n  - name: short\n    body: \"x\"\n";
+		let bodies = embedded_bodies(yaml, ConfigFormat::Yaml, "body").unwrap();
+		assert_eq!(bodies.len(), 2);
+		assert_eq!(bodies[0].path, "snippets[0].body");
+		assert_eq!(bodies[0].body, "fn ${1:name}() {\n    $0\n}\n");
+		assert_eq!((bodies[0].source.start_line, bodies[0].source.end_line), (3, 6));
+		assert_eq!(bodies[1].body, "x");
+
+		let toml = "[snippets.fn]\nbody = '''\nfn ${1:name}() {\n    $0\n}\n'''\n";
+		let bodies = embedded_bodies(toml, ConfigFormat::Toml, "body").unwrap();
+		assert_eq!(bodies, [EmbeddedBody {
+			path: String::from("snippets.fn.body"),
+			source: SourceLocation { path: None, start_line: 2, end_line: 6 },
+			body: String::from("fn ${1:name}() {\n    $0\n}\n")
+		}]);
+		assert_eq!(ConfigFormat::from_path("plugin/snippets.yml"), Some(ConfigFormat::Yaml));
+		assert!(matches!(embedded_bodies("a = \"open\n", ConfigFormat::Toml, "a"), Err(ConfigError::Syntax(1, _))));
+	}
+}
@@ -0,0 +1,285 @@
+//! Measuring how closely parsing and rendering follow a dialect of snippet syntax, by running the cases of a corpus
+//! and reporting how many of them pass for each feature of the dialect.
+//!
+//! Curated corpora of the LSP (VS Code) and UltiSnips syntaxes are built in. Others, such as cases taken from the VS Code test suite,
+//! are read from files of `syntax feature source expected` lines (separated by tabs), where `syntax` is `lsp` or `ultisnips` and `expected`
+//! is the text the snippet renders as, or `!error` for a snippet that must not parse. `\n`, `\t` and `\\` escape source and expected text,
+//! and lines starting with `#` are comments.
+
+use std::fmt;
+use crate::Snippet;
+use crate::parse::{ParseError, SnippetSyntax};
+
+/// What a case of a corpus should come to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expected {
+	/// The snippet parses and renders as the text.
+	Render(String),
+	/// The snippet does not parse.
+	Error
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConformanceCase {
+	/// Feature of the dialect the case is about, such as `choices`.
+	pub feature: String,
+	pub syntax: SnippetSyntax,
+	pub source: String,
+	pub expected: Expected
+}
+
+/// A case that did not come to what was expected, with what it came to instead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Failure {
+	pub case: ConformanceCase,
+	pub actual: Result<String, ParseError>
+}
+
+/// Results of the cases of one feature.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeatureReport {
+	pub feature: String,
+	pub passed: usize,
+	pub failures: Vec<Failure>
+}
+
+impl FeatureReport {
+	pub fn total(&self) -> usize {
+		self.passed + self.failures.len()
+	}
+}
+
+/// Results of a corpus by feature, in the order the features first appear in it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConformanceReport {
+	pub features: Vec<FeatureReport>
+}
+
+impl ConformanceReport {
+	pub fn passed(&self) -> usize {
+		self.features.iter().map(|feature| feature.passed).sum()
+	}
+
+	pub fn total(&self) -> usize {
+		self.features.iter().map(FeatureReport::total).sum()
+	}
+
+	/// Whether every case passed.
+	pub fn is_conformant(&self) -> bool {
+		self.features.iter().all(|feature| feature.failures.is_empty())
+	}
+}
+
+/// A line per feature giving the cases passed out of those run, followed by a line per failure.
+impl fmt::Display for ConformanceReport {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		for feature in &self.features {
+			writeln!(f, "{}: {}/{}", feature.feature, feature.passed, feature.total())?;
+			for failure in &feature.failures {
+				let expected = match &failure.case.expected {
+					Expected::Render(text) => format!("{:?}", text),
+					Expected::Error => String::from("an error")
+				};
+				match &failure.actual {
+					Ok(text) => writeln!(f, "  {:?}: expected {}, rendered {:?}", failure.case.source, expected, text)?,
+					Err(error) => writeln!(f, "  {:?}: expected {}, failed at {}", failure.case.source, expected, error)?
+				}
+			}
+		}
+		writeln!(f, "total: {}/{}", self.passed(), self.total())
+	}
+}
+
+/// A line of a corpus file that is not a case.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorpusError {
+	/// Line of the file (starting at 1).
+	pub line: usize,
+	pub message: &'static str
+}
+
+impl fmt::Display for CorpusError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "line {}: {}", self.line, self.message)
+	}
+}
+
+impl std::error::Error for CorpusError {}
+
+fn unescape(text: &str) -> String {
+	let mut unescaped = String::new();
+	let mut chars = text.chars();
+	while let Some(c) = chars.next() {
+		match (c, chars.clone().next()) {
+			('\\', Some('n')) => unescaped.push('\n'),
+			('\\', Some('t')) => unescaped.push('\t'),
+			('\\', Some('\\')) => unescaped.push('\\'),
+			_ => {
+				unescaped.push(c);
+				continue
+			}
+		}
+		chars.next();
+	}
+	unescaped
+}
+
+/// Reads the cases of a corpus file, see the module documentation for its form.
+pub fn read_corpus(text: &str) -> Result<Vec<ConformanceCase>, CorpusError> {
+	let mut cases = Vec::new();
+	for (i, line) in text.lines().enumerate() {
+		if line.trim().is_empty() || line.starts_with('#') {
+			continue
+		}
+		let error = |message| CorpusError { line: i + 1, message };
+		let [syntax, feature, source, expected] = line.split('\t').collect::<Vec<_>>()[..] else {
+			return Err(error("expected syntax, feature, source and expected text separated by tabs"))
+		};
+		let syntax = match syntax {
+			"lsp" => SnippetSyntax::Lsp,
+			"ultisnips" => SnippetSyntax::UltiSnips,
+			_ => return Err(error("syntax is not lsp or ultisnips"))
+		};
+		cases.push(ConformanceCase {
+			feature: feature.to_string(),
+			syntax,
+			source: unescape(source),
+			expected: if expected == "!error" { Expected::Error } else { Expected::Render(unescape(expected)) }
+		});
+	}
+	Ok(cases)
+}
+
+/// The text the source renders as once parsed, with the transformations of tabs applied to their default text
+/// as an editor applies them on expanding. Nothing is run or resolved: code has no output and variables show their defaults.
+pub fn render(syntax: SnippetSyntax, source: &str) -> Result<String, ParseError> {
+	let mut snippet = Snippet::parse_with(syntax, source)?;
+	// Fields nested in others come first, so the text of the outer ones includes them.
+	let tabs: Vec<(u8, Option<usize>, String)> = snippet.tabs().iter().filter_map(|tab| {
+		let field = tab.field.upgrade()?;
+		Some((tab.num, field.selected_index(), field.to_string()))
+	}).collect();
+	for (num, choice, text) in tabs {
+		match choice {
+			Some(index) => snippet.set_choice(num, index),
+			None => snippet.set_field_text(num, &text)
+		};
+	}
+	Ok(snippet.to_string())
+}
+
+/// Runs every case, rendering it as [`render`] does.
+pub fn run(cases: &[ConformanceCase]) -> ConformanceReport {
+	let mut report = ConformanceReport::default();
+	for case in cases {
+		let actual = render(case.syntax, &case.source);
+		let passed = match (&case.expected, &actual) {
+			(Expected::Render(expected), Ok(text)) => expected == text,
+			(Expected::Error, Err(_)) => true,
+			_ => false
+		};
+		let index = match report.features.iter().position(|feature| feature.feature == case.feature) {
+			Some(index) => index,
+			None => {
+				report.features.push(FeatureReport { feature: case.feature.clone(), passed: 0, failures: Vec::new() });
+				report.features.len() - 1
+			}
+		};
+		let feature = &mut report.features[index];
+		if passed {
+			feature.passed += 1;
+		} else {
+			feature.failures.push(Failure { case: case.clone(), actual });
+		}
+	}
+	report
+}
+
+fn corpus(syntax: SnippetSyntax, cases: &[(&str, &str, Option<&str>)]) -> Vec<ConformanceCase> {
+	cases.iter().map(|(feature, source, expected)| ConformanceCase {
+		feature: feature.to_string(),
+		syntax,
+		source: source.to_string(),
+		expected: expected.map_or(Expected::Error, |text| Expected::Render(text.to_string()))
+	}).collect()
+}
+
+/// Cases of the LSP syntax as VS Code expands it, leaving out what depends on the editor (the values of its variables).
+pub fn lsp_corpus() -> Vec<ConformanceCase> {
+	corpus(SnippetSyntax::Lsp, &[
+		("text", "foo", Some("foo")),
+		("text", "far{{}}boo", Some("far{{}}boo")),
+		("text", "cost: $ 5", Some("cost: $ 5")),
+		("text", "a } b", Some("a } b")),
+		("escapes", "\\$1 \\} \\\\", Some("$1 } \\")),
+		("escapes", "\\a", Some("\\a")),
+		("tab stops", "$1", Some("")),
+		("tab stops", "${1}x$0", Some("x")),
+		("tab stops", "a$1b$1c", Some("abc")),
+		("placeholders", "${1:foo}", Some("foo")),
+		("placeholders", "${1:foo} $1", Some("foo foo")),
+		("placeholders", "$1 ${1:foo}", Some("foo foo")),
+		("placeholders", "${1:foo${2:bar}}", Some("foobar")),
+		("placeholders", "${1:a${2:b${3:c}}}", Some("abc")),
+		("placeholders", "${1:\\}\\$}", Some("}$")),
+		("choices", "${1|one,two,three|}", Some("one")),
+		("choices", "${1|a\\,b,c|}", Some("a,b")),
+		("choices", "${1|one,two|} $1", Some("one one")),
+		("variables", "${UNKNOWN:default}", Some("default")),
+		("variables", "${UNKNOWN:${1:nested}}", Some("nested")),
+		("variables", "$UNKNOWN", Some("UNKNOWN")),
+		("variables", "$TM_SELECTED_TEXT", Some("")),
+		("transformations", "${1:foo} ${1/(.*)/${1:/upcase}/}", Some("foo FOO")),
+		("transformations", "${1:a.b.c} ${1/\\./-/g}", Some("a.b.c a-b-c")),
+		("transformations", "${1:abc} ${1/(b)/[$1]/}", Some("abc a[b]c")),
+		("transformations", "${1:some} ${1/^(.)/${1:/capitalize}/}", Some("some Some")),
+		("transformations", "${1:x} ${1/(y)?/${1:?yes:no}/}", Some("x nox")),
+		("transformations", "${1:} ${1/^$/empty/}", Some(" empty")),
+		("errors", "${1:open", None),
+		("errors", "${1|a,b}", None),
+		("errors", "${1/a/b}", None),
+		("errors", "$256", None)
+	])
+}
+
+/// Cases of UltiSnips snippet bodies as UltiSnips expands them, leaving out what depends on running code.
+pub fn ultisnips_corpus() -> Vec<ConformanceCase> {
+	corpus(SnippetSyntax::UltiSnips, &[
+		("text", "$HOME and ${HOME}", Some("$HOME and ${HOME}")),
+		("escapes", "\\` \\$1 \\}", Some("` $1 }")),
+		("tab stops", "${1:a} $1 $0", Some("a a ")),
+		("placeholders", "${1:outer ${2:inner}}", Some("outer inner")),
+		("visual", "${VISUAL}", Some("")),
+		("visual", "${VISUAL:default}", Some("default")),
+		("visual", "${1:${VISUAL:x}}", Some("x")),
+		("code", "a`date`b", Some("ab")),
+		("code", "`!p snip.rv = 1` !p", Some(" !p")),
+		("transformations", "${1:name} ${1/(\\w+)/\\u$1/}", Some("name Name")),
+		("transformations", "${1:a b} ${1/ /_/g}", Some("a b a_b")),
+		("errors", "`unterminated", None),
+		("errors", "${1:open", None)
+	])
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn builtin_corpora_conform() {
+		let report = run(&lsp_corpus());
+		assert!(report.is_conformant(), "{}", report);
+		assert!(report.to_string().starts_with("text: 4/4\nescapes: 2/2\n"));
+		let report = run(&ultisnips_corpus());
+		assert!(report.is_conformant(), "{}", report);
+	}
+
+	#[test]
+	fn run_corpus_file() {
+		let corpus = "# cases\nlsp\tplaceholders\t${1:a\\nb}\ta\\nb\nlsp\tplaceholders\t${1:a}\tb\nultisnips\terrors\t${1:a}\t!error\n";
+		let report = run(&read_corpus(corpus).unwrap());
+		assert_eq!(report.to_string(), "placeholders: 1/2\n  \"${1:a}\": expected \"b\", rendered \"a\"\nerrors: 0/1\n  \"${1:a}\": expected an error, rendered \"a\"\ntotal: 1/3\n");
+		assert_eq!(read_corpus("lsp\tx\n").unwrap_err(), CorpusError { line: 1, message: "expected syntax, feature, source and expected text separated by tabs" });
+		assert_eq!(read_corpus("\nvim\tx\ty\tz").unwrap_err().line, 2);
+	}
+}
@@ -0,0 +1,99 @@
+//! Finding literal text that is repeated within a snippet and turning it into mirrors of one field,
+//! so the text is only typed once when the snippet is filled in.
+
+use crate::shared::Rc;
+use crate::{Snippet, Segment, Field, Tab};
+
+/// Word of literal text occurring more than once within a snippet's body.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RepeatedText {
+	pub text: String,
+	/// Number of occurrences.
+	pub count: usize
+}
+
+fn is_word(c: char) -> bool {
+	c.is_alphanumeric() || c == '_'
+}
+
+/// Words within the text along with their byte offsets.
+fn words(text: &str) -> impl Iterator<Item = (usize, &str)> {
+	text.split(|c: char| !is_word(c))
+		.filter(|word| !word.is_empty())
+		.map(move |word| (word.as_ptr() as usize - text.as_ptr() as usize, word))
+}
+
+impl Snippet {
+	/// Words (runs of letters, digits and underscores) of at least min_len characters repeated within the literal text of the body
+	/// (not within fields), most repeated first and otherwise in order of first occurrence.
+	pub fn repeated_text(&self, min_len: usize) -> Vec<RepeatedText> {
+		let mut repeated: Vec<RepeatedText> = Vec::new();
+		for segment in &self.body {
+			let Segment::Text(text) = segment else {
+				continue
+			};
+			for (_, word) in words(text).filter(|(_, word)| word.chars().count() >= min_len) {
+				match repeated.iter_mut().find(|repeated| repeated.text == word) {
+					Some(repeated) => repeated.count += 1,
+					None => repeated.push(RepeatedText { text: word.to_string(), count: 1 })
+				}
+			}
+		}
+		repeated.retain(|repeated| repeated.count > 1);
+		// Sorting is stable, keeping words repeated equally often in order of first occurrence.
+		repeated.sort_by_key(|repeated| std::cmp::Reverse(repeated.count));
+		repeated
+	}
+
+	/// Copy of the snippet with every occurrence of the word within the literal text of the body replaced by a mirror of one new
+	/// placeholder defaulting to the word, tabbed after the other tabs (which are renumbered from 1 as [`Snippet::extract`] does).
+	/// None when the word occurs less than twice or tab numbers have run out.
+	pub fn mirror_text(&self, word: &str) -> Option<Snippet> {
+		let mut snippet = self.extract(0..self.body.len())?;
+		let num = snippet.tabs.iter().map(|tab| tab.num).max().unwrap_or(0).checked_add(1)?;
+		let field = Rc::new(Field::Placeholder(vec![Segment::Text(word.to_string())]));
+		let mut count = 0;
+		let mut body = Vec::new();
+		for segment in std::mem::take(&mut snippet.body) {
+			let Segment::Text(text) = segment else {
+				body.push(segment);
+				continue
+			};
+			let mut copied = 0;
+			for (start, _) in words(&text).filter(|(_, found)| *found == word) {
+				if start > copied {
+					body.push(Segment::Text(text[copied..start].to_string()));
+				}
+				body.push(Segment::Field(field.clone()));
+				copied = start + word.len();
+				count += 1;
+			}
+			if copied < text.len() {
+				body.push(Segment::Text(text[copied..].to_string()));
+			}
+		}
+		if count < 2 {
+			return None
+		}
+		snippet.body = body;
+		snippet.tabs.push(Tab { num, field: Rc::downgrade(&field), transformations: Vec::new(), label: None });
+		Some(snippet)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn mirror_repeated_text() {
+		let snippet = Snippet::parse("impl Widget for Widget { fn widget(&self) -> Widget {$0} }").unwrap();
+		assert_eq!(snippet.repeated_text(3), [RepeatedText { text: String::from("Widget"), count: 3 }]);
+		assert!(snippet.mirror_text("self").is_none());
+		let mut mirrored = snippet.mirror_text("Widget").unwrap();
+		assert_eq!(mirrored.to_string(), snippet.to_string());
+		assert_eq!(mirrored.tabs().iter().map(|tab| tab.num).collect::<Vec<_>>(), [0, 1]);
+		mirrored.set_field_text(1, "Gadget");
+		assert_eq!(mirrored.to_string(), "impl Gadget for Gadget { fn widget(&self) -> Gadget {} }");
+	}
+}
@@ -0,0 +1,167 @@
+//! Rendering only what changed since a snippet was last rendered, as edits of the text rendered before
+//! (such as to send to an editor as LSP TextEdits after every keystroke in a placeholder).
+//!
+//! Segments of the body that are the same parts as when last rendered are not rendered again.
+//! Fields, variables, code and the like can not change once shared, and whatever holds one that is replaced is rebuilt,
+//! so a part is changed exactly when it is no longer the part rendered before.
+
+use std::mem;
+use crate::shared::{Rc, Weak};
+use crate::{Snippet, Segment, Field, Transformation, Variable, Code, Conditional};
+use crate::rendered::Span;
+
+/// Replacement of a range of the text rendered before, the range being in bytes and UTF-16 code units of that text.
+/// The edits of a delta are in order and do not overlap, each applying to the text rendered before as LSP TextEdits do.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextEdit {
+	pub range: Span,
+	pub text: String
+}
+
+/// What rendered a piece of the text, to tell whether a segment is still it.
+#[derive(Debug, Clone)]
+enum Identity {
+	/// Text of the body, compared by what it is.
+	Text,
+	Field(Weak<Field>),
+	Transformation(Weak<Transformation>),
+	Variable(Weak<Variable>),
+	Code(Weak<Code>),
+	Conditional(Weak<Conditional>),
+	Snippet(Weak<Snippet>)
+}
+
+/// Text a segment of the body rendered as. Weak references keep the parts rendered from being reused for others,
+/// without keeping the parts themselves.
+#[derive(Debug, Clone)]
+struct Piece {
+	identity: Identity,
+	text: String,
+	/// Length of the text in UTF-16 code units.
+	utf16: usize
+}
+
+impl Piece {
+	fn of(segment: &Segment) -> Self {
+		let identity = match segment {
+			Segment::Text(_) => Identity::Text,
+			Segment::Field(field) => Identity::Field(Rc::downgrade(field)),
+			Segment::Transformation(transformation) => Identity::Transformation(Rc::downgrade(transformation)),
+			Segment::Variable(variable) => Identity::Variable(Rc::downgrade(variable)),
+			Segment::Code(code) => Identity::Code(Rc::downgrade(code)),
+			Segment::Conditional(conditional) => Identity::Conditional(Rc::downgrade(conditional)),
+			Segment::Snippet(nested) => Identity::Snippet(Rc::downgrade(nested))
+		};
+		let text = segment.to_string();
+		Piece { identity, utf16: text.encode_utf16().count(), text }
+	}
+
+	/// Whether the segment is what rendered the piece, so renders as it.
+	fn is(&self, segment: &Segment) -> bool {
+		match (&self.identity, segment) {
+			(Identity::Text, Segment::Text(text)) => self.text == *text,
+			(Identity::Field(weak), Segment::Field(field)) => weak.as_ptr() == Rc::as_ptr(field),
+			(Identity::Transformation(weak), Segment::Transformation(transformation)) => weak.as_ptr() == Rc::as_ptr(transformation),
+			(Identity::Variable(weak), Segment::Variable(variable)) => weak.as_ptr() == Rc::as_ptr(variable),
+			(Identity::Code(weak), Segment::Code(code)) => weak.as_ptr() == Rc::as_ptr(code),
+			(Identity::Conditional(weak), Segment::Conditional(conditional)) => weak.as_ptr() == Rc::as_ptr(conditional),
+			(Identity::Snippet(weak), Segment::Snippet(nested)) => weak.as_ptr() == Rc::as_ptr(nested),
+			_ => false
+		}
+	}
+}
+
+/// What each segment of the body rendered as when the snippet was last rendered through [`Snippet::render_delta`].
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Baseline {
+	pieces: Vec<Piece>
+}
+
+/// The edit replacing the old text starting at the position (in bytes and UTF-16 code units) with the new,
+/// narrowed to leave out what they start and end with alike. None when they are the same.
+fn edit((byte, utf16): (usize, usize), old: &str, new: &str) -> Option<TextEdit> {
+	if old == new {
+		return None
+	}
+	let prefix: usize = old.chars().zip(new.chars()).take_while(|(a, b)| a == b).map(|(c, _)| c.len_utf8()).sum();
+	let suffix: usize = old[prefix..].chars().rev().zip(new[prefix..].chars().rev()).take_while(|(a, b)| a == b).map(|(c, _)| c.len_utf8()).sum();
+	let replaced = &old[prefix..old.len() - suffix];
+	let start = utf16 + old[..prefix].encode_utf16().count();
+	Some(TextEdit {
+		range: Span {
+			bytes: byte + prefix..byte + prefix + replaced.len(),
+			utf16: start..start + replaced.encode_utf16().count()
+		},
+		text: new[prefix..new.len() - suffix].to_string()
+	})
+}
+
+impl Snippet {
+	/// Edits turning the text the snippet rendered as when this was last called into the text it renders as now (see [`TextEdit`]),
+	/// rendering only the segments of the body that changed since. The first call inserts the whole text.
+	/// When segments were added to or removed from the body, everything between the first and last segment changed is replaced at once.
+	pub fn render_delta(&mut self) -> Vec<TextEdit> {
+		let old = mem::take(&mut self.baseline.pieces);
+		let body = &self.body;
+		let start = old.iter().zip(body).take_while(|(piece, segment)| piece.is(segment)).count();
+		let end = old[start..].iter().rev().zip(body[start..].iter().rev()).take_while(|(piece, segment)| piece.is(segment)).count();
+		let changed = &body[start..body.len() - end];
+		let changed_len = old.len() - start - end;
+		let mut old = old.into_iter();
+		let mut pieces: Vec<Piece> = old.by_ref().take(start).collect();
+		let mut position = pieces.iter().fold((0, 0), |(byte, utf16), piece| (byte + piece.text.len(), utf16 + piece.utf16));
+		let mut edits = Vec::new();
+		if changed_len == changed.len() {
+			for (before, segment) in old.by_ref().take(changed_len).zip(changed) {
+				let after = if before.is(segment) { before.clone() } else { Piece::of(segment) };
+				edits.extend(edit(position, &before.text, &after.text));
+				position = (position.0 + before.text.len(), position.1 + before.utf16);
+				pieces.push(after);
+			}
+		} else {
+			let before: String = old.by_ref().take(changed_len).map(|piece| piece.text).collect();
+			let after: Vec<Piece> = changed.iter().map(Piece::of).collect();
+			edits.extend(edit(position, &before, &after.iter().map(|piece| piece.text.as_str()).collect::<String>()));
+			pieces.extend(after);
+		}
+		pieces.extend(old);
+		self.baseline.pieces = pieces;
+		edits
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn apply(text: &str, edits: &[TextEdit]) -> String {
+		let mut text = text.to_string();
+		for edit in edits.iter().rev() {
+			text.replace_range(edit.range.bytes.clone(), &edit.text);
+		}
+		text
+	}
+
+	#[test]
+	fn render_changed_spans() {
+		let mut snippet = Snippet::parse("${1:a} x $1 ${2:é} y").unwrap();
+		let edits = snippet.render_delta();
+		assert_eq!(edits, [TextEdit { range: Span { bytes: 0..0, utf16: 0..0 }, text: String::from("a x a é y") }]);
+		assert!(snippet.render_delta().is_empty());
+
+		snippet.set_field_text(1, "ab").unwrap();
+		let edits = snippet.render_delta();
+		assert_eq!(edits, [
+			TextEdit { range: Span { bytes: 1..1, utf16: 1..1 }, text: String::from("b") },
+			TextEdit { range: Span { bytes: 5..5, utf16: 5..5 }, text: String::from("b") }
+		]);
+		snippet.set_field_text(2, "ü").unwrap();
+		assert_eq!(snippet.render_delta(), [TextEdit { range: Span { bytes: 8..10, utf16: 8..9 }, text: String::from("ü") }]);
+
+		let before = snippet.to_string();
+		snippet.splice(4..6, Snippet::parse("${1:n} z $1").unwrap());
+		let edits = snippet.render_delta();
+		assert_eq!(apply(&before, &edits), snippet.to_string());
+		assert_eq!(edits, [TextEdit { range: Span { bytes: 8..12, utf16: 8..11 }, text: String::from("n z n") }]);
+	}
+}
@@ -0,0 +1,97 @@
+use crate::shared::Rc;
+use crate::{Snippet, Segment, Field};
+use crate::compose::{Replacer, shared_body};
+use crate::transform::TransformError;
+
+impl Snippet {
+	/// Replaces the contents of the field selected by the tab with the text, the field becoming a placeholder whatever it was.
+	/// Mirrors show the text and the transformations acting upon the field are re-run. Fields nested in it are no longer shown.
+	/// Returns why transformations could not be applied (keeping their previous result), None when there is no tab with the number.
+	pub fn set_field_text(&mut self, num: u8, text: &str) -> Option<Vec<TransformError>> {
+		let body = if text.is_empty() { Vec::new() } else { vec![Segment::Text(text.to_string())] };
+		self.replace_field(num, |_| Some(Field::Placeholder(body)))
+	}
+
+	/// Chooses the option of the choice field selected by the tab, updating mirrors and transformations as [`Snippet::set_field_text`] does.
+	/// None when there is no tab with the number, its field is not a choice or the choice has no such option.
+	pub fn set_choice(&mut self, num: u8, index: usize) -> Option<Vec<TransformError>> {
+		self.replace_field(num, |field| match field {
+			Field::Choice(_, choices, labels) if index < choices.len() => Some(Field::Choice(index, choices.iter().map(|body| shared_body(body)).collect(), labels.clone())),
+			_ => None
+		})
+	}
+
+	/// Chooses the option after the chosen one (the first after the last), as [`Snippet::set_choice`] does,
+	/// such as for cycling through the options with a key.
+	pub fn select_next_choice(&mut self, num: u8) -> Option<Vec<TransformError>> {
+		self.cycle_choice(num, true)
+	}
+
+	/// Chooses the option before the chosen one (the last before the first), as [`Snippet::set_choice`] does.
+	pub fn select_prev_choice(&mut self, num: u8) -> Option<Vec<TransformError>> {
+		self.cycle_choice(num, false)
+	}
+
+	fn cycle_choice(&mut self, num: u8, forward: bool) -> Option<Vec<TransformError>> {
+		let field = self.tabs.iter().find(|tab| tab.num == num)?.field.upgrade()?;
+		let Field::Choice(choice, choices, _) = &*field else {
+			return None
+		};
+		if choices.is_empty() {
+			return None
+		}
+		let len = choices.len();
+		// Choices read from elsewhere may be out of range.
+		let choice = (*choice).min(len - 1);
+		let index = if forward { (choice + 1) % len } else { (choice + len - 1) % len };
+		self.set_choice(num, index)
+	}
+
+	/// Rebuilds everything holding the tab's field around its replacement, as fields can not change once shared.
+	pub(crate) fn replace_field(&mut self, num: u8, replace: impl FnOnce(&Field) -> Option<Field>) -> Option<Vec<TransformError>> {
+		let field = self.tabs.iter().find(|tab| tab.num == num)?.field.upgrade()?;
+		let replaced = Rc::new(replace(&field)?);
+		let text = replaced.to_string();
+		let mut replacer = Replacer::default();
+		replacer.fields.push((Rc::as_ptr(&field), replaced));
+		let mut errors = Vec::new();
+		for tab in self.tabs.iter().filter(|tab| tab.field.as_ptr() == Rc::as_ptr(&field)) {
+			errors.extend(replacer.reapply(&tab.transformations, &text));
+		}
+		replacer.snippet(self);
+		Some(errors)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn edit_fields() {
+		let mut snippet = Snippet::parse("${1:a} $1 ${1/(.*)/${1:/upcase}/} ${2|x,y|}").unwrap();
+		assert!(snippet.set_field_text(1, "bc").unwrap().is_empty());
+		assert_eq!(snippet.to_string(), "bc bc BC x");
+		assert!(snippet.set_choice(2, 1).unwrap().is_empty());
+		assert_eq!(snippet.to_string(), "bc bc BC y");
+		assert!(snippet.set_choice(2, 2).is_none());
+		assert!(snippet.set_choice(1, 0).is_none());
+		assert!(snippet.set_field_text(3, "z").is_none());
+		assert!(snippet.tabs().iter().all(|tab| tab.field.upgrade().is_some()));
+	}
+
+	#[test]
+	fn cycle_choices() {
+		let mut snippet = Snippet::parse("${1|a,b,c|} ${1/(.*)/${1:/upcase}/} ${2:x}").unwrap();
+		let field = |snippet: &Snippet| snippet.tabs()[0].field.upgrade().unwrap();
+		assert_eq!(field(&snippet).choices(), ["a", "b", "c"]);
+		assert!(snippet.select_prev_choice(1).unwrap().is_empty());
+		assert_eq!(field(&snippet).selected_index(), Some(2));
+		assert_eq!(snippet.to_string(), "c C x");
+		snippet.select_next_choice(1).unwrap();
+		snippet.select_next_choice(1).unwrap();
+		assert_eq!(snippet.to_string(), "b B x");
+		assert!(snippet.select_next_choice(2).is_none());
+		assert_eq!(snippet.tabs()[1].field.upgrade().unwrap().selected_index(), None);
+	}
+}
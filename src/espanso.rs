@@ -0,0 +1,542 @@
+use crate::shared::Rc;
+use std::{fmt, fs, io};
+use std::path::Path;
+use crate::{Snippet, Segment, Field, Tab, Variable, VariableSource, Code, Expansion};
+use crate::library::{SnippetDefinition, SourceLocation};
+use crate::yaml::{self, Node};
+use crate::warning::{self, Warning, WarningKind};
+
+/// Outcome of importing an espanso match file.
+#[derive(Debug)]
+pub struct Import {
+	/// Snippet definitions made from the matches of the file.
+	pub definitions: Vec<SnippetDefinition>,
+	/// Matches and extension variables that have no equivalent within this library.
+	pub unmapped: Vec<Unmapped>,
+	/// Quality issues found in the matches.
+	pub warnings: Vec<Warning>
+}
+
+/// A match or extension variable that could not be mapped onto this library's snippets.
+/// Unmapped variables are imported as empty placeholders and unmapped matches are skipped.
+#[derive(Debug)]
+pub struct Unmapped {
+	/// First trigger of the match (or its regex when it has no trigger).
+	pub trigger: String,
+	/// Name of the variable that could not be mapped, or the match key that could not be for whole matches.
+	pub name: String,
+	/// Extension type of the variable (`random`, `script`, ...) or kind of the match (`regex`, `image_path`, ...).
+	pub kind: String
+}
+
+/// Reasons a match file could not be imported.
+#[derive(Debug)]
+pub enum ImportError {
+	/// The file could not be read.
+	Io(io::Error),
+	/// The file is not valid YAML. Carries the line (starting at 1) and a description of the problem.
+	Syntax(usize, &'static str),
+	/// The YAML at this line does not have the shape of a match file.
+	Structure(usize, &'static str)
+}
+
+impl fmt::Display for ImportError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			ImportError::Io(error) => write!(f, "{}", error),
+			ImportError::Syntax(line, message) => write!(f, "line {}: {}", line, message),
+			ImportError::Structure(line, message) => write!(f, "line {}: {}", line, message)
+		}
+	}
+}
+
+impl std::error::Error for ImportError {}
+
+impl From<io::Error> for ImportError {
+	fn from(error: io::Error) -> Self {
+		ImportError::Io(error)
+	}
+}
+
+impl From<yaml::Error> for ImportError {
+	fn from(error: yaml::Error) -> Self {
+		ImportError::Syntax(error.line, error.message)
+	}
+}
+
+/// Imports the match file at the path, recording the path in every definition's source location.
+pub fn import_file(path: impl AsRef<Path>) -> Result<Import, ImportError> {
+	let path = path.as_ref();
+	let mut import = import(&fs::read_to_string(path)?)?;
+	for definition in &mut import.definitions {
+		if let Some(source) = &mut definition.source {
+			source.path = Some(path.to_path_buf());
+		}
+	}
+	Ok(import)
+}
+
+/// Imports every match of an espanso match file (the `matches` list, with `global_vars` available to all of them).
+///
+/// `{{name}}` references become the extension variable they name: `echo` becomes text, `clipboard` the clipboard variable,
+/// `shell` and `date` code blocks and `choice` a choice field. Form `[[name]]` fields become placeholders and `$|$` tab 0.
+/// References to variables not declared in the file become daemon variables, since they are likely global variables defined elsewhere.
+pub fn import(yaml: &str) -> Result<Import, ImportError> {
+	let root = yaml::parse(yaml)?;
+	if root.as_str().is_some_and(str::is_empty) {
+		return Ok(Import { definitions: Vec::new(), unmapped: Vec::new(), warnings: Vec::new() })
+	}
+	if root.get("matches").is_none() && root.get("global_vars").is_none() {
+		return Err(ImportError::Structure(root.line, "expected a matches list"))
+	}
+	let global_vars = root.get("global_vars").map_or(&[][..], Node::items);
+	let mut import = Import {
+		definitions: Vec::new(),
+		unmapped: Vec::new(),
+		warnings: Vec::new()
+	};
+	for entry in root.get("matches").map_or(&[][..], Node::items) {
+		let triggers: Vec<String> = match (entry.get("trigger"), entry.get("triggers")) {
+			(Some(trigger), _) => trigger.as_str().map(str::to_string).into_iter().collect(),
+			(None, Some(triggers)) => triggers.items().iter().filter_map(Node::as_str).map(str::to_string).collect(),
+			(None, None) => Vec::new()
+		};
+		if triggers.is_empty() {
+			let regex = entry.get("regex").and_then(Node::as_str);
+			match regex {
+				Some(regex) => import.unmapped.push(Unmapped {
+					trigger: regex.to_string(),
+					name: String::from("regex"),
+					kind: String::from("regex")
+				}),
+				None => return Err(ImportError::Structure(entry.line, "match has no trigger"))
+			}
+			continue
+		}
+		let (body, form) = match ["replace", "markdown", "html", "form"].iter().find_map(|key| entry.get(key).map(|node| (*key, node))) {
+			Some((key, node)) => (node.as_str().ok_or(ImportError::Structure(node.line, "expected text"))?, key == "form"),
+			None => {
+				let kind = if entry.get("image_path").is_some() { "image_path" } else { "unknown" };
+				import.unmapped.push(Unmapped {
+					trigger: triggers[0].clone(),
+					name: String::from(kind),
+					kind: String::from(kind)
+				});
+				continue
+			}
+		};
+		let mut builder = Builder {
+			trigger: &triggers[0],
+			vars: entry.get("vars").map_or(&[][..], Node::items),
+			global_vars,
+			form_fields: entry.get("form_fields"),
+			unmapped: &mut import.unmapped,
+			warnings: &mut import.warnings,
+			line: entry.line,
+			references: Vec::new(),
+			snippet: Snippet {
+				body: Vec::new(),
+				tabs: Vec::new(),
+				variables: Vec::new(),
+				code_expansions: Vec::new(),
+				named_segments: Vec::new(),
+				current_tab: None,
+				choice_sources: Vec::new(),
+				baseline: Default::default()
+			}
+		};
+		let (pieces, suspicious) = split_body(body, form);
+		for text in suspicious {
+			builder.warn(WarningKind::SuspiciousEscape(String::from(text)));
+		}
+		for piece in pieces {
+			let segment = match piece {
+				Piece::Text(text) => Segment::Text(text),
+				Piece::Cursor => builder.cursor(),
+				Piece::Variable(name) => builder.variable(name),
+				Piece::FormField(name) => builder.form_field(name)
+			};
+			builder.snippet.body.push(segment);
+		}
+		if let Some(num) = warning::tab_number_gap(&builder.snippet) {
+			builder.warn(WarningKind::TabNumberGap(num));
+		}
+		let snippet = builder.snippet;
+		let description = entry.get("label").and_then(Node::as_str).map(str::to_string);
+		let mut definition = SnippetDefinition::new(triggers, description, snippet);
+		definition.source = Some(SourceLocation {
+			path: None,
+			start_line: entry.line,
+			end_line: entry.end
+		});
+		import.definitions.push(definition);
+	}
+	Ok(import)
+}
+
+enum Piece<'a> {
+	Text(String),
+	/// `$|$`
+	Cursor,
+	/// `{{name}}`
+	Variable(&'a str),
+	/// `[[name]]`
+	FormField(&'a str)
+}
+
+/// Splits a match body into literal text, `{{name}}` variable references and (for forms) `[[name]]` fields.
+/// Also returns the openings that had to be taken literally because they are not closed.
+fn split_body(body: &str, form: bool) -> (Vec<Piece<'_>>, Vec<&'static str>) {
+	let mut pieces = Vec::new();
+	let mut suspicious = Vec::new();
+	let mut text = String::new();
+	let mut rest = body;
+	loop {
+		let next = ["{{", "$|$", "[["].iter()
+			.filter(|open| form || **open != "[[")
+			.filter_map(|open| rest.find(open).map(|start| (start, *open)))
+			.min();
+		let Some((start, open)) = next else {
+			break
+		};
+		text.push_str(&rest[..start]);
+		let after = &rest[start + open.len()..];
+		let close = match open { "{{" => "}}", "[[" => "]]", _ => "" };
+		let piece = if open == "$|$" {
+			rest = after;
+			Some(Piece::Cursor)
+		} else {
+			after.find(close)
+				.map(|end| (after[..end].trim(), &after[end + close.len()..]))
+				.filter(|(name, _)| !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || "_-.".contains(c)))
+				.map(|(name, after)| {
+					rest = after;
+					if open == "{{" { Piece::Variable(name) } else { Piece::FormField(name) }
+				})
+		};
+		match piece {
+			Some(piece) => {
+				if !text.is_empty() {
+					pieces.push(Piece::Text(std::mem::take(&mut text)));
+				}
+				pieces.push(piece);
+			},
+			None => {
+				text.push_str(open);
+				if !suspicious.contains(&open) {
+					suspicious.push(open);
+				}
+				rest = after;
+			}
+		}
+	}
+	text.push_str(rest);
+	if !text.is_empty() {
+		pieces.push(Piece::Text(text));
+	}
+	(pieces, suspicious)
+}
+
+/// Segments already made for a name, so that repeated references share (mirror) them.
+enum Reference {
+	Field(Rc<Field>),
+	Variable(Rc<Variable>),
+	Code(Rc<Code>),
+	Text(String)
+}
+
+struct Builder<'a> {
+	trigger: &'a str,
+	vars: &'a [Node],
+	global_vars: &'a [Node],
+	form_fields: Option<&'a Node>,
+	unmapped: &'a mut Vec<Unmapped>,
+	warnings: &'a mut Vec<Warning>,
+	/// Line the match starts on.
+	line: usize,
+	/// References made so far, `$|$` being named by the empty string.
+	references: Vec<(String, Reference)>,
+	snippet: Snippet
+}
+
+impl Builder<'_> {
+	fn warn(&mut self, kind: WarningKind) {
+		self.warnings.push(Warning {
+			trigger: self.trigger.to_string(),
+			line: self.line,
+			kind
+		});
+	}
+
+	fn segment(reference: &Reference) -> Segment {
+		match reference {
+			Reference::Field(field) => Segment::Field(field.clone()),
+			Reference::Variable(variable) => Segment::Variable(variable.clone()),
+			Reference::Code(code) => Segment::Code(code.clone()),
+			Reference::Text(text) => Segment::Text(text.clone())
+		}
+	}
+
+	fn existing(&self, name: &str) -> Option<Segment> {
+		self.references.iter().find(|(reference, _)| reference == name).map(|(_, reference)| Self::segment(reference))
+	}
+
+	fn add(&mut self, name: &str, reference: Reference) -> Segment {
+		let segment = Self::segment(&reference);
+		self.references.push((name.to_string(), reference));
+		segment
+	}
+
+	/// Adds the field, as a tab when there is a number for it.
+	fn field(&mut self, name: &str, field: Field, num: Option<u8>) -> Segment {
+		let field = Rc::new(field);
+		match num {
+			Some(num) => self.snippet.tabs.push(Tab {
+				num,
+				field: Rc::downgrade(&field),
+				transformations: Vec::new(),
+				label: Some(name.to_string())
+			}),
+			None => self.warn(WarningKind::TooManyTabs(name.to_string()))
+		}
+		self.add(name, Reference::Field(field))
+	}
+
+	/// None once every tab number is taken.
+	fn next_num(&self) -> Option<u8> {
+		self.snippet.tabs.iter().map(|tab| tab.num).max().unwrap_or(0).checked_add(1)
+	}
+
+	fn cursor(&mut self) -> Segment {
+		self.existing("").unwrap_or_else(|| self.field("", Field::Placeholder(Vec::new()), Some(0)))
+	}
+
+	fn variable(&mut self, name: &str) -> Segment {
+		if let Some(segment) = self.existing(name) {
+			return segment
+		}
+		let declaration = self.vars.iter().chain(self.global_vars)
+			.find(|var| var.get("name").and_then(Node::as_str) == Some(name));
+		let kind = match declaration.and_then(|declaration| declaration.get("type")).and_then(Node::as_str) {
+			Some("dummy") => {
+				self.warn(WarningKind::DeprecatedSyntax(String::from("type: dummy")));
+				"echo"
+			},
+			Some(kind) => kind,
+			None => {
+				if declaration.is_none() {
+					self.warn(WarningKind::UnknownVariable(name.to_string()));
+				}
+				""
+			}
+		};
+		let param = |key: &str| declaration.and_then(|declaration| declaration.get("params")).and_then(|params| params.get(key));
+		let param_str = |key: &str| param(key).and_then(Node::as_str);
+		let reference = match kind {
+			_ if declaration.is_none() => Some(Reference::Variable(Rc::new(Variable {
+				name: name.to_string(),
+				value: String::new(),
+				source: VariableSource::Daemon,
+				default: None
+			}))),
+			"echo" => param_str("echo").map(|echo| Reference::Text(echo.to_string())),
+			"clipboard" => Some(Reference::Variable(Rc::new(Variable {
+				name: String::from("CLIPBOARD"),
+				value: String::new(),
+				source: VariableSource::Client,
+				default: None
+			}))),
+			"shell" => param_str("cmd").map(|cmd| Reference::Code(Rc::new(Code {
+				code: cmd.to_string(),
+				output: String::new(),
+				shebang: format!("#!/usr/bin/env {}", param_str("shell").unwrap_or("sh"))
+			}))),
+			"date" => param_str("format").map(|format| Reference::Code(Rc::new(Code {
+				code: format!("date '+{}'", format.replace('\'', "'\\''")),
+				output: String::new(),
+				shebang: String::from("#!/bin/sh")
+			}))),
+			"choice" => {
+				// Values are either the text to insert or a label shown in the menu along with the id to insert.
+				let values: Vec<(&str, Option<&str>)> = param("values").map_or(&[][..], Node::items).iter()
+					.filter_map(|value| match value.as_str() {
+						Some(text) => Some((text, None)),
+						None => {
+							let label = value.get("label").and_then(Node::as_str);
+							value.get("id").and_then(Node::as_str).or(label).map(|id| (id, label))
+						}
+					})
+					.collect();
+				if values.is_empty() {
+					None
+				} else {
+					let labels = if values.iter().any(|(_, label)| label.is_some()) {
+						values.iter().map(|(id, label)| label.unwrap_or(id).to_string()).collect()
+					} else {
+						Vec::new()
+					};
+					let choices = values.iter().map(|(id, _)| vec![Segment::Text(id.to_string())]).collect();
+					let num = self.next_num();
+					return self.field(name, Field::Choice(0, choices, labels), num)
+				}
+			},
+			_ => None
+		};
+		match reference {
+			Some(Reference::Variable(variable)) => {
+				self.snippet.variables.push(Expansion {
+					expansion: Rc::downgrade(&variable),
+					transformations: Vec::new()
+				});
+				self.add(name, Reference::Variable(variable))
+			},
+			Some(Reference::Code(code)) => {
+				self.snippet.code_expansions.push(Expansion {
+					expansion: Rc::downgrade(&code),
+					transformations: Vec::new()
+				});
+				self.add(name, Reference::Code(code))
+			},
+			Some(reference) => self.add(name, reference),
+			None => {
+				self.unmapped.push(Unmapped {
+					trigger: self.trigger.to_string(),
+					name: name.to_string(),
+					kind: kind.to_string()
+				});
+				let num = self.next_num();
+				self.field(name, Field::Placeholder(Vec::new()), num)
+			}
+		}
+	}
+
+	fn form_field(&mut self, name: &str) -> Segment {
+		let key = format!("[[{}]]", name);
+		if let Some(segment) = self.existing(&key) {
+			return segment
+		}
+		let options = self.form_fields.and_then(|fields| fields.get(name));
+		let values: Vec<Vec<Segment>> = options.and_then(|options| options.get("values")).map_or(&[][..], Node::items).iter()
+			.filter_map(Node::as_str)
+			.map(|value| vec![Segment::Text(value.to_string())])
+			.collect();
+		let field = if values.is_empty() {
+			let default = options.and_then(|options| options.get("default")).and_then(Node::as_str).unwrap_or("");
+			Field::Placeholder(if default.is_empty() { Vec::new() } else { vec![Segment::Text(default.to_string())] })
+		} else {
+			Field::Choice(0, values, Vec::new())
+		};
+		let num = self.next_num();
+		self.field(&key, field, num)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const MATCHES: &str = r#"
+global_vars:
+  - name: sign
+    type: echo
+    params:
+      echo: "J. Doe"
+
+matches:
+  - trigger: ":sig"
+    replace: "Regards,\n{{sign}}"
+  - triggers: [":hi", ":hello"]
+    label: Greeting
+    replace: "Hi {{who}}, {{who}}! $|$"
+    vars:
+      - name: who
+        type: choice
+        params:
+          values: [Ann, Bob]
+  - trigger: ":now"
+    replace: "{{time}} {{paste}} {{roll}} {{shared}}"
+    vars:
+      - name: time
+        type: date
+        params:
+          format: "%H:%M"
+      - name: paste
+        type: clipboard
+      - name: roll
+        type: random
+        params:
+          choices: [a, b]
+  - trigger: ":form"
+    form: |
+      Dear [[name]], {{x}
+    form_fields:
+      name:
+        default: friend
+  - regex: ":d(?P<n>\\d)"
+    replace: "{{n}}"
+"#;
+
+	#[test]
+	fn import_matches() {
+		let import = import(MATCHES).unwrap();
+		assert_eq!(import.definitions.len(), 4);
+		let source = import.definitions[1].source.as_ref().unwrap();
+		assert_eq!((source.start_line, source.end_line), (11, 18));
+		assert_eq!(import.definitions[0].static_text(), Some("Regards,\nJ. Doe"));
+		let greeting = &import.definitions[1];
+		assert_eq!(greeting.triggers, [":hi", ":hello"]);
+		assert_eq!(greeting.description.as_deref(), Some("Greeting"));
+		let snippet = greeting.snippet().unwrap();
+		assert_eq!(snippet.to_string(), "Hi Ann, Ann! ");
+		assert_eq!(snippet.tabs().iter().map(|tab| tab.num).collect::<Vec<_>>(), [1, 0]);
+		let form = import.definitions[3].snippet().unwrap();
+		assert_eq!(form.to_string(), "Dear friend, {{x}\n");
+		assert_eq!(form.tabs().len(), 1);
+	}
+
+	#[test]
+	fn map_extensions() {
+		let import = import(MATCHES).unwrap();
+		let now = import.definitions[2].snippet().unwrap();
+		assert_eq!(now.code_expansions()[0].expansion.upgrade().unwrap().code, "date '+%H:%M'");
+		let names: Vec<_> = now.variables().iter().map(|variable| variable.expansion.upgrade().unwrap().name.clone()).collect();
+		assert_eq!(names, ["CLIPBOARD", "shared"]);
+		assert!(matches!(&now.body()[6], Segment::Variable(variable) if variable.name == "shared"));
+		let unmapped: Vec<_> = import.unmapped.iter().map(|unmapped| (unmapped.name.as_str(), unmapped.kind.as_str())).collect();
+		assert_eq!(unmapped, [("roll", "random"), ("regex", "regex")]);
+
+		let import = super::import("matches:\n  - trigger: lic\n    replace: \"{{license}}\"\n    vars:\n      - name: license\n        type: choice\n        params:\n          values:\n            - label: GNU GPL v3\n              id: \"This program is free software\"\n            - MIT\n").unwrap();
+		let snippet = import.definitions[0].snippet().unwrap();
+		let field = snippet.tabs()[0].field.upgrade().unwrap();
+		assert_eq!(field.choice_labels(), ["GNU GPL v3", "MIT"]);
+		assert_eq!(snippet.to_string(), "This program is free software");
+	}
+
+	#[test]
+	fn warn_about_quality_issues() {
+		let import = import("matches:\n  - trigger: a\n    replace: \"{{x}} {{y} {{z\"\n    vars:\n      - name: x\n        type: dummy\n        params:\n          echo: X\n").unwrap();
+		assert_eq!(import.definitions[0].static_text(), Some("X {{y} {{z"));
+		let kinds: Vec<_> = import.warnings.iter().map(|warning| (warning.line, &warning.kind)).collect();
+		assert_eq!(kinds, [(2, &WarningKind::SuspiciousEscape(String::from("{{"))), (2, &WarningKind::DeprecatedSyntax(String::from("type: dummy")))]);
+		let import = super::import("matches:\n  - trigger: a\n    replace: \"{{elsewhere}}\"\n").unwrap();
+		assert_eq!(import.warnings[0].kind, WarningKind::UnknownVariable(String::from("elsewhere")));
+	}
+
+	#[test]
+	fn reject_malformed() {
+		assert!(matches!(import("- a\n- b\n"), Err(ImportError::Structure(1, "expected a matches list"))));
+		assert!(matches!(import("matches:\n  - replace: x\n"), Err(ImportError::Structure(2, "match has no trigger"))));
+		assert!(matches!(import_file("/nonexistent/match.yml"), Err(ImportError::Io(_))));
+		assert!(matches!(import("matches: [\n"), Err(ImportError::Syntax(1, _))));
+	}
+
+	#[test]
+	fn handle_multi_byte_text() {
+		let yaml = "matches:\n  - trigger: \":ü\"\n    form: \"π {{名前}} → [[形]] 🎉$|$\"\n    vars:\n      - name: 名前\n        type: echo\n        params:\n          echo: é✓\n";
+		let import = import(yaml).unwrap();
+		assert_eq!(import.definitions[0].snippet().unwrap().to_string(), "π é✓ →  🎉");
+		for (i, _) in yaml.char_indices() {
+			let _ = super::import(&yaml[..i]);
+		}
+	}
+}
@@ -0,0 +1,151 @@
+//! Applying every transformation of a snippet at once, such as for templates with dozens of mirrors.
+//!
+//! Transformations act upon the text of a field, a variable or code output. A field may show the results of other transformations,
+//! so those transforming it are applied after them. Transformations independent of each other are applied in the same round,
+//! spread over threads with the `parallel` feature.
+
+use crate::shared::{Rc, Weak};
+use crate::{Snippet, Segment, Transformation};
+use crate::compose::Replacer;
+use crate::transform::TransformError;
+use crate::visit::Segments;
+
+/// Where a transformation of the snippet is listed: by the index of the tab or expansion, then among its transformations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Listed {
+	Tab(usize, usize),
+	Variable(usize, usize),
+	Code(usize, usize)
+}
+
+impl Snippet {
+	/// Applies every transformation of the tabs and expansions to the text it acts upon: the text of the field,
+	/// the value of the variable or the output of the code. Transformations of a field showing the results of others
+	/// are applied after those, and the others are applied in the same round, at once with the `parallel` feature.
+	/// Transformations of variables only shown transformed have no value to act upon, so are left as they are.
+	/// Returns why transformations could not be applied (keeping their previous result), in the order they were applied.
+	pub fn apply_transformations(&mut self) -> Vec<TransformError> {
+		self.apply_in_rounds(cfg!(feature = "parallel"))
+	}
+
+	fn apply_in_rounds(&mut self, parallel: bool) -> Vec<TransformError> {
+		let mut pending = self.listed();
+		let mut errors = Vec::new();
+		while !pending.is_empty() {
+			let current: Vec<*const Transformation> = pending.iter().filter_map(|&listed| Some(Rc::as_ptr(&self.transformation(listed)?))).collect();
+			let mut ready: Vec<Listed> = pending.iter().copied().filter(|&listed| !self.shows_any(listed, &current)).collect();
+			// Fields showing transformations of their own wait on each other, so are applied together.
+			if ready.is_empty() {
+				ready = pending.clone();
+			}
+			pending.retain(|listed| !ready.contains(listed));
+			let mut jobs: Vec<(Rc<Transformation>, String)> = Vec::new();
+			for listed in ready {
+				let (Some(transformation), Some(input)) = (self.transformation(listed), self.input(listed)) else {
+					continue
+				};
+				if !jobs.iter().any(|(known, _)| Rc::ptr_eq(known, &transformation)) {
+					jobs.push((transformation, input));
+				}
+			}
+			let results = if parallel { transform_parallel(&jobs) } else { transform_each(&jobs) };
+			let mut replacer = Replacer::default();
+			for ((transformation, _), result) in jobs.iter().zip(results) {
+				let result = result.unwrap_or_else(|error| {
+					errors.push(error);
+					transformation.result.clone()
+				});
+				replacer.transformations.push((Rc::as_ptr(transformation), Rc::new(Transformation {
+					section: transformation.section.clone(),
+					format: transformation.format.clone(),
+					flags: transformation.flags.clone(),
+					result,
+					compiled: transformation.compiled.clone()
+				})));
+			}
+			replacer.snippet(self);
+		}
+		errors
+	}
+
+	/// Every transformation listed by the tabs and expansions.
+	fn listed(&self) -> Vec<Listed> {
+		let tabs = self.tabs.iter().enumerate().flat_map(|(tab, listed)| (0..listed.transformations.len()).map(move |index| Listed::Tab(tab, index)));
+		let variables = self.variables.iter().enumerate().flat_map(|(variable, listed)| (0..listed.transformations.len()).map(move |index| Listed::Variable(variable, index)));
+		let codes = self.code_expansions.iter().enumerate().flat_map(|(code, listed)| (0..listed.transformations.len()).map(move |index| Listed::Code(code, index)));
+		tabs.chain(variables).chain(codes).collect()
+	}
+
+	fn transformation(&self, listed: Listed) -> Option<Rc<Transformation>> {
+		let weak: &Weak<Transformation> = match listed {
+			Listed::Tab(tab, index) => &self.tabs[tab].transformations[index],
+			Listed::Variable(variable, index) => &self.variables[variable].transformations[index],
+			Listed::Code(code, index) => &self.code_expansions[code].transformations[index]
+		};
+		weak.upgrade()
+	}
+
+	/// Text the transformation acts upon.
+	fn input(&self, listed: Listed) -> Option<String> {
+		Some(match listed {
+			Listed::Tab(tab, _) => self.tabs[tab].field.upgrade()?.to_string(),
+			Listed::Variable(variable, _) => self.variables[variable].expansion.upgrade()?.value.clone(),
+			Listed::Code(code, _) => self.code_expansions[code].expansion.upgrade()?.output.clone()
+		})
+	}
+
+	/// Whether the text the transformation acts upon shows any of the transformations.
+	fn shows_any(&self, listed: Listed, transformations: &[*const Transformation]) -> bool {
+		let Listed::Tab(tab, _) = listed else {
+			return false
+		};
+		let Some(field) = self.tabs[tab].field.upgrade() else {
+			return false
+		};
+		let field = [Segment::Field(field)];
+		Segments::of(&field).any(|segment| matches!(segment, Segment::Transformation(shown) if transformations.contains(&Rc::as_ptr(shown))))
+	}
+}
+
+fn transform_each(jobs: &[(Rc<Transformation>, String)]) -> Vec<Result<String, TransformError>> {
+	jobs.iter().map(|(transformation, input)| transformation.transform(input)).collect()
+}
+
+/// Transforms the inputs as [`transform_each`] does, spread over as many threads as there are processors.
+#[cfg(feature = "parallel")]
+fn transform_parallel(jobs: &[(Rc<Transformation>, String)]) -> Vec<Result<String, TransformError>> {
+	let threads = std::thread::available_parallelism().map_or(1, usize::from);
+	let chunk = jobs.len().div_ceil(threads).max(1);
+	std::thread::scope(|scope| {
+		let handles: Vec<_> = jobs.chunks(chunk).map(|jobs| scope.spawn(move || transform_each(jobs))).collect();
+		handles.into_iter().flat_map(|handle| handle.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic))).collect()
+	})
+}
+
+#[cfg(not(feature = "parallel"))]
+use transform_each as transform_parallel;
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn apply_in_dependency_order() {
+		let source = "${1:ab} ${2:<${1/(.*)/${1:/upcase}/}>} ${2/(.*)/[$1]/} ${3:x} ${3/x/y/} $TM_FILENAME${TM_FILENAME/(.*)/($1)/} ${4/(/n/}";
+		let mut snippet = Snippet::parse(source).unwrap();
+		let errors = snippet.apply_transformations();
+		assert_eq!(errors.len(), 1);
+		assert_eq!(snippet.to_string(), "ab <AB> [<AB>] x y () ");
+
+		let mut many = String::new();
+		for num in 1..=40 {
+			many.push_str(&format!("${{{0}:t{0}}} ${{{0}/(t)(.*)/${{1:/upcase}}$2/}} ", num));
+		}
+		many.push_str("${41:${1/(.*)/$1!/}} ${41/(.*)/$1$1/}");
+		let mut parallel = Snippet::parse(&many).unwrap();
+		let mut sequential = parallel.deep_clone();
+		assert!(parallel.apply_in_rounds(true).is_empty() && sequential.apply_in_rounds(false).is_empty());
+		assert_eq!(parallel.to_test_fixture(), sequential.to_test_fixture());
+		assert!(parallel.to_string().ends_with("t40 T40 t1! t1!t1!"));
+	}
+}
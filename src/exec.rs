@@ -0,0 +1,389 @@
+//! Running code expansions, each through the interpreter of its shebang (or a default shell) with the code on its standard input.
+//!
+//! Python code of UltiSnips `` `!p` `` interpolation is run with a `snip` object, its output being what it sets `snip.rv` to.
+//! Only `snip.rv` is provided.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::shared::Rc;
+use crate::{Snippet, Code};
+use crate::library::{SnippetLibrary, SnippetDefinition, GlobalCode};
+use crate::compose::Replacer;
+use crate::transform::TransformError;
+
+/// Why code could not be run.
+#[derive(Debug)]
+pub enum CodeError {
+	/// The interpreter could not be started or communicated with.
+	Spawn(io::Error),
+	/// The code exited unsuccessfully. Carries its exit code (None when killed by a signal) and what it wrote to standard error.
+	Failed(Option<i32>, String),
+	/// The code ran longer than the timeout of the runner and was killed.
+	TimedOut(Duration),
+	/// The code ran but a transformation acting upon its output could not be applied.
+	Transformation(TransformError),
+	/// The host the code was delegated to could not evaluate it. Carries the reason it gave.
+	Delegated(String),
+	/// The code is not a valid expression, see [`crate::expr`].
+	#[cfg(feature = "expr")]
+	Expression(crate::expr::ExprError)
+}
+
+impl fmt::Display for CodeError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			CodeError::Spawn(error) => write!(f, "could not run interpreter: {}", error),
+			CodeError::Failed(Some(status), stderr) => write!(f, "code exited with status {}: {}", status, stderr.trim_end()),
+			CodeError::Failed(None, stderr) => write!(f, "code was terminated: {}", stderr.trim_end()),
+			CodeError::TimedOut(timeout) => write!(f, "code ran longer than {:?}", timeout),
+			CodeError::Transformation(error) => write!(f, "could not transform output: {}", error),
+			CodeError::Delegated(message) => write!(f, "host could not evaluate code: {}", message),
+			#[cfg(feature = "expr")]
+			CodeError::Expression(error) => write!(f, "invalid expression: {}", error)
+		}
+	}
+}
+
+impl std::error::Error for CodeError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			CodeError::Spawn(error) => Some(error),
+			CodeError::Failed(_, _) | CodeError::TimedOut(_) | CodeError::Delegated(_) => None,
+			CodeError::Transformation(error) => Some(error),
+			#[cfg(feature = "expr")]
+			CodeError::Expression(error) => Some(error)
+		}
+	}
+}
+
+impl From<io::Error> for CodeError {
+	fn from(error: io::Error) -> Self {
+		CodeError::Spawn(error)
+	}
+}
+
+impl From<TransformError> for CodeError {
+	fn from(error: TransformError) -> Self {
+		CodeError::Transformation(error)
+	}
+}
+
+/// How code is run.
+#[derive(Debug, Clone)]
+pub struct CodeRunner {
+	/// Interpreter of code without a shebang, as a shebang (`#!/bin/sh`).
+	pub shell: String,
+	/// Environment variables set for the interpreter, on top of those of this program.
+	pub environment: Vec<(String, String)>,
+	/// Directory the interpreter runs in. That of this program when None.
+	pub directory: Option<PathBuf>,
+	/// Code run ahead of the code with the same interpreter, such as helper functions it calls.
+	pub globals: Vec<GlobalCode>,
+	/// Interpreters to run code with in place of those of their shebang, as pairs of shebangs. The first matching pair is used.
+	pub interpreters: Vec<(String, String)>,
+	/// Whether the [`crate::library::CodeSettings`] of the files snippets are loaded from may change how their code is run.
+	pub source_settings: bool,
+	/// How long code may run before it is killed. Unlimited when None.
+	pub timeout: Option<Duration>
+}
+
+/// Shebang of the python code of UltiSnips `` `!p` `` interpolation.
+pub const PYTHON_SHEBANG: &str = "#!/usr/bin/env python3";
+
+/// Run ahead of `!p` code and its globals, giving it a `snip` object and keeping what it prints from its output.
+const SNIP_PRELUDE: &str = "import io as _snip_io, sys as _snip_sys\n\
+	class _Snip:\n\
+	\trv = ''\n\
+	snip = _Snip()\n\
+	_snip_stdout, _snip_sys.stdout = _snip_sys.stdout, _snip_io.StringIO()\n";
+
+/// Run after `!p` code, writing `snip.rv` as its output.
+const SNIP_EPILOGUE: &str = "\n_snip_stdout.write(str(snip.rv))\n";
+
+impl Default for CodeRunner {
+	fn default() -> Self {
+		CodeRunner {
+			shell: String::from("#!/bin/sh"),
+			environment: Vec::new(),
+			directory: None,
+			globals: Vec::new(),
+			interpreters: Vec::new(),
+			source_settings: true,
+			timeout: Some(Duration::from_secs(10))
+		}
+	}
+}
+
+impl CodeRunner {
+	/// Shebang of the interpreter of the code: its own, or the shell when it has none.
+	/// Not yet replaced by the interpreters of the runner.
+	pub fn shebang<'a>(&'a self, code: &'a Code) -> &'a str {
+		if code.shebang.trim().is_empty() { &self.shell } else { &code.shebang }
+	}
+
+	/// Runs the code, keeping what it wrote to standard output (without trailing line breaks) as its output.
+	pub fn run<'a>(&self, code: &'a mut Code) -> Result<&'a str, CodeError> {
+		let shebang = self.shebang(code);
+		let interpreter = self.interpreters.iter()
+			.find(|(from, _)| from.trim() == shebang.trim())
+			.map_or(shebang, |(_, to)| to);
+		let mut words = interpreter.trim_start_matches("#!").split_whitespace();
+		let Some(program) = words.next() else {
+			return Err(CodeError::Spawn(io::Error::new(io::ErrorKind::InvalidInput, "no interpreter")))
+		};
+		let mut command = Command::new(program);
+		command.args(words)
+			.envs(self.environment.iter().map(|(name, value)| (name, value)))
+			.stdin(Stdio::piped())
+			.stdout(Stdio::piped())
+			.stderr(Stdio::piped());
+		if let Some(directory) = &self.directory {
+			command.current_dir(directory);
+		}
+		// The interpreter may be replaced, such as by the python of a virtual environment, but not by one other than python.
+		let snip = code.shebang.trim() == PYTHON_SHEBANG
+			&& interpreter.trim_start_matches("#!").split_whitespace().take(2).any(|word| word.rsplit('/').next().is_some_and(|name| name.starts_with("python")));
+		let mut input = String::new();
+		if snip {
+			input.push_str(SNIP_PRELUDE);
+		}
+		for global in self.globals.iter().filter(|global| global.shebang.trim() == shebang.trim()) {
+			input.push_str(&global.code);
+			input.push('\n');
+		}
+		input.push_str(&code.code);
+		if snip {
+			input.push_str(SNIP_EPILOGUE);
+		}
+		let mut child = command.spawn()?;
+		let output = self.communicate(&mut child, input);
+		if output.is_err() {
+			let _ = child.kill();
+			let _ = child.wait();
+		}
+		let (status, stdout, stderr) = output?;
+		if !status.success() {
+			return Err(CodeError::Failed(status.code(), String::from_utf8_lossy(&stderr).into_owned()))
+		}
+		let stdout = String::from_utf8_lossy(&stdout);
+		code.output = stdout.trim_end_matches(['\n', '\r']).to_string();
+		Ok(&code.output)
+	}
+
+	/// Writes the input to the child and reads its standard output and error, each on its own thread
+	/// so the child never waits on a full pipe, until it exits or runs out of time.
+	fn communicate(&self, child: &mut Child, input: String) -> Result<(std::process::ExitStatus, Vec<u8>, Vec<u8>), CodeError> {
+		let stdin = child.stdin.take();
+		let writer = thread::spawn(move || match stdin {
+			// Code exiting before reading all of its input is reported by its exit status instead.
+			Some(mut stdin) => match stdin.write_all(input.as_bytes()) {
+				Err(error) if error.kind() != io::ErrorKind::BrokenPipe => Err(error),
+				_ => Ok(())
+			},
+			None => Ok(())
+		});
+		let stdout = child.stdout.take().map(read_all);
+		let stderr = child.stderr.take().map(read_all);
+		let started = Instant::now();
+		let status = loop {
+			if let Some(status) = child.try_wait()? {
+				break status
+			}
+			if let Some(timeout) = self.timeout {
+				if started.elapsed() >= timeout {
+					return Err(CodeError::TimedOut(timeout))
+				}
+			}
+			thread::sleep(Duration::from_millis(5));
+		};
+		let joined = |reader: Option<thread::JoinHandle<io::Result<Vec<u8>>>>| reader.map_or(Ok(Vec::new()), |reader| reader.join().unwrap_or_else(|_| Ok(Vec::new())));
+		writer.join().unwrap_or(Ok(()))?;
+		Ok((status, joined(stdout)?, joined(stderr)?))
+	}
+}
+
+/// Reads everything from the pipe on a thread of its own.
+fn read_all(mut pipe: impl Read + Send + 'static) -> thread::JoinHandle<io::Result<Vec<u8>>> {
+	thread::spawn(move || {
+		let mut bytes = Vec::new();
+		pipe.read_to_end(&mut bytes)?;
+		Ok(bytes)
+	})
+}
+
+impl SnippetLibrary {
+	/// The runner with the shared code available to the definition added to its globals, see [`SnippetLibrary::globals_for`].
+	/// When the runner permits, the settings applying to the definition are applied in the order they were added,
+	/// taking precedence over those of the runner.
+	pub fn code_runner(&self, definition: &SnippetDefinition, runner: &CodeRunner) -> CodeRunner {
+		let mut runner = runner.clone();
+		runner.globals.extend(self.globals_for(definition).cloned());
+		if runner.source_settings {
+			for settings in self.code_settings_for(definition) {
+				if let Some(shell) = &settings.shell {
+					runner.shell = shell.clone();
+				}
+				// Later settings take precedence, so their interpreters are found first.
+				runner.interpreters.splice(0..0, settings.interpreters.iter().cloned());
+				runner.environment.extend(settings.environment.iter().cloned());
+			}
+		}
+		runner
+	}
+}
+
+impl Code {
+	/// Runs the code with the default [`CodeRunner`], keeping what it wrote to standard output as its output.
+	pub fn execute(&mut self) -> Result<&str, CodeError> {
+		CodeRunner::default().run(self)
+	}
+}
+
+impl Snippet {
+	/// Runs every code expansion of the snippet with the default [`CodeRunner`], see [`Snippet::run_code_expansions_with`].
+	pub fn run_code_expansions(&mut self) -> Vec<CodeError> {
+		self.run_code_expansions_with(&CodeRunner::default())
+	}
+
+	/// Runs every code expansion of the snippet, re-running the transformations acting upon their output.
+	/// Everything holding the code is rebuilt around its output, as code can not change once shared.
+	/// Returns why code could not be run (its output staying as it was) or its output transformed.
+	pub fn run_code_expansions_with(&mut self, runner: &CodeRunner) -> Vec<CodeError> {
+		self.replace_code_output(|code| runner.run(code).map(drop))
+	}
+
+	/// Runs every code expansion of the snippet as [`Snippet::run_code_expansions_with`] does, except for code whose shebang
+	/// (see [`CodeRunner::shebang`]) is one of those delegated, which is given to evaluate (along with its shebang)
+	/// for the host to evaluate instead, such as an editor evaluating its own script. Evaluate gives the output of the code.
+	/// Code that is not delegated keeps its output when there is no runner.
+	pub fn run_code_expansions_delegating(&mut self, runner: Option<&CodeRunner>, delegated: &[String], evaluate: impl FnMut(&str, &Code) -> Result<String, CodeError>) -> Vec<CodeError> {
+		self.replace_code_output(delegating(runner, delegated, evaluate))
+	}
+
+	/// Gives every code expansion the output that run leaves in its copy, rebuilding everything holding it
+	/// and re-running the transformations acting upon it. Code that run fails for keeps its output.
+	pub(crate) fn replace_code_output(&mut self, mut run: impl FnMut(&mut Code) -> Result<(), CodeError>) -> Vec<CodeError> {
+		let mut replacer = Replacer::default();
+		let mut errors = Vec::new();
+		for expansion in &self.code_expansions {
+			let Some(code) = expansion.expansion.upgrade() else {
+				continue
+			};
+			if replacer.codes.iter().any(|(ptr, _)| *ptr == Rc::as_ptr(&code)) {
+				continue
+			}
+			let mut ran = Code {
+				code: code.code.clone(),
+				output: code.output.clone(),
+				shebang: code.shebang.clone()
+			};
+			match run(&mut ran) {
+				Ok(()) => errors.extend(replacer.reapply(&expansion.transformations, &ran.output).into_iter().map(CodeError::from)),
+				Err(error) => {
+					errors.push(error);
+					continue
+				}
+			}
+			replacer.codes.push((Rc::as_ptr(&code), Rc::new(ran)));
+		}
+		replacer.snippet(self);
+		errors
+	}
+}
+
+/// Runs code as [`Snippet::run_code_expansions_delegating`] does, for [`Snippet::replace_code_output`].
+pub(crate) fn delegating<'a>(runner: Option<&'a CodeRunner>, delegated: &'a [String], mut evaluate: impl FnMut(&str, &Code) -> Result<String, CodeError> + 'a) -> impl FnMut(&mut Code) -> Result<(), CodeError> + 'a {
+	let shell = CodeRunner::default();
+	move |code| {
+		let shebang = runner.unwrap_or(&shell).shebang(code).trim();
+		if delegated.iter().any(|delegated| delegated.trim() == shebang) {
+			code.output = evaluate(shebang, code)?;
+			return Ok(())
+		}
+		match runner {
+			Some(runner) => runner.run(code).map(drop),
+			None => Ok(())
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::parse::SnippetSyntax;
+
+	#[test]
+	fn execute_code() {
+		let mut code = Code { code: String::from("echo \"$GREETING\"; echo"), output: String::new(), shebang: String::new() };
+		let runner = CodeRunner { environment: vec![(String::from("GREETING"), String::from("hi"))], ..CodeRunner::default() };
+		assert_eq!(runner.run(&mut code).unwrap(), "hi");
+		let mut failing = Code { code: String::from("echo oops >&2; exit 3"), output: String::new(), shebang: String::from("#!/bin/sh") };
+		assert!(matches!(failing.execute(), Err(CodeError::Failed(Some(3), stderr)) if stderr == "oops\n"));
+		assert_eq!(failing.output, "");
+	}
+
+	#[test]
+	fn run_without_blocking() {
+		let large = "x".repeat(1 << 20);
+		let mut writing = Code { code: format!("head -c 200000 /dev/zero\n# {}", large), output: String::new(), shebang: String::new() };
+		assert!(CodeRunner::default().run(&mut writing).is_ok());
+		let mut sleeping = Code { code: String::from("sleep 5"), output: String::new(), shebang: String::new() };
+		let runner = CodeRunner { timeout: Some(Duration::from_millis(50)), ..CodeRunner::default() };
+		assert!(matches!(runner.run(&mut sleeping), Err(CodeError::TimedOut(_))));
+		let mut python = Code { code: String::from("print('ignored')\nsnip.rv = greet()"), output: String::new(), shebang: String::from(PYTHON_SHEBANG) };
+		let runner = CodeRunner {
+			globals: vec![GlobalCode { code: String::from("def greet():\n    return 'hi'"), shebang: String::from(PYTHON_SHEBANG), source: None }],
+			..CodeRunner::default()
+		};
+		assert_eq!(runner.run(&mut python).unwrap(), "hi");
+	}
+
+	#[test]
+	fn apply_code_settings() {
+		let mut library = SnippetLibrary::new();
+		let mut definition = SnippetDefinition::new(vec![String::from("v")], None, Snippet::parse("x").unwrap());
+		definition.source = Some(crate::library::SourceLocation { path: Some(PathBuf::from("python.snippets")), start_line: 1, end_line: 1 });
+		library.add(definition);
+		library.add_code_settings(crate::library::CodeSettings {
+			path: Some(PathBuf::from("python.snippets")),
+			interpreters: vec![(String::from("#!/usr/bin/env python3"), String::from("#!/bin/sh"))],
+			environment: vec![(String::from("VIRTUAL_ENV"), String::from("venv"))],
+			..Default::default()
+		});
+		library.add_code_settings(crate::library::CodeSettings { path: Some(PathBuf::from("other.snippets")), shell: Some(String::from("#!/bin/false")), ..Default::default() });
+		let mut code = Code { code: String::from("echo $VIRTUAL_ENV"), output: String::new(), shebang: String::from("#!/usr/bin/env python3") };
+		let runner = library.code_runner(&library.definitions()[0], &CodeRunner::default());
+		assert_eq!(runner.shell, "#!/bin/sh");
+		assert_eq!(runner.run(&mut code).unwrap(), "venv");
+		let locked = library.code_runner(&library.definitions()[0], &CodeRunner { source_settings: false, ..CodeRunner::default() });
+		assert!(locked.interpreters.is_empty() && locked.environment.is_empty());
+	}
+
+	#[test]
+	fn run_snippet_code() {
+		let mut snippet = Snippet::parse_with(SnippetSyntax::UltiSnips, "${1:`echo a`} `echo b` `exit 1`").unwrap();
+		let errors = snippet.run_code_expansions();
+		assert_eq!(errors.len(), 1);
+		assert_eq!(snippet.to_string(), "a b ");
+		assert!(snippet.code_expansions().iter().all(|code| code.expansion.upgrade().is_some()));
+	}
+
+	#[test]
+	fn delegate_code() {
+		let mut snippet = Snippet::parse_with(SnippetSyntax::UltiSnips, "`!p snip.rv = 1` `echo b` `echo c`").unwrap();
+		let delegated = [String::from(PYTHON_SHEBANG)];
+		let errors = snippet.run_code_expansions_delegating(Some(&CodeRunner::default()), &delegated, |shebang, code| match code.code.as_str() {
+			"snip.rv = 1" if shebang == PYTHON_SHEBANG => Ok(String::from("one")),
+			_ => Err(CodeError::Delegated(String::from("unexpected code")))
+		});
+		assert!(errors.is_empty());
+		assert_eq!(snippet.to_string(), "one b c");
+		let mut snippet = Snippet::parse_with(SnippetSyntax::UltiSnips, "`echo b`").unwrap();
+		assert!(snippet.run_code_expansions_delegating(None, &[], |_, _| unreachable!()).is_empty());
+		assert_eq!(snippet.to_string(), "");
+	}
+}
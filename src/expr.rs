@@ -0,0 +1,398 @@
+//! A small expression language for code blocks, evaluated within this program for hosts that do not run other programs.
+//!
+//! Expressions are made of numbers, `'single'` or `"double"` quoted strings, `true` and `false`, variables by name,
+//! fields by tab number (`$1`), `+ - * / %`, comparisons (`== != < <= > >=`), `&& || !`, `condition ? then : otherwise`
+//! and the functions `upper`, `lower`, `trim`, `len`, `round`, `floor` and `ceil`.
+//! `+` adds when both sides are numbers (including text holding a number) and concatenates otherwise,
+//! and comparisons compare numbers when both sides are numbers and text otherwise.
+
+use std::fmt;
+use crate::{Snippet, Code};
+use crate::exec::CodeError;
+use crate::parse::MAX_NESTING;
+
+/// Why an expression could not be evaluated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExprError {
+	/// Byte offset within the expression.
+	pub offset: usize,
+	pub message: &'static str
+}
+
+impl fmt::Display for ExprError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "offset {}: {}", self.offset, self.message)
+	}
+}
+
+impl std::error::Error for ExprError {}
+
+/// Result of an expression.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+	Number(f64),
+	Text(String),
+	Bool(bool)
+}
+
+impl Value {
+	/// The number the value is or holds.
+	fn number(&self) -> Option<f64> {
+		match self {
+			Value::Number(number) => Some(*number),
+			Value::Text(text) => text.trim().parse().ok(),
+			Value::Bool(_) => None
+		}
+	}
+
+	/// Whether the value counts as true: true, numbers other than 0 and text that is not empty.
+	pub fn is_true(&self) -> bool {
+		match self {
+			Value::Number(number) => *number != 0.0,
+			Value::Text(text) => !text.is_empty(),
+			Value::Bool(value) => *value
+		}
+	}
+}
+
+impl fmt::Display for Value {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Value::Number(number) => write!(f, "{}", number),
+			Value::Text(text) => write!(f, "{}", text),
+			Value::Bool(value) => write!(f, "{}", value)
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operator {
+	Add,
+	Subtract,
+	Multiply,
+	Divide,
+	Remainder,
+	Equal,
+	NotEqual,
+	Less,
+	LessEqual,
+	Greater,
+	GreaterEqual,
+	And,
+	Or
+}
+
+#[derive(Debug)]
+enum Expr {
+	Value(Value),
+	/// Variable or field (`$1`) by name.
+	Name(String),
+	Not(Box<Expr>),
+	Negate(usize, Box<Expr>),
+	/// Offset of the operator, its operator and operands.
+	Binary(usize, Operator, Box<Expr>, Box<Expr>),
+	Conditional(Box<Expr>, Box<Expr>, Box<Expr>),
+	/// Offset of the name, the function's name and arguments.
+	Call(usize, String, Vec<Expr>)
+}
+
+struct Parser<'a> {
+	text: &'a str,
+	pos: usize,
+	/// Depth of the expression being read, counting each operand of chained operators as a level.
+	depth: usize
+}
+
+impl Parser<'_> {
+	fn error(&self, message: &'static str) -> ExprError {
+		ExprError { offset: self.pos, message }
+	}
+
+	fn skip_space(&mut self) {
+		let rest = &self.text[self.pos..];
+		self.pos += rest.len() - rest.trim_start().len();
+	}
+
+	/// Consumes the token when it comes next (after any space).
+	fn eat(&mut self, token: &str) -> bool {
+		self.skip_space();
+		if self.text[self.pos..].starts_with(token) {
+			self.pos += token.len();
+			return true
+		}
+		false
+	}
+
+	fn expect(&mut self, token: &str, message: &'static str) -> Result<(), ExprError> {
+		if self.eat(token) {
+			return Ok(())
+		}
+		Err(self.error(message))
+	}
+
+	/// Counts a level of the expression, which fails past [`MAX_NESTING`] levels as evaluating recurses through them.
+	fn nest(&mut self) -> Result<(), ExprError> {
+		if self.depth == MAX_NESTING {
+			return Err(self.error("expression is nested too deeply"))
+		}
+		self.depth += 1;
+		Ok(())
+	}
+
+	fn conditional(&mut self) -> Result<Expr, ExprError> {
+		self.nest()?;
+		let expr = self.conditional_within();
+		self.depth -= 1;
+		expr
+	}
+
+	fn conditional_within(&mut self) -> Result<Expr, ExprError> {
+		let condition = self.binary(0)?;
+		if !self.eat("?") {
+			return Ok(condition)
+		}
+		let then = self.conditional()?;
+		self.expect(":", "expected : of ?")?;
+		let otherwise = self.conditional()?;
+		Ok(Expr::Conditional(Box::new(condition), Box::new(then), Box::new(otherwise)))
+	}
+
+	/// Operators by precedence, those of a level binding tighter than those of the levels before it.
+	const LEVELS: [&'static [(&'static str, Operator)]; 5] = [
+		&[("||", Operator::Or)],
+		&[("&&", Operator::And)],
+		&[("==", Operator::Equal), ("!=", Operator::NotEqual)],
+		&[("<=", Operator::LessEqual), (">=", Operator::GreaterEqual), ("<", Operator::Less), (">", Operator::Greater)],
+		&[("+", Operator::Add), ("-", Operator::Subtract)]
+	];
+
+	fn binary(&mut self, level: usize) -> Result<Expr, ExprError> {
+		if level == Self::LEVELS.len() {
+			return self.product()
+		}
+		let depth = self.depth;
+		let mut left = self.binary(level + 1)?;
+		'operands: loop {
+			for &(token, operator) in Self::LEVELS[level] {
+				self.skip_space();
+				let offset = self.pos;
+				if self.eat(token) {
+					self.nest()?;
+					let right = self.binary(level + 1)?;
+					left = Expr::Binary(offset, operator, Box::new(left), Box::new(right));
+					continue 'operands
+				}
+			}
+			self.depth = depth;
+			return Ok(left)
+		}
+	}
+
+	fn product(&mut self) -> Result<Expr, ExprError> {
+		let depth = self.depth;
+		let mut left = self.unary()?;
+		loop {
+			self.skip_space();
+			let offset = self.pos;
+			let operator = match self.text[self.pos..].chars().next() {
+				Some('*') => Operator::Multiply,
+				Some('/') => Operator::Divide,
+				Some('%') => Operator::Remainder,
+				_ => {
+					self.depth = depth;
+					return Ok(left)
+				}
+			};
+			self.pos += 1;
+			self.nest()?;
+			let right = self.unary()?;
+			left = Expr::Binary(offset, operator, Box::new(left), Box::new(right));
+		}
+	}
+
+	fn unary(&mut self) -> Result<Expr, ExprError> {
+		self.skip_space();
+		let offset = self.pos;
+		if self.eat("!") {
+			self.nest()?;
+			let operand = self.unary()?;
+			self.depth -= 1;
+			return Ok(Expr::Not(Box::new(operand)))
+		}
+		if self.eat("-") {
+			self.nest()?;
+			let operand = self.unary()?;
+			self.depth -= 1;
+			return Ok(Expr::Negate(offset, Box::new(operand)))
+		}
+		self.primary()
+	}
+
+	fn primary(&mut self) -> Result<Expr, ExprError> {
+		self.skip_space();
+		let start = self.pos;
+		let rest = &self.text[self.pos..];
+		let Some(first) = rest.chars().next() else {
+			return Err(self.error("expected a value"))
+		};
+		if self.eat("(") {
+			let inner = self.conditional()?;
+			self.expect(")", "expected )")?;
+			return Ok(inner)
+		}
+		if first == '\'' || first == '"' {
+			let Some(len) = rest[1..].find(first) else {
+				return Err(self.error("string is not closed"))
+			};
+			self.pos += len + 2;
+			return Ok(Expr::Value(Value::Text(rest[1..len + 1].to_string())))
+		}
+		let len = rest.find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '.' || c == '$')).unwrap_or(rest.len());
+		let word = &rest[..len];
+		if word.is_empty() {
+			return Err(self.error("expected a value"))
+		}
+		self.pos += len;
+		if first.is_ascii_digit() {
+			return word.parse().map(|number| Expr::Value(Value::Number(number))).map_err(|_| ExprError { offset: start, message: "invalid number" })
+		}
+		match word {
+			"true" => return Ok(Expr::Value(Value::Bool(true))),
+			"false" => return Ok(Expr::Value(Value::Bool(false))),
+			_ => {}
+		}
+		if !self.eat("(") {
+			return Ok(Expr::Name(word.to_string()))
+		}
+		let mut arguments = Vec::new();
+		if !self.eat(")") {
+			loop {
+				arguments.push(self.conditional()?);
+				if self.eat(")") {
+					break
+				}
+				self.expect(",", "expected , or )")?;
+			}
+		}
+		Ok(Expr::Call(start, word.to_string(), arguments))
+	}
+}
+
+fn evaluate_expr(expr: &Expr, lookup: &dyn Fn(&str) -> Option<String>) -> Result<Value, ExprError> {
+	Ok(match expr {
+		Expr::Value(value) => value.clone(),
+		Expr::Name(name) => Value::Text(lookup(name).unwrap_or_default()),
+		Expr::Not(operand) => Value::Bool(!evaluate_expr(operand, lookup)?.is_true()),
+		Expr::Negate(offset, operand) => match evaluate_expr(operand, lookup)?.number() {
+			Some(number) => Value::Number(-number),
+			None => return Err(ExprError { offset: *offset, message: "expected a number after -" })
+		},
+		Expr::Conditional(condition, then, otherwise) => if evaluate_expr(condition, lookup)?.is_true() {
+			evaluate_expr(then, lookup)?
+		} else {
+			evaluate_expr(otherwise, lookup)?
+		},
+		Expr::Binary(_, Operator::And, left, right) => Value::Bool(evaluate_expr(left, lookup)?.is_true() && evaluate_expr(right, lookup)?.is_true()),
+		Expr::Binary(_, Operator::Or, left, right) => Value::Bool(evaluate_expr(left, lookup)?.is_true() || evaluate_expr(right, lookup)?.is_true()),
+		Expr::Binary(offset, operator, left, right) => {
+			let left = evaluate_expr(left, lookup)?;
+			let right = evaluate_expr(right, lookup)?;
+			let numbers = left.number().zip(right.number());
+			let error = || ExprError { offset: *offset, message: "expected numbers" };
+			match (operator, numbers) {
+				(Operator::Add, Some((a, b))) => Value::Number(a + b),
+				(Operator::Add, None) => Value::Text(format!("{}{}", left, right)),
+				(Operator::Subtract, _) => numbers.map(|(a, b)| Value::Number(a - b)).ok_or_else(error)?,
+				(Operator::Multiply, _) => numbers.map(|(a, b)| Value::Number(a * b)).ok_or_else(error)?,
+				(Operator::Divide | Operator::Remainder, Some((_, 0.0))) => return Err(ExprError { offset: *offset, message: "division by zero" }),
+				(Operator::Divide, _) => numbers.map(|(a, b)| Value::Number(a / b)).ok_or_else(error)?,
+				(Operator::Remainder, _) => numbers.map(|(a, b)| Value::Number(a % b)).ok_or_else(error)?,
+				(_, _) => {
+					let ordering = match numbers {
+						Some((a, b)) => a.partial_cmp(&b),
+						None => Some(left.to_string().cmp(&right.to_string()))
+					};
+					Value::Bool(match operator {
+						Operator::Equal => ordering == Some(std::cmp::Ordering::Equal),
+						Operator::NotEqual => ordering != Some(std::cmp::Ordering::Equal),
+						Operator::Less => ordering == Some(std::cmp::Ordering::Less),
+						Operator::LessEqual => matches!(ordering, Some(std::cmp::Ordering::Less | std::cmp::Ordering::Equal)),
+						Operator::Greater => ordering == Some(std::cmp::Ordering::Greater),
+						_ => matches!(ordering, Some(std::cmp::Ordering::Greater | std::cmp::Ordering::Equal))
+					})
+				}
+			}
+		},
+		Expr::Call(offset, name, arguments) => {
+			let [argument] = &arguments[..] else {
+				return Err(ExprError { offset: *offset, message: "functions take one argument" })
+			};
+			let value = evaluate_expr(argument, lookup)?;
+			let number = || value.number().ok_or(ExprError { offset: *offset, message: "expected a number" });
+			match name.as_str() {
+				"upper" => Value::Text(value.to_string().to_uppercase()),
+				"lower" => Value::Text(value.to_string().to_lowercase()),
+				"trim" => Value::Text(value.to_string().trim().to_string()),
+				"len" => Value::Number(value.to_string().chars().count() as f64),
+				"round" => Value::Number(number()?.round()),
+				"floor" => Value::Number(number()?.floor()),
+				"ceil" => Value::Number(number()?.ceil()),
+				_ => return Err(ExprError { offset: *offset, message: "unknown function" })
+			}
+		}
+	})
+}
+
+/// Evaluates the expression, looking up the text of the names (variables and fields) it uses. Unknown names are empty text.
+pub fn evaluate(expression: &str, lookup: &dyn Fn(&str) -> Option<String>) -> Result<Value, ExprError> {
+	let mut parser = Parser { text: expression, pos: 0, depth: 0 };
+	let expr = parser.conditional()?;
+	parser.skip_space();
+	if parser.pos < expression.len() {
+		return Err(parser.error("expected the end of the expression"))
+	}
+	evaluate_expr(&expr, lookup)
+}
+
+impl Snippet {
+	/// Gives every code expansion the result of evaluating its code as an expression (whatever its interpreter),
+	/// as [`Snippet::run_code_expansions`] does by running it. Names are the snippet's variables and `$1` the text of tab 1's field.
+	pub fn evaluate_code_expansions(&mut self) -> Vec<CodeError> {
+		let mut names: Vec<(String, String)> = self.variables.iter()
+			.filter_map(|variable| variable.expansion.upgrade())
+			.map(|variable| (variable.name.clone(), variable.value.clone()))
+			.collect();
+		names.extend(self.tabs.iter().filter_map(|tab| Some((format!("${}", tab.num), tab.field.upgrade()?.to_string()))));
+		let lookup = move |name: &str| names.iter().find(|(known, _)| known == name).map(|(_, value)| value.clone());
+		self.replace_code_output(|code: &mut Code| {
+			code.output = evaluate(&code.code, &lookup).map_err(CodeError::Expression)?.to_string();
+			Ok(())
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn evaluate_expressions() {
+		let lookup = |name: &str| (name == "n").then(|| String::from("4"));
+		let value = |expression: &str| evaluate(expression, &lookup).map(|value| value.to_string());
+		assert_eq!(value("1 + 2 * 3 - -1").unwrap(), "8");
+		assert_eq!(value("n % 3 == 1 && !false ? 'one' : 'other'").unwrap(), "one");
+		assert_eq!(value("upper('a' + n) + len(\"héllo\")").unwrap(), "A45");
+		assert_eq!(value("(n + 1) / 2 >= 2.5").unwrap(), "true");
+		assert_eq!(value("n / 0"), Err(ExprError { offset: 2, message: "division by zero" }));
+		assert_eq!(value("n +"), Err(ExprError { offset: 3, message: "expected a value" }));
+		assert_eq!(value("shout(n)"), Err(ExprError { offset: 0, message: "unknown function" }));
+	}
+
+	#[test]
+	fn evaluate_snippet_code() {
+		let mut snippet = crate::Snippet::parse_with(crate::parse::SnippetSyntax::UltiSnips, "${1:3} x 2 = `$1 * 2` `(`").unwrap();
+		let errors = snippet.evaluate_code_expansions();
+		assert_eq!(errors.len(), 1);
+		assert_eq!(snippet.to_string(), "3 x 2 = 6 ");
+	}
+}
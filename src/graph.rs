@@ -0,0 +1,546 @@
+//! Serializing snippets with serde, such as for a daemon to persist its sessions between runs.
+//!
+//! Parts of a snippet are shared by mirrors and named segments, and tabs and expansions refer to them weakly, so they are
+//! written as a graph: every shared part is a node listed once, after the nodes it holds or refers to, and segments
+//! and references name nodes by their index. References to parts that are not written before them (or no longer exist)
+//! are written as null, and read back as references to nothing.
+//!
+//! Snippets are written in full, as [`Snippet::write_state`] writes them. Segments, fields, variables, transformations and code
+//! can be written on their own too, but not tabs, which only refer to the fields of their snippet.
+
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use serde::de::Error;
+use crate::shared::{Rc, Weak};
+use crate::{Snippet, Segment, Field, Transformation, Variable, VariableSource, Code, Conditional, Condition, Tab, Expansion, NamedSegment, NumberField, RepeatField};
+use crate::regex::Regex;
+
+/// Nodes of the shared parts, then what refers to them.
+#[derive(Serialize, Deserialize)]
+struct Graph<R> {
+	nodes: Vec<Node>,
+	root: R
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Node {
+	Field(FieldNode),
+	Transformation(TransformationNode),
+	Variable(VariableNode),
+	Code(CodeNode),
+	Conditional(ConditionalNode),
+	Snippet(SnippetNode)
+}
+
+/// A segment, naming the node of the part it shows unless it is text.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum SegmentNode {
+	Text(String),
+	Field(usize),
+	Transformation(usize),
+	Variable(usize),
+	Code(usize),
+	Conditional(usize),
+	Snippet(usize)
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum FieldNode {
+	Placeholder(Vec<SegmentNode>),
+	Choice { choice: usize, choices: Vec<Vec<SegmentNode>>, labels: Vec<String> },
+	Number { value: i64, min: Option<i64>, max: Option<i64>, step: i64 },
+	Toggle { on: bool, when_on: Vec<SegmentNode>, when_off: Vec<SegmentNode> },
+	Repeat { template: usize, separator: String, repetitions: Vec<Vec<SegmentNode>> }
+}
+
+#[derive(Serialize, Deserialize)]
+struct TransformationNode {
+	section: String,
+	format: String,
+	flags: String,
+	result: String
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum SourceNode {
+	Daemon,
+	Client
+}
+
+#[derive(Serialize, Deserialize)]
+struct VariableNode {
+	name: String,
+	value: String,
+	source: SourceNode,
+	default: Option<Vec<SegmentNode>>
+}
+
+#[derive(Serialize, Deserialize)]
+struct CodeNode {
+	code: String,
+	output: String,
+	shebang: String
+}
+
+#[derive(Serialize, Deserialize)]
+struct ConditionalNode {
+	field: Option<usize>,
+	condition: ConditionNode,
+	then: Vec<SegmentNode>,
+	otherwise: Vec<SegmentNode>
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ConditionNode {
+	Empty,
+	NonEmpty,
+	Matches { pattern: String, flags: String }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SnippetNode {
+	body: Vec<SegmentNode>,
+	tabs: Vec<TabNode>,
+	variables: Vec<ExpansionNode>,
+	code_expansions: Vec<ExpansionNode>,
+	named_segments: Vec<NamedNode>,
+	current_tab: Option<u8>
+}
+
+#[derive(Serialize, Deserialize)]
+struct TabNode {
+	num: u8,
+	field: Option<usize>,
+	transformations: Vec<Option<usize>>,
+	label: Option<String>
+}
+
+#[derive(Serialize, Deserialize)]
+struct ExpansionNode {
+	expansion: Option<usize>,
+	transformations: Vec<Option<usize>>
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum NamedNode {
+	Transformation { name: String, transformation: Option<usize> },
+	Code { name: String, code: Option<usize> }
+}
+
+/// Lists the shared parts as nodes, each once and after those it holds or refers to.
+#[derive(Default)]
+struct Encoder {
+	parts: Vec<*const ()>,
+	nodes: Vec<Node>
+}
+
+impl Encoder {
+	fn graph<R>(encode: impl FnOnce(&mut Encoder) -> R) -> Graph<R> {
+		let mut encoder = Encoder::default();
+		let root = encode(&mut encoder);
+		Graph { nodes: encoder.nodes, root }
+	}
+
+	fn known<T>(&self, rc: &Rc<T>) -> Option<usize> {
+		let ptr = Rc::as_ptr(rc) as *const ();
+		self.parts.iter().position(|part| *part == ptr)
+	}
+
+	/// Index of the part's node, listing the node encoded from the part when it is not yet listed.
+	fn node<T>(&mut self, rc: &Rc<T>, encode: impl FnOnce(&mut Encoder) -> Node) -> usize {
+		if let Some(id) = self.known(rc) {
+			return id
+		}
+		let node = encode(self);
+		self.parts.push(Rc::as_ptr(rc) as *const ());
+		self.nodes.push(node);
+		self.nodes.len() - 1
+	}
+
+	fn reference<T>(&self, weak: &Weak<T>) -> Option<usize> {
+		self.known(&weak.upgrade()?)
+	}
+
+	fn references<T>(&self, weaks: &[Weak<T>]) -> Vec<Option<usize>> {
+		weaks.iter().map(|weak| self.reference(weak)).collect()
+	}
+
+	fn segments(&mut self, segments: &[Segment]) -> Vec<SegmentNode> {
+		segments.iter().map(|segment| match segment {
+			Segment::Text(text) => SegmentNode::Text(text.clone()),
+			Segment::Field(field) => SegmentNode::Field(self.node(field, |encoder| Node::Field(encoder.field(field)))),
+			Segment::Transformation(transformation) => SegmentNode::Transformation(self.node(transformation, |_| Node::Transformation(transformation_node(transformation)))),
+			Segment::Variable(variable) => SegmentNode::Variable(self.node(variable, |encoder| Node::Variable(encoder.variable(variable)))),
+			Segment::Code(code) => SegmentNode::Code(self.node(code, |_| Node::Code(code_node(code)))),
+			Segment::Conditional(conditional) => SegmentNode::Conditional(self.node(conditional, |encoder| Node::Conditional(encoder.conditional(conditional)))),
+			Segment::Snippet(snippet) => SegmentNode::Snippet(self.node(snippet, |encoder| Node::Snippet(encoder.snippet(snippet))))
+		}).collect()
+	}
+
+	fn bodies(&mut self, bodies: &[Vec<Segment>]) -> Vec<Vec<SegmentNode>> {
+		bodies.iter().map(|body| self.segments(body)).collect()
+	}
+
+	fn field(&mut self, field: &Field) -> FieldNode {
+		match field {
+			Field::Placeholder(body) => FieldNode::Placeholder(self.segments(body)),
+			Field::Choice(choice, choices, labels) => FieldNode::Choice { choice: *choice, choices: self.bodies(choices), labels: labels.clone() },
+			Field::Number(number) => FieldNode::Number { value: number.value, min: number.min, max: number.max, step: number.step },
+			Field::Toggle(on, when_on, when_off) => FieldNode::Toggle { on: *on, when_on: self.segments(when_on), when_off: self.segments(when_off) },
+			Field::Repeat(repeat) => FieldNode::Repeat {
+				template: self.node(&repeat.template, |encoder| Node::Snippet(encoder.snippet(&repeat.template))),
+				separator: repeat.separator.clone(),
+				repetitions: self.bodies(&repeat.repetitions)
+			}
+		}
+	}
+
+	fn variable(&mut self, variable: &Variable) -> VariableNode {
+		VariableNode {
+			name: variable.name.clone(),
+			value: variable.value.clone(),
+			source: match variable.source {
+				VariableSource::Daemon => SourceNode::Daemon,
+				VariableSource::Client => SourceNode::Client
+			},
+			default: variable.default.as_ref().map(|default| self.segments(default))
+		}
+	}
+
+	fn conditional(&mut self, conditional: &Conditional) -> ConditionalNode {
+		// The tested field is listed ahead of the conditional when it was not listed before, so it can be referred to.
+		let field = conditional.field.upgrade().map(|field| self.node(&field, |encoder| Node::Field(encoder.field(&field))));
+		ConditionalNode {
+			field,
+			condition: match &conditional.condition {
+				Condition::Empty => ConditionNode::Empty,
+				Condition::NonEmpty => ConditionNode::NonEmpty,
+				Condition::Matches(regex) => ConditionNode::Matches { pattern: regex.as_str().to_string(), flags: regex.flags().to_string() }
+			},
+			then: self.segments(&conditional.then),
+			otherwise: self.segments(&conditional.otherwise)
+		}
+	}
+
+	fn snippet(&mut self, snippet: &Snippet) -> SnippetNode {
+		let body = self.segments(&snippet.body);
+		SnippetNode {
+			body,
+			tabs: snippet.tabs.iter().map(|tab| TabNode {
+				num: tab.num,
+				field: self.reference(&tab.field),
+				transformations: self.references(&tab.transformations),
+				label: tab.label.clone()
+			}).collect(),
+			variables: snippet.variables.iter().map(|expansion| ExpansionNode {
+				expansion: self.reference(&expansion.expansion),
+				transformations: self.references(&expansion.transformations)
+			}).collect(),
+			code_expansions: snippet.code_expansions.iter().map(|expansion| ExpansionNode {
+				expansion: self.reference(&expansion.expansion),
+				transformations: self.references(&expansion.transformations)
+			}).collect(),
+			named_segments: snippet.named_segments.iter().map(|named| match named {
+				NamedSegment::Transformation(name, transformation) => NamedNode::Transformation { name: name.clone(), transformation: self.reference(transformation) },
+				NamedSegment::Code(name, code) => NamedNode::Code { name: name.clone(), code: self.reference(code) }
+			}).collect(),
+			current_tab: snippet.current_tab
+		}
+	}
+}
+
+fn transformation_node(transformation: &Transformation) -> TransformationNode {
+	TransformationNode {
+		section: transformation.section.clone(),
+		format: transformation.format.clone(),
+		flags: transformation.flags.clone(),
+		result: transformation.result.clone()
+	}
+}
+
+fn code_node(code: &Code) -> CodeNode {
+	CodeNode { code: code.code.clone(), output: code.output.clone(), shebang: code.shebang.clone() }
+}
+
+/// A part read back from its node.
+enum Part {
+	Field(Rc<Field>),
+	Transformation(Rc<Transformation>),
+	Variable(Rc<Variable>),
+	Code(Rc<Code>),
+	Conditional(Rc<Conditional>),
+	Snippet(Rc<Snippet>)
+}
+
+/// Builds the parts of the nodes in order, nodes only naming those before them.
+#[derive(Default)]
+struct Decoder {
+	parts: Vec<Part>
+}
+
+const MISSING: &str = "reference to a missing node";
+
+/// The part of the kind read back from the node with the index.
+macro_rules! part {
+	($decoder:expr, $id:expr, $kind:ident) => {
+		match $decoder.parts.get($id) {
+			Some(Part::$kind(rc)) => rc.clone(),
+			_ => return Err(MISSING)
+		}
+	};
+}
+
+/// A weak reference to the part of the kind read back from the node with the index, or to nothing without an index.
+macro_rules! reference {
+	($decoder:expr, $id:expr, $kind:ident) => {
+		match $id {
+			Some(id) => Rc::downgrade(&part!($decoder, id, $kind)),
+			None => Weak::new()
+		}
+	};
+}
+
+impl Decoder {
+	fn read<R, T>(graph: Graph<R>, decode: impl FnOnce(&Decoder, R) -> Result<T, &'static str>) -> Result<T, &'static str> {
+		let mut decoder = Decoder::default();
+		for node in graph.nodes {
+			let part = decoder.part(node)?;
+			decoder.parts.push(part);
+		}
+		decode(&decoder, graph.root)
+	}
+
+	fn part(&self, node: Node) -> Result<Part, &'static str> {
+		Ok(match node {
+			Node::Field(field) => Part::Field(Rc::new(self.field(field)?)),
+			Node::Transformation(transformation) => Part::Transformation(Rc::new(transformation.into())),
+			Node::Variable(variable) => Part::Variable(Rc::new(self.variable(variable)?)),
+			Node::Code(code) => Part::Code(Rc::new(code.into())),
+			Node::Conditional(conditional) => Part::Conditional(Rc::new(self.conditional(conditional)?)),
+			Node::Snippet(snippet) => Part::Snippet(Rc::new(self.snippet(snippet)?))
+		})
+	}
+
+	fn segments(&self, segments: Vec<SegmentNode>) -> Result<Vec<Segment>, &'static str> {
+		segments.into_iter().map(|segment| self.segment(segment)).collect()
+	}
+
+	fn segment(&self, segment: SegmentNode) -> Result<Segment, &'static str> {
+		Ok(match segment {
+			SegmentNode::Text(text) => Segment::Text(text),
+			SegmentNode::Field(id) => Segment::Field(part!(self, id, Field)),
+			SegmentNode::Transformation(id) => Segment::Transformation(part!(self, id, Transformation)),
+			SegmentNode::Variable(id) => Segment::Variable(part!(self, id, Variable)),
+			SegmentNode::Code(id) => Segment::Code(part!(self, id, Code)),
+			SegmentNode::Conditional(id) => Segment::Conditional(part!(self, id, Conditional)),
+			SegmentNode::Snippet(id) => Segment::Snippet(part!(self, id, Snippet))
+		})
+	}
+
+	fn bodies(&self, bodies: Vec<Vec<SegmentNode>>) -> Result<Vec<Vec<Segment>>, &'static str> {
+		bodies.into_iter().map(|body| self.segments(body)).collect()
+	}
+
+	fn transformations(&self, ids: Vec<Option<usize>>) -> Result<Vec<Weak<Transformation>>, &'static str> {
+		ids.into_iter().map(|id| Ok(reference!(self, id, Transformation))).collect()
+	}
+
+	fn field(&self, field: FieldNode) -> Result<Field, &'static str> {
+		Ok(match field {
+			FieldNode::Placeholder(body) => Field::Placeholder(self.segments(body)?),
+			FieldNode::Choice { choice, choices, labels } => Field::Choice(choice, self.bodies(choices)?, labels),
+			FieldNode::Number { value, min, max, step } => Field::Number(NumberField { value, min, max, step }),
+			FieldNode::Toggle { on, when_on, when_off } => Field::Toggle(on, self.segments(when_on)?, self.segments(when_off)?),
+			FieldNode::Repeat { template, separator, repetitions } => Field::Repeat(RepeatField {
+				template: part!(self, template, Snippet),
+				separator,
+				repetitions: self.bodies(repetitions)?
+			})
+		})
+	}
+
+	fn variable(&self, variable: VariableNode) -> Result<Variable, &'static str> {
+		Ok(Variable {
+			name: variable.name,
+			value: variable.value,
+			source: match variable.source {
+				SourceNode::Daemon => VariableSource::Daemon,
+				SourceNode::Client => VariableSource::Client
+			},
+			default: variable.default.map(|default| self.segments(default)).transpose()?
+		})
+	}
+
+	fn conditional(&self, conditional: ConditionalNode) -> Result<Conditional, &'static str> {
+		Ok(Conditional {
+			field: reference!(self, conditional.field, Field),
+			condition: match conditional.condition {
+				ConditionNode::Empty => Condition::Empty,
+				ConditionNode::NonEmpty => Condition::NonEmpty,
+				ConditionNode::Matches { pattern, flags } => Condition::Matches(Regex::with_flags(&pattern, &flags).map_err(|_| "invalid pattern")?)
+			},
+			then: self.segments(conditional.then)?,
+			otherwise: self.segments(conditional.otherwise)?
+		})
+	}
+
+	fn snippet(&self, snippet: SnippetNode) -> Result<Snippet, &'static str> {
+		let mut tabs = Vec::new();
+		for tab in snippet.tabs {
+			tabs.push(Tab {
+				num: tab.num,
+				field: reference!(self, tab.field, Field),
+				transformations: self.transformations(tab.transformations)?,
+				label: tab.label
+			});
+		}
+		let mut variables = Vec::new();
+		for expansion in snippet.variables {
+			variables.push(Expansion { expansion: reference!(self, expansion.expansion, Variable), transformations: self.transformations(expansion.transformations)? });
+		}
+		let mut code_expansions = Vec::new();
+		for expansion in snippet.code_expansions {
+			code_expansions.push(Expansion { expansion: reference!(self, expansion.expansion, Code), transformations: self.transformations(expansion.transformations)? });
+		}
+		let mut named_segments = Vec::new();
+		for named in snippet.named_segments {
+			named_segments.push(match named {
+				NamedNode::Transformation { name, transformation } => NamedSegment::Transformation(name, reference!(self, transformation, Transformation)),
+				NamedNode::Code { name, code } => NamedSegment::Code(name, reference!(self, code, Code))
+			});
+		}
+		Ok(Snippet {
+			body: self.segments(snippet.body)?,
+			tabs,
+			variables,
+			code_expansions,
+			named_segments,
+			current_tab: snippet.current_tab,
+			choice_sources: Vec::new(),
+			baseline: Default::default()
+		})
+	}
+}
+
+impl From<TransformationNode> for Transformation {
+	fn from(node: TransformationNode) -> Self {
+		Transformation { section: node.section, format: node.format, flags: node.flags, result: node.result, compiled: Default::default() }
+	}
+}
+
+impl From<CodeNode> for Code {
+	fn from(node: CodeNode) -> Self {
+		Code { code: node.code, output: node.output, shebang: node.shebang }
+	}
+}
+
+/// Serialized as a graph of its parts, along with the text filled in, the values of variables and the selected tab.
+impl Serialize for Snippet {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		Encoder::graph(|encoder| encoder.snippet(self)).serialize(serializer)
+	}
+}
+
+/// Snippets read back are checked (see [`Snippet::verify`]), so that a corrupt graph does not make a snippet that misbehaves.
+impl<'de> Deserialize<'de> for Snippet {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		let snippet = Decoder::read(Graph::deserialize(deserializer)?, Decoder::snippet).map_err(D::Error::custom)?;
+		snippet.verify().map_err(|_| D::Error::custom("inconsistent snippet"))?;
+		Ok(snippet)
+	}
+}
+
+impl Serialize for Segment {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		Encoder::graph(|encoder| encoder.segments(std::slice::from_ref(self)).remove(0)).serialize(serializer)
+	}
+}
+
+impl<'de> Deserialize<'de> for Segment {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		Decoder::read(Graph::deserialize(deserializer)?, Decoder::segment).map_err(D::Error::custom)
+	}
+}
+
+impl Serialize for Field {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		Encoder::graph(|encoder| encoder.field(self)).serialize(serializer)
+	}
+}
+
+impl<'de> Deserialize<'de> for Field {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		Decoder::read(Graph::deserialize(deserializer)?, Decoder::field).map_err(D::Error::custom)
+	}
+}
+
+impl Serialize for Variable {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		Encoder::graph(|encoder| encoder.variable(self)).serialize(serializer)
+	}
+}
+
+impl<'de> Deserialize<'de> for Variable {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		Decoder::read(Graph::deserialize(deserializer)?, Decoder::variable).map_err(D::Error::custom)
+	}
+}
+
+/// Serialized without its compiled section, which is compiled again when first applied.
+impl Serialize for Transformation {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		transformation_node(self).serialize(serializer)
+	}
+}
+
+impl<'de> Deserialize<'de> for Transformation {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		TransformationNode::deserialize(deserializer).map(Transformation::from)
+	}
+}
+
+impl Serialize for Code {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		code_node(self).serialize(serializer)
+	}
+}
+
+impl<'de> Deserialize<'de> for Code {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		CodeNode::deserialize(deserializer).map(Code::from)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn round_trip() {
+		let source = "${1:a} $1 ${1/(.*)/${1:/upcase}/} ${2|x,y|} ${up=${1/(.*)/$1!/}} $up ${TM_FILENAME:f} ${3:${4:c}}$0";
+		let mut snippet = Snippet::parse(source).unwrap();
+		snippet.set_field_text(1, "ab").unwrap();
+		snippet.set_choice(2, 1).unwrap();
+		snippet.jump_to(2);
+		let json = serde_json::to_string(&snippet).unwrap();
+		let mut read: Snippet = serde_json::from_str(&json).unwrap();
+		assert_eq!(read.to_test_fixture(), snippet.to_test_fixture());
+		assert_eq!(read.current_tab().unwrap().tab.num(), 2);
+		// Mirrors still share their field.
+		read.set_field_text(1, "z").unwrap();
+		assert!(read.to_string().starts_with("z z Z y z! z! "));
+
+		let segment: Segment = serde_json::from_str(&serde_json::to_string(&snippet.body()[0]).unwrap()).unwrap();
+		assert_eq!(segment.to_string(), "ab");
+		let transformation = r#"{"section": "a", "format": "b", "flags": "g", "result": "bb"}"#;
+		assert_eq!(serde_json::from_str::<Transformation>(transformation).unwrap().transform("aa").unwrap(), "bb");
+		// Nodes only name those listed before them.
+		let forward = r#"{"nodes": [{"field": {"placeholder": [{"field": 1}]}}, {"field": {"placeholder": []}}], "root": {"field": 0}}"#;
+		assert!(serde_json::from_str::<Segment>(forward).is_err());
+	}
+}
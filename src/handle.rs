@@ -0,0 +1,295 @@
+//! Handles to the parts of a snippet that hide how the parts are shared and referred to,
+//! so the representation behind them can change without breaking users of the handles.
+//! Parts are made into handles, and tabs and segments are made from handles, rather than from the shared pointers within them.
+
+use std::fmt;
+use crate::shared::Rc;
+use crate::{Snippet, Segment, Field, Transformation, Tab, Expansion, Variable, Code, Conditional, RepeatField};
+
+/// A field of a snippet. Handles compare equal when they refer to the same field (such as a field and its mirror).
+#[derive(Clone)]
+pub struct FieldRef(pub(crate) Rc<Field>);
+
+/// A transformation of a snippet. Handles compare equal when they refer to the same transformation.
+#[derive(Clone)]
+pub struct TransformRef(Rc<Transformation>);
+
+/// A variable or code of a snippet. Handles compare equal when they refer to the same part.
+pub struct ExpansionRef<E>(pub(crate) Rc<E>);
+
+/// A tab of a snippet.
+#[derive(Clone, Copy)]
+pub struct TabRef<'a>(&'a Tab);
+
+impl FieldRef {
+	/// Field not yet part of a snippet, to put in one as a segment (see `Segment::from`) selected by a tab (see [`FieldRef::tab`]).
+	pub fn new(field: Field) -> Self {
+		FieldRef(Rc::new(field))
+	}
+
+	pub fn get(&self) -> &Field {
+		&self.0
+	}
+
+	/// Tab selecting the field, with no transformations or label.
+	pub fn tab(&self, num: u8) -> Tab {
+		Tab { num, field: Rc::downgrade(&self.0), transformations: Vec::new(), label: None }
+	}
+}
+
+impl TransformRef {
+	/// Transformation not yet part of a snippet, to put in one as a segment (see `Segment::from`).
+	pub fn new(transformation: Transformation) -> Self {
+		TransformRef(Rc::new(transformation))
+	}
+
+	pub fn get(&self) -> &Transformation {
+		&self.0
+	}
+}
+
+impl<E> ExpansionRef<E> {
+	/// Variable or code not yet part of a snippet, to put in one as a segment (see `Segment::from`).
+	pub fn new(expansion: E) -> Self {
+		ExpansionRef(Rc::new(expansion))
+	}
+
+	pub fn get(&self) -> &E {
+		&self.0
+	}
+
+	/// Expansion listing the variable or code in a snippet, with no transformations.
+	pub fn expansion(&self) -> Expansion<E> {
+		Expansion { expansion: Rc::downgrade(&self.0), transformations: Vec::new() }
+	}
+}
+
+impl<E> Clone for ExpansionRef<E> {
+	fn clone(&self) -> Self {
+		ExpansionRef(self.0.clone())
+	}
+}
+
+impl<E> Expansion<E> {
+	/// The variable or code. None when it no longer exists.
+	pub fn expansion_ref(&self) -> Option<ExpansionRef<E>> {
+		self.expansion.upgrade().map(ExpansionRef)
+	}
+
+	/// Transformations acting upon the variable or code that still exist.
+	pub fn transform_refs(&self) -> impl Iterator<Item = TransformRef> + '_ {
+		self.transformations.iter().filter_map(|transformation| transformation.upgrade().map(TransformRef))
+	}
+}
+
+impl Tab {
+	/// The tab as a handle.
+	pub fn handle(&self) -> TabRef<'_> {
+		TabRef(self)
+	}
+}
+
+impl Conditional {
+	/// Field whose text is tested. None when it no longer exists.
+	pub fn field_ref(&self) -> Option<FieldRef> {
+		self.field.upgrade().map(FieldRef)
+	}
+}
+
+impl RepeatField {
+	/// What each repetition is copied from, with the tabs within it numbered from 1.
+	pub fn template(&self) -> &Snippet {
+		&self.template
+	}
+}
+
+impl From<FieldRef> for Segment {
+	fn from(field: FieldRef) -> Self {
+		Segment::Field(field.0)
+	}
+}
+
+impl From<TransformRef> for Segment {
+	fn from(transformation: TransformRef) -> Self {
+		Segment::Transformation(transformation.0)
+	}
+}
+
+impl From<ExpansionRef<Variable>> for Segment {
+	fn from(variable: ExpansionRef<Variable>) -> Self {
+		Segment::Variable(variable.0)
+	}
+}
+
+impl From<ExpansionRef<Code>> for Segment {
+	fn from(code: ExpansionRef<Code>) -> Self {
+		Segment::Code(code.0)
+	}
+}
+
+impl<'a> TabRef<'a> {
+	/// Indicates the order in which this tab is selected in the cycle.
+	pub fn num(&self) -> u8 {
+		self.0.num
+	}
+
+	/// Field that is selected when this tab is selected. None when the field no longer exists.
+	pub fn field(&self) -> Option<FieldRef> {
+		self.0.field.upgrade().map(FieldRef)
+	}
+
+	/// Text to prompt the user with when the tab is selected. None when the tab is only shown by its number.
+	pub fn label(&self) -> Option<&'a str> {
+		self.0.label.as_deref()
+	}
+
+	/// Transformations acting upon the tab's field that still exist.
+	pub fn transformations(&self) -> impl Iterator<Item = TransformRef> + 'a {
+		self.0.transformations.iter().filter_map(|transformation| transformation.upgrade().map(TransformRef))
+	}
+}
+
+impl PartialEq for FieldRef {
+	fn eq(&self, other: &Self) -> bool {
+		Rc::ptr_eq(&self.0, &other.0)
+	}
+}
+
+impl Eq for FieldRef {}
+
+impl PartialEq for TransformRef {
+	fn eq(&self, other: &Self) -> bool {
+		Rc::ptr_eq(&self.0, &other.0)
+	}
+}
+
+impl Eq for TransformRef {}
+
+impl<E> PartialEq for ExpansionRef<E> {
+	fn eq(&self, other: &Self) -> bool {
+		Rc::ptr_eq(&self.0, &other.0)
+	}
+}
+
+impl<E> Eq for ExpansionRef<E> {}
+
+impl fmt::Debug for FieldRef {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		self.0.fmt(f)
+	}
+}
+
+impl fmt::Debug for TransformRef {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		self.0.fmt(f)
+	}
+}
+
+impl<E: fmt::Debug> fmt::Debug for ExpansionRef<E> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		self.0.fmt(f)
+	}
+}
+
+impl fmt::Debug for TabRef<'_> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		self.0.fmt(f)
+	}
+}
+
+impl fmt::Display for FieldRef {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		self.0.fmt(f)
+	}
+}
+
+impl Segment {
+	/// The field when this segment is a field.
+	pub fn field_ref(&self) -> Option<FieldRef> {
+		match self {
+			Segment::Field(field) => Some(FieldRef(field.clone())),
+			_ => None
+		}
+	}
+
+	/// The transformation when this segment is a transformation.
+	pub fn transform_ref(&self) -> Option<TransformRef> {
+		match self {
+			Segment::Transformation(transformation) => Some(TransformRef(transformation.clone())),
+			_ => None
+		}
+	}
+}
+
+impl Snippet {
+	/// Tabs of the snippet, in the order they are stored in (not necessarily the order of their numbers).
+	pub fn tab_refs(&self) -> impl Iterator<Item = TabRef<'_>> {
+		self.tabs.iter().map(TabRef)
+	}
+
+	/// The first tab with the number.
+	pub fn tab_ref(&self, num: u8) -> Option<TabRef<'_>> {
+		self.tab_refs().find(|tab| tab.num() == num)
+	}
+
+	/// The tab selecting the field. None when the field is not tabbed.
+	pub fn tab_of(&self, field: &FieldRef) -> Option<TabRef<'_>> {
+		self.tab_refs().find(|tab| tab.0.field.as_ptr() == Rc::as_ptr(&field.0))
+	}
+
+	/// Labels every tab with the number, replacing any label they had.
+	/// Returns false when the snippet has no tab with the number.
+	pub fn label_tab(&mut self, num: u8, label: &str) -> bool {
+		let mut found = false;
+		for tab in self.tabs.iter_mut().filter(|tab| tab.num == num) {
+			tab.label = Some(label.to_string());
+			found = true;
+		}
+		found
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::mustache::from_template;
+
+	#[test]
+	fn refer_through_handles() {
+		let snippet = from_template("{{a}} {{b}} {{a}}").output;
+		let first = snippet.body()[0].field_ref().unwrap();
+		let mirror = snippet.body()[4].field_ref().unwrap();
+		assert_eq!(first, mirror);
+		assert_ne!(first, snippet.body()[2].field_ref().unwrap());
+		assert!(snippet.body()[1].field_ref().is_none());
+		assert_eq!(snippet.tab_of(&mirror).unwrap().num(), 1);
+		let tab = snippet.tab_ref(2).unwrap();
+		assert_eq!(tab.field().unwrap().to_string(), "b");
+		assert_eq!(tab.transformations().count(), 0);
+		assert!(snippet.tab_ref(3).is_none());
+	}
+
+	#[test]
+	fn build_from_handles() {
+		use super::*;
+		use crate::VariableSource;
+		let field = FieldRef::new(Field::Placeholder(vec![Segment::Text(String::from("x"))]));
+		let user = ExpansionRef::new(Variable::new("USER", "me", VariableSource::Daemon));
+		let tab = field.tab(1);
+		assert_eq!(tab.handle().field(), Some(field.clone()));
+		let expansion = user.expansion();
+		assert_eq!(expansion.expansion_ref(), Some(user.clone()));
+		assert_eq!(expansion.transform_refs().count(), 0);
+		let segments: Vec<Segment> = vec![field.into(), Segment::Text(String::from(" ")), user.into()];
+		assert_eq!(segments.iter().map(Segment::to_string).collect::<String>(), "x me");
+	}
+
+	#[test]
+	fn label_tabs() {
+		let mut snippet = crate::Snippet::parse("fn ${1:name}() {$0}").unwrap();
+		assert_eq!(snippet.tab_ref(1).unwrap().label(), None);
+		assert!(snippet.label_tab(1, "Function name:"));
+		assert!(!snippet.label_tab(2, "Return type:"));
+		assert_eq!(snippet.tab_ref(1).unwrap().label(), Some("Function name:"));
+		assert_eq!(from_template("{{a}}").output.tab_ref(1).unwrap().label(), Some("a"));
+	}
+}
@@ -0,0 +1,107 @@
+//! Recording the states a snippet goes through as it is filled in, to step back and forth through them,
+//! such as to find where mirrors and transformations went wrong.
+
+use std::io;
+use crate::Snippet;
+
+/// States of a snippet in the binary form of [`Snippet::write_state`], and the one currently stepped to.
+#[derive(Debug, Default)]
+pub struct SnippetHistory {
+	states: Vec<Vec<u8>>,
+	position: usize
+}
+
+impl SnippetHistory {
+	pub fn new() -> Self {
+		SnippetHistory::default()
+	}
+
+	/// Records the state of the snippet after the current state, dropping the states stepped back from.
+	/// Nothing is recorded when the state is the same as the current one.
+	pub fn record(&mut self, snippet: &Snippet) -> io::Result<()> {
+		let mut state = Vec::new();
+		snippet.write_state(&mut state)?;
+		if self.states.get(self.position) == Some(&state) {
+			return Ok(())
+		}
+		if !self.states.is_empty() {
+			self.states.truncate(self.position + 1);
+			self.position += 1;
+		}
+		self.states.push(state);
+		Ok(())
+	}
+
+	/// Number of states recorded.
+	pub fn len(&self) -> usize {
+		self.states.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.states.is_empty()
+	}
+
+	/// Index of the current state. None when nothing is recorded.
+	pub fn position(&self) -> Option<usize> {
+		(!self.states.is_empty()).then_some(self.position)
+	}
+
+	/// The state with the index, rebuilt.
+	pub fn get(&self, index: usize) -> Option<Snippet> {
+		Snippet::read_state(&self.states.get(index)?[..]).ok()
+	}
+
+	/// The current state, rebuilt.
+	pub fn current(&self) -> Option<Snippet> {
+		self.get(self.position)
+	}
+
+	/// Steps to the state before the current one. None (staying) at the first state.
+	pub fn back(&mut self) -> Option<Snippet> {
+		self.position = self.position.checked_sub(1)?;
+		self.current()
+	}
+
+	/// Steps to the state after the current one. None (staying) at the last state.
+	pub fn forward(&mut self) -> Option<Snippet> {
+		if self.position + 1 >= self.states.len() {
+			return None
+		}
+		self.position += 1;
+		self.current()
+	}
+
+	/// Every state as it renders, in the order recorded.
+	pub fn renders(&self) -> Vec<String> {
+		(0..self.states.len()).filter_map(|index| self.get(index)).map(|snippet| snippet.to_string()).collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn step_through_states() {
+		let mut snippet = Snippet::parse("${1:a} ${1/(.*)/${1:/upcase}/}").unwrap();
+		let mut history = SnippetHistory::new();
+		assert!(history.back().is_none() && history.position().is_none());
+		history.record(&snippet).unwrap();
+		snippet.set_field_text(1, "b").unwrap();
+		history.record(&snippet).unwrap();
+		history.record(&snippet).unwrap();
+		snippet.set_field_text(1, "c").unwrap();
+		history.record(&snippet).unwrap();
+		assert_eq!(history.renders(), ["a ", "b B", "c C"]);
+		assert_eq!(history.back().unwrap().to_string(), "b B");
+		assert_eq!(history.back().unwrap().to_string(), "a ");
+		assert!(history.back().is_none());
+		assert_eq!(history.forward().unwrap().to_string(), "b B");
+		let mut branch = history.current().unwrap();
+		branch.set_field_text(1, "d").unwrap();
+		history.record(&branch).unwrap();
+		assert_eq!(history.renders(), ["a ", "b B", "d D"]);
+		assert!(history.forward().is_none());
+		assert_eq!(history.position(), Some(2));
+	}
+}
@@ -0,0 +1,1041 @@
+//! Driving snippet expansion from other programs over a Unix socket, the values they send being those of client variables.
+//!
+//! Each request and response is a JSON object on a line of its own, unless the client asks for MessagePack with `hello`.
+//! Requests name their `command`:
+//! - `{"command": "expand", "body": "..."}` expands the body (LSP syntax), or `{"command": "expand", "trigger": "..."}`
+//!   the first definition of the daemon's library with the trigger, as a new session of the connection (see below).
+//!   Responds with the rendered `text`, the number of the `session` and why code of the snippet could not be run as `errors`.
+//! - `{"command": "set-variable", "name": "...", "value": "..."}` gives the client variable the value,
+//!   both within the expanded snippet and those expanded later. Variables that do not come from the client are refused
+//!   (see [`crate::parse::variable_source`]). Responds with the `edits` of the text, as `set-field` does.
+//! - `{"command": "set-field", "tab": 1, "text": "..."}` sets the text of the tab's field (see [`Snippet::set_field_text`]).
+//!   Responds with the `edits` turning the text the client was last given into the text rendered now
+//!   (see [`Snippet::render_delta`]), rather than the whole text, and why transformations could not be applied as `errors`.
+//!   Edits are objects with the `text` replacing the byte range `start` to `end` of the text before,
+//!   which is `start_utf16` to `end_utf16` in UTF-16 code units.
+//! - `{"command": "next-tab"}` selects the next tab, responding with its number as `tab` (null after the last tab)
+//!   and the byte range of its field within the rendered text as `start` and `end` (null when it is not rendered).
+//! - `{"command": "render"}` responds with the whole rendered `text`.
+//! - `{"command": "sessions"}` responds with the state of every session as `sessions`, an array of objects as `query` responds with.
+//! - `{"command": "query", "session": 1}` responds with the state of the session: its number as `session`,
+//!   the selected tab as `tab` (null before one is selected), the text of the field of each tab as `fields`
+//!   (an array of objects with the `tab` and its `text`) and the rendered `text`.
+//! - `{"command": "resume", "session": 1}` makes the session that of the connection, responding as `query` does.
+//! - `{"command": "cancel", "session": 1}` ends the session.
+//! - `{"command": "hello", "evaluate": ["#!..."]}` tells the daemon what the client is capable of: the shebangs of the code
+//!   it evaluates itself (see below). Responds with those the daemon delegates to it as `evaluate`,
+//!   none when the daemon is built without the `exec` feature. Also carries the `token` of a daemon that needs one,
+//!   and the `framing` of the messages after the response: `"json"` (as described here) or `"msgpack"`,
+//!   the same messages written as MessagePack (see [`Framing`]). Responds with the `framing`.
+//!
+//! Each snippet expanded is filled in as a session, which outlives the connection that expanded it so that a client that
+//! lost its connection (such as an editor that crashed) can resume where it was. A session ends when it is cancelled,
+//! or when the connection it is that of expands another snippet. The other requests act upon the session of the connection.
+//!
+//! Responses have `ok` set to true, or to false with the reason as `error`.
+//!
+//! The daemon runs the code of the snippets it expands only when given a runner ([`SnippetDaemon::with_code_runner`]),
+//! except for code of the shebangs the client evaluates. For each, ahead of responding to the expand request,
+//! the daemon sends `{"evaluate": "...", "shebang": "..."}` with the code and awaits `{"command": "evaluated", "output": "..."}`
+//! (or `"error"` in place of `"output"` when it could not be evaluated).
+//!
+//! Other variables are resolved by the daemon as [`StandardVariables`] resolves them, except from the environment,
+//! which clients of the socket are not to read. Requests longer than [`MAX_REQUEST`] bytes close the connection,
+//! and what else each client may use is limited by [`DaemonLimits`].
+//!
+//! Only the user running the daemon may connect to a socket it binds ([`SnippetDaemon::bind`]), so other users of the machine
+//! can not give it variables or have it run code. A daemon given a token ([`SnippetDaemon::with_token`]) also closes the
+//! connection of a client whose first request is not `hello` with the token.
+
+use std::{fmt, fs, io, process};
+use std::io::{BufRead, BufReader, Write};
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Mutex, PoisonError};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::{Snippet, VariableSource};
+use crate::builder::SnippetBuilder;
+use crate::delta::TextEdit;
+use crate::json::{self, quote, Node, Value};
+use crate::msgpack;
+use crate::choices::ChoiceSource;
+use crate::library::{SnippetLibrary, SnippetKind};
+use crate::parse::variable_source;
+use crate::rendered::Span;
+use crate::resolve::{StandardVariables, VariableResolver};
+use crate::observe::{self, SessionObserver, SessionEvent};
+#[cfg(feature = "exec")]
+use crate::Code;
+#[cfg(feature = "exec")]
+use crate::exec::{CodeRunner, CodeError};
+
+/// Why a request could not be carried out.
+#[derive(Debug)]
+pub enum IpcError {
+	/// The socket could not be connected to, written or read.
+	Io(io::Error),
+	/// What was received is not part of the protocol. Carries a description of the problem.
+	Protocol(&'static str),
+	/// The daemon could not carry out the request. Carries the reason it responded with.
+	Daemon(String)
+}
+
+impl fmt::Display for IpcError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			IpcError::Io(error) => write!(f, "{}", error),
+			IpcError::Protocol(message) => write!(f, "invalid message: {}", message),
+			IpcError::Daemon(message) => write!(f, "daemon responded: {}", message)
+		}
+	}
+}
+
+impl std::error::Error for IpcError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			IpcError::Io(error) => Some(error),
+			_ => None
+		}
+	}
+}
+
+impl From<io::Error> for IpcError {
+	fn from(error: io::Error) -> Self {
+		IpcError::Io(error)
+	}
+}
+
+/// Longest request line the daemon reads, in bytes.
+pub const MAX_REQUEST: usize = 1 << 20;
+
+/// How much of the daemon each client may use, so one flooding it can not hold up the others or use up its memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DaemonLimits {
+	/// Connections served at once. Further clients are refused until one disconnects.
+	pub connections: usize,
+	/// Sessions kept at once, those of every connection together.
+	pub sessions: usize,
+	/// Client variables each connection gives values to.
+	pub variables: usize,
+	/// Requests of each connection read per second. Requests past the limit wait unread, their connection slowed down
+	/// rather than the requests queuing within the daemon.
+	pub requests_per_second: u32,
+	/// Requests of each connection read at once after it was idle, however fast they come.
+	pub burst: u32
+}
+
+impl Default for DaemonLimits {
+	fn default() -> Self {
+		DaemonLimits { connections: 64, sessions: 256, variables: 256, requests_per_second: 200, burst: 100 }
+	}
+}
+
+/// Rate a connection's requests are read at.
+#[derive(Debug, Default)]
+struct Throttle {
+	/// Requests that may be read before waiting.
+	allowance: f64,
+	checked: Option<Instant>
+}
+
+impl Throttle {
+	/// Waits until the next request may be read.
+	fn wait(&mut self, limits: &DaemonLimits) {
+		let rate = f64::from(limits.requests_per_second.max(1));
+		let burst = f64::from(limits.burst.max(1));
+		let now = Instant::now();
+		self.allowance = match self.checked {
+			Some(checked) => (self.allowance + now.duration_since(checked).as_secs_f64() * rate).min(burst),
+			None => burst
+		};
+		self.checked = Some(now);
+		if self.allowance < 1.0 {
+			thread::sleep(Duration::from_secs_f64((1.0 - self.allowance) / rate));
+			self.allowance = 1.0;
+			self.checked = Some(Instant::now());
+		}
+		self.allowance -= 1.0;
+	}
+}
+
+/// Serves requests for the snippets of a library, each connection on a thread of its own.
+/// Each connection has a snippet of its own (the one it last expanded) and variables of its own.
+pub struct SnippetDaemon {
+	listener: UnixListener,
+	library: SnippetLibrary,
+	sessions: Mutex<Sessions>,
+	/// Token clients send before anything else, when they have to.
+	token: Option<String>,
+	limits: DaemonLimits,
+	/// Number of connections being served.
+	connections: AtomicUsize,
+	#[cfg(feature = "exec")]
+	runner: Option<CodeRunner>,
+	observer: Option<Mutex<Box<dyn SessionObserver + Send>>>
+}
+
+impl fmt::Debug for SnippetDaemon {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let mut debug = f.debug_struct("SnippetDaemon");
+		debug.field("listener", &self.listener)
+			.field("library", &self.library)
+			.field("sessions", &self.sessions)
+			.field("token", &self.token)
+			.field("limits", &self.limits)
+			.field("connections", &self.connections);
+		#[cfg(feature = "exec")]
+		debug.field("runner", &self.runner);
+		debug.finish_non_exhaustive()
+	}
+}
+
+/// Snippets being filled in, by the numbers of their sessions.
+#[derive(Debug, Default)]
+struct Sessions {
+	next: u64,
+	snippets: BTreeMap<u64, Snippet>
+}
+
+/// State of a connection to the daemon.
+#[derive(Default)]
+struct Connection {
+	/// Number of the session requests act upon.
+	session: Option<u64>,
+	variables: Vec<(String, String)>,
+	/// Shebangs of the code the client evaluates.
+	evaluates: Vec<String>,
+	/// Whether the client sent the token of the daemon.
+	authenticated: bool,
+	/// Whether the connection is closed after responding, the client not having authenticated.
+	refused: bool
+}
+
+/// How messages are written over the socket, agreed upon with `hello`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Framing {
+	/// Each message is a JSON object on a line of its own.
+	#[default]
+	Json,
+	/// Each message is a MessagePack map, one after the other. Smaller and quicker to read than JSON,
+	/// such as for clients updating fields on every keystroke.
+	MessagePack
+}
+
+impl Framing {
+	/// Name of the framing in `hello`.
+	fn name(self) -> &'static str {
+		match self {
+			Framing::Json => "json",
+			Framing::MessagePack => "msgpack"
+		}
+	}
+}
+
+/// Writes the message, written as JSON, in the framing.
+fn write_message(writer: &mut impl Write, framing: Framing, message: &str) -> io::Result<()> {
+	match framing {
+		Framing::Json => writeln!(writer, "{}", message),
+		Framing::MessagePack => {
+			let message = json::parse(message).map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error.message))?;
+			let mut bytes = Vec::new();
+			msgpack::write(&message.value, &mut bytes);
+			writer.write_all(&bytes)
+		}
+	}
+}
+
+/// The stream of a connection, read and written a message at a time.
+struct Channel {
+	reader: BufReader<UnixStream>,
+	writer: UnixStream,
+	framing: Framing,
+	/// Framing of the messages after the next one sent, such as that agreed upon by the request being responded to.
+	switch: Option<Framing>
+}
+
+impl Channel {
+	/// Reads the next message, responding to those that can not be read without failing the connection
+	/// (lines that are not UTF-8 or JSON). None once the connection is closed.
+	/// Messages longer than [`MAX_REQUEST`] fail the connection, as do those that are not MessagePack.
+	fn receive(&mut self) -> io::Result<Option<Node>> {
+		loop {
+			if self.framing == Framing::MessagePack {
+				return match msgpack::read(&mut self.reader, MAX_REQUEST) {
+					Err(error) if error.kind() == io::ErrorKind::InvalidData => {
+						self.send(&format!("{{\"ok\": false, \"error\": {}}}", quote(&error.to_string())))?;
+						Err(error)
+					},
+					read => read
+				}
+			}
+			let mut line = Vec::new();
+			if io::Read::take(&mut self.reader, MAX_REQUEST as u64 + 1).read_until(b'\n', &mut line)? == 0 {
+				return Ok(None)
+			}
+			if line.len() > MAX_REQUEST {
+				self.send("{\"ok\": false, \"error\": \"request is too long\"}")?;
+				return Err(io::Error::new(io::ErrorKind::InvalidData, "request is too long"))
+			}
+			let Ok(line) = String::from_utf8(line) else {
+				self.send("{\"ok\": false, \"error\": \"request is not UTF-8\"}")?;
+				continue
+			};
+			if line.trim().is_empty() {
+				continue
+			}
+			match json::parse(&line) {
+				Ok(message) => return Ok(Some(message)),
+				Err(error) => self.send(&format!("{{\"ok\": false, \"error\": {}}}", quote(error.message)))?
+			}
+		}
+	}
+
+	/// Sends the message, written as JSON.
+	fn send(&mut self, message: &str) -> io::Result<()> {
+		write_message(&mut self.writer, self.framing, message)?;
+		if let Some(framing) = self.switch.take() {
+			self.framing = framing;
+		}
+		Ok(())
+	}
+
+	/// Has the client evaluate the code, waiting for the output it responds with.
+	#[cfg(feature = "exec")]
+	fn evaluate(&mut self, shebang: &str, code: &Code) -> Result<String, CodeError> {
+		let failed = |message: &str| CodeError::Delegated(message.to_string());
+		self.send(&format!("{{\"evaluate\": {}, \"shebang\": {}}}", quote(&code.code), quote(shebang)))
+			.map_err(|error| failed(&error.to_string()))?;
+		let Some(response) = self.receive().map_err(|error| failed(&error.to_string()))? else {
+			return Err(failed("the connection was closed"))
+		};
+		if response.get("command").and_then(Node::as_str) != Some("evaluated") {
+			return Err(failed("the client sent another request instead of the output"))
+		}
+		match (response.get("output").and_then(Node::as_str), response.get("error").and_then(Node::as_str)) {
+			(Some(output), _) => Ok(output.to_string()),
+			(None, Some(error)) => Err(failed(error)),
+			(None, None) => Err(failed("evaluated needs an output or an error"))
+		}
+	}
+}
+
+/// Whether the texts are the same in time depending only on their length, so tokens can not be guessed by timing.
+fn same(a: &[u8], b: &[u8]) -> bool {
+	a.len() == b.len() && a.iter().zip(b).fold(0, |differs, (a, b)| differs | (a ^ b)) == 0
+}
+
+/// The edits as an array of objects with the `text` replacing the byte range `start` to `end`,
+/// which is `start_utf16` to `end_utf16` in UTF-16 code units.
+fn edits(edits: &[TextEdit]) -> String {
+	let edits: Vec<String> = edits.iter().map(|edit| format!(
+		"{{\"start\": {}, \"end\": {}, \"start_utf16\": {}, \"end_utf16\": {}, \"text\": {}}}",
+		edit.range.bytes.start, edit.range.bytes.end, edit.range.utf16.start, edit.range.utf16.end, quote(&edit.text)
+	)).collect();
+	format!("[{}]", edits.join(", "))
+}
+
+/// Fields of a response giving the state of the session, without the braces around them.
+fn state(session: u64, snippet: &Snippet) -> String {
+	let tab = snippet.current_tab().map_or(String::from("null"), |stop| stop.tab.num().to_string());
+	let fields: Vec<String> = snippet.tab_refs().filter_map(|tab| {
+		let field = tab.field()?;
+		Some(format!("{{\"tab\": {}, \"text\": {}}}", tab.num(), quote(&field.get().to_string())))
+	}).collect();
+	format!("\"session\": {}, \"tab\": {}, \"fields\": [{}], \"text\": {}", session, tab, fields.join(", "), quote(&snippet.to_string()))
+}
+
+impl SnippetDaemon {
+	/// Listens on a new socket at the path, which only the user running the daemon may connect to.
+	/// Fails when there already is a file at the path.
+	pub fn bind(path: impl AsRef<Path>, library: SnippetLibrary) -> io::Result<Self> {
+		let path = path.as_ref();
+		// The socket is made private before it is linked to the path, so that no other user can connect in between.
+		let mut private = path.as_os_str().to_owned();
+		private.push(format!(".{}.private", process::id()));
+		let listener = UnixListener::bind(&private)?;
+		let linked = fs::set_permissions(&private, fs::Permissions::from_mode(0o600)).and_then(|()| fs::hard_link(&private, path));
+		fs::remove_file(&private)?;
+		linked?;
+		Ok(SnippetDaemon::new(listener, library))
+	}
+
+	pub fn new(listener: UnixListener, library: SnippetLibrary) -> Self {
+		SnippetDaemon {
+			listener,
+			library,
+			sessions: Mutex::default(),
+			token: None,
+			limits: DaemonLimits::default(),
+			connections: AtomicUsize::new(0),
+			#[cfg(feature = "exec")]
+			runner: None,
+			observer: None
+		}
+	}
+
+	/// Limits what each client may use of the daemon in place of [`DaemonLimits::default`].
+	pub fn with_limits(mut self, limits: DaemonLimits) -> Self {
+		self.limits = limits;
+		self
+	}
+
+	/// Has clients send the token with `hello` before anything else, closing the connections of those that do not.
+	/// Keeps other programs of the user from driving the daemon, such as when the socket is shared with other users.
+	pub fn with_token(mut self, token: impl Into<String>) -> Self {
+		self.token = Some(token.into());
+		self
+	}
+
+	/// Runs the code of the snippets expanded with the runner. Without one, the daemon runs no code,
+	/// and only code that clients evaluate themselves gets an output.
+	#[cfg(feature = "exec")]
+	pub fn with_code_runner(mut self, runner: CodeRunner) -> Self {
+		self.runner = Some(runner);
+		self
+	}
+
+	/// Tells the observer what happens to the sessions: when they start (running their code after),
+	/// when their tabs are selected and fields set, and when they are cancelled or finalized by expanding another snippet.
+	pub fn with_observer(mut self, observer: impl SessionObserver + Send + 'static) -> Self {
+		self.observer = Some(Mutex::new(Box::new(observer)));
+		self
+	}
+
+	fn tell(&self, session: u64, event: &SessionEvent) {
+		if let Some(observer) = &self.observer {
+			observer.lock().unwrap_or_else(PoisonError::into_inner).event(session, event);
+		}
+	}
+
+	/// Serves connections until the socket fails, each on a thread of its own so that an idle client does not hold up the others.
+	/// Connections failing (such as by sending a request that is too long) are closed without stopping the others.
+	/// Connections past the limit (see [`DaemonLimits`]) are refused.
+	pub fn serve(&self) -> io::Result<()> {
+		thread::scope(|scope| loop {
+			let (stream, _) = self.listener.accept()?;
+			scope.spawn(move || self.serve_counted(stream));
+		})
+	}
+
+	/// Accepts a connection and serves its requests until it is closed, on this thread.
+	pub fn serve_connection(&self) -> io::Result<()> {
+		let (stream, _) = self.listener.accept()?;
+		self.serve_counted(stream)
+	}
+
+	/// Serves the connection while counting it among those being served, refusing it when there are too many.
+	fn serve_counted(&self, mut stream: UnixStream) -> io::Result<()> {
+		let result = if self.connections.fetch_add(1, Ordering::SeqCst) < self.limits.connections {
+			self.serve_stream(stream)
+		} else {
+			writeln!(stream, "{{\"ok\": false, \"error\": \"the daemon is serving too many connections\"}}")
+		};
+		self.connections.fetch_sub(1, Ordering::SeqCst);
+		result
+	}
+
+	fn serve_stream(&self, stream: UnixStream) -> io::Result<()> {
+		let mut channel = Channel { writer: stream.try_clone()?, reader: BufReader::new(stream), framing: Framing::Json, switch: None };
+		let mut connection = Connection::default();
+		let mut throttle = Throttle::default();
+		loop {
+			throttle.wait(&self.limits);
+			let Some(request) = channel.receive()? else {
+				break
+			};
+			let response = match self.respond(&mut connection, &mut channel, &request) {
+				Ok(fields) => format!("{{\"ok\": true{}}}", fields),
+				Err(message) => format!("{{\"ok\": false, \"error\": {}}}", quote(&message))
+			};
+			channel.send(&response)?;
+			if connection.refused {
+				return Err(io::Error::new(io::ErrorKind::PermissionDenied, "client did not send the token"))
+			}
+		}
+		Ok(())
+	}
+
+	/// Carries out the request, giving the fields of the response besides `ok`.
+	fn respond(&self, connection: &mut Connection, channel: &mut Channel, request: &Node) -> Result<String, String> {
+		let text = |key: &str| request.get(key).and_then(Node::as_str);
+		if let Some(token) = self.token.as_deref().filter(|_| !connection.authenticated) {
+			let sent = text("token").filter(|_| text("command") == Some("hello"));
+			if !sent.is_some_and(|sent| same(sent.as_bytes(), token.as_bytes())) {
+				connection.refused = true;
+				return Err(String::from("the daemon needs its token, sent with hello"))
+			}
+			connection.authenticated = true;
+		}
+		match text("command") {
+			Some("expand") => return self.expand(connection, channel, request),
+			Some("hello") => {
+				if let Some(Value::Array(shebangs)) = request.get("evaluate").map(|node| &node.value) {
+					let shebangs: Option<Vec<&str>> = shebangs.iter().map(Node::as_str).collect();
+					let shebangs = shebangs.ok_or_else(|| String::from("evaluate needs to be shebangs"))?;
+					// Code is only evaluated when this daemon is built to run it.
+					if cfg!(feature = "exec") {
+						connection.evaluates = shebangs.into_iter().map(|shebang| shebang.trim().to_string()).collect();
+					}
+				}
+				let framing = match text("framing") {
+					Some("json") => Framing::Json,
+					Some("msgpack") => Framing::MessagePack,
+					Some(_) => return Err(String::from("unknown framing")),
+					None => channel.framing
+				};
+				// The response is still framed as the request was.
+				channel.switch = Some(framing);
+				let evaluates: Vec<String> = connection.evaluates.iter().map(|shebang| quote(shebang)).collect();
+				return Ok(format!(", \"evaluate\": [{}], \"framing\": {}", evaluates.join(", "), quote(framing.name())))
+			},
+			_ => {}
+		}
+		let number = |key: &str| match request.get(key).map(|node| &node.value) {
+			Some(Value::Number(number)) => number.parse::<u64>().ok(),
+			_ => None
+		};
+		let session = || number("session").ok_or_else(|| String::from("the request needs a session number"));
+		let rendered = |snippet: &Snippet| format!(", \"text\": {}", quote(&snippet.to_string()));
+		let mut sessions = self.sessions.lock().unwrap_or_else(PoisonError::into_inner);
+		let current = connection.session.and_then(|session| Some((session, sessions.snippets.get_mut(&session)?)))
+			.ok_or_else(|| String::from("no snippet has been expanded"));
+		match text("command") {
+			Some("set-variable") => {
+				let (Some(name), Some(value)) = (text("name"), text("value")) else {
+					return Err(String::from("set-variable needs a name and a value"))
+				};
+				if !matches!(variable_source(name), VariableSource::Client) {
+					return Err(format!("{} is not a client variable", name))
+				}
+				match connection.variables.iter().position(|(known, _)| known == name) {
+					Some(index) => connection.variables[index].1 = value.to_string(),
+					None if connection.variables.len() < self.limits.variables => connection.variables.push((name.to_string(), value.to_string())),
+					None => return Err(String::from("the connection gave values to too many variables"))
+				}
+				let Ok((_, snippet)) = current else {
+					return Ok(String::new())
+				};
+				snippet.resolve_variables(&|variable: &str| (variable == name).then(|| value.to_string()));
+				Ok(format!(", \"edits\": {}", edits(&snippet.render_delta())))
+			},
+			Some("set-field") => {
+				let (Some(tab), Some(value)) = (number("tab").and_then(|tab| u8::try_from(tab).ok()), text("text")) else {
+					return Err(String::from("set-field needs a tab and a text"))
+				};
+				let (session, snippet) = current?;
+				let errors = snippet.set_field_text(tab, value).ok_or_else(|| format!("there is no tab {}", tab))?;
+				self.tell(session, &observe::edited(snippet, tab));
+				let errors: Vec<String> = errors.iter().map(|error| quote(&error.to_string())).collect();
+				Ok(format!(", \"edits\": {}, \"errors\": [{}]", edits(&snippet.render_delta()), errors.join(", ")))
+			},
+			Some("next-tab") => {
+				let (session, snippet) = current?;
+				Ok(match snippet.next_tab() {
+					Some(stop) => {
+						self.tell(session, &SessionEvent::TabEntered(stop.tab.num()));
+						match stop.range {
+							Some(range) => format!(", \"tab\": {}, \"start\": {}, \"end\": {}", stop.tab.num(), range.start, range.end),
+							None => format!(", \"tab\": {}, \"start\": null, \"end\": null", stop.tab.num())
+						}
+					},
+					None => String::from(", \"tab\": null")
+				})
+			},
+			Some("render") => {
+				let (_, snippet) = current?;
+				snippet.render_delta();
+				Ok(rendered(snippet))
+			},
+			Some("sessions") => {
+				let states: Vec<String> = sessions.snippets.iter().map(|(&session, snippet)| format!("{{{}}}", state(session, snippet))).collect();
+				Ok(format!(", \"sessions\": [{}]", states.join(", ")))
+			},
+			Some("query") => {
+				let session = session()?;
+				let snippet = sessions.snippets.get(&session).ok_or_else(|| format!("there is no session {}", session))?;
+				Ok(format!(", {}", state(session, snippet)))
+			},
+			Some("resume") => {
+				let session = session()?;
+				let snippet = sessions.snippets.get_mut(&session).ok_or_else(|| format!("there is no session {}", session))?;
+				// The client is given the whole text, so edits are of it from now on.
+				snippet.render_delta();
+				connection.session = Some(session);
+				Ok(format!(", {}", state(session, snippet)))
+			},
+			Some("cancel") => {
+				let session = session()?;
+				sessions.snippets.remove(&session).ok_or_else(|| format!("there is no session {}", session))?;
+				if connection.session == Some(session) {
+					connection.session = None;
+				}
+				self.tell(session, &SessionEvent::Cancelled);
+				Ok(String::new())
+			},
+			Some(_) => Err(String::from("unknown command")),
+			None => Err(String::from("request has no command"))
+		}
+	}
+
+	/// Expands the snippet of the request as a new session of the connection, running its code
+	/// (see [`SnippetDaemon::with_code_runner`]) and having the client evaluate that of the interpreters it evaluates.
+	fn expand(&self, connection: &mut Connection, channel: &mut Channel, request: &Node) -> Result<String, String> {
+		let text = |key: &str| request.get(key).and_then(Node::as_str);
+		let mut snippet = match (text("body"), text("trigger")) {
+			(Some(body), _) => Snippet::parse(body).map_err(|error| error.to_string())?,
+			(None, Some(trigger)) => match self.library.find(trigger).next().map(|definition| &definition.kind) {
+				Some(SnippetKind::Dynamic(snippet)) => snippet.deep_clone(),
+				Some(SnippetKind::Static(text)) => SnippetBuilder::new().text(text).build().map_err(|error| error.to_string())?,
+				None => return Err(format!("no snippet with the trigger {}", trigger))
+			},
+			(None, None) => return Err(String::from("expand needs a body or a trigger"))
+		};
+		let standard = StandardVariables { environment: false, ..StandardVariables::default() };
+		let variable = |name: &str| connection.variables.iter().find(|(known, _)| known == name).map(|(_, value)| value.clone())
+			.or_else(|| standard.resolve(name));
+		snippet.resolve_variables(&variable);
+		// Options of choices are produced as the snippet's code and variables are, code only being run when code expansions are.
+		snippet.resolve_choices(|source| match source {
+			ChoiceSource::Variable(name) => variable(name),
+			#[cfg(feature = "exec")]
+			ChoiceSource::Code { code, shebang } => {
+				let mut code = Code { code: code.clone(), output: String::new(), shebang: shebang.clone() };
+				match self.runner.as_ref() {
+					_ if connection.evaluates.contains(&code.shebang) => channel.evaluate(&code.shebang, &code).ok(),
+					Some(runner) => runner.run(&mut code).ok().map(str::to_string),
+					None => None
+				}
+			},
+			#[cfg(not(feature = "exec"))]
+			ChoiceSource::Code { .. } => None
+		});
+		// Code is run before the session has a number, so what it did is told once the session started.
+		#[cfg(feature = "exec")]
+		let mut ran: Vec<(u64, SessionEvent)> = Vec::new();
+		#[cfg(feature = "exec")]
+		let errors: Vec<String> = if self.runner.is_some() || !connection.evaluates.is_empty() {
+			let failed = snippet.observe(0, &mut ran).run_code_expansions_delegating(self.runner.as_ref(), &connection.evaluates, |shebang, code| channel.evaluate(shebang, code));
+			failed.iter().map(|error| quote(&error.to_string())).collect()
+		} else {
+			Vec::new()
+		};
+		#[cfg(not(feature = "exec"))]
+		let errors: Vec<String> = {
+			let _ = channel;
+			Vec::new()
+		};
+		snippet.render_delta();
+		let mut sessions = self.sessions.lock().unwrap_or_else(PoisonError::into_inner);
+		if let Some(ended) = connection.session {
+			if sessions.snippets.remove(&ended).is_some() {
+				self.tell(ended, &SessionEvent::Finalized);
+			}
+			connection.session = None;
+		}
+		if sessions.snippets.len() >= self.limits.sessions {
+			return Err(String::from("the daemon keeps too many sessions; cancel some"))
+		}
+		sessions.next += 1;
+		let session = sessions.next;
+		let response = format!(", \"text\": {}, \"session\": {}, \"errors\": [{}]", quote(&snippet.to_string()), session, errors.join(", "));
+		sessions.snippets.insert(session, snippet);
+		connection.session = Some(session);
+		self.tell(session, &SessionEvent::Started);
+		#[cfg(feature = "exec")]
+		for (_, event) in &ran {
+			self.tell(session, event);
+		}
+		Ok(response)
+	}
+}
+
+/// Tab selected by the daemon, see [`crate::navigate::TabStop`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SelectedTab {
+	pub num: u8,
+	/// Byte range of the tab's field within the rendered text. None when the field is not rendered.
+	pub range: Option<Range<usize>>
+}
+
+/// State of a session of the daemon.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionState {
+	pub session: u64,
+	/// Number of the selected tab. None before a tab is selected.
+	pub tab: Option<u8>,
+	/// Number of each tab along with the text of its field.
+	pub fields: Vec<(u8, String)>,
+	/// The rendered text of the snippet.
+	pub text: String
+}
+
+/// The number of the key if the object has it.
+fn number<T: FromStr>(object: &Node, key: &str) -> Result<Option<T>, IpcError> {
+	match object.get(key).map(|node| &node.value) {
+		Some(Value::Number(number)) => number.parse().map(Some).map_err(|_| IpcError::Protocol("invalid number")),
+		_ => Ok(None)
+	}
+}
+
+impl SessionState {
+	fn read(object: &Node) -> Result<Self, IpcError> {
+		let session = number(object, "session")?.ok_or(IpcError::Protocol("state has no session"))?;
+		let Some(Value::Array(fields)) = object.get("fields").map(|node| &node.value) else {
+			return Err(IpcError::Protocol("state has no fields"))
+		};
+		let fields = fields.iter().map(|field| {
+			let tab = number(field, "tab")?.ok_or(IpcError::Protocol("field has no tab"))?;
+			Ok((tab, SnippetClient::text(field)?))
+		}).collect::<Result<_, IpcError>>()?;
+		Ok(SessionState { session, tab: number(object, "tab")?, fields, text: SnippetClient::text(object)? })
+	}
+}
+
+/// Evaluates code the daemon delegates to the client, given its shebang and the code. Gives the output or why there is none.
+pub type Evaluator = Box<dyn FnMut(&str, &str) -> Result<String, String>>;
+
+/// Sends requests to a [`SnippetDaemon`].
+pub struct SnippetClient {
+	reader: BufReader<UnixStream>,
+	writer: UnixStream,
+	session: Option<u64>,
+	evaluator: Option<Evaluator>,
+	framing: Framing
+}
+
+impl fmt::Debug for SnippetClient {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("SnippetClient")
+			.field("reader", &self.reader)
+			.field("writer", &self.writer)
+			.field("session", &self.session)
+			.field("framing", &self.framing)
+			.finish_non_exhaustive()
+	}
+}
+
+impl SnippetClient {
+	/// Connects to the daemon listening on the socket at the path.
+	pub fn connect(path: impl AsRef<Path>) -> Result<Self, IpcError> {
+		let writer = UnixStream::connect(path)?;
+		Ok(SnippetClient { reader: BufReader::new(writer.try_clone()?), writer, session: None, evaluator: None, framing: Framing::Json })
+	}
+
+	/// Sends the request, giving the response when it is ok. Evaluates the code the daemon delegates while awaiting it.
+	fn request(&mut self, request: &str) -> Result<Node, IpcError> {
+		write_message(&mut self.writer, self.framing, request)?;
+		let response = loop {
+			let message = self.receive()?;
+			let Some(code) = message.get("evaluate").and_then(Node::as_str) else {
+				break message
+			};
+			let shebang = message.get("shebang").and_then(Node::as_str).unwrap_or_default();
+			let evaluated = match &mut self.evaluator {
+				Some(evaluator) => evaluator(shebang, code),
+				None => Err(String::from("the client does not evaluate code"))
+			};
+			let evaluated = match evaluated {
+				Ok(output) => format!("{{\"command\": \"evaluated\", \"output\": {}}}", quote(&output)),
+				Err(error) => format!("{{\"command\": \"evaluated\", \"error\": {}}}", quote(&error))
+			};
+			write_message(&mut self.writer, self.framing, &evaluated)?;
+		};
+		match response.get("ok").map(|ok| &ok.value) {
+			Some(Value::Bool(true)) => Ok(response),
+			Some(Value::Bool(false)) => Err(IpcError::Daemon(response.get("error").and_then(Node::as_str).unwrap_or_default().to_string())),
+			_ => Err(IpcError::Protocol("response has no ok"))
+		}
+	}
+
+	/// Reads the next message from the daemon.
+	fn receive(&mut self) -> Result<Node, IpcError> {
+		let closed = || IpcError::Io(io::Error::new(io::ErrorKind::UnexpectedEof, "daemon closed the connection"));
+		if self.framing == Framing::MessagePack {
+			return msgpack::read(&mut self.reader, usize::MAX)?.ok_or_else(closed)
+		}
+		let mut line = String::new();
+		if self.reader.read_line(&mut line)? == 0 {
+			return Err(closed())
+		}
+		json::parse(&line).map_err(|error| IpcError::Protocol(error.message))
+	}
+
+	/// Has the daemon write messages in the framing from now on, as this client does. Fails when the daemon does not agree to it.
+	pub fn use_framing(&mut self, framing: Framing) -> Result<(), IpcError> {
+		let response = self.request(&format!("{{\"command\": \"hello\", \"framing\": {}}}", quote(framing.name())))?;
+		if response.get("framing").and_then(Node::as_str) != Some(framing.name()) {
+			return Err(IpcError::Protocol("daemon did not agree to the framing"))
+		}
+		self.framing = framing;
+		Ok(())
+	}
+
+	fn text(response: &Node) -> Result<String, IpcError> {
+		response.get("text").and_then(Node::as_str).map(str::to_string).ok_or(IpcError::Protocol("response has no text"))
+	}
+
+	/// Sends the expand request, keeping the number of the session it begins.
+	fn expand_request(&mut self, request: &str) -> Result<String, IpcError> {
+		let response = self.request(request)?;
+		self.session = Some(number(&response, "session")?.ok_or(IpcError::Protocol("response has no session"))?);
+		Self::text(&response)
+	}
+
+	/// Expands the body, giving the rendered text.
+	pub fn expand(&mut self, body: &str) -> Result<String, IpcError> {
+		self.expand_request(&format!("{{\"command\": \"expand\", \"body\": {}}}", quote(body)))
+	}
+
+	/// Expands the snippet of the daemon's library with the trigger, giving the rendered text.
+	pub fn expand_trigger(&mut self, trigger: &str) -> Result<String, IpcError> {
+		self.expand_request(&format!("{{\"command\": \"expand\", \"trigger\": {}}}", quote(trigger)))
+	}
+
+	/// Sends the token of a daemon that needs one, see [`SnippetDaemon::with_token`].
+	pub fn authenticate(&mut self, token: &str) -> Result<(), IpcError> {
+		self.request(&format!("{{\"command\": \"hello\", \"token\": {}}}", quote(token)))?;
+		Ok(())
+	}
+
+	/// Has the daemon delegate code of the shebangs to the evaluator rather than run it, giving the shebangs it delegates.
+	/// None are delegated when the daemon is built without the `exec` feature.
+	pub fn evaluate_with(&mut self, shebangs: &[&str], evaluator: impl FnMut(&str, &str) -> Result<String, String> + 'static) -> Result<Vec<String>, IpcError> {
+		let shebangs: Vec<String> = shebangs.iter().map(|shebang| quote(shebang)).collect();
+		let response = self.request(&format!("{{\"command\": \"hello\", \"evaluate\": [{}]}}", shebangs.join(", ")))?;
+		self.evaluator = Some(Box::new(evaluator));
+		let Some(Value::Array(delegated)) = response.get("evaluate").map(|node| &node.value) else {
+			return Err(IpcError::Protocol("response has no evaluate"))
+		};
+		delegated.iter().map(|shebang| shebang.as_str().map(str::to_string).ok_or(IpcError::Protocol("invalid shebang"))).collect()
+	}
+
+	/// Number of the session of the snippet this client expanded or resumed last,
+	/// to resume it with after reconnecting. None when there is none.
+	pub fn current_session(&self) -> Option<u64> {
+		self.session
+	}
+
+	pub fn set_variable(&mut self, name: &str, value: &str) -> Result<(), IpcError> {
+		self.request(&format!("{{\"command\": \"set-variable\", \"name\": {}, \"value\": {}}}", quote(name), quote(value)))?;
+		Ok(())
+	}
+
+	/// Sets the text of the tab's field, giving the edits turning the text last given to this client into the text rendered now
+	/// (see [`Snippet::render_delta`]).
+	pub fn set_field(&mut self, tab: u8, text: &str) -> Result<Vec<TextEdit>, IpcError> {
+		let response = self.request(&format!("{{\"command\": \"set-field\", \"tab\": {}, \"text\": {}}}", tab, quote(text)))?;
+		let Some(Value::Array(edits)) = response.get("edits").map(|node| &node.value) else {
+			return Err(IpcError::Protocol("response has no edits"))
+		};
+		edits.iter().map(|edit| {
+			let number = |key: &str| number(edit, key)?.ok_or(IpcError::Protocol("edit has no range"));
+			let range = Span { bytes: number("start")?..number("end")?, utf16: number("start_utf16")?..number("end_utf16")? };
+			Ok(TextEdit { range, text: Self::text(edit)? })
+		}).collect()
+	}
+
+	/// Selects the next tab. None after the last tab.
+	pub fn next_tab(&mut self) -> Result<Option<SelectedTab>, IpcError> {
+		let response = self.request("{\"command\": \"next-tab\"}")?;
+		let Some(num) = number(&response, "tab")? else {
+			return Ok(None)
+		};
+		let range = match (number(&response, "start")?, number(&response, "end")?) {
+			(Some(start), Some(end)) => Some(start..end),
+			_ => None
+		};
+		Ok(Some(SelectedTab { num, range }))
+	}
+
+	/// The rendered text of the snippet expanded last.
+	pub fn render(&mut self) -> Result<String, IpcError> {
+		let response = self.request("{\"command\": \"render\"}")?;
+		Self::text(&response)
+	}
+
+	/// The state of every session of the daemon, including those of other connections.
+	pub fn sessions(&mut self) -> Result<Vec<SessionState>, IpcError> {
+		let response = self.request("{\"command\": \"sessions\"}")?;
+		let Some(Value::Array(sessions)) = response.get("sessions").map(|node| &node.value) else {
+			return Err(IpcError::Protocol("response has no sessions"))
+		};
+		sessions.iter().map(SessionState::read).collect()
+	}
+
+	pub fn query(&mut self, session: u64) -> Result<SessionState, IpcError> {
+		let response = self.request(&format!("{{\"command\": \"query\", \"session\": {}}}", session))?;
+		SessionState::read(&response)
+	}
+
+	/// Makes the session that of this client, such as one expanded before the connection was lost, giving its state.
+	pub fn resume(&mut self, session: u64) -> Result<SessionState, IpcError> {
+		let response = self.request(&format!("{{\"command\": \"resume\", \"session\": {}}}", session))?;
+		self.session = Some(session);
+		SessionState::read(&response)
+	}
+
+	/// Ends the session.
+	pub fn cancel(&mut self, session: u64) -> Result<(), IpcError> {
+		self.request(&format!("{{\"command\": \"cancel\", \"session\": {}}}", session))?;
+		if self.session == Some(session) {
+			self.session = None;
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::library::SnippetDefinition;
+
+	#[test]
+	fn drive_daemon() {
+		let path = std::env::temp_dir().join(format!("snippet-parse-ipc-{}.sock", std::process::id()));
+		let _ = std::fs::remove_file(&path);
+		let listener = UnixListener::bind(&path).unwrap();
+		let daemon = std::thread::spawn(move || {
+			let library: SnippetLibrary = [SnippetDefinition::new(vec![String::from("hi")], None, Snippet::parse("hi ${1:$TM_SELECTED_TEXT}$0").unwrap())].into_iter().collect();
+			SnippetDaemon::new(listener, library).serve()
+		});
+		// A client that sends nothing does not hold up the others.
+		let idle = SnippetClient::connect(&path).unwrap();
+		let mut client = SnippetClient::connect(&path).unwrap();
+		client.set_variable("TM_SELECTED_TEXT", "you").unwrap();
+		assert_eq!(client.expand_trigger("hi").unwrap(), "hi you");
+		assert_eq!(client.next_tab().unwrap(), Some(SelectedTab { num: 1, range: Some(3..6) }));
+		client.set_variable("TM_SELECTED_TEXT", "me").unwrap();
+		assert_eq!(client.render().unwrap(), "hi me");
+		assert!(matches!(client.set_variable("HOME", "/"), Err(IpcError::Daemon(_))));
+		assert_eq!(client.expand("[$HOME]").unwrap(), "[HOME]");
+		assert_eq!(client.expand("a \"${1:b}\"").unwrap(), "a \"b\"");
+		assert_eq!(client.set_field(1, "bc").unwrap(), [TextEdit { range: Span { bytes: 4..4, utf16: 4..4 }, text: String::from("c") }]);
+		assert!(matches!(client.set_field(2, "x"), Err(IpcError::Daemon(_))));
+		assert!(matches!(client.expand_trigger("nope"), Err(IpcError::Daemon(_))));
+		assert!(client.expand(&"x".repeat(MAX_REQUEST + 1)).is_err());
+		assert!(client.render().is_err());
+		drop((idle, client));
+		assert!(!daemon.is_finished());
+		std::fs::remove_file(&path).unwrap();
+	}
+
+	#[test]
+	fn resume_sessions() {
+		let path = std::env::temp_dir().join(format!("snippet-parse-sessions-{}.sock", std::process::id()));
+		let _ = std::fs::remove_file(&path);
+		let listener = UnixListener::bind(&path).unwrap();
+		let (observer, events) = std::sync::mpsc::channel();
+		std::thread::spawn(move || SnippetDaemon::new(listener, SnippetLibrary::default()).with_observer(observer).serve());
+		let mut lost = SnippetClient::connect(&path).unwrap();
+		assert_eq!(lost.expand("${1:a} ${2:b}").unwrap(), "a b");
+		let session = lost.current_session().unwrap();
+		lost.next_tab().unwrap();
+		drop(lost);
+
+		let mut client = SnippetClient::connect(&path).unwrap();
+		client.use_framing(Framing::MessagePack).unwrap();
+		let state = SessionState { session, tab: Some(1), fields: vec![(1, String::from("a")), (2, String::from("b"))], text: String::from("a b") };
+		assert_eq!(client.sessions().unwrap(), std::slice::from_ref(&state));
+		assert!(client.render().is_err());
+		assert_eq!(client.resume(session).unwrap(), state);
+		assert_eq!(client.next_tab().unwrap().map(|tab| tab.num), Some(2));
+		assert_eq!(client.query(session).unwrap().tab, Some(2));
+		client.cancel(session).unwrap();
+		assert!(client.render().is_err());
+		assert!(matches!(client.query(session), Err(IpcError::Daemon(_))));
+		assert!(client.sessions().unwrap().is_empty());
+		let events: Vec<(u64, SessionEvent)> = events.try_iter().collect();
+		assert_eq!(events, [(session, SessionEvent::Started), (session, SessionEvent::TabEntered(1)), (session, SessionEvent::TabEntered(2)), (session, SessionEvent::Cancelled)]);
+		std::fs::remove_file(&path).unwrap();
+	}
+
+	#[test]
+	fn limit_clients() {
+		let path = std::env::temp_dir().join(format!("snippet-parse-limits-{}.sock", std::process::id()));
+		let _ = std::fs::remove_file(&path);
+		let limits = DaemonLimits { connections: 1, sessions: 1, variables: 1, requests_per_second: 20, burst: 2 };
+		let daemon = SnippetDaemon::bind(&path, SnippetLibrary::default()).unwrap().with_limits(limits);
+		std::thread::spawn(move || daemon.serve());
+		let mut client = SnippetClient::connect(&path).unwrap();
+		let started = Instant::now();
+		for _ in 0..6 {
+			client.expand("a").unwrap();
+		}
+		assert!(started.elapsed() >= Duration::from_millis(200));
+		client.set_variable("TM_A", "a").unwrap();
+		client.set_variable("TM_A", "b").unwrap();
+		assert!(matches!(client.set_variable("TM_B", "a"), Err(IpcError::Daemon(_))));
+		let mut refused = SnippetClient::connect(&path).unwrap();
+		assert!(matches!(refused.render(), Err(IpcError::Daemon(message)) if message.contains("too many connections")));
+		let session = client.current_session().unwrap();
+		drop(client);
+		// The session of the client that disconnected is kept, leaving no room for another.
+		let mut client = loop {
+			let mut client = SnippetClient::connect(&path).unwrap();
+			if client.sessions().is_ok() {
+				break client
+			}
+		};
+		assert!(matches!(client.expand("b"), Err(IpcError::Daemon(_))));
+		client.cancel(session).unwrap();
+		assert_eq!(client.expand("b").unwrap(), "b");
+		std::fs::remove_file(&path).unwrap();
+	}
+
+	#[test]
+	fn authenticate_clients() {
+		let path = std::env::temp_dir().join(format!("snippet-parse-token-{}.sock", std::process::id()));
+		let _ = std::fs::remove_file(&path);
+		let daemon = SnippetDaemon::bind(&path, SnippetLibrary::default()).unwrap().with_token("secret");
+		assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+		assert!(SnippetDaemon::bind(&path, SnippetLibrary::default()).is_err());
+		std::thread::spawn(move || daemon.serve());
+		let mut refused = SnippetClient::connect(&path).unwrap();
+		assert!(matches!(refused.expand("a"), Err(IpcError::Daemon(_))));
+		assert!(refused.render().is_err());
+		let mut guessing = SnippetClient::connect(&path).unwrap();
+		assert!(guessing.authenticate("guess").is_err());
+		let mut client = SnippetClient::connect(&path).unwrap();
+		client.authenticate("secret").unwrap();
+		assert_eq!(client.expand("a").unwrap(), "a");
+		std::fs::remove_file(&path).unwrap();
+	}
+
+	#[test]
+	#[cfg(feature = "exec")]
+	fn delegate_code() {
+		use crate::exec::PYTHON_SHEBANG;
+		let path = std::env::temp_dir().join(format!("snippet-parse-delegate-{}.sock", std::process::id()));
+		let _ = std::fs::remove_file(&path);
+		let listener = UnixListener::bind(&path).unwrap();
+		std::thread::spawn(move || {
+			let snippet = Snippet::parse_with(crate::parse::SnippetSyntax::UltiSnips, "`!p snip.rv = 1`-`echo b`").unwrap();
+			let mut branches = Snippet::parse("${1|main|}").unwrap();
+			branches.set_choice_source(1, ChoiceSource::Code { code: String::from("branches()"), shebang: String::from(PYTHON_SHEBANG) });
+			let library: SnippetLibrary = [
+				SnippetDefinition::new(vec![String::from("c")], None, snippet),
+				SnippetDefinition::new(vec![String::from("b")], None, branches)
+			].into_iter().collect();
+			SnippetDaemon::new(listener, library).serve()
+		});
+		let mut client = SnippetClient::connect(&path).unwrap();
+		// Without a runner, the daemon runs none of the code itself.
+		assert_eq!(client.expand_trigger("c").unwrap(), "-");
+		assert_eq!(client.expand_trigger("b").unwrap(), "main");
+		let delegated = client.evaluate_with(&[PYTHON_SHEBANG], |shebang, code| match (shebang, code) {
+			(PYTHON_SHEBANG, "snip.rv = 1") => Ok(String::from("one")),
+			(PYTHON_SHEBANG, "branches()") => Ok(String::from("dev\nfix")),
+			_ => Err(String::from("unexpected code"))
+		}).unwrap();
+		assert_eq!(delegated, [PYTHON_SHEBANG]);
+		assert_eq!(client.expand_trigger("c").unwrap(), "one-");
+		assert_eq!(client.expand_trigger("b").unwrap(), "dev");
+		std::fs::remove_file(&path).unwrap();
+	}
+}
@@ -0,0 +1,607 @@
+use crate::shared::Rc;
+use std::{fmt, fs, io};
+use std::path::Path;
+use crate::{Snippet, Segment, Field, Tab, Variable, VariableSource, Code, Expansion};
+use crate::library::{SnippetDefinition, SourceLocation};
+use crate::warning::{self, Warning, WarningKind};
+
+/// Outcome of importing a JetBrains (IntelliJ) live template file.
+#[derive(Debug)]
+pub struct Import {
+	/// Snippet definitions made from the templates of the file.
+	pub definitions: Vec<SnippetDefinition>,
+	/// Template variable expressions that have no equivalent within this library.
+	pub unmapped: Vec<Unmapped>,
+	/// Quality issues found in the templates.
+	pub warnings: Vec<Warning>
+}
+
+/// A template variable whose expression could not be mapped onto a variable or code block.
+/// Such a variable is imported as a placeholder holding its default value (if any) instead.
+#[derive(Debug)]
+pub struct Unmapped {
+	/// Name (abbreviation) of the template the variable belongs to.
+	pub template: String,
+	/// Name of the variable.
+	pub variable: String,
+	/// The expression that could not be mapped.
+	pub expression: String
+}
+
+/// Reasons a live template file could not be imported.
+#[derive(Debug)]
+pub enum ImportError {
+	/// The file could not be read.
+	Io(io::Error),
+	/// A tag opened at this byte offset is never closed.
+	UnterminatedTag(usize),
+	/// The `template` tag at this byte offset lacks the named attribute.
+	MissingAttribute(usize, &'static str)
+}
+
+impl fmt::Display for ImportError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			ImportError::Io(error) => write!(f, "{}", error),
+			ImportError::UnterminatedTag(offset) => write!(f, "unterminated tag at byte {}", offset),
+			ImportError::MissingAttribute(offset, name) => write!(f, "template at byte {} has no {} attribute", offset, name)
+		}
+	}
+}
+
+impl std::error::Error for ImportError {}
+
+impl From<io::Error> for ImportError {
+	fn from(error: io::Error) -> Self {
+		ImportError::Io(error)
+	}
+}
+
+/// Imports the live template file at the path, recording the path in every definition's source location.
+pub fn import_file(path: impl AsRef<Path>) -> Result<Import, ImportError> {
+	let path = path.as_ref();
+	let mut import = import(&fs::read_to_string(path)?)?;
+	for definition in &mut import.definitions {
+		if let Some(source) = &mut definition.source {
+			source.path = Some(path.to_path_buf());
+		}
+	}
+	Ok(import)
+}
+
+/// Imports every `<template>` of a live template XML file (a `<templateSet>`).
+///
+/// `$NAME$` references become placeholders numbered in the order their `<variable>` tags are declared,
+/// repeated references become mirrors of the same field, `$END$` becomes tab 0 and `$SELECTION$` the selected text variable.
+/// Expressions naming editor or environment values become variables and `groovyScript` expressions become code blocks.
+pub fn import(xml: &str) -> Result<Import, ImportError> {
+	let mut import = Import {
+		definitions: Vec::new(),
+		unmapped: Vec::new(),
+		warnings: Vec::new()
+	};
+	let mut template: Option<Template> = None;
+	for tag in Tags::new(xml) {
+		let tag = tag?;
+		match (tag.name.as_str(), tag.closing) {
+			("template", false) => {
+				let name = tag.attribute("name").ok_or(ImportError::MissingAttribute(tag.offset, "name"))?;
+				let value = tag.attribute("value").ok_or(ImportError::MissingAttribute(tag.offset, "value"))?;
+				let started = Template {
+					name: name.to_string(),
+					value: value.to_string(),
+					description: tag.attribute("description").map(str::to_string),
+					variables: Vec::new(),
+					line: line_of(xml, tag.offset)
+				};
+				if tag.self_closing {
+					let end_line = line_of(xml, tag.end);
+					import.definitions.push(started.into_definition(end_line, &mut import.unmapped, &mut import.warnings));
+				} else {
+					template = Some(started);
+				}
+			},
+			("template", true) => if let Some(finished) = template.take() {
+				let end_line = line_of(xml, tag.end);
+				import.definitions.push(finished.into_definition(end_line, &mut import.unmapped, &mut import.warnings));
+			},
+			("variable", false) => if let Some(template) = template.as_mut() {
+				if let Some(name) = tag.attribute("name") {
+					template.variables.push(Declaration {
+						name: name.to_string(),
+						expression: tag.attribute("expression").unwrap_or("").trim().to_string(),
+						default_value: tag.attribute("defaultValue").unwrap_or("").trim().to_string()
+					});
+				}
+			},
+			_ => {}
+		}
+	}
+	Ok(import)
+}
+
+/// Line (starting at 1) the byte offset is on.
+fn line_of(xml: &str, offset: usize) -> usize {
+	xml[..offset].matches('\n').count() + 1
+}
+
+/// A `<variable>` tag of a template.
+struct Declaration {
+	name: String,
+	expression: String,
+	default_value: String
+}
+
+/// A `<template>` tag and its `<variable>` children.
+struct Template {
+	name: String,
+	value: String,
+	description: Option<String>,
+	variables: Vec<Declaration>,
+	/// Line the template starts on.
+	line: usize
+}
+
+/// What a `$NAME$` reference within a template value turns into.
+enum Reference {
+	Field(Rc<Field>),
+	Variable(Rc<Variable>),
+	Code(Rc<Code>)
+}
+
+impl Template {
+	fn into_definition(self, end_line: usize, unmapped: &mut Vec<Unmapped>, warnings: &mut Vec<Warning>) -> SnippetDefinition {
+		let mut snippet = Snippet {
+			body: Vec::new(),
+			tabs: Vec::new(),
+			variables: Vec::new(),
+			code_expansions: Vec::new(),
+			named_segments: Vec::new(),
+			current_tab: None,
+			choice_sources: Vec::new(),
+			baseline: Default::default()
+		};
+		let mut warn = |kind| warnings.push(Warning {
+			trigger: self.name.clone(),
+			line: self.line,
+			kind
+		});
+		let (pieces, suspicious) = split_value(&self.value);
+		if suspicious {
+			warn(WarningKind::SuspiciousEscape(String::from("$")));
+		}
+		let mut names: Vec<&str> = self.variables.iter().map(|declaration| declaration.name.as_str()).collect();
+		for piece in &pieces {
+			if let Piece::Reference(name) = piece {
+				if !names.contains(name) {
+					if *name != "END" && *name != "SELECTION" {
+						warn(WarningKind::UnknownVariable(name.to_string()));
+					}
+					names.push(name);
+				}
+			}
+		}
+		let mut references: Vec<(&str, Reference)> = Vec::new();
+		let mut end = None;
+		// None once every tab number is taken, later fields not being tabs.
+		let mut num = Some(1u8);
+		for name in names {
+			let declaration = self.variables.iter().find(|declaration| declaration.name == name);
+			let expression = declaration.map_or("", |declaration| declaration.expression.as_str());
+			let reference = match name {
+				"END" => {
+					let field = Rc::new(Field::Placeholder(Vec::new()));
+					end = Some(Rc::downgrade(&field));
+					Reference::Field(field)
+				},
+				"SELECTION" => variable("TM_SELECTED_TEXT", VariableSource::Client),
+				_ => match map_expression(expression) {
+					Some(reference) => reference,
+					None => {
+						if !expression.is_empty() && literal(expression).is_none() {
+							unmapped.push(Unmapped {
+								template: self.name.clone(),
+								variable: name.to_string(),
+								expression: expression.to_string()
+							});
+						}
+						let default = literal(expression)
+							.or_else(|| declaration.and_then(|declaration| literal(&declaration.default_value)))
+							.unwrap_or_default();
+						let body = if default.is_empty() { Vec::new() } else { vec![Segment::Text(default)] };
+						let field = Rc::new(Field::Placeholder(body));
+						match num {
+							Some(n) => snippet.tabs.push(Tab {
+								num: n,
+								field: Rc::downgrade(&field),
+								transformations: Vec::new(),
+								label: Some(name.to_string())
+							}),
+							None => warn(WarningKind::TooManyTabs(name.to_string()))
+						}
+						num = num.and_then(|num| num.checked_add(1));
+						Reference::Field(field)
+					}
+				}
+			};
+			match &reference {
+				Reference::Variable(variable) => snippet.variables.push(Expansion {
+					expansion: Rc::downgrade(variable),
+					transformations: Vec::new()
+				}),
+				Reference::Code(code) => snippet.code_expansions.push(Expansion {
+					expansion: Rc::downgrade(code),
+					transformations: Vec::new()
+				}),
+				Reference::Field(_) => {}
+			}
+			references.push((name, reference));
+		}
+		if let Some(field) = end {
+			snippet.tabs.push(Tab {
+				num: 0,
+				field,
+				transformations: Vec::new(),
+				label: None
+			});
+		}
+		for piece in pieces {
+			snippet.body.push(match piece {
+				Piece::Text(text) => Segment::Text(text),
+				Piece::Reference(name) => match references.iter().find(|(reference, _)| *reference == name).map(|(_, reference)| reference) {
+					Some(Reference::Field(field)) => Segment::Field(field.clone()),
+					Some(Reference::Variable(variable)) => Segment::Variable(variable.clone()),
+					Some(Reference::Code(code)) => Segment::Code(code.clone()),
+					None => continue
+				}
+			});
+		}
+		if let Some(num) = warning::tab_number_gap(&snippet) {
+			warn(WarningKind::TabNumberGap(num));
+		}
+		let mut definition = SnippetDefinition::new(vec![self.name], self.description, snippet);
+		definition.source = Some(SourceLocation {
+			path: None,
+			start_line: self.line,
+			end_line
+		});
+		definition
+	}
+}
+
+fn variable(name: &str, source: VariableSource) -> Reference {
+	Reference::Variable(Rc::new(Variable {
+		name: name.to_string(),
+		value: String::new(),
+		source,
+		default: None
+	}))
+}
+
+/// Maps a template expression onto an equivalent variable or code block.
+fn map_expression(expression: &str) -> Option<Reference> {
+	let open = expression.find('(')?;
+	if !expression.ends_with(')') {
+		return None
+	}
+	let arguments = expression[open + 1..expression.len() - 1].trim();
+	Some(match (expression[..open].trim(), arguments) {
+		("fileName", "") => variable("TM_FILENAME", VariableSource::Client),
+		("fileNameWithoutExtension", "") => variable("TM_FILENAME_BASE", VariableSource::Client),
+		("filePath", "") => variable("TM_FILEPATH", VariableSource::Client),
+		("lineNumber", "") => variable("TM_LINE_NUMBER", VariableSource::Client),
+		("clipboard", "") => variable("CLIPBOARD", VariableSource::Client),
+		("user", "") => variable("USER", VariableSource::Daemon),
+		("groovyScript", script) => Reference::Code(Rc::new(Code {
+			code: literal(script)?,
+			output: String::new(),
+			shebang: String::from("#!/usr/bin/env groovy")
+		})),
+		_ => return None
+	})
+}
+
+/// Contents of a double quoted template expression string.
+fn literal(expression: &str) -> Option<String> {
+	let inner = expression.strip_prefix('"')?.strip_suffix('"')?;
+	let mut text = String::new();
+	let mut chars = inner.chars();
+	while let Some(c) = chars.next() {
+		match c {
+			'\\' => match chars.next() {
+				Some('n') => text.push('\n'),
+				Some('t') => text.push('\t'),
+				Some(escaped) => text.push(escaped),
+				None => text.push('\\')
+			},
+			'"' => return None,
+			_ => text.push(c)
+		}
+	}
+	Some(text)
+}
+
+enum Piece<'a> {
+	Text(String),
+	Reference(&'a str)
+}
+
+/// Splits a template value into literal text and `$NAME$` references (`$$` being a literal `$`).
+/// Also tells whether a `$` had to be taken literally without being escaped.
+fn split_value(value: &str) -> (Vec<Piece<'_>>, bool) {
+	let mut pieces = Vec::new();
+	let mut suspicious = false;
+	let mut text = String::new();
+	let mut rest = value;
+	while let Some(start) = rest.find('$') {
+		text.push_str(&rest[..start]);
+		let after = &rest[start + 1..];
+		match after.find('$') {
+			Some(0) => {
+				text.push('$');
+				rest = &after[1..];
+			},
+			Some(end) if after[..end].chars().all(|c| c.is_alphanumeric() || c == '_') => {
+				if !text.is_empty() {
+					pieces.push(Piece::Text(std::mem::take(&mut text)));
+				}
+				pieces.push(Piece::Reference(&after[..end]));
+				rest = &after[end + 1..];
+			},
+			_ => {
+				text.push('$');
+				suspicious = true;
+				rest = after;
+			}
+		}
+	}
+	text.push_str(rest);
+	if !text.is_empty() {
+		pieces.push(Piece::Text(text));
+	}
+	(pieces, suspicious)
+}
+
+/// An XML start, end or empty element tag.
+struct Tag {
+	name: String,
+	attributes: Vec<(String, String)>,
+	closing: bool,
+	self_closing: bool,
+	/// Byte offset of the opening `<`.
+	offset: usize,
+	/// Byte offset of the closing `>`.
+	end: usize
+}
+
+impl Tag {
+	fn attribute(&self, name: &str) -> Option<&str> {
+		self.attributes.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+	}
+}
+
+/// Iterator over the element tags of an XML document, skipping text, comments and declarations.
+struct Tags<'a> {
+	xml: &'a str,
+	pos: usize
+}
+
+impl<'a> Tags<'a> {
+	fn new(xml: &'a str) -> Self {
+		Tags { xml, pos: 0 }
+	}
+}
+
+impl Iterator for Tags<'_> {
+	type Item = Result<Tag, ImportError>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		loop {
+			let offset = self.pos + self.xml[self.pos..].find('<')?;
+			let rest = &self.xml[offset..];
+			let terminator = if rest.starts_with("<!--") {
+				"-->"
+			} else if rest.starts_with("<![CDATA[") {
+				"]]>"
+			} else if rest.starts_with("<?") || rest.starts_with("<!") {
+				">"
+			} else {
+				return Some(self.tag(offset))
+			};
+			match rest.find(terminator) {
+				Some(end) => self.pos = offset + end + terminator.len(),
+				None => {
+					self.pos = self.xml.len();
+					return Some(Err(ImportError::UnterminatedTag(offset)))
+				}
+			}
+		}
+	}
+}
+
+impl Tags<'_> {
+	fn tag(&mut self, offset: usize) -> Result<Tag, ImportError> {
+		let mut end = None;
+		let mut quote = None;
+		for (i, c) in self.xml[offset..].char_indices() {
+			match (quote, c) {
+				(None, '"') | (None, '\'') => quote = Some(c),
+				(Some(q), _) if q == c => quote = None,
+				(None, '>') => {
+					end = Some(offset + i);
+					break
+				},
+				_ => {}
+			}
+		}
+		let Some(end) = end else {
+			self.pos = self.xml.len();
+			return Err(ImportError::UnterminatedTag(offset))
+		};
+		self.pos = end + 1;
+		let mut inner = &self.xml[offset + 1..end];
+		let closing = inner.starts_with('/');
+		if closing {
+			inner = &inner[1..];
+		}
+		let self_closing = inner.ends_with('/');
+		if self_closing {
+			inner = &inner[..inner.len() - 1];
+		}
+		let name_end = inner.find(|c: char| c.is_whitespace()).unwrap_or(inner.len());
+		let mut tag = Tag {
+			name: inner[..name_end].to_string(),
+			attributes: Vec::new(),
+			closing,
+			self_closing,
+			offset,
+			end
+		};
+		let mut rest = inner[name_end..].trim_start();
+		while let Some(eq) = rest.find('=') {
+			let key = rest[..eq].trim().to_string();
+			let value = rest[eq + 1..].trim_start();
+			let Some(q) = value.chars().next().filter(|q| *q == '"' || *q == '\'') else {
+				break
+			};
+			let Some(close) = value[1..].find(q) else {
+				break
+			};
+			tag.attributes.push((key, decode_entities(&value[1..close + 1])));
+			rest = value[close + 2..].trim_start();
+		}
+		Ok(tag)
+	}
+}
+
+/// Replaces XML character and entity references with the characters they stand for.
+fn decode_entities(text: &str) -> String {
+	let mut decoded = String::with_capacity(text.len());
+	let mut rest = text;
+	while let Some(start) = rest.find('&') {
+		decoded.push_str(&rest[..start]);
+		rest = &rest[start..];
+		let Some(end) = rest.find(';') else {
+			break
+		};
+		let entity = &rest[1..end];
+		let c = match entity {
+			"lt" => Some('<'),
+			"gt" => Some('>'),
+			"amp" => Some('&'),
+			"quot" => Some('"'),
+			"apos" => Some('\''),
+			_ => if let Some(hex) = entity.strip_prefix("#x").or_else(|| entity.strip_prefix("#X")) {
+				u32::from_str_radix(hex, 16).ok().and_then(char::from_u32)
+			} else if let Some(dec) = entity.strip_prefix('#') {
+				dec.parse().ok().and_then(char::from_u32)
+			} else {
+				None
+			}
+		};
+		match c {
+			Some(c) => {
+				decoded.push(c);
+				rest = &rest[end + 1..];
+			},
+			None => {
+				decoded.push('&');
+				rest = &rest[1..];
+			}
+		}
+	}
+	decoded.push_str(rest);
+	decoded
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const TEMPLATES: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<templateSet group="Java">
+  <!-- printing -->
+  <template name="sout" value="System.out.println($END$);" description="Prints a string to System.out" toReformat="true" />
+  <template name="for" value="for (int $INDEX$ = 0; $INDEX$ &lt; $LIMIT$; $INDEX$++) {&#10;  $END$&#10;}" description="">
+    <variable name="INDEX" expression="suggestIndexName()" defaultValue="&quot;i&quot;" alwaysStopAt="true" />
+    <variable name="LIMIT" expression="&quot;n&quot;" defaultValue="" alwaysStopAt="true" />
+  </template>
+  <template name="hdr" value="// $FILE$ by $AUTHOR$ costs $$5 $STAMP$">
+    <variable name="FILE" expression="fileName()" defaultValue="" alwaysStopAt="false" />
+    <variable name="AUTHOR" expression="user()" defaultValue="" alwaysStopAt="false" />
+    <variable name="STAMP" expression="groovyScript(&quot;new Date().format(\&quot;yyyy\&quot;)&quot;)" defaultValue="" alwaysStopAt="false" />
+  </template>
+</templateSet>"#;
+
+	#[test]
+	fn import_templates() {
+		let import = import(TEMPLATES).unwrap();
+		assert_eq!(import.definitions.len(), 3);
+		let lines: Vec<_> = import.definitions.iter().map(|definition| definition.source.as_ref().map(|source| (source.start_line, source.end_line))).collect();
+		assert_eq!(lines, [Some((4, 4)), Some((5, 8)), Some((9, 13))]);
+		let sout = &import.definitions[0];
+		assert_eq!(sout.triggers, ["sout"]);
+		assert_eq!(sout.description.as_deref(), Some("Prints a string to System.out"));
+		let sout = sout.snippet().unwrap();
+		assert_eq!(sout.to_string(), "System.out.println();");
+		assert_eq!(sout.tabs().iter().map(|tab| tab.num).collect::<Vec<_>>(), [0]);
+		let for_loop = import.definitions[1].snippet().unwrap();
+		assert_eq!(for_loop.to_string(), "for (int i = 0; i < n; i++) {\n  \n}");
+		assert_eq!(for_loop.tabs().iter().map(|tab| tab.num).collect::<Vec<_>>(), [1, 2, 0]);
+		assert!(matches!(&for_loop.body()[1], Segment::Field(field) if Rc::ptr_eq(field, &for_loop.tabs()[0].field.upgrade().unwrap())));
+		assert_eq!(import.unmapped.len(), 1);
+		assert_eq!(import.unmapped[0].variable, "INDEX");
+		assert_eq!(import.unmapped[0].expression, "suggestIndexName()");
+	}
+
+	#[test]
+	fn map_expressions() {
+		let import = import(TEMPLATES).unwrap();
+		let header = import.definitions[2].snippet().unwrap();
+		assert!(header.tabs().is_empty());
+		let names: Vec<_> = header.variables().iter().map(|variable| variable.expansion.upgrade().unwrap().name.clone()).collect();
+		assert_eq!(names, ["TM_FILENAME", "USER"]);
+		assert_eq!(header.code_expansions()[0].expansion.upgrade().unwrap().code, "new Date().format(\"yyyy\")");
+		assert!(matches!(&header.body()[4], Segment::Text(text) if text == " costs $5 "));
+	}
+
+	#[test]
+	fn warn_about_quality_issues() {
+		let import = import("<templateSet>\n<template name=\"t\" value=\"$A$ $B$ costs $5\">\n<variable name=\"A\" expression=\"\" />\n</template>\n</templateSet>").unwrap();
+		let kinds: Vec<_> = import.warnings.iter().map(|warning| (warning.line, &warning.kind)).collect();
+		assert_eq!(kinds, [(2, &WarningKind::SuspiciousEscape(String::from("$"))), (2, &WarningKind::UnknownVariable(String::from("B")))]);
+		assert!(super::import("<templateSet>\n<template name=\"t\" value=\"$END$\" /></templateSet>").unwrap().warnings.is_empty());
+	}
+
+	#[test]
+	fn run_out_of_tab_numbers() {
+		let names: Vec<String> = (0..300).map(|i| format!("V{}", i)).collect();
+		let value: String = names.iter().map(|name| format!("${}$", name)).collect();
+		let variables: String = names.iter().map(|name| format!("<variable name=\"{}\" expression=\"\"/>", name)).collect();
+		let import = import(&format!("<templateSet><template name=\"t\" value=\"{}\">{}</template></templateSet>", value, variables)).unwrap();
+		let snippet = import.definitions[0].snippet().unwrap();
+		assert_eq!(snippet.tabs().len(), 255);
+		assert_eq!(snippet.tabs().last().unwrap().num, 255);
+		assert_eq!(import.warnings.len(), 45);
+		assert_eq!(import.warnings[0].kind, WarningKind::TooManyTabs(String::from("V255")));
+		assert!(snippet.verify().is_ok());
+	}
+
+	#[test]
+	fn reject_malformed() {
+		assert!(matches!(import("<templateSet><template value=\"x\"/>"), Err(ImportError::MissingAttribute(13, "name"))));
+		assert!(matches!(import("<templateSet><template name=\"x\""), Err(ImportError::UnterminatedTag(13))));
+		assert!(matches!(import_file("/nonexistent/templates.xml"), Err(ImportError::Io(_))));
+	}
+
+	#[test]
+	fn handle_multi_byte_text() {
+		let xml = "<templateSet><template name=\"ü\" value=\"π $名前$ → $名前$ 🎉$END$\" description=\"説明\"><variable name=\"名前\" expression=\"&quot;é✓&quot;\"/></template></templateSet>";
+		let import = import(xml).unwrap();
+		assert_eq!(import.definitions[0].triggers, ["ü"]);
+		assert_eq!(import.definitions[0].snippet().unwrap().to_string(), "π é✓ → é✓ 🎉");
+		for (i, _) in xml.char_indices() {
+			let _ = super::import(&xml[..i]);
+		}
+	}
+}
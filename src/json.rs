@@ -0,0 +1,292 @@
+//! Reader for JSON documents, along with the comments and trailing commas VSCode permits in its snippet files.
+
+use std::cell::Cell;
+use crate::parse::MAX_NESTING;
+
+/// A value read from a JSON document along with the lines (starting at 1) it begins and ends on.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Node {
+	pub(crate) line: usize,
+	pub(crate) end: usize,
+	pub(crate) value: Value
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum Value {
+	Null,
+	Bool(bool),
+	/// Numbers are kept as written, as nothing reading snippets does arithmetic with them.
+	Number(String),
+	String(String),
+	Array(Vec<Node>),
+	Object(Vec<(String, Node)>)
+}
+
+/// Why a document could not be read, and the line (starting at 1) where that was noticed.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Error {
+	pub(crate) line: usize,
+	pub(crate) message: &'static str
+}
+
+impl Node {
+	/// Value of the key if this node is an object containing it.
+	pub(crate) fn get(&self, key: &str) -> Option<&Node> {
+		match &self.value {
+			Value::Object(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, node)| node),
+			_ => None
+		}
+	}
+
+	pub(crate) fn as_str(&self) -> Option<&str> {
+		match &self.value {
+			Value::String(text) => Some(text),
+			_ => None
+		}
+	}
+}
+
+pub(crate) fn parse(text: &str) -> Result<Node, Error> {
+	let mut reader = Reader { text, pos: 0, depth: 0, lines: Cell::new((0, 1)) };
+	let node = reader.node()?;
+	reader.skip_trivia()?;
+	if reader.pos < text.len() {
+		return Err(reader.error("unexpected text after value"))
+	}
+	Ok(node)
+}
+
+struct Reader<'a> {
+	text: &'a str,
+	pos: usize,
+	/// Number of values being read.
+	depth: usize,
+	/// A position the reader has been at along with its line, newlines being counted from there as the reader advances
+	/// rather than from the start of the text.
+	lines: Cell<(usize, usize)>
+}
+
+impl<'a> Reader<'a> {
+	fn rest(&self) -> &'a str {
+		&self.text[self.pos..]
+	}
+
+	fn peek(&self) -> Option<char> {
+		self.rest().chars().next()
+	}
+
+	fn line(&self) -> usize {
+		let (counted, line) = self.lines.get();
+		let line = line + self.text[counted..self.pos].matches('\n').count();
+		self.lines.set((self.pos, line));
+		line
+	}
+
+	fn error(&self, message: &'static str) -> Error {
+		Error { line: self.line(), message }
+	}
+
+	/// Skips whitespace and comments.
+	fn skip_trivia(&mut self) -> Result<(), Error> {
+		loop {
+			let rest = self.rest();
+			let trimmed = rest.trim_start();
+			self.pos += rest.len() - trimmed.len();
+			if trimmed.starts_with("//") {
+				self.pos += trimmed.find('\n').unwrap_or(trimmed.len());
+			} else if let Some(comment) = trimmed.strip_prefix("/*") {
+				let Some(end) = comment.find("*/") else {
+					return Err(self.error("unterminated comment"))
+				};
+				self.pos += end + 4;
+			} else {
+				return Ok(())
+			}
+		}
+	}
+
+	fn node(&mut self) -> Result<Node, Error> {
+		if self.depth == MAX_NESTING {
+			return Err(self.error("arrays and objects are nested too deeply"))
+		}
+		self.depth += 1;
+		let node = self.value();
+		self.depth -= 1;
+		node
+	}
+
+	fn value(&mut self) -> Result<Node, Error> {
+		self.skip_trivia()?;
+		let line = self.line();
+		let value = match self.peek() {
+			Some('{') => {
+				self.pos += 1;
+				let mut entries = Vec::new();
+				let mut first = true;
+				while self.item('}', &mut first)? {
+					let Value::String(key) = self.node()?.value else {
+						return Err(self.error("expected string key"))
+					};
+					self.skip_trivia()?;
+					if self.peek() != Some(':') {
+						return Err(self.error("expected : after key"))
+					}
+					self.pos += 1;
+					entries.push((key, self.node()?));
+				}
+				Value::Object(entries)
+			},
+			Some('[') => {
+				self.pos += 1;
+				let mut items = Vec::new();
+				let mut first = true;
+				while self.item(']', &mut first)? {
+					items.push(self.node()?);
+				}
+				Value::Array(items)
+			},
+			Some('"') => Value::String(self.string()?),
+			Some(c) if c == '-' || c.is_ascii_digit() => {
+				let len = self.rest().find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '+' | '.'))).unwrap_or(self.rest().len());
+				let number = &self.rest()[..len];
+				if number.parse::<f64>().is_err() {
+					return Err(self.error("invalid number"))
+				}
+				self.pos += len;
+				Value::Number(number.to_string())
+			},
+			_ => {
+				let rest = self.rest();
+				let (value, len) = if rest.starts_with("true") {
+					(Value::Bool(true), 4)
+				} else if rest.starts_with("false") {
+					(Value::Bool(false), 5)
+				} else if rest.starts_with("null") {
+					(Value::Null, 4)
+				} else {
+					return Err(self.error("expected value"))
+				};
+				self.pos += len;
+				value
+			}
+		};
+		Ok(Node { line, end: self.line(), value })
+	}
+
+	/// Whether another item of the array or object follows, consuming the comma before it or the closing bracket.
+	fn item(&mut self, close: char, first: &mut bool) -> Result<bool, Error> {
+		self.skip_trivia()?;
+		if !*first {
+			if self.peek() == Some(',') {
+				self.pos += 1;
+				self.skip_trivia()?;
+			} else if self.peek() != Some(close) {
+				return Err(self.error(if close == '}' { "expected , or }" } else { "expected , or ]" }))
+			}
+		}
+		*first = false;
+		if self.peek() == Some(close) {
+			self.pos += 1;
+			return Ok(false)
+		}
+		Ok(true)
+	}
+
+	fn string(&mut self) -> Result<String, Error> {
+		self.pos += 1;
+		let mut string = String::new();
+		let text = self.text;
+		let mut chars = text[self.pos..].char_indices();
+		while let Some((i, c)) = chars.next() {
+			match c {
+				'"' => {
+					self.pos += i + 1;
+					return Ok(string)
+				},
+				'\\' => {
+					let escaped = match chars.next() {
+						Some((_, '"')) => '"',
+						Some((_, '\\')) => '\\',
+						Some((_, '/')) => '/',
+						Some((_, 'b')) => '\u{8}',
+						Some((_, 'f')) => '\u{c}',
+						Some((_, 'n')) => '\n',
+						Some((_, 'r')) => '\r',
+						Some((_, 't')) => '\t',
+						Some((_, 'u')) => {
+							let Some(high) = unit(&mut chars) else {
+								return Err(self.error("invalid unicode escape"))
+							};
+							let code = if (0xd800..0xdc00).contains(&high) {
+								let low = match (chars.next(), chars.next()) {
+									(Some((_, '\\')), Some((_, 'u'))) => unit(&mut chars).filter(|low| (0xdc00..0xe000).contains(low)),
+									_ => None
+								};
+								let Some(low) = low else {
+									return Err(self.error("unpaired surrogate"))
+								};
+								0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+							} else {
+								high
+							};
+							match char::from_u32(code) {
+								Some(c) => c,
+								None => return Err(self.error("unpaired surrogate"))
+							}
+						},
+						_ => return Err(self.error("invalid escape"))
+					};
+					string.push(escaped);
+				},
+				'\n' => return Err(self.error("unterminated string")),
+				c => string.push(c)
+			}
+		}
+		Err(self.error("unterminated string"))
+	}
+}
+
+/// The text as a JSON string, quotes included.
+pub(crate) fn quote(text: &str) -> String {
+	let mut quoted = String::from("\"");
+	for c in text.chars() {
+		match c {
+			'"' => quoted.push_str("\\\""),
+			'\\' => quoted.push_str("\\\\"),
+			'\n' => quoted.push_str("\\n"),
+			'\r' => quoted.push_str("\\r"),
+			'\t' => quoted.push_str("\\t"),
+			c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+			c => quoted.push(c)
+		}
+	}
+	quoted.push('"');
+	quoted
+}
+
+/// Code unit of a `\u` escape, from its 4 hexadecimal digits.
+fn unit(chars: &mut std::str::CharIndices) -> Option<u32> {
+	let hex: String = (0..4).map_while(|_| chars.next().map(|(_, c)| c)).collect();
+	u32::from_str_radix(&hex, 16).ok().filter(|_| hex.len() == 4)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn read_document() {
+		let node = parse("{\n\t// comment\n\t\"a\": [1, \"x\\ty\\u00e9\\ud83d\\ude00\", true, null,],\n\t/* b */ \"b\": {}\n}").unwrap();
+		let a = node.get("a").unwrap();
+		assert_eq!(a.line, 3);
+		let Value::Array(items) = &a.value else {
+			panic!("expected array")
+		};
+		assert_eq!(items[0].value, Value::Number(String::from("1")));
+		assert_eq!(items[1].as_str(), Some("x\tyé😀"));
+		assert_eq!(items[2..].iter().map(|item| &item.value).collect::<Vec<_>>(), [&Value::Bool(true), &Value::Null]);
+		assert_eq!(node.get("b").unwrap().value, Value::Object(Vec::new()));
+		assert_eq!(parse("[1 2]"), Err(Error { line: 1, message: "expected , or ]" }));
+		assert_eq!(parse("{\"a\": 1}}").unwrap_err().message, "unexpected text after value");
+	}
+}
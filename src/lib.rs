@@ -1,5 +1,69 @@
-use std::rc::{Weak, Rc};
-use std::fmt;
+use crate::shared::{Weak, Rc};
+use std::{fmt, io};
+
+pub mod shared;
+pub mod model;
+pub mod compat;
+pub mod library;
+pub mod parse;
+pub mod regex;
+pub mod transform;
+#[cfg(feature = "resolve")]
+pub mod resolve;
+#[cfg(feature = "resolve")]
+pub mod oneshot;
+#[cfg(feature = "exec")]
+pub mod exec;
+#[cfg(feature = "expr")]
+pub mod expr;
+#[cfg(all(unix, feature = "daemon"))]
+pub mod ipc;
+#[cfg(feature = "formats-jetbrains")]
+pub mod jetbrains;
+#[cfg(feature = "formats-espanso")]
+pub mod espanso;
+#[cfg(feature = "formats-ultisnips")]
+pub mod ultisnips;
+pub mod warning;
+pub mod check;
+pub mod cache;
+#[cfg(feature = "serde")]
+pub mod graph;
+mod memory;
+pub mod compose;
+pub mod handle;
+pub mod bidi;
+pub mod regions;
+pub mod rendered;
+pub mod delta;
+pub mod template;
+pub mod testing;
+pub mod mustache;
+pub mod scaffold;
+pub mod numbering;
+pub mod navigate;
+pub mod choices;
+mod edit;
+mod evaluate;
+pub mod dedupe;
+pub mod sanitize;
+pub mod collection;
+pub mod builder;
+pub mod sync;
+pub mod patch;
+pub mod source;
+pub mod preview;
+pub mod history;
+pub mod observe;
+pub mod conformance;
+pub mod verify;
+pub mod visit;
+pub mod config;
+mod yaml;
+mod toml;
+mod json;
+#[cfg(all(unix, feature = "daemon"))]
+mod msgpack;
 
 /// Part of the snippet that is fashioned from user input.
 #[derive(Debug)]
@@ -7,7 +71,84 @@ pub enum Field {
 	/// Typed in text.
 	Placeholder(Vec<Segment>),
 	/// 1 choice selected from menu of choices.
-	Choice(usize, Vec<Vec<Segment>>),
+	/// The labels shown in the menu come last, by choice. Choices beyond the labels given are shown as their text.
+	Choice(usize, Vec<Vec<Segment>>, Vec<String>),
+	/// Number stepped up or down by the user.
+	Number(NumberField),
+	/// Switched on or off by the user, showing the first segments when on (first) and the second when off.
+	/// Either may be empty, such as to leave out an optional block, and transformations acting upon the field see the segments shown.
+	Toggle(bool, Vec<Segment>, Vec<Segment>),
+	/// Group of segments repeated as many times as the user asks for, such as the parameters of a function.
+	Repeat(RepeatField)
+}
+
+/// Segments repeated by copying a template, see [`Snippet::add_repetition`].
+#[derive(Debug)]
+pub struct RepeatField {
+	/// What each repetition is copied from, with the tabs within it numbered from 1. See [`RepeatField::template`].
+	#[doc(hidden)]
+	pub template: Rc<Snippet>,
+	/// Text put between repetitions.
+	pub separator: String,
+	/// The repetitions so far.
+	pub repetitions: Vec<Vec<Segment>>
+}
+
+/// A whole number within optional bounds that is changed by a step at a time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NumberField {
+	/// Current value.
+	pub value: i64,
+	/// Smallest value allowed.
+	pub min: Option<i64>,
+	/// Largest value allowed.
+	pub max: Option<i64>,
+	/// Amount the value changes by per increment or decrement.
+	pub step: i64
+}
+
+/// Why text is not a valid value of a number field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NumberError {
+	/// The text is not a whole number.
+	NotANumber,
+	/// The number is smaller than the minimum (carried).
+	BelowMinimum(i64),
+	/// The number is larger than the maximum (carried).
+	AboveMaximum(i64)
+}
+
+impl fmt::Display for NumberError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			NumberError::NotANumber => write!(f, "not a whole number"),
+			NumberError::BelowMinimum(min) => write!(f, "smaller than the minimum of {}", min),
+			NumberError::AboveMaximum(max) => write!(f, "larger than the maximum of {}", max)
+		}
+	}
+}
+
+impl std::error::Error for NumberError {}
+
+impl NumberField {
+	/// Value after stepping the given number of times (negative to decrement), kept within the bounds.
+	pub fn stepped(&self, steps: i64) -> i64 {
+		let value = self.value.saturating_add(self.step.saturating_mul(steps));
+		let value = self.min.map_or(value, |min| value.max(min));
+		self.max.map_or(value, |max| value.min(max))
+	}
+
+	/// Reads text typed into the field as a value of it.
+	pub fn validate(&self, text: &str) -> Result<i64, NumberError> {
+		let value: i64 = text.trim().parse().map_err(|_| NumberError::NotANumber)?;
+		if let Some(min) = self.min.filter(|min| value < *min) {
+			return Err(NumberError::BelowMinimum(min))
+		}
+		if let Some(max) = self.max.filter(|max| value > *max) {
+			return Err(NumberError::AboveMaximum(max))
+		}
+		Ok(value)
+	}
 }
 
 /// Part of the snippet produced by replacing matching patterns within the snippet's specified fields and variables (transformables).
@@ -20,7 +161,56 @@ pub struct Transformation {
 	/// Changes how pattern and/or format is applied.
 	pub flags: String,
 	/// Result of performing the transformation.
-	pub result: String
+	pub result: String,
+	/// Regex of the section, compiled when the transformation is first applied.
+	pub compiled: transform::CompiledSection
+}
+
+/// Part of the snippet that shows one of two blocks depending on the text of a field (typically that of another tab).
+#[derive(Debug)]
+pub struct Conditional {
+	/// Field whose text is tested. Once it no longer exists its text is taken to be empty. See [`Conditional::field_ref`].
+	#[doc(hidden)]
+	pub field: Weak<Field>,
+	/// Test upon the field's text.
+	pub condition: Condition,
+	/// Shown when the condition holds.
+	pub then: Vec<Segment>,
+	/// Shown when the condition does not hold.
+	pub otherwise: Vec<Segment>
+}
+
+/// Test upon the text of a field.
+#[derive(Debug, Clone)]
+pub enum Condition {
+	Empty,
+	NonEmpty,
+	/// The pattern matches somewhere within the text.
+	Matches(regex::Regex)
+}
+
+impl Condition {
+	/// Whether the text passes the test.
+	pub fn holds(&self, text: &str) -> bool {
+		match self {
+			Condition::Empty => text.is_empty(),
+			Condition::NonEmpty => !text.is_empty(),
+			Condition::Matches(regex) => regex.is_match(text)
+		}
+	}
+}
+
+impl Conditional {
+	/// Whether the condition holds for the current text of the field.
+	pub fn holds(&self) -> bool {
+		let text = self.field.upgrade().map(|field| field.to_string()).unwrap_or_default();
+		self.condition.holds(&text)
+	}
+
+	/// The block currently shown.
+	pub fn shown(&self) -> &[Segment] {
+		if self.holds() { &self.then } else { &self.otherwise }
+	}
 }
 
 /// Defines where a variable comes from.
@@ -37,10 +227,14 @@ pub enum VariableSource {
 pub struct Variable {
 	/// Name of the variable.
 	pub name: String,
-	/// Value of the variable.
+	/// Value of the variable. When parsed and until resolved, the default if one is given, otherwise the name
+	/// or nothing for variables describing the editor (see [`Snippet::parse`]).
 	pub value: String,
 	/// Where a variable comes from.
-	pub source: VariableSource
+	pub source: VariableSource,
+	/// Default given where the variable appears (`${NAME:default}`), what it falls back to when it can not be resolved
+	/// (see `Snippet::fall_through_variables`, with the resolve feature). None when no default is given.
+	pub default: Option<Vec<Segment>>
 }
 
 /// Part of the snippet that is filled in with the output of an external program - likely some type of shell code.
@@ -68,6 +262,8 @@ pub enum Segment {
 	Variable(Rc<Variable>),
 	/// Output of an external program.
 	Code(Rc<Code>),
+	/// Text shown depending on the text of a field.
+	Conditional(Rc<Conditional>),
 	/// A expanded snippet nested within this segment's snippet.
 	/// Not able to be set through this segment's snippet's initialization string.
 	/// Only able to be set after initialization to idicate that the user expanded a snippet within this segment's snippet.
@@ -87,22 +283,29 @@ pub enum NamedSegment {
 #[derive(Debug)]
 pub struct Tab {
 	/// Indicates the order in which this tab is selected in the cycle.
-	/// Should be unique to each tab.
-	/// Otherwise this module might not function as intended.
+	/// Should be unique to each tab (mirrors share the field rather than the number).
+	/// [`library::SnippetLibrary::check`] and [`Snippet::validate`] report duplicates and [`Snippet::renumber_tabs`] resolves them.
 	pub num: u8,
-	/// Part of the snippet that will be selected when this tab is selected.
+	/// Part of the snippet that will be selected when this tab is selected. See [`handle::TabRef::field`].
+	#[doc(hidden)]
 	pub field: Weak<Field>,
 	/// All transformations that act upon this variable.
 	/// Empty transformations means there are no transformations that are acting upon this variable.
-	pub transformations: Vec<Weak<Transformation>>
+	#[doc(hidden)]
+	pub transformations: Vec<Weak<Transformation>>,
+	/// Text to prompt the user with when this tab is selected, such as `Function name:`.
+	/// None when the tab should only be shown by its number.
+	pub label: Option<String>
 }
 
 ///Represents text filled in by a program.
 #[derive(Debug)]
 pub struct Expansion<E> {
-	/// The text that is actually filled in by program.
+	/// The text that is actually filled in by program. See [`Expansion::expansion_ref`].
+	#[doc(hidden)]
 	pub expansion: Weak<E>,
-	/// Text that is filled in by transformations upon the expansion field above.
+	/// Text that is filled in by transformations upon the expansion field above. See [`Expansion::transform_refs`].
+	#[doc(hidden)]
 	pub transformations: Vec<Weak<Transformation>>
 }
 
@@ -121,7 +324,104 @@ pub struct Snippet {
 	/// Output of a program.
 	code_expansions: Vec<Expansion<Code>>,
 	/// Segments that otherwise wouldn't have a name (not variables or fields) but are given 1 so they can be reused without having to retype them in full.
-	named_segments: Vec<NamedSegment>
+	named_segments: Vec<NamedSegment>,
+	/// Number of the tab the user is on, moved by [`Snippet::next_tab`] and the like. None before a tab is selected.
+	current_tab: Option<u8>,
+	/// Sources of the options of choice fields, by the field, until resolved by [`Snippet::resolve_choices`].
+	choice_sources: Vec<(Weak<Field>, choices::ChoiceSource)>,
+	/// What the body rendered as when last rendered through [`Snippet::render_delta`].
+	baseline: delta::Baseline
+}
+
+impl Snippet {
+	/// Segments of normal text and non normal text.
+	pub fn body(&self) -> &[Segment] {
+		&self.body
+	}
+
+	/// Selections within this snippet that are cycled through in order to be fashioned from user input.
+	pub fn tabs(&self) -> &[Tab] {
+		&self.tabs
+	}
+
+	/// Program variables.
+	pub fn variables(&self) -> &[Expansion<Variable>] {
+		&self.variables
+	}
+
+	/// Output of a program.
+	pub fn code_expansions(&self) -> &[Expansion<Code>] {
+		&self.code_expansions
+	}
+
+	/// Segments that were given a name so they can be reused.
+	pub fn named_segments(&self) -> &[NamedSegment] {
+		&self.named_segments
+	}
+
+	/// Whether the snippet consists of normal text only, not having anything to be filled in.
+	pub fn is_static(&self) -> bool {
+		self.body.iter().all(|segment| matches!(segment, Segment::Text(_)))
+	}
+
+	/// Segments that appear more than once within the snippet by sharing a single Rc (such as mirrored fields),
+	/// each given with the number of places it appears in. Listed in order of first appearance.
+	pub fn shared_segments(&self) -> Vec<(&Segment, usize)> {
+		let mut found: Vec<(&Segment, usize)> = Vec::new();
+		count_shared(&self.body, &mut found);
+		found.retain(|(_, count)| *count > 1);
+		found
+	}
+}
+
+impl Segment {
+	/// Address of the Rc the segment holds, or nothing for text.
+	fn shared_ptr(&self) -> Option<*const ()> {
+		match self {
+			Segment::Text(_) => None,
+			Segment::Field(field) => Some(Rc::as_ptr(field) as *const ()),
+			Segment::Transformation(transformation) => Some(Rc::as_ptr(transformation) as *const ()),
+			Segment::Variable(variable) => Some(Rc::as_ptr(variable) as *const ()),
+			Segment::Code(code) => Some(Rc::as_ptr(code) as *const ()),
+			Segment::Conditional(conditional) => Some(Rc::as_ptr(conditional) as *const ()),
+			Segment::Snippet(snippet) => Some(Rc::as_ptr(snippet) as *const ())
+		}
+	}
+}
+
+fn count_shared<'a>(segments: &'a [Segment], found: &mut Vec<(&'a Segment, usize)>) {
+	for segment in segments {
+		let Some(ptr) = segment.shared_ptr() else {
+			continue
+		};
+		if let Some((_, count)) = found.iter_mut().find(|(seen, _)| seen.shared_ptr() == Some(ptr)) {
+			*count += 1;
+			continue
+		}
+		found.push((segment, 1));
+		match segment {
+			Segment::Field(field) => match &**field {
+				Field::Placeholder(body) => count_shared(body, found),
+				Field::Choice(_, choices, _) => for body in choices {
+					count_shared(body, found);
+				},
+				Field::Number(_) => {},
+				Field::Toggle(_, on, off) => {
+					count_shared(on, found);
+					count_shared(off, found);
+				},
+				Field::Repeat(repeat) => for body in &repeat.repetitions {
+					count_shared(body, found);
+				}
+			},
+			Segment::Conditional(conditional) => {
+				count_shared(&conditional.then, found);
+				count_shared(&conditional.otherwise, found);
+			},
+			Segment::Snippet(snippet) => count_shared(&snippet.body, found),
+			_ => {}
+		}
+	}
 }
 
 impl fmt::Display for Variable {
@@ -142,44 +442,185 @@ impl fmt::Display for Transformation {
 	}
 }
 
-impl fmt::Display for Segment {
-	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+impl Segment {
+	/// Writes the text of the segment into the writer.
+	pub fn render_to<W: fmt::Write + ?Sized>(&self, w: &mut W) -> fmt::Result {
 		match self {
-			Segment::Text(text) => write!(f, "{}", text),
-			Segment::Variable(variable) => write!(f, "{}", variable),
-			Segment::Code(code) => write!(f, "{}", code),
-			Segment::Snippet(snippet) => write!(f, "{}", snippet),
-			Segment::Field(field) => write!(f, "{}", field),
-			Segment::Transformation(transformation) => write!(f, "{}", transformation)
+			Segment::Text(text) => w.write_str(text),
+			Segment::Variable(variable) => w.write_str(&variable.value),
+			Segment::Code(code) => w.write_str(&code.output),
+			Segment::Snippet(snippet) => snippet.render_to(w),
+			Segment::Field(field) => field.render_to(w),
+			Segment::Conditional(conditional) => conditional.shown().iter().try_for_each(|seg| seg.render_to(w)),
+			Segment::Transformation(transformation) => w.write_str(&transformation.result)
 		}
 	}
 }
 
-impl fmt::Display for Field {
-	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-		let mut body = match self {
+impl Field {
+	/// Writes the text of the field (the selected choice for choice fields, the shown segments for toggles) into the writer.
+	pub fn render_to<W: fmt::Write + ?Sized>(&self, w: &mut W) -> fmt::Result {
+		let body = match self {
 			Field::Placeholder(child_body) => child_body,
-			Field::Choice(choice, child_body) => if let Some(child_body) = child_body.get(*choice) {
+			Field::Choice(choice, child_body, _) => if let Some(child_body) = child_body.get(*choice) {
 				child_body
 			} else {
 				return Ok(())
+			},
+			Field::Number(number) => return write!(w, "{}", number.value),
+			Field::Toggle(on, when_on, when_off) => if *on { when_on } else { when_off },
+			Field::Repeat(repeat) => {
+				for (i, body) in repeat.repetitions.iter().enumerate() {
+					if i > 0 {
+						w.write_str(&repeat.separator)?;
+					}
+					for seg in body {
+						seg.render_to(w)?;
+					}
+				}
+				return Ok(())
 			}
-		}.iter();
-		while let Some(seg) = body.next() {
-			write!(f, "{}", seg)?;
+		};
+		for seg in body {
+			seg.render_to(w)?;
 		}
 		Ok(())
 	}
 }
 
-impl fmt::Display for Snippet {
-	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-		let mut body = self.body.iter();
-		while let Some(seg) = body.next() {
-			write!(f, "{}", seg)?;
+impl Field {
+	/// Labels shown in the menu of a choice field, by choice: the given label or else the choice's text.
+	/// Empty for other fields.
+	pub fn choice_labels(&self) -> Vec<String> {
+		match self {
+			Field::Placeholder(_) | Field::Number(_) | Field::Toggle(..) | Field::Repeat(_) => Vec::new(),
+			Field::Choice(_, child_body, labels) => child_body.iter().enumerate()
+				.map(|(i, body)| labels.get(i).cloned().unwrap_or_else(|| body.iter().map(Segment::to_string).collect()))
+				.collect()
+		}
+	}
+
+	/// Text of each option of a choice field, as it shows once chosen. Empty for other fields.
+	pub fn choices(&self) -> Vec<String> {
+		match self {
+			Field::Choice(_, child_body, _) => child_body.iter().map(|body| body.iter().map(Segment::to_string).collect()).collect(),
+			_ => Vec::new()
+		}
+	}
+
+	/// Index of the option chosen in a choice field. None for other fields.
+	pub fn selected_index(&self) -> Option<usize> {
+		match self {
+			Field::Choice(choice, ..) => Some(*choice),
+			_ => None
+		}
+	}
+}
+
+impl Snippet {
+	/// Writes the text of the snippet directly into the writer, without building an intermediate String.
+	pub fn render_to<W: fmt::Write + ?Sized>(&self, w: &mut W) -> fmt::Result {
+		for seg in &self.body {
+			seg.render_to(w)?;
 		}
 		Ok(())
 	}
+
+	/// Writes the text of the snippet as UTF-8 directly into the writer.
+	pub fn render_to_io<W: io::Write + ?Sized>(&self, w: &mut W) -> io::Result<()> {
+		/// Forwards text to an io writer, keeping the io error that fmt::Error can not carry.
+		struct Adapter<'a, W: io::Write + ?Sized> {
+			inner: &'a mut W,
+			error: Option<io::Error>
+		}
+		impl<W: io::Write + ?Sized> fmt::Write for Adapter<'_, W> {
+			fn write_str(&mut self, s: &str) -> fmt::Result {
+				self.inner.write_all(s.as_bytes()).map_err(|error| {
+					self.error = Some(error);
+					fmt::Error
+				})
+			}
+		}
+		let mut adapter = Adapter { inner: w, error: None };
+		self.render_to(&mut adapter).map_err(|_| adapter.error.take().unwrap_or_else(|| io::Error::other("formatting failed")))
+	}
+}
+
+/// Why a snippet can not be rendered faithfully.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RenderError {
+	/// A choice field's selected choice (first) does not exist among its choices (second is how many there are).
+	ChoiceOutOfRange(usize, usize),
+	/// The field of the tab with this number no longer exists.
+	DanglingTab(u8)
+}
+
+impl fmt::Display for RenderError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			RenderError::ChoiceOutOfRange(choice, count) => write!(f, "choice {} selected out of {} choices", choice, count),
+			RenderError::DanglingTab(num) => write!(f, "field of tab {} no longer exists", num)
+		}
+	}
+}
+
+impl std::error::Error for RenderError {}
+
+impl Snippet {
+	/// Renders the snippet, failing instead of leaving out what can not be rendered (as Display does).
+	pub fn try_render(&self) -> Result<String, RenderError> {
+		self.check_render()?;
+		Ok(self.to_string())
+	}
+
+	fn check_render(&self) -> Result<(), RenderError> {
+		if let Some(tab) = self.tabs.iter().find(|tab| tab.field.strong_count() == 0) {
+			return Err(RenderError::DanglingTab(tab.num))
+		}
+		check_render(&self.body)
+	}
+}
+
+fn check_render(segments: &[Segment]) -> Result<(), RenderError> {
+	for segment in segments {
+		match segment {
+			Segment::Field(field) => match &**field {
+				Field::Placeholder(child_body) => check_render(child_body)?,
+				Field::Choice(choice, child_body, _) => if let Some(child_body) = child_body.get(*choice) {
+					check_render(child_body)?
+				} else {
+					return Err(RenderError::ChoiceOutOfRange(*choice, child_body.len()))
+				},
+				Field::Number(_) => {},
+				Field::Toggle(on, when_on, when_off) => check_render(if *on { when_on } else { when_off })?,
+				Field::Repeat(repeat) => for body in &repeat.repetitions {
+					check_render(body)?
+				}
+			},
+			Segment::Conditional(conditional) => check_render(conditional.shown())?,
+			Segment::Snippet(nested) => nested.check_render()?,
+			_ => {}
+		}
+	}
+	Ok(())
+}
+
+impl fmt::Display for Segment {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		self.render_to(f)
+	}
+}
+
+impl fmt::Display for Field {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		self.render_to(f)
+	}
+}
+
+impl fmt::Display for Snippet {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		self.render_to(f)
+	}
 }
 
 #[cfg(test)]
@@ -188,14 +629,133 @@ mod tests {
 
 	#[test]
 	fn construct_snippet() {
-		let mut result = Snippet {
-			body: vec![Segment::Field(Rc::new(Field::Choice(1, vec![vec![Segment::Text(String::from("Hi"))], vec![Segment::Text(String::from("Hello"))], vec![Segment::Text(String::from("Howdee"))]]))), Segment::Text(String::from(" there ")), Segment::Field(Rc::new(Field::Placeholder(vec![Segment::Text(String::from("John"))])))],
+		let result = Snippet {
+			body: vec![Segment::Field(Rc::new(Field::Choice(1, vec![vec![Segment::Text(String::from("Hi"))], vec![Segment::Text(String::from("Hello"))], vec![Segment::Text(String::from("Howdee"))]], Vec::new()))), Segment::Text(String::from(" there ")), Segment::Field(Rc::new(Field::Placeholder(vec![Segment::Text(String::from("John"))])))],
 			tabs: Vec::new(),
 			variables: Vec::new(),
 			code_expansions: Vec::new(),
-			named_segments: Vec::new()
+			named_segments: Vec::new(),
+			current_tab: None,
+			choice_sources: Vec::new(),
+			baseline: Default::default()
 		};
 		println!("{}", result);
 		println!("{:?}", result);
 	}
+
+	#[test]
+	fn render_into_writers() {
+		let snippet = Snippet {
+			body: vec![Segment::Text(String::from("fn ")), Segment::Field(Rc::new(Field::Placeholder(vec![Segment::Text(String::from("main"))]))), Segment::Text(String::from("() {}"))],
+			tabs: Vec::new(),
+			variables: Vec::new(),
+			code_expansions: Vec::new(),
+			named_segments: Vec::new(),
+			current_tab: None,
+			choice_sources: Vec::new(),
+			baseline: Default::default()
+		};
+		let mut buffer = String::from("// ");
+		snippet.render_to(&mut buffer).unwrap();
+		assert_eq!(buffer, "// fn main() {}");
+		let mut bytes = Vec::new();
+		snippet.render_to_io(&mut bytes).unwrap();
+		assert_eq!(bytes, b"fn main() {}");
+		assert_eq!(snippet.render_to_io(&mut &mut [0u8; 4][..]).unwrap_err().kind(), io::ErrorKind::WriteZero);
+	}
+
+	#[test]
+	fn step_number_fields() {
+		let port = NumberField { value: 8080, min: Some(1), max: Some(65535), step: 1 };
+		assert_eq!(Field::Number(port.clone()).to_string(), "8080");
+		assert_eq!(port.stepped(-2), 8078);
+		assert_eq!(NumberField { step: 1000, ..port.clone() }.stepped(100), 65535);
+		assert_eq!(port.stepped(i64::MIN), 1);
+		assert_eq!(port.validate(" 443 "), Ok(443));
+		assert_eq!(port.validate("0"), Err(NumberError::BelowMinimum(1)));
+		assert_eq!(port.validate("70000"), Err(NumberError::AboveMaximum(65535)));
+		assert_eq!(port.validate("http"), Err(NumberError::NotANumber));
+	}
+
+	#[test]
+	fn toggle_optional_block() {
+		let name = Rc::new(Field::Placeholder(vec![Segment::Text(String::from("x"))]));
+		let block = |on| Field::Toggle(on, vec![Segment::Text(String::from(", ")), Segment::Field(name.clone())], Vec::new());
+		assert_eq!(block(true).to_string(), ", x");
+		assert_eq!(block(false).to_string(), "");
+		assert!(block(true).choice_labels().is_empty());
+		let snippet = Snippet {
+			body: vec![Segment::Field(name.clone()), Segment::Field(Rc::new(block(true)))],
+			tabs: Vec::new(),
+			variables: Vec::new(),
+			code_expansions: Vec::new(),
+			named_segments: Vec::new(),
+			current_tab: None,
+			choice_sources: Vec::new(),
+			baseline: Default::default()
+		};
+		assert_eq!(snippet.try_render().unwrap(), "x, x");
+		assert_eq!(snippet.shared_segments().len(), 1);
+	}
+
+	#[test]
+	fn show_conditional_segments() {
+		let table = |text: &str| Rc::new(Field::Placeholder(vec![Segment::Text(text.to_string())]));
+		let conditional = |field: &Rc<Field>, condition| Segment::Conditional(Rc::new(Conditional {
+			field: Rc::downgrade(field),
+			condition,
+			then: vec![Segment::Text(String::from(" WHERE ")), Segment::Field(field.clone())],
+			otherwise: vec![Segment::Text(String::from(";"))]
+		}));
+		let filtered = table("id = 1");
+		let unfiltered = table("");
+		assert_eq!(conditional(&filtered, Condition::NonEmpty).to_string(), " WHERE id = 1");
+		assert_eq!(conditional(&unfiltered, Condition::NonEmpty).to_string(), ";");
+		assert_eq!(conditional(&unfiltered, Condition::Empty).to_string(), " WHERE ");
+		let pattern = Condition::Matches(regex::Regex::new("^id\\b").unwrap());
+		assert_eq!(conditional(&filtered, pattern.clone()).to_string(), " WHERE id = 1");
+		assert_eq!(conditional(&table("ids"), pattern).to_string(), ";");
+		let dangling = Conditional { field: Weak::new(), condition: Condition::Empty, then: Vec::new(), otherwise: Vec::new() };
+		assert!(dangling.holds());
+	}
+
+	#[test]
+	fn checked_rendering() {
+		let choice = Rc::new(Field::Choice(2, vec![vec![Segment::Text(String::from("a"))]], Vec::new()));
+		let mut snippet = Snippet {
+			body: vec![Segment::Text(String::from("x"))],
+			tabs: vec![Tab { num: 1, field: Rc::downgrade(&choice), transformations: Vec::new(), label: None }],
+			variables: Vec::new(),
+			code_expansions: Vec::new(),
+			named_segments: Vec::new(),
+			current_tab: None,
+			choice_sources: Vec::new(),
+			baseline: Default::default()
+		};
+		snippet.body.push(Segment::Field(choice));
+		assert_eq!(snippet.try_render(), Err(RenderError::ChoiceOutOfRange(2, 1)));
+		assert_eq!(snippet.to_string(), "x");
+		snippet.body.pop();
+		assert_eq!(snippet.try_render(), Err(RenderError::DanglingTab(1)));
+		snippet.tabs.clear();
+		assert_eq!(snippet.try_render().unwrap(), "x");
+	}
+
+	#[test]
+	fn detect_shared_segments() {
+		let name = Rc::new(Field::Placeholder(vec![Segment::Text(String::from("x"))]));
+		let user = Rc::new(Variable { name: String::from("USER"), value: String::new(), source: VariableSource::Daemon, default: None });
+		let snippet = Snippet {
+			body: vec![Segment::Field(name.clone()), Segment::Variable(user.clone()), Segment::Field(name.clone()), Segment::Field(Rc::new(Field::Placeholder(vec![Segment::Variable(user)]))), Segment::Field(name)],
+			tabs: Vec::new(),
+			variables: Vec::new(),
+			code_expansions: Vec::new(),
+			named_segments: Vec::new(),
+			current_tab: None,
+			choice_sources: Vec::new(),
+			baseline: Default::default()
+		};
+		let shared: Vec<_> = snippet.shared_segments().into_iter().map(|(segment, count)| (segment.to_string(), count)).collect();
+		assert_eq!(shared, [(String::from("x"), 3), (String::new(), 2)]);
+	}
 }
@@ -0,0 +1,227 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use crate::Snippet;
+
+/// A snippet together with the information needed to offer it to a user.
+#[derive(Debug)]
+pub struct SnippetDefinition {
+	/// Texts that, when typed, are expanded into this definition's snippet.
+	pub triggers: Vec<String>,
+	/// Human readable explanation of what the snippet is for.
+	pub description: Option<String>,
+	/// What the triggers expand into.
+	pub kind: SnippetKind,
+	/// Where the definition was loaded from, if it was loaded.
+	pub source: Option<SourceLocation>
+}
+
+/// Snippet definitions gathered from any number of sources.
+#[derive(Debug, Default)]
+pub struct SnippetLibrary {
+	pub(crate) definitions: Vec<SnippetDefinition>,
+	pub(crate) globals: Vec<GlobalCode>,
+	pub(crate) code_settings: Vec<CodeSettings>
+}
+
+/// Code shared by the snippets of a file, such as the helper functions of UltiSnips `global !p` blocks.
+/// It is run ahead of the code of each of those snippets that has the same interpreter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GlobalCode {
+	pub code: String,
+	/// The program that runs the code, as a shebang.
+	pub shebang: String,
+	/// Where the code is written. Shared by the snippets loaded from the same file, or by every snippet when not loaded from a file.
+	pub source: Option<SourceLocation>
+}
+
+/// How the code of the snippets of a file is run, such as with the Python of a virtualenv.
+/// Applied by `SnippetLibrary::code_runner` (with the exec feature) when the runner permits it.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CodeSettings {
+	/// File whose snippets the settings apply to. Every snippet when None.
+	pub path: Option<PathBuf>,
+	/// Interpreter of code without a shebang, as a shebang.
+	pub shell: Option<String>,
+	/// Interpreters to run code with in place of those of their shebang, as pairs of shebangs
+	/// (`#!/usr/bin/env python3` to `#!/home/me/venv/bin/python`).
+	pub interpreters: Vec<(String, String)>,
+	/// Environment variables set for the interpreter.
+	pub environment: Vec<(String, String)>
+}
+
+/// Whether something of the file at the path (or of no file) applies to the definition.
+fn applies_to(path: Option<&Path>, definition: &SnippetDefinition) -> bool {
+	path.is_none() || path == definition.source.as_ref().and_then(|source| source.path.as_deref())
+}
+
+/// Where in a loaded source a definition is written.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceLocation {
+	/// File the definition was loaded from, if it was loaded from a file rather than from text.
+	pub path: Option<PathBuf>,
+	/// First line (starting at 1) of the definition.
+	pub start_line: usize,
+	/// Last line of the definition.
+	pub end_line: usize
+}
+
+/// What a definition expands into.
+#[derive(Debug)]
+pub enum SnippetKind {
+	/// Plain text without anything to fill in (an abbreviation).
+	/// Expanded by inserting the text as is, without any tabs to cycle through or segments to render.
+	Static(String),
+	/// A snippet with parts that are filled in by the user or a program.
+	Dynamic(Snippet)
+}
+
+impl SnippetDefinition {
+	/// Makes a definition, detecting whether the snippet is static.
+	pub fn new(triggers: Vec<String>, description: Option<String>, snippet: Snippet) -> Self {
+		SnippetDefinition {
+			triggers,
+			description,
+			kind: snippet.into(),
+			source: None
+		}
+	}
+
+	/// Whether the definition expands into plain text.
+	pub fn is_static(&self) -> bool {
+		matches!(self.kind, SnippetKind::Static(_))
+	}
+
+	/// The text the definition expands into if it is static.
+	pub fn static_text(&self) -> Option<&str> {
+		match &self.kind {
+			SnippetKind::Static(text) => Some(text),
+			SnippetKind::Dynamic(_) => None
+		}
+	}
+
+	/// The snippet the definition expands into if it is not static.
+	pub fn snippet(&self) -> Option<&Snippet> {
+		match &self.kind {
+			SnippetKind::Static(_) => None,
+			SnippetKind::Dynamic(snippet) => Some(snippet)
+		}
+	}
+}
+
+impl SnippetLibrary {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Adds a definition to the library.
+	pub fn add(&mut self, definition: SnippetDefinition) {
+		self.definitions.push(definition);
+	}
+
+	/// Definitions in the order they were added.
+	pub fn definitions(&self) -> &[SnippetDefinition] {
+		&self.definitions
+	}
+
+	/// Adds code shared by the snippets of its source file.
+	pub fn add_global(&mut self, global: GlobalCode) {
+		self.globals.push(global);
+	}
+
+	/// Shared code in the order it was added.
+	pub fn globals(&self) -> &[GlobalCode] {
+		&self.globals
+	}
+
+	/// Shared code available to the definition's code: that of the file the definition was loaded from,
+	/// along with any not loaded from a file.
+	pub fn globals_for<'a>(&'a self, definition: &'a SnippetDefinition) -> impl Iterator<Item = &'a GlobalCode> {
+		self.globals.iter().filter(move |global| applies_to(global.source.as_ref().and_then(|source| source.path.as_deref()), definition))
+	}
+
+	/// Adds settings for running the code of the snippets of a file.
+	pub fn add_code_settings(&mut self, settings: CodeSettings) {
+		self.code_settings.push(settings);
+	}
+
+	/// Settings for running code in the order they were added.
+	pub fn code_settings(&self) -> &[CodeSettings] {
+		&self.code_settings
+	}
+
+	/// Settings applying to the definition's code: those of the file it was loaded from, along with those for every snippet.
+	pub fn code_settings_for<'a>(&'a self, definition: &'a SnippetDefinition) -> impl Iterator<Item = &'a CodeSettings> {
+		self.code_settings.iter().filter(move |settings| applies_to(settings.path.as_deref(), definition))
+	}
+
+	/// Definitions that expand from the trigger.
+	pub fn find<'a>(&'a self, trigger: &'a str) -> impl Iterator<Item = &'a SnippetDefinition> {
+		self.definitions.iter().filter(move |definition| definition.triggers.iter().any(|t| t == trigger))
+	}
+}
+
+impl Extend<SnippetDefinition> for SnippetLibrary {
+	fn extend<I: IntoIterator<Item = SnippetDefinition>>(&mut self, definitions: I) {
+		self.definitions.extend(definitions);
+	}
+}
+
+impl FromIterator<SnippetDefinition> for SnippetLibrary {
+	fn from_iter<I: IntoIterator<Item = SnippetDefinition>>(definitions: I) -> Self {
+		SnippetLibrary {
+			definitions: definitions.into_iter().collect(),
+			globals: Vec::new(),
+			code_settings: Vec::new()
+		}
+	}
+}
+
+impl From<Snippet> for SnippetKind {
+	fn from(snippet: Snippet) -> Self {
+		if snippet.is_static() {
+			SnippetKind::Static(snippet.to_string())
+		} else {
+			SnippetKind::Dynamic(snippet)
+		}
+	}
+}
+
+impl fmt::Display for SnippetKind {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			SnippetKind::Static(text) => write!(f, "{}", text),
+			SnippetKind::Dynamic(snippet) => write!(f, "{}", snippet)
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::shared::Rc;
+	use crate::{Field, Segment};
+
+	fn snippet(body: Vec<Segment>) -> Snippet {
+		Snippet {
+			body,
+			tabs: Vec::new(),
+			variables: Vec::new(),
+			code_expansions: Vec::new(),
+			named_segments: Vec::new(),
+			current_tab: None,
+			choice_sources: Vec::new(),
+			baseline: Default::default()
+		}
+	}
+
+	#[test]
+	fn detect_static() {
+		let abbreviation = SnippetDefinition::new(vec![String::from("brb")], None, snippet(vec![Segment::Text(String::from("be right ")), Segment::Text(String::from("back"))]));
+		assert!(abbreviation.is_static());
+		assert_eq!(abbreviation.static_text(), Some("be right back"));
+		assert!(abbreviation.snippet().is_none());
+		let template = SnippetDefinition::new(vec![String::from("hi")], None, snippet(vec![Segment::Text(String::from("Hi ")), Segment::Field(Rc::new(Field::Placeholder(Vec::new())))]));
+		assert!(!template.is_static());
+		assert_eq!(template.kind.to_string(), "Hi ");
+	}
+}
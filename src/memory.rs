@@ -0,0 +1,173 @@
+use std::collections::HashSet;
+use std::mem::size_of;
+use crate::shared::Rc;
+use crate::{Snippet, Segment, Field, Condition, Expansion, NamedSegment};
+use crate::library::{SnippetLibrary, SnippetDefinition, SnippetKind};
+
+/// Bytes of a reference counted allocation: the strong and weak counts followed by the value.
+fn rc_allocation<T>() -> usize {
+	2 * size_of::<usize>() + size_of::<T>()
+}
+
+fn vec_heap<T>(vec: &Vec<T>) -> usize {
+	vec.capacity() * size_of::<T>()
+}
+
+/// Adds up heap usage, counting each reference counted allocation once however often it is shared.
+#[derive(Default)]
+struct Footprint {
+	seen: HashSet<*const ()>,
+	bytes: usize
+}
+
+impl Footprint {
+	/// Counts the allocation behind the Rc, telling whether it had not been counted before (and its contents need counting).
+	fn rc<T>(&mut self, rc: &Rc<T>) -> bool {
+		let first = self.seen.insert(Rc::as_ptr(rc) as *const ());
+		if first {
+			self.bytes += rc_allocation::<T>();
+		}
+		first
+	}
+
+	fn segments(&mut self, segments: &Vec<Segment>) {
+		self.bytes += vec_heap(segments);
+		for segment in segments {
+			match segment {
+				Segment::Text(text) => self.bytes += text.capacity(),
+				Segment::Field(field) => if self.rc(field) {
+					match &**field {
+						Field::Placeholder(body) => self.segments(body),
+						Field::Choice(_, choices, labels) => {
+							self.bytes += vec_heap(choices) + vec_heap(labels) + labels.iter().map(String::capacity).sum::<usize>();
+							for body in choices {
+								self.segments(body);
+							}
+						},
+						Field::Number(_) => {},
+						Field::Toggle(_, on, off) => {
+							self.segments(on);
+							self.segments(off);
+						},
+						Field::Repeat(repeat) => {
+							self.bytes += repeat.separator.capacity() + vec_heap(&repeat.repetitions);
+							if self.rc(&repeat.template) {
+								self.snippet(&repeat.template);
+							}
+							for body in &repeat.repetitions {
+								self.segments(body);
+							}
+						}
+					}
+				},
+				Segment::Transformation(transformation) => if self.rc(transformation) {
+					self.bytes += transformation.section.capacity() + transformation.format.capacity() + transformation.flags.capacity() + transformation.result.capacity();
+				},
+				Segment::Variable(variable) => if self.rc(variable) {
+					self.bytes += variable.name.capacity() + variable.value.capacity();
+					if let Some(default) = &variable.default {
+						self.segments(default);
+					}
+				},
+				Segment::Code(code) => if self.rc(code) {
+					self.bytes += code.code.capacity() + code.output.capacity() + code.shebang.capacity();
+				},
+				Segment::Conditional(conditional) => if self.rc(conditional) {
+					if let Condition::Matches(regex) = &conditional.condition {
+						self.bytes += regex.as_str().len();
+					}
+					self.segments(&conditional.then);
+					self.segments(&conditional.otherwise);
+				},
+				Segment::Snippet(snippet) => if self.rc(snippet) {
+					self.snippet(snippet);
+				}
+			}
+		}
+	}
+
+	fn expansions<E>(&mut self, expansions: &Vec<Expansion<E>>) {
+		self.bytes += vec_heap(expansions);
+		for expansion in expansions {
+			self.bytes += vec_heap(&expansion.transformations);
+		}
+	}
+
+	fn snippet(&mut self, snippet: &Snippet) {
+		self.segments(&snippet.body);
+		self.bytes += vec_heap(&snippet.tabs);
+		for tab in &snippet.tabs {
+			self.bytes += vec_heap(&tab.transformations);
+		}
+		self.expansions(&snippet.variables);
+		self.expansions(&snippet.code_expansions);
+		self.bytes += vec_heap(&snippet.named_segments);
+		for named in &snippet.named_segments {
+			self.bytes += match named {
+				NamedSegment::Transformation(name, _) | NamedSegment::Code(name, _) => name.capacity()
+			};
+		}
+	}
+
+	fn definition(&mut self, definition: &SnippetDefinition) {
+		self.bytes += vec_heap(&definition.triggers) + definition.triggers.iter().map(String::capacity).sum::<usize>();
+		self.bytes += definition.description.as_ref().map_or(0, String::capacity);
+		self.bytes += definition.source.as_ref().and_then(|source| source.path.as_ref()).map_or(0, |path| path.capacity());
+		match &definition.kind {
+			SnippetKind::Static(text) => self.bytes += text.capacity(),
+			SnippetKind::Dynamic(snippet) => self.snippet(snippet)
+		}
+	}
+}
+
+impl Snippet {
+	/// Estimate of the bytes of heap memory taken up by the snippet's segments and references,
+	/// counting segments shared between several places (like mirrors) once.
+	pub fn memory_footprint(&self) -> usize {
+		let mut footprint = Footprint::default();
+		footprint.snippet(self);
+		footprint.bytes
+	}
+}
+
+impl SnippetLibrary {
+	/// Estimate of the bytes of heap memory taken up by the library's definitions and their snippets.
+	pub fn memory_footprint(&self) -> usize {
+		let mut footprint = Footprint::default();
+		footprint.bytes += vec_heap(&self.definitions);
+		for definition in self.definitions() {
+			footprint.definition(definition);
+		}
+		footprint.bytes
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn snippet(body: Vec<Segment>) -> Snippet {
+		Snippet {
+			body,
+			tabs: Vec::new(),
+			variables: Vec::new(),
+			code_expansions: Vec::new(),
+			named_segments: Vec::new(),
+			current_tab: None,
+			choice_sources: Vec::new(),
+			baseline: Default::default()
+		}
+	}
+
+	#[test]
+	fn count_shared_segments_once() {
+		let field = Rc::new(Field::Placeholder(vec![Segment::Text(String::from("name"))]));
+		let once = snippet(vec![Segment::Field(field.clone())]);
+		let twice = snippet(vec![Segment::Field(field.clone()), Segment::Field(field)]);
+		let field_bytes = rc_allocation::<Field>() + size_of::<Segment>() + 4;
+		assert_eq!(once.memory_footprint(), size_of::<Segment>() + field_bytes);
+		assert_eq!(twice.memory_footprint(), 2 * size_of::<Segment>() + field_bytes);
+		let library: SnippetLibrary = [SnippetDefinition::new(vec![String::from("t")], None, twice)].into_iter().collect();
+		assert!(library.memory_footprint() > size_of::<SnippetDefinition>() + field_bytes);
+	}
+}
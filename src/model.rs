@@ -0,0 +1,9 @@
+//! The types snippets are made of in one place, for crates that only read, check or convert snippets (such as linters)
+//! and so have no use for running code, resolving variables or the daemon. Everything here is built with no features enabled.
+//! Regexes are those of the [`crate::regex`] module, as conditionals hold them, and parsing comes along with the types.
+
+pub use crate::{Snippet, Segment, Field, RepeatField, NumberField, NumberError, Transformation, Conditional, Condition};
+pub use crate::{Variable, VariableSource, Code, NamedSegment, Tab, Expansion, RenderError};
+pub use crate::shared::{Rc, Weak};
+pub use crate::regex::{Regex, RegexError};
+pub use crate::parse::{ParseError, Position, SnippetSyntax};
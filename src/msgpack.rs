@@ -0,0 +1,213 @@
+//! Writing and reading the values of JSON documents as MessagePack, a more compact encoding of the same values.
+//! Numbers are written as integers when they are whole and fit in 64 bits, as 64 bit floats otherwise.
+//! Binary data and extension types are not read, as nothing written as JSON is either.
+
+use std::io::{self, Read};
+use crate::json::{Node, Value};
+use crate::parse::MAX_NESTING;
+
+/// Appends the value to the bytes.
+pub(crate) fn write(value: &Value, bytes: &mut Vec<u8>) {
+	match value {
+		Value::Null => bytes.push(0xc0),
+		Value::Bool(false) => bytes.push(0xc2),
+		Value::Bool(true) => bytes.push(0xc3),
+		Value::Number(number) => write_number(number, bytes),
+		Value::String(text) => write_string(text, bytes),
+		Value::Array(items) => {
+			write_length(items.len(), [0x90, 0xdc, 0xdd], 16, bytes);
+			for item in items {
+				write(&item.value, bytes);
+			}
+		},
+		Value::Object(entries) => {
+			write_length(entries.len(), [0x80, 0xde, 0xdf], 16, bytes);
+			for (key, node) in entries {
+				write_string(key, bytes);
+				write(&node.value, bytes);
+			}
+		}
+	}
+}
+
+fn write_number(number: &str, bytes: &mut Vec<u8>) {
+	if let Ok(number) = number.parse::<i64>() {
+		match number {
+			0..=0x7f => bytes.push(number as u8),
+			-32..=-1 => bytes.push(number as i8 as u8),
+			_ => {
+				bytes.push(0xd3);
+				bytes.extend(number.to_be_bytes());
+			}
+		}
+	} else if let Ok(number) = number.parse::<u64>() {
+		bytes.push(0xcf);
+		bytes.extend(number.to_be_bytes());
+	} else {
+		bytes.push(0xcb);
+		bytes.extend(number.parse::<f64>().unwrap_or(f64::NAN).to_be_bytes());
+	}
+}
+
+fn write_string(text: &str, bytes: &mut Vec<u8>) {
+	if text.len() < 32 {
+		bytes.push(0xa0 | text.len() as u8);
+	} else if text.len() <= u8::MAX as usize {
+		bytes.extend([0xd9, text.len() as u8]);
+	} else {
+		write_length(text.len(), [0, 0xda, 0xdb], 0, bytes);
+	}
+	bytes.extend(text.as_bytes());
+}
+
+/// Writes the length with the first marker (its low bits holding the length) when the length is below the limit,
+/// with the second followed by 16 bits or the third followed by 32 bits otherwise.
+fn write_length(length: usize, [fixed, short, long]: [u8; 3], limit: usize, bytes: &mut Vec<u8>) {
+	if length < limit {
+		bytes.push(fixed | length as u8);
+	} else if let Ok(length) = u16::try_from(length) {
+		bytes.push(short);
+		bytes.extend(length.to_be_bytes());
+	} else {
+		bytes.push(long);
+		bytes.extend((length as u32).to_be_bytes());
+	}
+}
+
+/// Reads a value from the reader, reading no more than the limit of bytes. None when the reader ends before the value.
+pub(crate) fn read(reader: &mut impl Read, limit: usize) -> io::Result<Option<Node>> {
+	let mut decoder = Decoder { reader, remaining: limit, depth: 0 };
+	let mut first = [0];
+	if decoder.reader.read(&mut first)? == 0 {
+		return Ok(None)
+	}
+	decoder.remaining = decoder.remaining.saturating_sub(1);
+	decoder.value(first[0]).map(|value| Some(Node { line: 0, end: 0, value }))
+}
+
+struct Decoder<'a, R> {
+	reader: &'a mut R,
+	/// Bytes that may still be read.
+	remaining: usize,
+	/// Number of arrays and maps being read.
+	depth: usize
+}
+
+fn invalid(message: &str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl<R: Read> Decoder<'_, R> {
+	fn bytes(&mut self, count: usize) -> io::Result<Vec<u8>> {
+		if count > self.remaining {
+			return Err(invalid("message is too long"))
+		}
+		self.remaining -= count;
+		// Read rather than allocated up front, so a length that is a lie does not allocate more than is sent.
+		let mut bytes = Vec::new();
+		self.reader.take(count as u64).read_to_end(&mut bytes)?;
+		if bytes.len() < count {
+			return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "message ended early"))
+		}
+		Ok(bytes)
+	}
+
+	fn array<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+		let bytes = self.bytes(N)?;
+		Ok(bytes.try_into().unwrap_or([0; N]))
+	}
+
+	fn length(&mut self, bits: u8) -> io::Result<usize> {
+		Ok(match bits {
+			8 => u8::from_be_bytes(self.array()?) as usize,
+			16 => u16::from_be_bytes(self.array()?) as usize,
+			_ => u32::from_be_bytes(self.array()?) as usize
+		})
+	}
+
+	fn node(&mut self) -> io::Result<Node> {
+		let [marker] = self.array()?;
+		Ok(Node { line: 0, end: 0, value: self.value(marker)? })
+	}
+
+	fn string(&mut self, length: usize) -> io::Result<String> {
+		String::from_utf8(self.bytes(length)?).map_err(|_| invalid("string is not UTF-8"))
+	}
+
+	fn key(&mut self) -> io::Result<String> {
+		let [marker] = self.array()?;
+		match self.value(marker)? {
+			Value::String(key) => Ok(key),
+			_ => Err(invalid("map key is not a string"))
+		}
+	}
+
+	fn value(&mut self, marker: u8) -> io::Result<Value> {
+		Ok(match marker {
+			0x00..=0x7f => Value::Number(marker.to_string()),
+			0xe0..=0xff => Value::Number((marker as i8).to_string()),
+			0xc0 => Value::Null,
+			0xc2 => Value::Bool(false),
+			0xc3 => Value::Bool(true),
+			0xcc => Value::Number(u8::from_be_bytes(self.array()?).to_string()),
+			0xcd => Value::Number(u16::from_be_bytes(self.array()?).to_string()),
+			0xce => Value::Number(u32::from_be_bytes(self.array()?).to_string()),
+			0xcf => Value::Number(u64::from_be_bytes(self.array()?).to_string()),
+			0xd0 => Value::Number(i8::from_be_bytes(self.array()?).to_string()),
+			0xd1 => Value::Number(i16::from_be_bytes(self.array()?).to_string()),
+			0xd2 => Value::Number(i32::from_be_bytes(self.array()?).to_string()),
+			0xd3 => Value::Number(i64::from_be_bytes(self.array()?).to_string()),
+			0xca => Value::Number(f32::from_be_bytes(self.array()?).to_string()),
+			0xcb => Value::Number(f64::from_be_bytes(self.array()?).to_string()),
+			0xa0..=0xbf => Value::String(self.string((marker & 0x1f) as usize)?),
+			0xd9..=0xdb => {
+				let length = self.length(8 << (marker - 0xd9))?;
+				Value::String(self.string(length)?)
+			},
+			0x80..=0x9f | 0xdc..=0xdf => {
+				if self.depth == MAX_NESTING {
+					return Err(invalid("arrays and maps are nested too deeply"))
+				}
+				let length = match marker {
+					0x80..=0x9f => (marker & 0x0f) as usize,
+					0xdc | 0xde => self.length(16)?,
+					_ => self.length(32)?
+				};
+				self.depth += 1;
+				let value = if marker <= 0x8f || marker >= 0xde {
+					let entries = (0..length).map(|_| Ok((self.key()?, self.node()?))).collect::<io::Result<_>>()?;
+					Value::Object(entries)
+				} else {
+					Value::Array((0..length).map(|_| self.node()).collect::<io::Result<_>>()?)
+				};
+				self.depth -= 1;
+				value
+			},
+			_ => return Err(invalid("unsupported MessagePack type"))
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::json;
+
+	#[test]
+	fn round_trip_values() {
+		let text = format!("{{\"ok\": true, \"n\": [0, 127, 128, -1, -33, 1.5, 18446744073709551615, null], \"s\": {}}}", json::quote(&"é".repeat(200)));
+		let node = json::parse(&text).unwrap();
+		let mut bytes = Vec::new();
+		write(&node.value, &mut bytes);
+		assert_eq!(bytes[..4], [0x83, 0xa2, b'o', b'k']);
+		let read = read(&mut bytes.as_slice(), bytes.len()).unwrap().unwrap();
+		assert_eq!(read.get("s").and_then(Node::as_str), node.get("s").and_then(Node::as_str));
+		let mut written = Vec::new();
+		write(&read.value, &mut written);
+		assert_eq!(written, bytes);
+		assert!(super::read(&mut &bytes[..bytes.len() - 1], bytes.len()).is_err());
+		assert!(super::read(&mut bytes.as_slice(), 10).is_err());
+		assert!(super::read(&mut [0xc1].as_slice(), 1).is_err());
+		assert!(super::read(&mut [].as_slice(), 1).unwrap().is_none());
+	}
+}
@@ -0,0 +1,269 @@
+//! Best effort conversion between snippets and `{{placeholder}}` style (Mustache, Handlebars, Jinja) templates.
+
+use crate::shared::Rc;
+use crate::{Snippet, Segment, Field, Condition, Tab};
+
+/// Result of a conversion along with what could not be represented in the target syntax.
+#[derive(Debug)]
+pub struct Conversion<T> {
+	pub output: T,
+	/// Constructs that were dropped or approximated.
+	pub lost: Vec<Lost>
+}
+
+/// A construct that could not be converted.
+#[derive(Debug, PartialEq)]
+pub struct Lost {
+	pub construct: Construct,
+	/// The construct's source text or placeholder name.
+	pub detail: String
+}
+
+/// Kinds of constructs that have no equivalent in the other syntax.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Construct {
+	/// A choice field, converted into a plain placeholder.
+	Choice,
+	/// A transformation, converted into its current result.
+	Transformation,
+	/// A code block, converted into a placeholder.
+	Code,
+	/// The final tab (tab 0), dropped.
+	FinalTab,
+	/// A template filter (`{{ name | upper }}`), dropped leaving the placeholder.
+	Filter,
+	/// A template block or helper (`{% if %}`, `{{#each}}`), kept as literal text.
+	Block,
+	/// A conditional segment, converted into the block currently shown.
+	Conditional
+}
+
+/// Converts a snippet into a template.
+/// Tabbed fields become placeholders named after their default text when that is a unique identifier (`tabN` otherwise),
+/// mirrors sharing a placeholder. Variables become placeholders named after the variable.
+pub fn to_template(snippet: &Snippet) -> Conversion<String> {
+	let mut converter = ToTemplate {
+		snippet,
+		output: String::new(),
+		lost: Vec::new(),
+		names: Vec::new(),
+		code_count: 0
+	};
+	converter.segments(&snippet.body);
+	Conversion {
+		output: converter.output,
+		lost: converter.lost
+	}
+}
+
+struct ToTemplate<'a> {
+	snippet: &'a Snippet,
+	output: String,
+	lost: Vec<Lost>,
+	/// Placeholder names given to fields so far.
+	names: Vec<(*const Field, String)>,
+	code_count: usize
+}
+
+fn is_identifier(text: &str) -> bool {
+	text.chars().next().is_some_and(|c| c.is_alphabetic() || c == '_') && text.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+impl ToTemplate<'_> {
+	fn placeholder(&mut self, name: &str) {
+		self.output.push_str("{{");
+		self.output.push_str(name);
+		self.output.push_str("}}");
+	}
+
+	fn lose(&mut self, construct: Construct, detail: String) {
+		self.lost.push(Lost { construct, detail });
+	}
+
+	fn segments(&mut self, segments: &[Segment]) {
+		for segment in segments {
+			match segment {
+				Segment::Text(text) => self.output.push_str(&text.replace("{{", "\\{{")),
+				Segment::Variable(variable) => self.placeholder(&variable.name),
+				Segment::Code(code) => {
+					self.code_count += 1;
+					let name = format!("code{}", self.code_count);
+					self.lose(Construct::Code, code.code.clone());
+					self.placeholder(&name);
+				},
+				Segment::Transformation(transformation) => {
+					self.lose(Construct::Transformation, format!("/{}/{}/{}", transformation.section, transformation.format, transformation.flags));
+					self.output.push_str(&transformation.result.replace("{{", "\\{{"));
+				},
+				Segment::Snippet(nested) => self.segments(&nested.body),
+				Segment::Conditional(conditional) => {
+					let condition = match &conditional.condition {
+						Condition::Empty => String::from("empty"),
+						Condition::NonEmpty => String::from("non-empty"),
+						Condition::Matches(regex) => format!("/{}/{}", regex.as_str(), regex.flags())
+					};
+					self.lose(Construct::Conditional, condition);
+					self.segments(conditional.shown());
+				},
+				Segment::Field(field) => self.field(field)
+			}
+		}
+	}
+
+	fn field(&mut self, field: &Rc<Field>) {
+		if let Some((_, name)) = self.names.iter().find(|(ptr, _)| *ptr == Rc::as_ptr(field)) {
+			let name = name.clone();
+			self.placeholder(&name);
+			return
+		}
+		let num = self.snippet.tabs.iter()
+			.find(|tab| tab.field.upgrade().is_some_and(|tabbed| Rc::ptr_eq(&tabbed, field)))
+			.map(|tab| tab.num);
+		let default = field.to_string();
+		match num {
+			None => self.output.push_str(&default.replace("{{", "\\{{")),
+			Some(0) => {
+				self.lose(Construct::FinalTab, String::from("$0"));
+				self.output.push_str(&default.replace("{{", "\\{{"));
+			},
+			Some(num) => {
+				if let Field::Choice(_, choices, _) = &**field {
+					let options: Vec<String> = choices.iter().map(|choice| choice.iter().map(Segment::to_string).collect()).collect();
+					self.lose(Construct::Choice, options.join(","));
+				}
+				let taken = |name: &str| self.names.iter().any(|(_, taken)| taken == name);
+				let name = if is_identifier(&default) && !taken(&default) {
+					default
+				} else {
+					format!("tab{}", num)
+				};
+				self.names.push((Rc::as_ptr(field), name.clone()));
+				self.placeholder(&name);
+			}
+		}
+	}
+}
+
+/// Converts a template into a snippet.
+/// Every distinct placeholder name becomes a field holding the name as its default text,
+/// numbered in order of first appearance, with repeated placeholders mirroring the first.
+/// `\{{` is a literal `{{`.
+pub fn from_template(template: &str) -> Conversion<Snippet> {
+	let mut snippet = Snippet {
+		body: Vec::new(),
+		tabs: Vec::new(),
+		variables: Vec::new(),
+		code_expansions: Vec::new(),
+		named_segments: Vec::new(),
+		current_tab: None,
+		choice_sources: Vec::new(),
+		baseline: Default::default()
+	};
+	let mut lost = Vec::new();
+	let mut fields: Vec<(String, Rc<Field>)> = Vec::new();
+	let mut text = String::new();
+	let mut rest = template;
+	while let Some(start) = rest.find(['{', '\\']) {
+		text.push_str(&rest[..start]);
+		let from = &rest[start..];
+		if let Some(after) = from.strip_prefix("\\{{") {
+			text.push_str("{{");
+			rest = after;
+			continue
+		}
+		let close = if from.starts_with("{{") {
+			"}}"
+		} else if from.starts_with("{%") || from.starts_with("{#") {
+			if from.starts_with("{%") { "%}" } else { "#}" }
+		} else {
+			text.push_str(&from[..1]);
+			rest = &from[1..];
+			continue
+		};
+		let Some(end) = from[2..].find(close) else {
+			text.push_str(from);
+			rest = "";
+			break
+		};
+		let tag = &from[..end + 2 + close.len()];
+		rest = &from[tag.len()..];
+		let inner = tag[2..tag.len() - close.len()].trim();
+		if close != "}}" || inner.starts_with(['#', '/', '^', '!', '>', '{', '&']) {
+			lost.push(Lost { construct: Construct::Block, detail: tag.to_string() });
+			text.push_str(tag);
+			continue
+		}
+		let name = match inner.split_once('|') {
+			Some((name, _)) => {
+				lost.push(Lost { construct: Construct::Filter, detail: tag.to_string() });
+				name.trim()
+			},
+			None => inner
+		};
+		if !text.is_empty() {
+			snippet.body.push(Segment::Text(std::mem::take(&mut text)));
+		}
+		let field = match fields.iter().find(|(known, _)| known == name) {
+			Some((_, field)) => field.clone(),
+			None => {
+				let field = Rc::new(Field::Placeholder(vec![Segment::Text(name.to_string())]));
+				// Fields past the last tab number are left without a tab.
+				if let Ok(num) = u8::try_from(fields.len() + 1) {
+					snippet.tabs.push(Tab {
+						num,
+						field: Rc::downgrade(&field),
+						transformations: Vec::new(),
+						label: Some(name.to_string())
+					});
+				}
+				fields.push((name.to_string(), field.clone()));
+				field
+			}
+		};
+		snippet.body.push(Segment::Field(field));
+	}
+	text.push_str(rest);
+	if !text.is_empty() {
+		snippet.body.push(Segment::Text(text));
+	}
+	Conversion { output: snippet, lost }
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn template_round_trip() {
+		let conversion = from_template("Hello {{ name }}, {{name|upper}}! {% if x %}\\{{raw}} {{#each items}}");
+		let snippet = conversion.output;
+		assert_eq!(snippet.to_string(), "Hello name, name! {% if x %}{{raw}} {{#each items}}");
+		assert_eq!(snippet.tabs().len(), 1);
+		let lost: Vec<_> = conversion.lost.iter().map(|lost| lost.construct).collect();
+		assert_eq!(lost, [Construct::Filter, Construct::Block, Construct::Block]);
+		let back = to_template(&snippet);
+		assert_eq!(back.output, "Hello {{name}}, {{name}}! {% if x %}\\{{raw}} \\{{#each items}}");
+		assert!(back.lost.is_empty());
+	}
+
+	#[test]
+	fn handle_multi_byte_text() {
+		let template = "π {{ 名前 }} → {{名前|upper}} \\{{é}} {% 🎉 %}";
+		let snippet = from_template(template).output;
+		assert_eq!(snippet.to_string(), "π 名前 → 名前 {{é}} {% 🎉 %}");
+		assert_eq!(to_template(&snippet).output, "π {{名前}} → {{名前}} \\{{é}} {% 🎉 %}");
+		for (i, _) in template.char_indices() {
+			let _ = from_template(&template[..i]);
+		}
+	}
+
+	#[test]
+	#[cfg(feature = "formats-espanso")]
+	fn report_unconvertible_snippet_constructs() {
+		let import = crate::espanso::import("matches:\n  - trigger: a\n    replace: \"{{who}} {{date}} {{USER}} $|$\"\n    vars:\n      - name: who\n        type: choice\n        params:\n          values: [x y, z]\n      - name: date\n        type: shell\n        params:\n          cmd: date\n").unwrap();
+		let conversion = to_template(import.definitions[0].snippet().unwrap());
+		assert_eq!(conversion.output, "{{tab1}} {{code1}} {{USER}} ");
+		let lost: Vec<_> = conversion.lost.iter().map(|lost| (lost.construct, lost.detail.as_str())).collect();
+		assert_eq!(lost, [(Construct::Choice, "x y,z"), (Construct::Code, "date"), (Construct::FinalTab, "$0")]);
+	}
+}
@@ -0,0 +1,171 @@
+//! Moving between the tabs of a snippet in the order they are selected: by number, with the final tab (tab 0) last.
+
+use std::ops::Range;
+use crate::shared::Rc;
+use crate::{Snippet, Segment, Field};
+use crate::handle::TabRef;
+
+/// The selected tab, along with where its field is within the rendered snippet.
+#[derive(Debug)]
+pub struct TabStop<'a> {
+	pub tab: TabRef<'a>,
+	/// Byte range of the first occurrence of the field in the rendered snippet (as Display renders it).
+	/// None when the field is not rendered, such as when it is within an option that is not chosen.
+	pub range: Option<Range<usize>>
+}
+
+impl Snippet {
+	/// Numbers of the tabs in the order they are selected.
+	fn tab_order(&self) -> Vec<u8> {
+		let mut nums: Vec<u8> = self.tabs.iter().map(|tab| tab.num).collect();
+		nums.sort_by_key(|&num| (num == 0, num));
+		nums.dedup();
+		nums
+	}
+
+	pub(crate) fn tab_stop(&self, num: u8) -> Option<TabStop<'_>> {
+		let tab = self.tab_ref(num)?;
+		let field = self.tabs.iter().find(|tab| tab.num == num)?.field.upgrade();
+		let range = field.and_then(|field| rendered_range(&self.body, Rc::as_ptr(&field), &mut 0));
+		Some(TabStop { tab, range })
+	}
+
+	/// The selected tab. None before a tab is selected or when the selected tab no longer exists.
+	pub fn current_tab(&self) -> Option<TabStop<'_>> {
+		self.tab_stop(self.current_tab?)
+	}
+
+	/// Selects the tab after the selected one, or the first tab when none is selected.
+	/// None (keeping the selected tab) when the final tab is already selected.
+	pub fn next_tab(&mut self) -> Option<TabStop<'_>> {
+		let order = self.tab_order();
+		let next = match self.current_tab.and_then(|current| order.iter().position(|&num| num == current)) {
+			Some(index) => order.get(index + 1),
+			None => order.first()
+		};
+		self.current_tab = Some(*next?);
+		self.current_tab()
+	}
+
+	/// Selects the tab before the selected one. None (keeping the selected tab) when the first tab or no tab is selected.
+	pub fn prev_tab(&mut self) -> Option<TabStop<'_>> {
+		let order = self.tab_order();
+		let index = order.iter().position(|&num| Some(num) == self.current_tab)?;
+		self.current_tab = Some(*order.get(index.checked_sub(1)?)?);
+		self.current_tab()
+	}
+
+	/// Selects the tab with the number. None (keeping the selected tab) when there is no such tab.
+	pub fn jump_to(&mut self, num: u8) -> Option<TabStop<'_>> {
+		self.tab_ref(num)?;
+		self.current_tab = Some(num);
+		self.current_tab()
+	}
+
+	/// A short description of the selected tab for screen readers, such as `tab 2 of 5, placeholder 'type', currently 'u32'`:
+	/// its place in the order tabs are selected in, the kind of its field, its label if it has one and its text.
+	/// None before a tab is selected or when the selected tab no longer exists.
+	pub fn describe(&self) -> Option<String> {
+		let num = self.current_tab?;
+		let tab = self.tabs.iter().find(|tab| tab.num == num)?;
+		let field = tab.field.upgrade()?;
+		let order = self.tab_order();
+		let position = order.iter().position(|&known| known == num)? + 1;
+		let kind = match &*field {
+			Field::Placeholder(_) => "placeholder",
+			Field::Choice(..) => "choice",
+			Field::Number(_) => "number",
+			Field::Toggle(..) => "toggle",
+			Field::Repeat(_) => "repeated group"
+		};
+		let mut description = format!("tab {} of {}, {}", position, order.len(), kind);
+		if let Some(label) = &tab.label {
+			description.push_str(&format!(" '{}'", label));
+		}
+		match field.to_string() {
+			text if text.is_empty() => description.push_str(", empty"),
+			text => description.push_str(&format!(", currently '{}'", text))
+		}
+		Some(description)
+	}
+}
+
+/// Finds the field within the rendered segments, advancing start past the text rendered before it.
+fn rendered_range(segments: &[Segment], field: *const Field, start: &mut usize) -> Option<Range<usize>> {
+	for segment in segments {
+		let found = match segment {
+			Segment::Text(text) => {
+				*start += text.len();
+				None
+			},
+			Segment::Variable(variable) => {
+				*start += variable.value.len();
+				None
+			},
+			Segment::Code(code) => {
+				*start += code.output.len();
+				None
+			},
+			Segment::Transformation(transformation) => {
+				*start += transformation.result.len();
+				None
+			},
+			Segment::Snippet(nested) => rendered_range(&nested.body, field, start),
+			Segment::Conditional(conditional) => rendered_range(conditional.shown(), field, start),
+			Segment::Field(child) if Rc::as_ptr(child) == field => return Some(*start..*start + child.to_string().len()),
+			Segment::Field(child) => match &**child {
+				Field::Placeholder(child_body) => rendered_range(child_body, field, start),
+				Field::Choice(choice, child_body, _) => child_body.get(*choice).and_then(|child_body| rendered_range(child_body, field, start)),
+				Field::Number(number) => {
+					*start += number.value.to_string().len();
+					None
+				},
+				Field::Toggle(on, when_on, when_off) => rendered_range(if *on { when_on } else { when_off }, field, start),
+				Field::Repeat(repeat) => repeat.repetitions.iter().enumerate().find_map(|(i, body)| {
+					if i > 0 {
+						*start += repeat.separator.len();
+					}
+					rendered_range(body, field, start)
+				})
+			}
+		};
+		if found.is_some() {
+			return found
+		}
+	}
+	None
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn navigate_tabs() {
+		let mut snippet = Snippet::parse("fn ${2:name}(${1:arg}) -> $3 {$0} $2").unwrap();
+		assert!(snippet.current_tab().is_none());
+		assert!(snippet.prev_tab().is_none());
+		let order: Vec<(u8, Option<Range<usize>>)> = std::iter::from_fn(|| snippet.next_tab().map(|stop| (stop.tab.num(), stop.range))).collect();
+		assert_eq!(order, [(1, Some(8..11)), (2, Some(3..7)), (3, Some(16..16)), (0, Some(18..18))]);
+		assert_eq!(snippet.current_tab().unwrap().tab.num(), 0);
+		assert_eq!(snippet.prev_tab().unwrap().tab.num(), 3);
+		assert!(snippet.jump_to(7).is_none());
+		assert_eq!(snippet.jump_to(1).unwrap().tab.num(), 1);
+		assert!(snippet.prev_tab().is_none());
+		assert_eq!(snippet.current_tab().unwrap().tab.num(), 1);
+	}
+
+	#[test]
+	fn describe_tabs() {
+		let mut snippet = Snippet::parse("let ${1:x}: ${2:type} = ${3|a,b|};$0").unwrap();
+		assert!(snippet.describe().is_none());
+		snippet.label_tab(2, "type");
+		snippet.set_field_text(2, "u32").unwrap();
+		snippet.jump_to(2);
+		assert_eq!(snippet.describe().unwrap(), "tab 2 of 4, placeholder 'type', currently 'u32'");
+		snippet.next_tab();
+		assert_eq!(snippet.describe().unwrap(), "tab 3 of 4, choice, currently 'a'");
+		snippet.next_tab();
+		assert_eq!(snippet.describe().unwrap(), "tab 4 of 4, placeholder, empty");
+	}
+}
@@ -0,0 +1,79 @@
+use crate::Snippet;
+
+/// What to do with tabs sharing a number when renumbering.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicateTabs {
+	/// Duplicates keep sharing their new number.
+	#[default]
+	Keep,
+	/// Each duplicate gets a number of its own, in order of the snippet's tabs.
+	Separate,
+	/// Only the first tab with a number is kept, the fields of the others no longer being tabbed.
+	Drop
+}
+
+/// How a tab was renumbered: its old number and its new number, None when the tab was dropped.
+pub type Renumbered = (u8, Option<u8>);
+
+impl Snippet {
+	/// Compacts the tab numbers into 1, 2, 3... keeping their order (1, 3, 7 becoming 1, 2, 3),
+	/// with tabs sharing a number handled according to the policy. The final tab (tab 0) keeps its number.
+	/// Returns how each of the snippet's tabs was renumbered, in the order of its tabs before renumbering.
+	pub fn renumber_tabs(&mut self, duplicates: DuplicateTabs) -> Vec<Renumbered> {
+		let mut order: Vec<usize> = (0..self.tabs.len()).collect();
+		order.sort_by_key(|&index| self.tabs[index].num);
+		let mut mapping: Vec<Renumbered> = self.tabs.iter().map(|tab| (tab.num, Some(tab.num))).collect();
+		let mut next = 0u8;
+		let mut previous = None;
+		for index in order {
+			let old = self.tabs[index].num;
+			let duplicate = previous == Some(old);
+			previous = Some(old);
+			mapping[index].1 = match (old, duplicate, duplicates) {
+				(_, true, DuplicateTabs::Drop) => None,
+				(0, _, _) => Some(0),
+				(_, true, DuplicateTabs::Keep) => Some(next),
+				_ => {
+					next = next.saturating_add(1);
+					Some(next)
+				}
+			};
+		}
+		let mut index = 0;
+		self.tabs.retain_mut(|tab| {
+			let new = mapping[index].1;
+			index += 1;
+			if let Some(new) = new {
+				tab.num = new;
+			}
+			new.is_some()
+		});
+		if let Some(current) = self.current_tab {
+			self.current_tab = mapping.iter().find(|(old, _)| *old == current).and_then(|(_, new)| *new);
+		}
+		mapping
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mustache::from_template;
+
+	#[test]
+	fn compact_and_resolve_duplicates() {
+		let renumber = |nums: &[u8], duplicates| {
+			let mut snippet = from_template(&"{{a}}{{b}}{{c}}{{d}}{{e}}"[..nums.len() * 5]).output;
+			for (tab, num) in snippet.tabs.iter_mut().zip(nums) {
+				tab.num = *num;
+			}
+			let mapping = snippet.renumber_tabs(duplicates);
+			let nums: Vec<u8> = snippet.tabs().iter().map(|tab| tab.num).collect();
+			(mapping, nums)
+		};
+		assert_eq!(renumber(&[7, 1, 3], DuplicateTabs::Keep), (vec![(7, Some(3)), (1, Some(1)), (3, Some(2))], vec![3, 1, 2]));
+		assert_eq!(renumber(&[0, 4, 2, 4], DuplicateTabs::Keep).1, [0, 2, 1, 2]);
+		assert_eq!(renumber(&[0, 4, 2, 4], DuplicateTabs::Separate).1, [0, 2, 1, 3]);
+		assert_eq!(renumber(&[5, 4, 2, 4, 0], DuplicateTabs::Drop), (vec![(5, Some(3)), (4, Some(2)), (2, Some(1)), (4, None), (0, Some(0))], vec![3, 2, 1, 0]));
+	}
+}
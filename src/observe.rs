@@ -0,0 +1,234 @@
+//! Telling an observer what happens as snippets are filled in, such as for analytics, replaying sessions when debugging
+//! them or tests asserting on the order things happen in.
+//!
+//! Snippets are observed through [`Snippet::observe`], which tells the observer of tabs selected, fields edited and code run
+//! through it, and the daemon tells the observer given with [`crate::ipc::SnippetDaemon::with_observer`] of its sessions.
+
+use std::sync::mpsc::Sender;
+use crate::Snippet;
+use crate::navigate::TabStop;
+use crate::rendered::{CursorBehavior, Span};
+use crate::transform::TransformError;
+#[cfg(feature = "exec")]
+use crate::exec::{self, CodeRunner, CodeError};
+#[cfg(feature = "exec")]
+use crate::Code;
+
+/// What happened to a session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SessionEvent {
+	/// The snippet was expanded, filling it in starting.
+	Started,
+	/// The tab with the number was selected.
+	TabEntered(u8),
+	/// The field of the tab with the number was edited, now showing the text.
+	FieldEdited(u8, String),
+	/// The code was run, giving the output or why it could not be run.
+	CodeExecuted { code: String, outcome: Result<String, String> },
+	/// Filling in the snippet ended, leaving it as it is.
+	Finalized,
+	/// Filling in the snippet ended, leaving it out.
+	Cancelled
+}
+
+/// Told what happens to sessions, by the number of the session.
+pub trait SessionObserver {
+	fn event(&mut self, session: u64, event: &SessionEvent);
+}
+
+/// Records the events in the order they happen.
+impl SessionObserver for Vec<(u64, SessionEvent)> {
+	fn event(&mut self, session: u64, event: &SessionEvent) {
+		self.push((session, event.clone()));
+	}
+}
+
+/// Sends the events to another thread, such as from the threads of the daemon. Events are dropped once nothing receives them.
+impl SessionObserver for Sender<(u64, SessionEvent)> {
+	fn event(&mut self, session: u64, event: &SessionEvent) {
+		let _ = self.send((session, event.clone()));
+	}
+}
+
+/// A snippet being filled in as a session of the observer, see [`Snippet::observe`].
+pub struct ObservedSession<'a> {
+	snippet: &'a mut Snippet,
+	session: u64,
+	observer: &'a mut dyn SessionObserver
+}
+
+impl Snippet {
+	/// Fills in the snippet as the session with the number, telling the observer of what is done through the session.
+	/// Nothing is told until then, so that a session can be resumed; see [`ObservedSession::start`].
+	pub fn observe<'a>(&'a mut self, session: u64, observer: &'a mut dyn SessionObserver) -> ObservedSession<'a> {
+		ObservedSession { snippet: self, session, observer }
+	}
+}
+
+impl ObservedSession<'_> {
+	/// The snippet as filled in so far.
+	pub fn snippet(&self) -> &Snippet {
+		self.snippet
+	}
+
+	/// A short description of the selected tab for screen readers, see [`Snippet::describe`].
+	pub fn describe(&self) -> Option<String> {
+		self.snippet.describe()
+	}
+
+	fn tell(&mut self, event: SessionEvent) {
+		self.observer.event(self.session, &event);
+	}
+
+	/// Tells the observer that the session started.
+	pub fn start(&mut self) {
+		self.tell(SessionEvent::Started);
+	}
+
+	/// Selects the tab after the selected one, see [`Snippet::next_tab`].
+	pub fn next_tab(&mut self) -> Option<TabStop<'_>> {
+		let num = self.snippet.next_tab()?.tab.num();
+		self.entered(num)
+	}
+
+	/// Selects the tab before the selected one, see [`Snippet::prev_tab`].
+	pub fn prev_tab(&mut self) -> Option<TabStop<'_>> {
+		let num = self.snippet.prev_tab()?.tab.num();
+		self.entered(num)
+	}
+
+	/// Selects the tab with the number, see [`Snippet::jump_to`].
+	pub fn jump_to(&mut self, num: u8) -> Option<TabStop<'_>> {
+		self.snippet.jump_to(num)?;
+		self.entered(num)
+	}
+
+	fn entered(&mut self, num: u8) -> Option<TabStop<'_>> {
+		self.tell(SessionEvent::TabEntered(num));
+		self.snippet.current_tab()
+	}
+
+	/// Replaces the contents of the tab's field with the text, see [`Snippet::set_field_text`].
+	pub fn set_field_text(&mut self, num: u8, text: &str) -> Option<Vec<TransformError>> {
+		let errors = self.snippet.set_field_text(num, text)?;
+		self.edited(num);
+		Some(errors)
+	}
+
+	/// Chooses the option of the tab's choice field, see [`Snippet::set_choice`].
+	pub fn set_choice(&mut self, num: u8, index: usize) -> Option<Vec<TransformError>> {
+		let errors = self.snippet.set_choice(num, index)?;
+		self.edited(num);
+		Some(errors)
+	}
+
+	fn edited(&mut self, num: u8) {
+		self.tell(edited(self.snippet, num));
+	}
+
+	/// Runs every code expansion of the snippet, see [`Snippet::run_code_expansions_with`].
+	#[cfg(feature = "exec")]
+	pub fn run_code_expansions_with(&mut self, runner: &CodeRunner) -> Vec<CodeError> {
+		self.run_code(|code| runner.run(code).map(drop))
+	}
+
+	/// Runs every code expansion of the snippet, delegating that of some shebangs,
+	/// see [`Snippet::run_code_expansions_delegating`].
+	#[cfg(feature = "exec")]
+	pub fn run_code_expansions_delegating(&mut self, runner: Option<&CodeRunner>, delegated: &[String], evaluate: impl FnMut(&str, &Code) -> Result<String, CodeError>) -> Vec<CodeError> {
+		self.run_code(exec::delegating(runner, delegated, evaluate))
+	}
+
+	#[cfg(feature = "exec")]
+	fn run_code(&mut self, mut run: impl FnMut(&mut Code) -> Result<(), CodeError>) -> Vec<CodeError> {
+		let (session, observer) = (self.session, &mut *self.observer);
+		self.snippet.replace_code_output(|code| {
+			let result = run(code);
+			let outcome = match &result {
+				Ok(()) => Ok(code.output.clone()),
+				Err(error) => Err(error.to_string())
+			};
+			observer.event(session, &SessionEvent::CodeExecuted { code: code.code.clone(), outcome });
+			result
+		})
+	}
+
+	/// Ends the session, telling the observer that the snippet was filled in, see [`Snippet::finalize`].
+	pub fn finalize(mut self, behavior: CursorBehavior) -> Vec<Span> {
+		self.tell(SessionEvent::Finalized);
+		self.snippet.finalize(behavior)
+	}
+
+	/// Ends the session, telling the observer that the snippet was left out.
+	pub fn cancel(mut self) {
+		self.tell(SessionEvent::Cancelled);
+	}
+}
+
+/// The event of the field of the tab with the number having been edited.
+pub(crate) fn edited(snippet: &Snippet, num: u8) -> SessionEvent {
+	let text = snippet.tab_ref(num).and_then(|tab| tab.field()).map(|field| field.get().to_string()).unwrap_or_default();
+	SessionEvent::FieldEdited(num, text)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn observe_in_order() {
+		let mut snippet = Snippet::parse("${1:a} ${2|x,y|} $1$0").unwrap();
+		let mut events = Vec::new();
+		let mut session = snippet.observe(3, &mut events);
+		session.start();
+		assert_eq!(session.next_tab().unwrap().tab.num(), 1);
+		session.set_field_text(1, "bc").unwrap();
+		assert_eq!(session.describe().as_deref(), Some("tab 1 of 3, placeholder, currently 'bc'"));
+		assert!(session.set_field_text(4, "z").is_none());
+		assert_eq!(session.jump_to(2).unwrap().range, Some(3..4));
+		session.set_choice(2, 1).unwrap();
+		assert!(session.jump_to(5).is_none());
+		session.next_tab();
+		assert!(session.next_tab().is_none());
+		assert_eq!(session.snippet().to_string(), "bc y bc");
+		assert_eq!(session.finalize(CursorBehavior::FinalTab)[0].bytes, 7..7);
+		let events: Vec<SessionEvent> = events.into_iter().map(|(session, event)| {
+			assert_eq!(session, 3);
+			event
+		}).collect();
+		assert_eq!(events, [
+			SessionEvent::Started,
+			SessionEvent::TabEntered(1),
+			SessionEvent::FieldEdited(1, String::from("bc")),
+			SessionEvent::TabEntered(2),
+			SessionEvent::FieldEdited(2, String::from("y")),
+			SessionEvent::TabEntered(0),
+			SessionEvent::Finalized
+		]);
+	}
+
+	#[cfg(feature = "exec")]
+	#[test]
+	fn observe_code() {
+		use crate::parse::SnippetSyntax;
+		let mut snippet = Snippet::parse_with(SnippetSyntax::UltiSnips, "`!p snip.rv = 1` `!p snip.rv = 2` $1").unwrap();
+		let mut events = Vec::new();
+		let mut session = snippet.observe(1, &mut events);
+		session.start();
+		let delegated = [String::from(exec::PYTHON_SHEBANG)];
+		let errors = session.run_code_expansions_delegating(None, &delegated, |_, code| match code.code.as_str() {
+			"snip.rv = 1" => Ok(String::from("one")),
+			_ => Err(CodeError::Delegated(String::from("no")))
+		});
+		assert_eq!(errors.len(), 1);
+		session.next_tab();
+		session.cancel();
+		assert_eq!(events.into_iter().map(|(_, event)| event).collect::<Vec<_>>(), [
+			SessionEvent::Started,
+			SessionEvent::CodeExecuted { code: String::from("snip.rv = 1"), outcome: Ok(String::from("one")) },
+			SessionEvent::CodeExecuted { code: String::from("snip.rv = 2"), outcome: Err(CodeError::Delegated(String::from("no")).to_string()) },
+			SessionEvent::TabEntered(1),
+			SessionEvent::Cancelled
+		]);
+	}
+}
@@ -0,0 +1,152 @@
+//! Expanding a snippet in one go, such as from Makefiles and shell pipelines (see the `snippet-expand` command):
+//! a request naming the snippet and what to fill it in with is read as JSON, and the snippet is written filled in.
+//!
+//! The request is an object with the `snippet` body, optionally its `syntax` (`"lsp"`, the default, or `"ultisnips"`),
+//! and optionally `fill`, an object with the text of tabs' fields by their number (`"1"`) and the values of variables by name.
+//! Variables not filled in are resolved as [`StandardVariables`] resolves them, then fall back to their default or name
+//! (see [`Snippet::fall_through_variables`]).
+//!
+//! Failures are written as a JSON object with the `kind` of failure (`io`, `request`, `parse`, `tab` or `transformation`)
+//! and a `message`, along with the `line` and `column` of parse errors and the `tab` of the others.
+
+use std::{fmt, io};
+use std::io::{Read, Write};
+use crate::Snippet;
+use crate::json::{self, quote, Node, Value};
+use crate::parse::{ParseError, SnippetSyntax};
+use crate::resolve::{StandardVariables, VariableResolver};
+use crate::transform::TransformError;
+
+/// Why a request could not be expanded.
+#[derive(Debug)]
+pub enum ExpandError {
+	/// The request could not be read.
+	Io(io::Error),
+	/// The request is not an object as described in the module documentation. Carries a description of the problem.
+	Request(String),
+	Parse(ParseError),
+	/// The fill map gives text to a tab the snippet does not have.
+	UnknownTab(u8),
+	/// A transformation acting upon the tab could not be applied to the text it was filled in with.
+	Transformation(u8, TransformError)
+}
+
+impl fmt::Display for ExpandError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			ExpandError::Io(error) => write!(f, "could not read request: {}", error),
+			ExpandError::Request(message) => write!(f, "invalid request: {}", message),
+			ExpandError::Parse(error) => write!(f, "{}", error),
+			ExpandError::UnknownTab(num) => write!(f, "the snippet has no tab {}", num),
+			ExpandError::Transformation(num, error) => write!(f, "tab {}: {}", num, error)
+		}
+	}
+}
+
+impl std::error::Error for ExpandError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			ExpandError::Io(error) => Some(error),
+			ExpandError::Parse(error) => Some(error),
+			ExpandError::Transformation(_, error) => Some(error),
+			_ => None
+		}
+	}
+}
+
+impl ExpandError {
+	/// The error as a JSON object, as described in the module documentation.
+	pub fn to_json(&self) -> String {
+		let message = quote(&self.to_string());
+		match self {
+			ExpandError::Io(_) => format!("{{\"kind\": \"io\", \"message\": {}}}", message),
+			ExpandError::Request(_) => format!("{{\"kind\": \"request\", \"message\": {}}}", message),
+			ExpandError::Parse(error) => {
+				let position = error.position();
+				format!("{{\"kind\": \"parse\", \"message\": {}, \"line\": {}, \"column\": {}}}", message, position.line, position.column)
+			},
+			ExpandError::UnknownTab(num) => format!("{{\"kind\": \"tab\", \"message\": {}, \"tab\": {}}}", message, num),
+			ExpandError::Transformation(num, _) => format!("{{\"kind\": \"transformation\", \"message\": {}, \"tab\": {}}}", message, num)
+		}
+	}
+}
+
+/// Expands the request, giving the text of the snippet filled in.
+pub fn expand(request: &str) -> Result<String, ExpandError> {
+	let invalid = |message: &str| ExpandError::Request(message.to_string());
+	let request = json::parse(request).map_err(|error| ExpandError::Request(format!("line {}: {}", error.line, error.message)))?;
+	let syntax = match request.get("syntax").map(|syntax| syntax.as_str()) {
+		None | Some(Some("lsp")) => SnippetSyntax::Lsp,
+		Some(Some("ultisnips")) => SnippetSyntax::UltiSnips,
+		Some(_) => return Err(invalid("syntax needs to be \"lsp\" or \"ultisnips\""))
+	};
+	let body = request.get("snippet").and_then(Node::as_str).ok_or_else(|| invalid("the request needs a snippet"))?;
+	let mut snippet = Snippet::parse_with(syntax, body).map_err(ExpandError::Parse)?;
+	let fill = match request.get("fill").map(|fill| &fill.value) {
+		Some(Value::Object(entries)) => entries.iter()
+			.map(|(key, value)| Ok((key.as_str(), value.as_str().ok_or_else(|| invalid("fill values need to be text"))?)))
+			.collect::<Result<Vec<_>, _>>()?,
+		None => Vec::new(),
+		Some(_) => return Err(invalid("fill needs to be an object"))
+	};
+	for &(key, text) in &fill {
+		let Ok(num) = key.parse::<u8>() else {
+			continue
+		};
+		let errors = snippet.set_field_text(num, text).ok_or(ExpandError::UnknownTab(num))?;
+		if let Some(error) = errors.into_iter().next() {
+			return Err(ExpandError::Transformation(num, error))
+		}
+	}
+	let standard = StandardVariables::default();
+	snippet.fall_through_variables(&|name: &str| {
+		fill.iter().find(|(key, _)| *key == name).map(|(_, value)| value.to_string()).or_else(|| standard.resolve(name))
+	});
+	Ok(snippet.to_string())
+}
+
+/// Expands the request read from the input, writing the text to the output or the error (as JSON) to errors.
+/// Returns whether the request was expanded, failing only when the output or errors could not be written.
+pub fn run(input: &mut impl Read, output: &mut impl Write, errors: &mut impl Write) -> io::Result<bool> {
+	let mut request = String::new();
+	let expanded = match input.read_to_string(&mut request) {
+		Ok(_) => expand(&request),
+		Err(error) if error.kind() == io::ErrorKind::InvalidData => Err(ExpandError::Request(String::from("the request is not UTF-8"))),
+		Err(error) => Err(ExpandError::Io(error))
+	};
+	match expanded {
+		Ok(text) => {
+			output.write_all(text.as_bytes())?;
+			Ok(true)
+		},
+		Err(error) => {
+			writeln!(errors, "{}", error.to_json())?;
+			Ok(false)
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn expand_requests() {
+		let request = r#"{"snippet": "fn ${1:name}(${2:args}) -> ${3:()} {\n\t$0\n}", "fill": {"1": "main", "3": "i32"}}"#;
+		assert_eq!(expand(request).unwrap(), "fn main(args) -> i32 {\n\t\n}");
+		let request = r#"{"snippet": "${VISUAL} $1 `echo x`", "syntax": "ultisnips", "fill": {"VISUAL": "v", "1": "a"}}"#;
+		assert_eq!(expand(request).unwrap(), "v a ");
+		assert!(matches!(expand(r#"{"snippet": "x", "syntax": "yaml"}"#), Err(ExpandError::Request(_))));
+		assert!(matches!(expand(r#"{"fill": {}}"#), Err(ExpandError::Request(_))));
+		let request = r#"{"snippet": "${1:a} ${NAME:b} ${NOPE}", "fill": {"NAME": "n"}}"#;
+		assert_eq!(expand(request).unwrap(), "a n NOPE");
+
+		let (mut output, mut errors) = (Vec::new(), Vec::new());
+		assert!(!run(&mut r#"{"snippet": "${1:a", "fill": {}}"#.as_bytes(), &mut output, &mut errors).unwrap());
+		assert!(output.is_empty());
+		assert_eq!(String::from_utf8(errors).unwrap(), "{\"kind\": \"parse\", \"message\": \"line 1, column 1: placeholder is not closed by }\", \"line\": 1, \"column\": 1}\n");
+		let mut errors = Vec::new();
+		assert!(!run(&mut r#"{"snippet": "$1", "fill": {"2": "x"}}"#.as_bytes(), &mut output, &mut errors).unwrap());
+		assert!(String::from_utf8(errors).unwrap().starts_with("{\"kind\": \"tab\""));
+	}
+}
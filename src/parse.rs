@@ -0,0 +1,1393 @@
+//! Parsing of snippets written in the LSP (TextMate) snippet syntax:
+//! tabs (`$1`, `${1}`), placeholders (`${1:default}`), choices (`${1|a,b,c|}`),
+//! variables (`$NAME`, `${NAME}`, `${NAME:default}`) and transformations (`${1/regex/format/flags}`, `${NAME/regex/format/flags}`).
+//! Transformations and code can be named where they appear (`${name=${1/regex/format/flags}}`) and reused after by name (`${name}`).
+//! Also parses the UltiSnips flavour of the syntax, which adds interpolated code and the `${VISUAL}` placeholder.
+//! Options can also number tabs written without a number, written numbers from 0 rather than 1,
+//! and repeat part of a snippet a number of times (`${repeat:3:item$i, }`).
+//! Programs using this library can add constructs of their own (`${name:argument}`) through [`Extensions`].
+//! A [`GrammarProfile`] limits what is read to the constructs of one dialect, and may read malformed constructs as text.
+//! Which constructs a profile has, and which of those a snippet uses it lacks, can be asked for ahead of writing a snippet for that dialect.
+//! Constructs can be written with other [`Delimiters`] than `$`, `{` and `}`, such as `%1` or `{{1}}`.
+
+use std::fmt;
+use std::borrow::Cow;
+use std::collections::HashSet;
+use crate::shared::{Rc, Weak};
+use crate::{Snippet, Segment, Field, Transformation, Variable, VariableSource, Code, NamedSegment, Tab, Expansion};
+use crate::compose::shared;
+
+/// Flavours of snippet syntax that can be parsed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SnippetSyntax {
+	/// The LSP (TextMate, VSCode) snippet syntax.
+	#[default]
+	Lsp,
+	/// The syntax of UltiSnips snippet bodies. Adds shell (`` `cmd` ``) and python (`` `!p code` ``) interpolation, read as code,
+	/// and `${VISUAL}` (the text selected before expanding), read as a variable coming from the client.
+	/// `\` also escapes `` ` ``, and `$` followed by a name other than VISUAL is normal text.
+	UltiSnips
+}
+
+/// Dialects of snippet syntax, differing in which constructs they have (see [`Construct`]) and in whether what is malformed is an error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GrammarProfile {
+	/// The grammar of the LSP specification: no named segments or code, and malformed constructs are errors.
+	Lsp,
+	/// The grammar of VSCode: that of the LSP specification, with malformed constructs read as text.
+	VsCode,
+	/// The grammar of TextMate: no choices or named segments, with shell (`` `cmd` ``) interpolation read as code.
+	TextMate,
+	/// The grammar of UltiSnips: the [`SnippetSyntax::UltiSnips`] syntax, without transformations of variables or named segments.
+	UltiSnips,
+	/// Every construct of the syntax, with malformed constructs read as text.
+	Permissive
+}
+
+/// Constructs that the dialects of [`GrammarProfile`] disagree on having.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Construct {
+	/// `${1|a,b|}`.
+	Choice,
+	/// `${1/regex/format/flags}`.
+	TabTransformation,
+	/// `${NAME/regex/format/flags}`.
+	VariableTransformation,
+	/// `${name=...}`.
+	NamedSegment,
+	/// Interpolated code.
+	Code
+}
+
+impl Construct {
+	/// Every construct, in the order they are listed in.
+	pub const ALL: [Construct; 5] = [Construct::Choice, Construct::TabTransformation, Construct::VariableTransformation, Construct::NamedSegment, Construct::Code];
+}
+
+impl fmt::Display for Construct {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(match self {
+			Construct::Choice => "choices",
+			Construct::TabTransformation => "transformations of tabs",
+			Construct::VariableTransformation => "transformations of variables",
+			Construct::NamedSegment => "named segments",
+			Construct::Code => "interpolated code"
+		})
+	}
+}
+
+impl GrammarProfile {
+	/// Syntax the profile reads, None for [`GrammarProfile::Permissive`], which reads that of the options.
+	pub fn syntax(self) -> Option<SnippetSyntax> {
+		match self {
+			GrammarProfile::Lsp | GrammarProfile::VsCode | GrammarProfile::TextMate => Some(SnippetSyntax::Lsp),
+			GrammarProfile::UltiSnips => Some(SnippetSyntax::UltiSnips),
+			GrammarProfile::Permissive => None
+		}
+	}
+
+	/// Whether the dialect has the construct.
+	pub fn supports(self, construct: Construct) -> bool {
+		!matches!((self, construct),
+			(GrammarProfile::Lsp | GrammarProfile::VsCode, Construct::NamedSegment | Construct::Code)
+			| (GrammarProfile::TextMate, Construct::Choice | Construct::NamedSegment)
+			| (GrammarProfile::UltiSnips, Construct::VariableTransformation | Construct::NamedSegment))
+	}
+
+	/// The constructs the dialect has, in the order of [`Construct::ALL`].
+	pub fn capabilities(self) -> Vec<Construct> {
+		Construct::ALL.into_iter().filter(|construct| self.supports(*construct)).collect()
+	}
+
+	/// The constructs the snippet uses (see [`Snippet::constructs`]) that the dialect does not have,
+	/// so that the snippet can not be written in it as it is.
+	pub fn unsupported(self, snippet: &Snippet) -> Vec<Construct> {
+		snippet.constructs().into_iter().filter(|construct| !self.supports(*construct)).collect()
+	}
+
+	/// Whether malformed constructs are read as text rather than being errors.
+	fn lenient(self) -> bool {
+		matches!(self, GrammarProfile::VsCode | GrammarProfile::Permissive)
+	}
+}
+
+impl Snippet {
+	/// The constructs of [`Construct`] the snippet uses, in the order of [`Construct::ALL`].
+	/// Transformations of variables are listed as named segments named after the variable (see [`Snippet::parse`]),
+	/// so a transformation is a named segment when it is also that of a tab or is named more than once.
+	pub fn constructs(&self) -> Vec<Construct> {
+		let transformations: Vec<&Weak<Transformation>> = self.named_segments.iter().filter_map(|named| match named {
+			NamedSegment::Transformation(_, transformation) => Some(transformation),
+			NamedSegment::Code(..) => None
+		}).collect();
+		let of_tab = |transformation: &Weak<Transformation>| self.tabs.iter().any(|tab| tab.transformations.iter().any(|of| of.ptr_eq(transformation)));
+		let named_again = |transformation: &Weak<Transformation>| transformations.iter().filter(|named| named.ptr_eq(transformation)).count() > 1;
+		Construct::ALL.into_iter().filter(|construct| match construct {
+			Construct::Choice => self.segments().any(|segment| matches!(segment, Segment::Field(field) if matches!(**field, Field::Choice(..)))),
+			Construct::TabTransformation => self.tabs.iter().any(|tab| !tab.transformations.is_empty()),
+			Construct::VariableTransformation => self.variables.iter().any(|variable| !variable.transformations.is_empty())
+				|| transformations.iter().any(|transformation| !of_tab(transformation)),
+			Construct::NamedSegment => self.named_segments.iter().any(|named| matches!(named, NamedSegment::Code(..)))
+				|| transformations.iter().any(|transformation| of_tab(transformation) || named_again(transformation)),
+			Construct::Code => !self.code_expansions.is_empty()
+		}).collect()
+	}
+}
+
+/// What constructs are written with: a sigil before tabs and variables, and braces around those that need them,
+/// `$` and `{` `}` by default (`$1`, `${1:default}`). Other delimiters, such as `%` (`%1`, `%{1:default}`) or only `{{` and `}}` (`{{1:default}}`),
+/// keep constructs from clashing with shell-heavy snippet bodies. The sigil and closing brace replace `$` and `}` in escapes:
+/// `\` escapes their first characters and itself, and nothing else, so with `%` `\%1` is the text `%1` and `$1` is text as it is.
+/// Formats of transformations keep their own syntax (`$1`, `${1:/upcase}`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Delimiters {
+	sigil: Cow<'static, str>,
+	open: Cow<'static, str>,
+	close: Cow<'static, str>
+}
+
+impl Default for Delimiters {
+	fn default() -> Self {
+		Delimiters { sigil: Cow::Borrowed("$"), open: Cow::Borrowed("{"), close: Cow::Borrowed("}") }
+	}
+}
+
+impl Delimiters {
+	/// Delimiters of the sigil and braces. With no opening brace, every construct is braced and opened by the sigil alone.
+	/// None when the sigil or closing brace is empty, when either starts with the first character of the other,
+	/// or when any starts with `\`, `` ` ``, a letter, digit, `_`, space or one of `:|/=` (which would be read as part of a construct).
+	/// They can be read at runtime, such as from a configuration file.
+	pub fn new(sigil: impl Into<Cow<'static, str>>, open: impl Into<Cow<'static, str>>, close: impl Into<Cow<'static, str>>) -> Option<Self> {
+		let (sigil, open, close) = (sigil.into(), open.into(), close.into());
+		let reserved = |delimiter: &str| delimiter.starts_with(|c: char| c.is_alphanumeric() || c.is_whitespace() || "\\`_:|/=".contains(c));
+		let (Some(first), Some(closing)) = (sigil.chars().next(), close.chars().next()) else {
+			return None
+		};
+		if [&sigil, &open, &close].into_iter().any(|delimiter| reserved(delimiter)) || sigil.starts_with(closing) || close.starts_with(first) {
+			return None
+		}
+		Some(Delimiters { sigil, open, close })
+	}
+
+	pub fn sigil(&self) -> &str {
+		&self.sigil
+	}
+
+	pub fn open(&self) -> &str {
+		&self.open
+	}
+
+	pub fn close(&self) -> &str {
+		&self.close
+	}
+
+	/// Whether `\` escapes the character.
+	fn escapes(&self, c: char) -> bool {
+		c == '\\' || self.sigil.starts_with(c) || self.close.starts_with(c)
+	}
+}
+
+/// Which number written in a snippet is its first tab.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NumberingBase {
+	/// `$1` is the first tab and `$0` the final tab (where the cursor ends up), as in the LSP syntax.
+	#[default]
+	One,
+	/// `$0` is the first tab, each number written being the tab after it (`$0` tab 1, `$1` tab 2 and so on), and there is no final tab.
+	Zero
+}
+
+/// How [`Snippet::parse_with_options`] reads a snippet.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ParseOptions {
+	pub syntax: SnippetSyntax,
+	pub base: NumberingBase,
+	/// Whether `${:default}`, `$_` and `${_}` are tabs without a number written, numbered after the highest number written in the order they appear,
+	/// rather than a malformed placeholder and the variable `_`.
+	pub anonymous_tabs: bool,
+	/// Whether `${repeat:count:body}` is the body repeated count times (at most 255) rather than the variable `repeat`.
+	/// Within the body `$i` is the number of the repetition (starting at 1), and each repetition numbers its tabs after those of the one before:
+	/// tab numbers other than 0 grow by the highest of them for every repetition, so `${repeat:2:${1:a}$2 }` is `${1:a}$2 ${3:a}$4 `.
+	/// Tabs outside of the body are not renumbered. Repetitions can not be nested.
+	pub repetition: bool,
+	/// Dialect whose grammar is read, in place of everything the syntax has. Its syntax (see [`GrammarProfile::syntax`]) is read in place of that of the options.
+	pub profile: Option<GrammarProfile>,
+	pub delimiters: Delimiters
+}
+
+impl ParseOptions {
+	/// Options reading the grammar of the profile.
+	pub fn with_profile(profile: GrammarProfile) -> Self {
+		ParseOptions { syntax: profile.syntax().unwrap_or_default(), profile: Some(profile), ..ParseOptions::default() }
+	}
+}
+
+/// Produces the segments a `${name:argument}` of an extension stands for from its argument, or why it can not.
+pub type ExtensionFn = dyn Fn(&str) -> Result<Vec<Segment>, String>;
+
+/// Constructs added to the syntax by the program using this library: `${name:argument}` for each name registered,
+/// standing for the segments the extension produces from the argument (the text up to the next `}`, `\` escaping `}` and `\`).
+/// Variables and code among the segments produced are expansions of the snippet. Fields among them are not tabs.
+#[derive(Default)]
+pub struct Extensions {
+	extensions: Vec<(String, Box<ExtensionFn>)>
+}
+
+impl fmt::Debug for Extensions {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_list().entries(self.extensions.iter().map(|(name, _)| name)).finish()
+	}
+}
+
+impl Extensions {
+	pub fn new() -> Self {
+		Extensions::default()
+	}
+
+	/// Adds the extension read as `${name:argument}`, in place of any registered before with the name.
+	/// Names are read as names of variables are, so must start with a letter or `_`.
+	pub fn register(&mut self, name: &str, produce: impl Fn(&str) -> Result<Vec<Segment>, String> + 'static) {
+		self.extensions.retain(|(registered, _)| registered != name);
+		self.extensions.push((name.to_string(), Box::new(produce)));
+	}
+
+	/// Parses a snippet as [`Snippet::parse_with_options`] does, reading the registered extensions.
+	pub fn parse(&self, options: ParseOptions, text: &str) -> Result<Snippet, ParseError> {
+		parse(options, self, text)
+	}
+
+	fn get(&self, name: &str) -> Option<&ExtensionFn> {
+		self.extensions.iter().find(|(registered, _)| registered == name).map(|(_, produce)| produce.as_ref())
+	}
+}
+
+/// Deepest nesting the parsers of this crate accept (of placeholders within placeholders, groups within groups and so on),
+/// so that neither parsing nor anything done with what was parsed runs out of stack however the input is nested.
+pub const MAX_NESTING: usize = 128;
+
+/// Where within the parsed text something is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+	/// Byte offset from the start of the text.
+	pub offset: usize,
+	/// Line (starting at 1).
+	pub line: usize,
+	/// Character within the line (starting at 1).
+	pub column: usize
+}
+
+impl Position {
+	/// Position of the byte offset within the text.
+	pub fn of(text: &str, offset: usize) -> Self {
+		let before = &text[..offset];
+		let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+		Position {
+			offset,
+			line: before.matches('\n').count() + 1,
+			column: before[line_start..].chars().count() + 1
+		}
+	}
+}
+
+/// Why a snippet could not be parsed. Each variant carries the position of the construct at fault.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+	/// A `${` is never closed by a `}`.
+	UnterminatedPlaceholder(Position),
+	/// A choice is never closed by `|}`.
+	UnterminatedChoice(Position),
+	/// A tab number that does not fit in a u8, or an anonymous tab (see [`ParseOptions::anonymous_tabs`]) left without a number.
+	InvalidTabIndex(Position),
+	/// A transformation is missing one of its `/` separators or its closing `}`.
+	MalformedTransformation(Position),
+	/// A `${` is followed by something other than a tab number or variable name,
+	/// or the number or name is followed by something other than `}`, `:`, `|` or `/`.
+	MalformedPlaceholder(Position),
+	/// Interpolated code is never closed by a `` ` ``.
+	UnterminatedCode(Position),
+	/// A `\` within the format of a transformation is followed by a letter or digit that has no meaning there.
+	UnknownEscape(Position),
+	/// A `${name=` is followed by something other than a single transformation or code and `}`, or names a segment already named.
+	MalformedNamedSegment(Position),
+	/// A `$` is nested within more than [`MAX_NESTING`] placeholders.
+	TooDeeplyNested(Position),
+	/// An extension (see [`Extensions`]) could not produce segments from its argument, for the reason given.
+	Extension(Position, String),
+	/// A construct that the grammar profile being read does not have.
+	Unsupported(Position, Construct)
+}
+
+impl ParseError {
+	/// Where the construct at fault starts.
+	pub fn position(&self) -> Position {
+		match self {
+			ParseError::UnterminatedPlaceholder(position)
+			| ParseError::UnterminatedChoice(position)
+			| ParseError::InvalidTabIndex(position)
+			| ParseError::MalformedTransformation(position)
+			| ParseError::MalformedPlaceholder(position)
+			| ParseError::UnterminatedCode(position)
+			| ParseError::UnknownEscape(position)
+			| ParseError::MalformedNamedSegment(position)
+			| ParseError::TooDeeplyNested(position)
+			| ParseError::Extension(position, _)
+			| ParseError::Unsupported(position, _) => *position
+		}
+	}
+}
+
+impl fmt::Display for ParseError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let position = self.position();
+		write!(f, "line {}, column {}: ", position.line, position.column)?;
+		match self {
+			ParseError::UnterminatedPlaceholder(_) => write!(f, "placeholder is not closed by }}"),
+			ParseError::UnterminatedChoice(_) => write!(f, "choice is not closed by |}}"),
+			ParseError::InvalidTabIndex(_) => write!(f, "tab number is larger than 255"),
+			ParseError::MalformedTransformation(_) => write!(f, "transformation is not of the form /regex/format/flags}}"),
+			ParseError::MalformedPlaceholder(_) => write!(f, "expected a tab number or variable name followed by }}, :, | or /"),
+			ParseError::UnterminatedCode(_) => write!(f, "interpolated code is not closed by `"),
+			ParseError::UnknownEscape(_) => write!(f, "unknown escape in transformation format"),
+			ParseError::MalformedNamedSegment(_) => write!(f, "named segment is not a single transformation or code, or is named again"),
+			ParseError::TooDeeplyNested(_) => write!(f, "placeholders are nested more than {} deep", MAX_NESTING),
+			ParseError::Extension(_, reason) => write!(f, "extension failed: {}", reason),
+			ParseError::Unsupported(_, construct) => write!(f, "{} are not part of the grammar", construct)
+		}
+	}
+}
+
+impl std::error::Error for ParseError {}
+
+/// What a transformation transforms.
+#[derive(Debug, Clone, PartialEq)]
+enum Target {
+	Tab(u8),
+	Variable(String)
+}
+
+/// Snippet syntax as written, before mirrors are tied together.
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+	Text(String),
+	Tab(u8),
+	Placeholder(u8, Vec<Node>),
+	Choice(u8, Vec<String>),
+	Variable(String, Option<Vec<Node>>),
+	Transform(Target, String, String, String),
+	/// Shebang and code.
+	Code(&'static str, String),
+	/// Definition or reuse of a named segment, what it is defined as being kept by the parser.
+	Named(String),
+	/// Tab or placeholder without a number written, at the offset. Numbered before building.
+	Anonymous(usize, Option<Vec<Node>>),
+	/// Repetitions of the body of a `${repeat:...}`, taking its place among the nodes read.
+	Repeated(Vec<Node>),
+	/// Segments produced by an extension, by index among those kept by the parser.
+	Extension(usize)
+}
+
+/// Variables provided by the editor (the client) rather than the environment.
+const CLIENT_VARIABLES: &[&str] = &[
+	"SELECTION", "CLIPBOARD", "RELATIVE_FILEPATH", "WORKSPACE_NAME", "WORKSPACE_FOLDER", "CURSOR_INDEX", "CURSOR_NUMBER",
+	"RANDOM", "RANDOM_HEX", "UUID", "BLOCK_COMMENT_START", "BLOCK_COMMENT_END", "LINE_COMMENT"
+];
+
+/// Names of variables that UltiSnips reads in `${NAME}`.
+const ULTISNIPS_VARIABLES: &[&str] = &["VISUAL"];
+
+/// Where a variable of the name comes from in the LSP syntax: the editor (the client) for the TextMate and VSCode variables
+/// describing the editor's state, such as `TM_SELECTED_TEXT` and `CLIPBOARD`, and the program using this library for any other.
+pub fn variable_source(name: &str) -> VariableSource {
+	if name.starts_with("TM_") || name.starts_with("CURRENT_") || CLIENT_VARIABLES.contains(&name) {
+		VariableSource::Client
+	} else {
+		VariableSource::Daemon
+	}
+}
+
+impl Snippet {
+	/// Parses a snippet written in the LSP snippet syntax.
+	///
+	/// Every occurrence of a tab number shares one field (the first placeholder or choice given for the number defining it),
+	/// and every occurrence of a variable with the same default shares one variable.
+	/// Variables start out with their default as value and keep it to fall back to, nested tabs and variables within a default being left as their text.
+	/// Those without a default start out as their name, as TextMate shows variables it does not know, unless they describe the editor's state
+	/// (see [`variable_source`]), which start out empty.
+	/// Transformations start out with an empty result. Those of a tab are listed with the tab, and a tab that only appears
+	/// in transformations gets an empty field where it first appears. Those of a variable are listed with the variable and
+	/// as named segments named after the variable, as the variable may not appear in the snippet otherwise.
+	/// A transformation or code given a name with `${name=...}` is a named segment, shown where it is defined, and `$name` or `${name}`
+	/// after the definition shows the same segment rather than a variable.
+	/// A `$` not starting a tab or variable, like a `}` outside of a placeholder, is normal text, and `\` escapes `$`, `}` and `\`.
+	pub fn parse(text: &str) -> Result<Snippet, ParseError> {
+		Snippet::parse_with(SnippetSyntax::Lsp, text)
+	}
+
+	/// Parses a snippet written in the given flavour of syntax, as [`Snippet::parse`] does.
+	/// Interpolated code is listed among the code expansions with no output yet.
+	pub fn parse_with(syntax: SnippetSyntax, text: &str) -> Result<Snippet, ParseError> {
+		Snippet::parse_with_options(ParseOptions { syntax, ..ParseOptions::default() }, text)
+	}
+
+	/// Parses a snippet as [`Snippet::parse_with`] does, numbering its tabs according to the options.
+	/// Tab numbers are those of the snippet whichever base it is written in, so with [`NumberingBase::Zero`] `$0` is tab 1 and `$255` does not fit.
+	pub fn parse_with_options(options: ParseOptions, text: &str) -> Result<Snippet, ParseError> {
+		parse(options, &Extensions::default(), text)
+	}
+}
+
+fn parse(mut options: ParseOptions, extensions: &Extensions, text: &str) -> Result<Snippet, ParseError> {
+	if let Some(syntax) = options.profile.and_then(GrammarProfile::syntax) {
+		options.syntax = syntax;
+	}
+	let mut parser = Parser { text, pos: 0, options, extensions, extended: Vec::new(), named: Vec::new(), depth: 0, repeating: false, malformed: HashSet::new() };
+	let mut nodes = parser.nodes(false)?;
+	let named = parser.named;
+	let highest = highest_number(&nodes).max(named.iter().filter_map(|(_, node)| highest_number(std::slice::from_ref(node))).max());
+	let mut next = highest.map_or(Some(1), |highest| highest.checked_add(1));
+	number_anonymous(&mut nodes, &mut next, text)?;
+	Ok(Builder::new(parser.options.syntax, &nodes, &named, &parser.extended).build(&nodes))
+}
+
+struct Parser<'a> {
+	text: &'a str,
+	pos: usize,
+	options: ParseOptions,
+	extensions: &'a Extensions,
+	/// Segments produced by extensions so far.
+	extended: Vec<Vec<Segment>>,
+	/// Names of the named segments defined so far, with what they are defined as.
+	named: Vec<(String, Node)>,
+	/// Number of `$` constructs being read.
+	depth: usize,
+	/// Whether the body of a `${repeat:...}` is being read.
+	repeating: bool,
+	/// Offsets of the `$` found to start malformed constructs when those are read as text,
+	/// so that what they contain is not read again for every one of them it is within.
+	malformed: HashSet<usize>
+}
+
+fn is_name_start(c: char) -> bool {
+	c.is_ascii_alphabetic() || c == '_'
+}
+
+fn is_name(c: char) -> bool {
+	c.is_ascii_alphanumeric() || c == '_'
+}
+
+impl<'a> Parser<'a> {
+	fn rest(&self) -> &'a str {
+		&self.text[self.pos..]
+	}
+
+	fn peek(&self) -> Option<char> {
+		self.rest().chars().next()
+	}
+
+	fn position(&self, offset: usize) -> Position {
+		Position::of(self.text, offset)
+	}
+
+	fn at(&self, token: &str) -> bool {
+		self.rest().starts_with(token)
+	}
+
+	/// Whether the closing brace is at the current position.
+	fn closing(&self) -> bool {
+		self.at(self.options.delimiters.close())
+	}
+
+	/// Whether `` ` `` starts interpolated code.
+	fn interpolates(&self) -> bool {
+		self.options.syntax == SnippetSyntax::UltiSnips || self.options.profile == Some(GrammarProfile::TextMate)
+	}
+
+	/// Nothing when the profile being read has the construct starting at the offset.
+	fn supported(&self, construct: Construct, start: usize) -> Result<(), ParseError> {
+		match self.options.profile {
+			Some(profile) if !profile.supports(construct) => Err(ParseError::Unsupported(self.position(start), construct)),
+			_ => Ok(())
+		}
+	}
+
+	/// Reads nodes until the end of the text, or the `}` closing the placeholder being read when `nested` is set (not consuming it).
+	fn nodes(&mut self, nested: bool) -> Result<Vec<Node>, ParseError> {
+		let mut nodes = Vec::new();
+		let mut text = String::new();
+		while let Some(c) = self.peek() {
+			match c {
+				_ if nested && self.closing() => break,
+				'\\' => {
+					self.pos += 1;
+					match self.peek() {
+						Some(escaped) if self.options.delimiters.escapes(escaped) => {
+							text.push(escaped);
+							self.pos += escaped.len_utf8();
+						},
+						Some('`') if self.interpolates() => {
+							text.push('`');
+							self.pos += 1;
+						},
+						_ => text.push('\\')
+					}
+				},
+				'`' if self.interpolates() => {
+					let start = self.pos;
+					match self.code() {
+						Ok(node) => {
+							if !text.is_empty() {
+								nodes.push(Node::Text(std::mem::take(&mut text)));
+							}
+							nodes.push(node);
+						},
+						Err(_) if self.options.profile.is_some_and(GrammarProfile::lenient) => {
+							self.pos = start + 1;
+							text.push('`');
+						},
+						Err(error) => return Err(error)
+					}
+				},
+				_ if self.at(self.options.delimiters.sigil()) => match self.dollar()? {
+					Some(Node::Repeated(repeated)) => {
+						if !text.is_empty() {
+							nodes.push(Node::Text(std::mem::take(&mut text)));
+						}
+						nodes.extend(repeated);
+					},
+					Some(node) => {
+						if !text.is_empty() {
+							nodes.push(Node::Text(std::mem::take(&mut text)));
+						}
+						nodes.push(node);
+					},
+					None => text.push_str(self.options.delimiters.sigil())
+				},
+				_ => {
+					text.push(c);
+					self.pos += c.len_utf8();
+				}
+			}
+		}
+		if !text.is_empty() {
+			nodes.push(Node::Text(text));
+		}
+		Ok(nodes)
+	}
+
+	/// Reads the digits (tab number) or name (variable) at the current position.
+	fn number_or_name(&mut self) -> Result<Option<Result<u8, String>>, ParseError> {
+		let start = self.pos;
+		let rest = self.rest();
+		if rest.starts_with(|c: char| c.is_ascii_digit()) {
+			let len = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+			self.pos += len;
+			let num = rest[..len].parse::<u8>().ok().and_then(|num| match self.options.base {
+				NumberingBase::One => Some(num),
+				NumberingBase::Zero => num.checked_add(1)
+			});
+			Ok(Some(Ok(num.ok_or_else(|| ParseError::InvalidTabIndex(self.position(start)))?)))
+		} else if rest.starts_with(is_name_start) {
+			let len = rest.find(|c: char| !is_name(c)).unwrap_or(rest.len());
+			self.pos += len;
+			Ok(Some(Err(rest[..len].to_string())))
+		} else {
+			Ok(None)
+		}
+	}
+
+	/// Reads what a `$` (the sigil) starts, leaving the `$` as normal text when it does not start anything
+	/// (or is malformed, when the profile being read reads malformed constructs as text).
+	fn dollar(&mut self) -> Result<Option<Node>, ParseError> {
+		if self.depth == MAX_NESTING {
+			return Err(ParseError::TooDeeplyNested(self.position(self.pos)))
+		}
+		let start = self.pos;
+		if self.malformed.contains(&start) {
+			self.pos += self.options.delimiters.sigil.len();
+			return Ok(None)
+		}
+		let named = self.named.len();
+		self.depth += 1;
+		let node = self.construct();
+		self.depth -= 1;
+		match node {
+			Err(error) if !matches!(error, ParseError::TooDeeplyNested(_)) && self.options.profile.is_some_and(GrammarProfile::lenient) => {
+				self.named.truncate(named);
+				self.malformed.insert(start);
+				self.pos = start + self.options.delimiters.sigil.len();
+				Ok(None)
+			},
+			node => node
+		}
+	}
+
+	/// Reads what the `$` at the current position starts (see [`Parser::dollar`]).
+	fn construct(&mut self) -> Result<Option<Node>, ParseError> {
+		let start = self.pos;
+		let (sigil, open, close) = (self.options.delimiters.sigil.len(), self.options.delimiters.open.len(), self.options.delimiters.close.len());
+		self.pos += sigil;
+		let braced = self.at(self.options.delimiters.open());
+		if braced {
+			self.pos += open;
+		}
+		if self.options.anonymous_tabs && braced && self.peek() == Some(':') {
+			self.pos += 1;
+			let body = self.nodes(true)?;
+			if !self.closing() {
+				return Err(ParseError::UnterminatedPlaceholder(self.position(start)))
+			}
+			self.pos += close;
+			return Ok(Some(Node::Anonymous(start, Some(body))))
+		}
+		let id = match self.number_or_name()? {
+			Some(Err(name)) if self.named.iter().any(|(defined, _)| *defined == name) && (!braced || self.closing()) => {
+				if braced {
+					self.pos += close;
+				}
+				return Ok(Some(Node::Named(name)))
+			},
+			Some(Err(name)) if braced && self.peek() == Some('=') => {
+				self.supported(Construct::NamedSegment, start)?;
+				self.pos += 1;
+				return self.named(name, start).map(Some)
+			},
+			Some(Err(name)) if self.options.repetition && braced && name == "repeat" && self.peek() == Some(':') => {
+				self.pos += 1;
+				return self.repeat(start).map(Some)
+			},
+			Some(Err(name)) if braced && self.peek() == Some(':') && self.extensions.get(&name).is_some() => {
+				self.pos += 1;
+				return self.extension(&name, start).map(Some)
+			},
+			Some(Err(name)) if self.options.anonymous_tabs && name == "_" && (!braced || self.closing()) => {
+				if braced {
+					self.pos += close;
+				}
+				return Ok(Some(Node::Anonymous(start, None)))
+			},
+			Some(Err(name)) if self.options.syntax == SnippetSyntax::UltiSnips && !ULTISNIPS_VARIABLES.contains(&name.as_str()) => {
+				self.pos = start + sigil;
+				return Ok(None)
+			},
+			id if !braced => return Ok(id.map(|id| match id {
+				Ok(num) => Node::Tab(num),
+				Err(name) => Node::Variable(name, None)
+			})),
+			Some(id) => id,
+			None => return Err(if self.peek().is_none() { ParseError::UnterminatedPlaceholder(self.position(start)) } else { ParseError::MalformedPlaceholder(self.position(start)) })
+		};
+		let node = match (self.peek(), id) {
+			(_, Ok(num)) if self.closing() => Node::Tab(num),
+			(_, Err(name)) if self.closing() => Node::Variable(name, None),
+			(Some(':'), id) => {
+				self.pos += 1;
+				let body = self.nodes(true)?;
+				match id {
+					Ok(num) => Node::Placeholder(num, body),
+					Err(name) => Node::Variable(name, Some(body))
+				}
+			},
+			(Some('|'), Ok(num)) => {
+				self.supported(Construct::Choice, start)?;
+				self.pos += 1;
+				let options = self.choice().ok_or_else(|| ParseError::UnterminatedChoice(self.position(start)))?;
+				return Ok(Some(Node::Choice(num, options)))
+			},
+			(Some('/'), id) => {
+				self.supported(if id.is_ok() { Construct::TabTransformation } else { Construct::VariableTransformation }, start)?;
+				self.pos += 1;
+				let transform = self.transform(start)?;
+				let target = match id {
+					Ok(num) => Target::Tab(num),
+					Err(name) => Target::Variable(name)
+				};
+				return Ok(Some(Node::Transform(target, transform.0, transform.1, transform.2)))
+			},
+			(None, _) => return Err(ParseError::UnterminatedPlaceholder(self.position(start))),
+			_ => return Err(ParseError::MalformedPlaceholder(self.position(start)))
+		};
+		if !self.closing() {
+			return Err(ParseError::UnterminatedPlaceholder(self.position(start)))
+		}
+		self.pos += close;
+		Ok(Some(node))
+	}
+
+	/// Reads what the segment is named as up to and including the closing `}`, starting with the `${name=` at the offset.
+	fn named(&mut self, name: String, start: usize) -> Result<Node, ParseError> {
+		let malformed = ParseError::MalformedNamedSegment(self.position(start));
+		if self.named.iter().any(|(defined, _)| *defined == name) {
+			return Err(malformed)
+		}
+		let node = match self.peek() {
+			_ if self.at(self.options.delimiters.sigil()) => self.dollar()?,
+			Some('`') if self.interpolates() => Some(self.code()?),
+			_ => None
+		};
+		let Some(node @ (Node::Transform(..) | Node::Code(..))) = node else {
+			return Err(malformed)
+		};
+		match self.peek() {
+			_ if self.closing() => self.pos += self.options.delimiters.close.len(),
+			None => return Err(ParseError::UnterminatedPlaceholder(self.position(start))),
+			Some(_) => return Err(malformed)
+		}
+		self.named.push((name.clone(), node));
+		Ok(Node::Named(name))
+	}
+
+	/// Reads the `count:body}` of a repetition (the `${repeat:` already read), starting at the offset, into its repetitions.
+	fn repeat(&mut self, start: usize) -> Result<Node, ParseError> {
+		let malformed = ParseError::MalformedPlaceholder(self.position(start));
+		let len = self.rest().find(|c: char| !c.is_ascii_digit()).unwrap_or(self.rest().len());
+		let Ok(count) = self.rest()[..len].parse::<u8>() else {
+			return Err(malformed)
+		};
+		self.pos += len;
+		if self.repeating || self.peek() != Some(':') {
+			return Err(malformed)
+		}
+		self.pos += 1;
+		self.repeating = true;
+		let body = self.nodes(true);
+		self.repeating = false;
+		let body = body?;
+		if !self.closing() {
+			return Err(ParseError::UnterminatedPlaceholder(self.position(start)))
+		}
+		self.pos += self.options.delimiters.close.len();
+		let span = highest_number(&body).unwrap_or(0);
+		let mut repeated = Vec::new();
+		for index in 0..count {
+			let offset = (span as usize) * (index as usize);
+			repeated.extend(repetition(&body, index as usize + 1, offset).ok_or_else(|| ParseError::InvalidTabIndex(self.position(start)))?);
+		}
+		Ok(Node::Repeated(repeated))
+	}
+
+	/// Reads the `argument}` of the extension of the name (the `${name:` already read), starting at the offset, producing its segments.
+	fn extension(&mut self, name: &str, start: usize) -> Result<Node, ParseError> {
+		let mut argument = String::new();
+		loop {
+			if self.closing() {
+				self.pos += self.options.delimiters.close.len();
+				break
+			}
+			let c = self.peek().ok_or_else(|| ParseError::UnterminatedPlaceholder(self.position(start)))?;
+			self.pos += c.len_utf8();
+			match c {
+				'\\' => match self.peek() {
+					Some(escaped) if escaped == '\\' || self.options.delimiters.close.starts_with(escaped) => {
+						argument.push(escaped);
+						self.pos += escaped.len_utf8();
+					},
+					_ => argument.push('\\')
+				},
+				_ => argument.push(c)
+			}
+		}
+		let produce = self.extensions.get(name).ok_or_else(|| ParseError::MalformedPlaceholder(self.position(start)))?;
+		let segments = produce(&argument).map_err(|reason| ParseError::Extension(self.position(start), reason))?;
+		self.extended.push(segments);
+		Ok(Node::Extension(self.extended.len() - 1))
+	}
+
+	/// Reads interpolated code up to and including the closing `` ` ``, `\` escaping `` ` ``.
+	fn code(&mut self) -> Result<Node, ParseError> {
+		let start = self.pos;
+		self.pos += 1;
+		let mut code = String::new();
+		loop {
+			let c = self.peek().ok_or_else(|| ParseError::UnterminatedCode(self.position(start)))?;
+			self.pos += c.len_utf8();
+			match c {
+				'`' => break,
+				'\\' if self.peek() == Some('`') => {
+					code.push('`');
+					self.pos += 1;
+				},
+				_ => code.push(c)
+			}
+		}
+		Ok(match code.strip_prefix("!p").filter(|_| self.options.syntax == SnippetSyntax::UltiSnips) {
+			Some(python) if python.is_empty() || python.starts_with(char::is_whitespace) => {
+				let python = python.strip_prefix([' ', '\n']).unwrap_or(python);
+				Node::Code("#!/usr/bin/env python3", python.to_string())
+			},
+			_ => Node::Code("#!/bin/sh", code)
+		})
+	}
+
+	/// Reads the options of a choice up to and including the closing `|}`.
+	fn choice(&mut self) -> Option<Vec<String>> {
+		let mut options = Vec::new();
+		let mut option = String::new();
+		loop {
+			let c = self.peek()?;
+			self.pos += c.len_utf8();
+			match c {
+				'\\' => match self.peek() {
+					Some(escaped) if self.options.delimiters.escapes(escaped) || escaped == ',' || escaped == '|' => {
+						option.push(escaped);
+						self.pos += escaped.len_utf8();
+					},
+					_ => option.push('\\')
+				},
+				',' => options.push(std::mem::take(&mut option)),
+				'|' if self.closing() => {
+					self.pos += self.options.delimiters.close.len();
+					options.push(option);
+					return Some(options)
+				},
+				_ => option.push(c)
+			}
+		}
+	}
+
+	/// Reads the `regex/format/flags}` of a transformation (the opening `/` already read), starting with the `${` at the offset.
+	fn transform(&mut self, start: usize) -> Result<(String, String, String), ParseError> {
+		let section = self.until_slash(false)?;
+		let format = if section.is_some() { self.until_slash(true)? } else { None };
+		let close = self.options.delimiters.close();
+		let len = format.as_ref().and_then(|_| self.rest().find(close));
+		let (Some(section), Some(format), Some(len)) = (section, format, len) else {
+			return Err(ParseError::MalformedTransformation(self.position(start)))
+		};
+		let flags = self.rest()[..len].to_string();
+		self.pos += len + close.len();
+		Ok((section, format, flags))
+	}
+
+	/// Reads up to and including the next unescaped `/`, unescaping `\/`. Nothing when there is no such `/`.
+	/// In formats, `${...}` groups (which may contain `/`) are read as a whole, and letters and digits may only be escaped
+	/// when the escape means something: `\n`, `\t`, `\r` and the case changes `\u`, `\l`, `\U`, `\L` and `\E`.
+	fn until_slash(&mut self, format: bool) -> Result<Option<String>, ParseError> {
+		let mut part = String::new();
+		loop {
+			let Some(c) = self.peek() else {
+				return Ok(None)
+			};
+			self.pos += c.len_utf8();
+			match c {
+				'/' => return Ok(Some(part)),
+				'\\' => match self.peek() {
+					Some('/') => {
+						part.push('/');
+						self.pos += 1;
+					},
+					Some(escaped) => {
+						if format && escaped.is_ascii_alphanumeric() && !"ntrulULE".contains(escaped) {
+							return Err(ParseError::UnknownEscape(self.position(self.pos - 1)))
+						}
+						part.push('\\');
+						part.push(escaped);
+						self.pos += escaped.len_utf8();
+					},
+					None => return Ok(None)
+				},
+				'$' if format && self.peek() == Some('{') => {
+					let Some(len) = self.rest().find('}') else {
+						return Ok(None)
+					};
+					part.push('$');
+					part.push_str(&self.rest()[..len + 1]);
+					self.pos += len + 1;
+				},
+				_ => part.push(c)
+			}
+		}
+	}
+}
+
+/// One repetition of the body of a `${repeat:...}`, `$i` being the index and every tab number other than 0 grown by the offset.
+/// None when a number no longer fits.
+fn repetition(nodes: &[Node], index: usize, offset: usize) -> Option<Vec<Node>> {
+	let num = |num: u8| if num == 0 { Some(0) } else { u8::try_from(num as usize + offset).ok() };
+	nodes.iter().map(|node| Some(match node {
+		Node::Variable(name, None) if name == "i" => Node::Text(index.to_string()),
+		Node::Tab(n) => Node::Tab(num(*n)?),
+		Node::Placeholder(n, body) => Node::Placeholder(num(*n)?, repetition(body, index, offset)?),
+		Node::Choice(n, options) => Node::Choice(num(*n)?, options.clone()),
+		Node::Transform(Target::Tab(n), section, format, flags) => Node::Transform(Target::Tab(num(*n)?), section.clone(), format.clone(), flags.clone()),
+		Node::Variable(name, Some(body)) => Node::Variable(name.clone(), Some(repetition(body, index, offset)?)),
+		Node::Anonymous(at, Some(body)) => Node::Anonymous(*at, Some(repetition(body, index, offset)?)),
+		node => node.clone()
+	})).collect()
+}
+
+/// Highest tab number written among the nodes, including within placeholders and variable defaults.
+fn highest_number(nodes: &[Node]) -> Option<u8> {
+	nodes.iter().filter_map(|node| match node {
+		Node::Tab(num) | Node::Choice(num, _) | Node::Transform(Target::Tab(num), ..) => Some(*num),
+		Node::Placeholder(num, body) => Some(highest_number(body).map_or(*num, |highest| highest.max(*num))),
+		Node::Variable(_, Some(body)) | Node::Anonymous(_, Some(body)) => highest_number(body),
+		Node::Repeated(nodes) => highest_number(nodes),
+		Node::Text(_) | Node::Variable(_, None) | Node::Transform(Target::Variable(_), ..) | Node::Code(..) | Node::Named(_) | Node::Anonymous(_, None)
+		| Node::Extension(_) => None
+	}).max()
+}
+
+/// Numbers the anonymous tabs among the nodes in the order they appear (outer placeholders before those within them) from the number given,
+/// None once numbers have run out.
+fn number_anonymous(nodes: &mut [Node], next: &mut Option<u8>, text: &str) -> Result<(), ParseError> {
+	for node in nodes {
+		if let Node::Anonymous(offset, body) = node {
+			let Some(num) = *next else {
+				return Err(ParseError::InvalidTabIndex(Position::of(text, *offset)))
+			};
+			*next = num.checked_add(1);
+			*node = match body.take() {
+				Some(body) => Node::Placeholder(num, body),
+				None => Node::Tab(num)
+			};
+		}
+		if let Node::Placeholder(_, body) | Node::Variable(_, Some(body)) | Node::Repeated(body) = node {
+			number_anonymous(body, next, text)?;
+		}
+	}
+	Ok(())
+}
+
+/// Text of nodes as they show before anything is filled in, for variable defaults.
+fn flatten(nodes: &[Node], out: &mut String) {
+	for node in nodes {
+		match node {
+			Node::Text(text) => out.push_str(text),
+			Node::Placeholder(_, body) | Node::Variable(_, Some(body)) | Node::Repeated(body) => flatten(body, out),
+			Node::Choice(_, options) => out.push_str(options.first().map_or("", String::as_str)),
+			Node::Tab(_) | Node::Variable(_, None) | Node::Transform(..) | Node::Code(..) | Node::Named(_) | Node::Anonymous(..) | Node::Extension(_) => {}
+		}
+	}
+}
+
+/// Ties the nodes together into a snippet.
+struct Builder<'a> {
+	syntax: SnippetSyntax,
+	/// The placeholder or choice defining each tab number.
+	definitions: Vec<(u8, &'a Node)>,
+	/// Tab numbers appearing other than in transformations.
+	placed: Vec<u8>,
+	fields: Vec<(u8, Rc<Field>)>,
+	/// Tabs whose field is being built, to stop a placeholder from containing itself.
+	building: Vec<u8>,
+	variables: Vec<(String, String, Rc<Variable>)>,
+	tab_transformations: Vec<(u8, Weak<Transformation>)>,
+	code_expansions: Vec<Expansion<Code>>,
+	named_segments: Vec<NamedSegment>,
+	/// What each named segment is defined as, and the segments built for those that appeared.
+	named_definitions: &'a [(String, Node)],
+	named: Vec<(String, Segment)>,
+	/// Segments produced by extensions.
+	extended: &'a [Vec<Segment>]
+}
+
+impl<'a> Builder<'a> {
+	fn new(syntax: SnippetSyntax, nodes: &'a [Node], named_definitions: &'a [(String, Node)], extended: &'a [Vec<Segment>]) -> Self {
+		let mut builder = Builder {
+			syntax,
+			definitions: Vec::new(),
+			placed: Vec::new(),
+			fields: Vec::new(),
+			building: Vec::new(),
+			variables: Vec::new(),
+			tab_transformations: Vec::new(),
+			code_expansions: Vec::new(),
+			named_segments: Vec::new(),
+			named_definitions,
+			named: Vec::new(),
+			extended
+		};
+		builder.scan(nodes);
+		builder
+	}
+
+	fn scan(&mut self, nodes: &'a [Node]) {
+		for node in nodes {
+			match node {
+				Node::Tab(num) => self.placed.push(*num),
+				Node::Placeholder(num, body) => {
+					self.placed.push(*num);
+					// Only the body of the placeholder defining the tab is built, so tabs within the others do not appear.
+					if !self.definitions.iter().any(|(defined, _)| defined == num) {
+						self.definitions.push((*num, node));
+						self.scan(body);
+					}
+				},
+				Node::Choice(num, _) => {
+					self.placed.push(*num);
+					if !self.definitions.iter().any(|(defined, _)| defined == num) {
+						self.definitions.push((*num, node));
+					}
+				},
+				Node::Repeated(nodes) => self.scan(nodes),
+				// Defaults of variables are flattened, so tabs within them are not placed.
+				Node::Text(_) | Node::Variable(..) | Node::Transform(..) | Node::Code(..) | Node::Named(_) | Node::Anonymous(..) | Node::Extension(_) => {}
+			}
+		}
+	}
+
+	fn field(&mut self, num: u8) -> Option<Rc<Field>> {
+		if let Some((_, field)) = self.fields.iter().find(|(known, _)| *known == num) {
+			return Some(field.clone())
+		}
+		if self.building.contains(&num) {
+			return None
+		}
+		self.building.push(num);
+		let field = match self.definitions.iter().find(|(defined, _)| *defined == num).map(|(_, node)| *node) {
+			Some(Node::Placeholder(_, body)) => Field::Placeholder(self.segments(body)),
+			Some(Node::Choice(_, options)) => Field::Choice(0, options.iter().map(|option| vec![Segment::Text(option.clone())]).collect(), Vec::new()),
+			_ => Field::Placeholder(Vec::new())
+		};
+		self.building.pop();
+		let field = Rc::new(field);
+		self.fields.push((num, field.clone()));
+		Some(field)
+	}
+
+	fn segments(&mut self, nodes: &[Node]) -> Vec<Segment> {
+		let mut segments = Vec::new();
+		for node in nodes {
+			match node {
+				// Text around a placeholder left out for containing itself is joined.
+				Node::Text(text) => match segments.last_mut() {
+					Some(Segment::Text(previous)) => previous.push_str(text),
+					_ => segments.push(Segment::Text(text.clone()))
+				},
+				Node::Tab(num) | Node::Placeholder(num, _) | Node::Choice(num, _) => if let Some(field) = self.field(*num) {
+					segments.push(Segment::Field(field));
+				},
+				Node::Variable(name, default) => {
+					let mut value = String::new();
+					match default {
+						Some(default) => flatten(default, &mut value),
+						// TextMate inserts the name of a variable it does not know, where the editor's are empty until resolved.
+						None if self.syntax != SnippetSyntax::UltiSnips && matches!(variable_source(name), VariableSource::Daemon) => value.push_str(name),
+						None => {}
+					}
+					// `$NAME` and `${NAME:}` differ in whether they fall back to a default.
+					let found = self.variables.iter().find(|(known, known_value, variable)| known == name && *known_value == value && variable.default.is_some() == default.is_some());
+					let variable = match found {
+						Some((_, _, variable)) => variable.clone(),
+						None => {
+							let variable = Rc::new(Variable {
+								name: name.clone(),
+								value: value.clone(),
+								source: if self.syntax == SnippetSyntax::UltiSnips { VariableSource::Client } else { variable_source(name) },
+								default: default.as_ref().map(|_| if value.is_empty() { Vec::new() } else { vec![Segment::Text(value.clone())] })
+							});
+							self.variables.push((name.clone(), value, variable.clone()));
+							variable
+						}
+					};
+					segments.push(Segment::Variable(variable));
+				},
+				Node::Transform(target, section, format, flags) => {
+					let transformation = Rc::new(Transformation {
+						section: section.clone(),
+						format: format.clone(),
+						flags: flags.clone(),
+						result: String::new(),
+						compiled: Default::default()
+					});
+					match target {
+						Target::Tab(num) => {
+							if !self.placed.contains(num) {
+								self.placed.push(*num);
+								if let Some(field) = self.field(*num) {
+									segments.push(Segment::Field(field));
+								}
+							}
+							self.tab_transformations.push((*num, Rc::downgrade(&transformation)));
+						},
+						Target::Variable(name) => self.named_segments.push(NamedSegment::Transformation(name.clone(), Rc::downgrade(&transformation)))
+					}
+					segments.push(Segment::Transformation(transformation));
+				},
+				Node::Code(shebang, code) => {
+					let code = Rc::new(Code {
+						code: code.clone(),
+						output: String::new(),
+						shebang: shebang.to_string()
+					});
+					self.code_expansions.push(Expansion {
+						expansion: Rc::downgrade(&code),
+						transformations: Vec::new()
+					});
+					segments.push(Segment::Code(code));
+				},
+				Node::Anonymous(..) => {},
+				Node::Repeated(nodes) => {
+					let repeated = self.segments(nodes);
+					segments.extend(repeated);
+				},
+				Node::Extension(index) => for segment in &self.extended[*index] {
+					match segment {
+						Segment::Variable(variable) if !self.variables.iter().any(|(_, _, known)| Rc::ptr_eq(known, variable)) => {
+							self.variables.push((variable.name.clone(), variable.value.clone(), variable.clone()));
+						},
+						Segment::Code(code) if !self.code_expansions.iter().any(|known| known.expansion.as_ptr() == Rc::as_ptr(code)) => {
+							self.code_expansions.push(Expansion { expansion: Rc::downgrade(code), transformations: Vec::new() });
+						},
+						_ => {}
+					}
+					segments.push(shared(segment));
+				},
+				// Built where the name first appears, which may not be its definition when that is in a part left out of the snippet.
+				Node::Named(name) => match self.named.iter().find(|(known, _)| known == name) {
+					Some((_, segment)) => segments.push(shared(segment)),
+					None => {
+						let definitions = self.named_definitions;
+						let Some((_, definition)) = definitions.iter().find(|(defined, _)| defined == name) else {
+							continue
+						};
+						let built = self.segments(std::slice::from_ref(definition));
+						match built.last() {
+							Some(Segment::Transformation(transformation)) => {
+								self.named_segments.push(NamedSegment::Transformation(name.clone(), Rc::downgrade(transformation)));
+							},
+							Some(Segment::Code(code)) => self.named_segments.push(NamedSegment::Code(name.clone(), Rc::downgrade(code))),
+							_ => continue
+						}
+						self.named.push((name.clone(), shared(&built[built.len() - 1])));
+						segments.extend(built);
+					}
+				}
+			}
+		}
+		segments
+	}
+
+	fn build(mut self, nodes: &[Node]) -> Snippet {
+		let body = self.segments(nodes);
+		let tabs = self.fields.iter().map(|(num, field)| Tab {
+			num: *num,
+			field: Rc::downgrade(field),
+			transformations: self.tab_transformations.iter().filter(|(of, _)| of == num).map(|(_, transformation)| transformation.clone()).collect(),
+			label: None
+		}).collect();
+		let variables = self.variables.iter().map(|(name, _, variable)| Expansion {
+			expansion: Rc::downgrade(variable),
+			transformations: self.named_segments.iter().filter_map(|named| match named {
+				NamedSegment::Transformation(of, transformation) if of == name => Some(transformation.clone()),
+				_ => None
+			}).collect()
+		}).collect();
+		Snippet {
+			body,
+			tabs,
+			variables,
+			code_expansions: self.code_expansions,
+			named_segments: self.named_segments,
+			current_tab: None,
+			choice_sources: Vec::new(),
+			baseline: Default::default()
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn field(snippet: &Snippet, num: u8) -> Rc<Field> {
+		snippet.tabs().iter().find(|tab| tab.num == num).unwrap().field.upgrade().unwrap()
+	}
+
+	#[test]
+	fn parse_lsp_syntax() {
+		let snippet = Snippet::parse("fn ${1:name}(${2:arg}: ${3|i32,u8\\,x|}) -> $3 {\n\t$1${0}\n}").unwrap();
+		assert_eq!(snippet.to_string(), "fn name(arg: i32) -> i32 {\n\tname\n}");
+		let nums: Vec<u8> = snippet.tabs().iter().map(|tab| tab.num).collect();
+		assert_eq!(nums, [1, 2, 3, 0]);
+		assert_eq!(field(&snippet, 3).choice_labels(), ["i32", "u8,x"]);
+		assert_eq!(snippet.shared_segments().len(), 2);
+
+		let snippet = Snippet::parse("$1 ${1:outer ${2:inner}} $TM_FILENAME ${USER:me} ${UNSET}").unwrap();
+		assert_eq!(snippet.to_string(), "outer inner outer inner  me UNSET");
+		assert!(matches!(&snippet.body()[4], Segment::Variable(variable) if matches!(variable.source, VariableSource::Client)));
+		assert!(matches!(&snippet.body()[6], Segment::Variable(variable) if variable.value == "me" && matches!(variable.source, VariableSource::Daemon)));
+		assert_eq!(snippet.variables().len(), 3);
+	}
+
+	#[test]
+	fn parse_transformations() {
+		let snippet = Snippet::parse("${1:a} ${1/(.*)/${1:/upcase}\\/x/g} ${TM_FILENAME/(.*)\\..+$/$1/}").unwrap();
+		let transformations: Vec<(String, String, String)> = snippet.body().iter().filter_map(|segment| match segment {
+			Segment::Transformation(transformation) => Some((transformation.section.clone(), transformation.format.clone(), transformation.flags.clone())),
+			_ => None
+		}).collect();
+		assert_eq!(transformations, [
+			(String::from("(.*)"), String::from("${1:/upcase}/x"), String::from("g")),
+			(String::from("(.*)\\..+$"), String::from("$1"), String::new())
+		]);
+		assert_eq!(snippet.tabs()[0].transformations.len(), 1);
+		assert!(matches!(&snippet.named_segments()[0], NamedSegment::Transformation(name, transformation) if name == "TM_FILENAME" && transformation.upgrade().is_some()));
+
+		let snippet = Snippet::parse("${2/a/b/}").unwrap();
+		assert_eq!(snippet.tabs()[0].num, 2);
+		assert!(snippet.tabs()[0].field.upgrade().is_some());
+	}
+
+	#[test]
+	fn parse_named_segments() {
+		let snippet = Snippet::parse("${1:a} ${up=${1/(.*)/${1:/upcase}/}} $up ${up} ${file=${TM_FILENAME/(.*)/$1/}}-${file}").unwrap();
+		assert!(snippet.shared_segments().iter().any(|(segment, count)| matches!(segment, Segment::Transformation(_)) && *count == 3));
+		assert_eq!(snippet.tabs()[0].transformations.len(), 1);
+		let names: Vec<&str> = snippet.named_segments().iter().map(|named| match named {
+			NamedSegment::Transformation(name, _) | NamedSegment::Code(name, _) => name.as_str()
+		}).collect();
+		assert_eq!(names, ["up", "TM_FILENAME", "file"]);
+		assert!(matches!(&Snippet::parse("$up ${up=${1/a/b/}}").unwrap().body()[0], Segment::Variable(variable) if variable.name == "up"));
+
+		let snippet = Snippet::parse_with(SnippetSyntax::UltiSnips, "${d=`date`} ${d}").unwrap();
+		assert_eq!(snippet.code_expansions().len(), 1);
+		assert!(matches!((&snippet.body()[0], &snippet.body()[2]), (Segment::Code(a), Segment::Code(b)) if Rc::ptr_eq(a, b)));
+		assert!(matches!(Snippet::parse("${x=text}").unwrap_err(), ParseError::MalformedNamedSegment(Position { offset: 0, .. })));
+		assert!(matches!(Snippet::parse("${x=${1/a/b/}} ${x=${1/c/d/}}").unwrap_err(), ParseError::MalformedNamedSegment(Position { offset: 15, .. })));
+		assert!(matches!(Snippet::parse("${x=${1/a/b/}").unwrap_err(), ParseError::UnterminatedPlaceholder(Position { offset: 0, .. })));
+	}
+
+	#[test]
+	fn literal_text_and_errors() {
+		let snippet = Snippet::parse("cost: $ 5 \\$1 {} \\x }").unwrap();
+		assert_eq!(snippet.to_string(), "cost: $ 5 $1 {} \\x }");
+		assert!(snippet.is_static());
+		assert!(matches!(Snippet::parse("a ${1:open").unwrap_err(), ParseError::UnterminatedPlaceholder(Position { offset: 2, .. })));
+		assert!(matches!(Snippet::parse("${1|a,b}").unwrap_err(), ParseError::UnterminatedChoice(Position { offset: 0, .. })));
+		assert!(matches!(Snippet::parse("x $256").unwrap_err(), ParseError::InvalidTabIndex(Position { offset: 3, .. })));
+		assert!(matches!(Snippet::parse("${1/a/b}").unwrap_err(), ParseError::MalformedTransformation(Position { offset: 0, .. })));
+		assert!(matches!(Snippet::parse("${-}").unwrap_err(), ParseError::MalformedPlaceholder(Position { offset: 0, .. })));
+		assert!(matches!(Snippet::parse("${1 }").unwrap_err(), ParseError::MalformedPlaceholder(Position { offset: 0, .. })));
+		assert_eq!(Snippet::parse("${1:${1:self}}").unwrap().to_string(), "");
+		assert!(matches!(Snippet::parse("${1/a/\\q/}").unwrap_err(), ParseError::UnknownEscape(Position { offset: 6, .. })));
+		assert!(Snippet::parse("${1/\\d/\\u$1\\n\\./}").is_ok());
+	}
+
+	#[test]
+	fn report_error_positions() {
+		let error = Snippet::parse("first\nsé ${1:ok} ${2|a,b").unwrap_err();
+		assert_eq!(error, ParseError::UnterminatedChoice(Position { offset: 18, line: 2, column: 12 }));
+		assert_eq!(error.to_string(), "line 2, column 12: choice is not closed by |}");
+		assert_eq!(Position::of("ab", 0), Position { offset: 0, line: 1, column: 1 });
+	}
+
+	#[test]
+	fn parse_ultisnips_syntax() {
+		let snippet = Snippet::parse_with(SnippetSyntax::UltiSnips, "`date +%F` `!p snip.rv = \"\\`\"` ${1:${VISUAL:x}} $HOME ${1/a/b/} \\`").unwrap();
+		let code: Vec<(&str, &str)> = snippet.body().iter().filter_map(|segment| match segment {
+			Segment::Code(code) => Some((code.shebang.as_str(), code.code.as_str())),
+			_ => None
+		}).collect();
+		assert_eq!(code, [("#!/bin/sh", "date +%F"), ("#!/usr/bin/env python3", "snip.rv = \"`\"")]);
+		assert_eq!(snippet.code_expansions().len(), 2);
+		assert_eq!(snippet.to_string(), "  x $HOME  `");
+		assert!(matches!(&snippet.variables()[0].expansion.upgrade().unwrap().source, VariableSource::Client));
+		assert_eq!(snippet.tabs()[0].transformations.len(), 1);
+		assert!(matches!(Snippet::parse_with(SnippetSyntax::UltiSnips, "a `b").unwrap_err(), ParseError::UnterminatedCode(Position { offset: 2, .. })));
+		assert_eq!(Snippet::parse("`date`").unwrap().to_string(), "`date`");
+	}
+
+	#[test]
+	fn parse_with_numbering_options() {
+		let nums = |snippet: &Snippet| snippet.tabs().iter().map(|tab| tab.num).collect::<Vec<_>>();
+		let options = ParseOptions { anonymous_tabs: true, ..ParseOptions::default() };
+		let snippet = Snippet::parse_with_options(options.clone(), "${:a ${_}} $2 $_ ${x=${3/b/c/}} $0").unwrap();
+		assert_eq!(snippet.to_string(), "a     ");
+		assert_eq!(nums(&snippet), [5, 4, 2, 6, 3, 0]);
+		assert!(matches!(&Snippet::parse("$_").unwrap().body()[0], Segment::Variable(variable) if variable.name == "_"));
+		assert!(matches!(Snippet::parse("${:a}").unwrap_err(), ParseError::MalformedPlaceholder(_)));
+		assert!(matches!(Snippet::parse_with_options(options.clone(), "$255 ${:a}").unwrap_err(), ParseError::InvalidTabIndex(Position { offset: 5, .. })));
+
+		let options = ParseOptions { base: NumberingBase::Zero, ..options };
+		let snippet = Snippet::parse_with_options(options.clone(), "${0:a} ${1|b,c|} $0 ${0/a/x/} $_").unwrap();
+		assert_eq!(snippet.to_string(), "a b a  ");
+		assert_eq!(nums(&snippet), [1, 2, 3]);
+		assert_eq!(snippet.tabs()[0].transformations.len(), 1);
+		assert!(matches!(Snippet::parse_with_options(options, "$255").unwrap_err(), ParseError::InvalidTabIndex(_)));
+	}
+
+	#[test]
+	fn parse_repetitions() {
+		let options = ParseOptions { repetition: true, ..ParseOptions::default() };
+		let snippet = Snippet::parse_with_options(options.clone(), "${repeat:3:item$i, }|${repeat:2:${1:a}$2 ${1/a/b/}}${9:z}").unwrap();
+		assert_eq!(snippet.to_string(), "item1, item2, item3, |a a z");
+		let nums: Vec<u8> = snippet.tabs().iter().map(|tab| tab.num).collect();
+		assert_eq!(nums, [1, 2, 3, 4, 9]);
+		assert!(snippet.tabs().iter().filter(|tab| tab.num % 2 == 1 && tab.num < 9).all(|tab| tab.transformations.len() == 1));
+		assert!(matches!(&Snippet::parse("${repeat:2:x}").unwrap().body()[0], Segment::Variable(variable) if variable.name == "repeat"));
+		assert!(matches!(Snippet::parse_with_options(options.clone(), "${repeat:2:${repeat:2:x}}").unwrap_err(), ParseError::MalformedPlaceholder(Position { offset: 11, .. })));
+		assert!(matches!(Snippet::parse_with_options(options.clone(), "${repeat:x:y}").unwrap_err(), ParseError::MalformedPlaceholder(_)));
+		assert!(matches!(Snippet::parse_with_options(options, "${repeat:200:$2}").unwrap_err(), ParseError::InvalidTabIndex(Position { offset: 0, .. })));
+	}
+
+	#[test]
+	fn parse_extensions() {
+		let mut extensions = Extensions::new();
+		extensions.register("upper", |argument| Ok(vec![Segment::Text(argument.to_uppercase())]));
+		extensions.register("user", |argument| match argument {
+			"" => Err(String::from("expected a default")),
+			_ => Ok(vec![Segment::Variable(Rc::new(Variable::new("USER", argument, VariableSource::Daemon)))])
+		});
+		let snippet = extensions.parse(ParseOptions::default(), "${1:${upper:a\\}b}} ${user:me} ${other:x}").unwrap();
+		assert_eq!(snippet.to_string(), "A}B me x");
+		assert_eq!(snippet.variables().len(), 2);
+		assert!(matches!(extensions.parse(ParseOptions::default(), "x ${user:}").unwrap_err(), ParseError::Extension(Position { offset: 2, .. }, _)));
+		assert!(matches!(Snippet::parse("${upper:a}").unwrap().body()[0], Segment::Variable(_)));
+	}
+
+	#[test]
+	fn parse_grammar_profiles() {
+		let parse = |profile, text| Snippet::parse_with_options(ParseOptions::with_profile(profile), text);
+		assert!(matches!(parse(GrammarProfile::Lsp, "a ${x=${1/a/b/}}").unwrap_err(), ParseError::Unsupported(Position { offset: 2, .. }, Construct::NamedSegment)));
+		assert!(matches!(parse(GrammarProfile::Lsp, "${1:open").unwrap_err(), ParseError::UnterminatedPlaceholder(_)));
+		assert!(matches!(parse(GrammarProfile::TextMate, "${1|a,b|}").unwrap_err(), ParseError::Unsupported(_, Construct::Choice)));
+		assert!(matches!(parse(GrammarProfile::UltiSnips, "${VISUAL/a/b/}").unwrap_err(), ParseError::Unsupported(_, Construct::VariableTransformation)));
+
+		let snippet = parse(GrammarProfile::TextMate, "`date` ${1:a}").unwrap();
+		assert_eq!(snippet.code_expansions().len(), 1);
+		assert_eq!(parse(GrammarProfile::VsCode, "${x=${1/a/b/}} ${1:a ${2:b").unwrap().to_string(), "${x=} ${1:a ${2:b");
+		let snippet = parse(GrammarProfile::Permissive, "${x=${1/a/b/}} $x ${1:a} `b ${2|c|").unwrap();
+		assert_eq!(snippet.to_string(), "  a `b ${2|c|");
+		assert_eq!(snippet.named_segments().len(), 1);
+		let nested = format!("{}{}", "${1:".repeat(100), "x");
+		assert_eq!(parse(GrammarProfile::VsCode, &nested).unwrap().to_string(), nested);
+	}
+
+	#[test]
+	fn report_capabilities() {
+		assert_eq!(GrammarProfile::Lsp.capabilities(), [Construct::Choice, Construct::TabTransformation, Construct::VariableTransformation]);
+		assert_eq!(GrammarProfile::Permissive.capabilities(), Construct::ALL);
+		let snippet = Snippet::parse_with(SnippetSyntax::UltiSnips, "${1|a,b|} ${1/a/b/} `date` ${d=`date`}").unwrap();
+		assert_eq!(snippet.constructs(), [Construct::Choice, Construct::TabTransformation, Construct::NamedSegment, Construct::Code]);
+		assert_eq!(GrammarProfile::TextMate.unsupported(&snippet), [Construct::Choice, Construct::NamedSegment]);
+		let snippet = Snippet::parse("${2:${3|x|}} ${TM_FILENAME/a/b/}").unwrap();
+		assert_eq!(snippet.constructs(), [Construct::Choice, Construct::VariableTransformation]);
+		assert!(GrammarProfile::VsCode.unsupported(&snippet).is_empty());
+	}
+
+	#[test]
+	fn parse_with_delimiters() {
+		let percent = ParseOptions { delimiters: Delimiters::new("%", "{", "}").unwrap(), ..ParseOptions::default() };
+		let snippet = Snippet::parse_with_options(percent, "echo $HOME %{1:dir} %1 \\%2 %{2|a,b\\%|} %{1/(.*)/${1:/upcase}/}").unwrap();
+		assert_eq!(snippet.to_string(), "echo $HOME dir dir %2 a ");
+		assert_eq!(field(&snippet, 2).choice_labels(), ["a", "b%"]);
+		assert_eq!(snippet.tabs()[0].transformations[0].upgrade().unwrap().format, "${1:/upcase}");
+
+		let mustache = ParseOptions { delimiters: Delimiters::new("{{", "", "}}").unwrap(), ..ParseOptions::default() };
+		let snippet = Snippet::parse_with_options(mustache.clone(), "{{1:a {b\\}}} {{1}} ${x} {{USER:me}} \\{{2}}").unwrap();
+		assert_eq!(snippet.to_string(), "a {b} a {b} ${x} me {{2}}");
+		assert!(matches!(Snippet::parse_with_options(mustache, "x {{1:a").unwrap_err(), ParseError::UnterminatedPlaceholder(Position { offset: 2, .. })));
+		assert_eq!(Delimiters::new("", "{", "}"), None);
+		assert_eq!(Delimiters::new("%", "{", "%"), None);
+		assert_eq!(Delimiters::new("a", "{", "}"), None);
+
+		let configured = String::from("<% %>");
+		let (open, close) = configured.split_once(' ').unwrap();
+		let delimiters = Delimiters::new(open.to_string(), String::new(), close.to_string()).unwrap();
+		assert_eq!((delimiters.sigil(), delimiters.open(), delimiters.close()), ("<%", "", "%>"));
+		let snippet = Snippet::parse_with_options(ParseOptions { delimiters, ..ParseOptions::default() }, "<%1:a%> <%1%>").unwrap();
+		assert_eq!(snippet.to_string(), "a a");
+		assert_eq!(Delimiters::new(String::from("%"), "{", String::from("%")), None);
+	}
+}
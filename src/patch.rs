@@ -0,0 +1,136 @@
+//! Sharing what has been filled into a snippet as a patch that can be applied to another copy of the snippet it was expanded from.
+//!
+//! Patches are JSON objects like `{"snippet": "<hash>", "fields": {"1": "text"}, "choices": {"2": 1}}`:
+//! the [`Snippet::content_hash`] of the snippet as expanded (in hexadecimal), the text of each field changed since, by tab number,
+//! and the option chosen of each choice changed since.
+
+use std::fmt;
+use crate::{Snippet, Field};
+use crate::json::{self, quote, Value};
+use crate::transform::TransformError;
+
+/// Why a patch could not be applied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PatchError {
+	/// The patch is not valid JSON. Carries the line (starting at 1) and a description of the problem.
+	Syntax(usize, &'static str),
+	/// The JSON does not have the shape of a patch. Carries a description of the problem.
+	Structure(&'static str),
+	/// The patch was made for a different snippet.
+	Mismatch,
+	/// The patch fills in a tab the snippet does not have.
+	UnknownTab(u8),
+	/// The patch chooses an option a choice does not have, or the field of the tab is not a choice.
+	InvalidChoice(u8)
+}
+
+impl fmt::Display for PatchError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			PatchError::Syntax(line, message) => write!(f, "line {}: {}", line, message),
+			PatchError::Structure(message) => write!(f, "invalid patch: {}", message),
+			PatchError::Mismatch => write!(f, "patch is for a different snippet"),
+			PatchError::UnknownTab(num) => write!(f, "snippet has no tab {}", num),
+			PatchError::InvalidChoice(num) => write!(f, "tab {} has no such choice", num)
+		}
+	}
+}
+
+impl std::error::Error for PatchError {}
+
+impl From<json::Error> for PatchError {
+	fn from(error: json::Error) -> Self {
+		PatchError::Syntax(error.line, error.message)
+	}
+}
+
+impl Snippet {
+	/// What has been filled into this snippet since it was the template (the snippet as expanded), as a patch.
+	/// Choices are recorded by the option chosen, other fields by their text.
+	pub fn fill_patch(&self, template: &Snippet) -> String {
+		let mut nums: Vec<u8> = self.tabs.iter().map(|tab| tab.num).collect();
+		nums.sort_unstable();
+		nums.dedup();
+		let mut fields = Vec::new();
+		let mut choices = Vec::new();
+		for num in nums {
+			let field = |snippet: &Snippet| snippet.tabs.iter().find(|tab| tab.num == num).and_then(|tab| tab.field.upgrade());
+			let (Some(filled), Some(expanded)) = (field(self), field(template)) else {
+				continue
+			};
+			match (&*filled, &*expanded) {
+				(Field::Choice(chosen, _, _), Field::Choice(initial, _, _)) => if chosen != initial {
+					choices.push(format!("\"{}\": {}", num, chosen));
+				},
+				(filled, expanded) => if filled.to_string() != expanded.to_string() {
+					fields.push(format!("\"{}\": {}", num, quote(&filled.to_string())));
+				}
+			}
+		}
+		format!(
+			"{{\"snippet\": \"{:016x}\", \"fields\": {{{}}}, \"choices\": {{{}}}}}",
+			template.content_hash(),
+			fields.join(", "),
+			choices.join(", ")
+		)
+	}
+
+	/// Fills the patch into this snippet, which must be as the template the patch was made from was.
+	/// Returns why transformations acting upon the fields could not be applied.
+	pub fn apply_fill_patch(&mut self, patch: &str) -> Result<Vec<TransformError>, PatchError> {
+		let patch = json::parse(patch)?;
+		if patch.get("snippet").and_then(|hash| hash.as_str()) != Some(&format!("{:016x}", self.content_hash())) {
+			return Err(PatchError::Mismatch)
+		}
+		let entries = |key: &str| -> Result<Vec<(u8, &Value)>, PatchError> {
+			match patch.get(key).map(|node| &node.value) {
+				Some(Value::Object(entries)) => entries.iter()
+					.map(|(num, node)| num.parse().map(|num| (num, &node.value)).map_err(|_| PatchError::Structure("key is not a tab number")))
+					.collect(),
+				None => Ok(Vec::new()),
+				Some(_) => Err(PatchError::Structure("expected an object of tabs"))
+			}
+		};
+		let fields = entries("fields")?;
+		let choices = entries("choices")?;
+		let mut errors = Vec::new();
+		for (num, choice) in choices {
+			let Value::Number(choice) = choice else {
+				return Err(PatchError::Structure("choice is not a number"))
+			};
+			let choice = choice.parse().map_err(|_| PatchError::InvalidChoice(num))?;
+			errors.extend(self.set_choice(num, choice).ok_or(PatchError::InvalidChoice(num))?);
+		}
+		for (num, text) in fields {
+			let Value::String(text) = text else {
+				return Err(PatchError::Structure("field text is not a string"))
+			};
+			if !self.tabs.iter().any(|tab| tab.num == num) {
+				return Err(PatchError::UnknownTab(num))
+			}
+			// Tabs nested within a field filled earlier are no longer shown, their text being part of that field's.
+			errors.extend(self.set_field_text(num, text).unwrap_or_default());
+		}
+		Ok(errors)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn share_filled_snippet() {
+		let body = "${1:name}: ${2|u8,i32|} = ${3:0}; $1";
+		let template = Snippet::parse(body).unwrap();
+		let mut filled = Snippet::parse(body).unwrap();
+		filled.set_field_text(1, "count \"n\"").unwrap();
+		filled.set_choice(2, 1).unwrap();
+		let patch = filled.fill_patch(&template);
+		assert_eq!(patch, format!("{{\"snippet\": \"{:016x}\", \"fields\": {{\"1\": \"count \\\"n\\\"\"}}, \"choices\": {{\"2\": 1}}}}", template.content_hash()));
+		let mut elsewhere = Snippet::parse(body).unwrap();
+		assert!(elsewhere.apply_fill_patch(&patch).unwrap().is_empty());
+		assert_eq!(elsewhere.to_string(), "count \"n\": i32 = 0; count \"n\"");
+		assert_eq!(Snippet::parse("$1").unwrap().apply_fill_patch(&patch), Err(PatchError::Mismatch));
+	}
+}
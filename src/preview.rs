@@ -0,0 +1,63 @@
+//! Rendering snippets for previews, such as on hover in a completion menu, without running anything they contain.
+
+use std::fmt;
+use crate::{Snippet, Segment, Field};
+
+impl Snippet {
+	/// The text of the snippet as Display renders it, except that code shows as a `` ⟨`code`⟩ `` marker rather than its output,
+	/// so that previews of untrusted snippets never suggest code was (or needs to be) run. Nothing is run or resolved:
+	/// variables show their values and fields their defaults (or what was filled into them) as they are.
+	pub fn preview(&self) -> String {
+		let mut text = String::new();
+		// Writing to a String can not fail.
+		let _ = self.preview_to(&mut text);
+		text
+	}
+
+	/// Writes the preview of the snippet (see [`Snippet::preview`]) into the writer.
+	pub fn preview_to<W: fmt::Write + ?Sized>(&self, w: &mut W) -> fmt::Result {
+		segments(&self.body, w)
+	}
+}
+
+fn segments<W: fmt::Write + ?Sized>(segments: &[Segment], w: &mut W) -> fmt::Result {
+	for segment in segments {
+		match segment {
+			Segment::Code(code) => write!(w, "⟨`{}`⟩", code.code)?,
+			Segment::Snippet(nested) => nested.preview_to(w)?,
+			Segment::Conditional(conditional) => self::segments(conditional.shown(), w)?,
+			Segment::Field(field) => match &**field {
+				Field::Placeholder(body) => self::segments(body, w)?,
+				Field::Choice(choice, bodies, _) => if let Some(body) = bodies.get(*choice) {
+					self::segments(body, w)?;
+				},
+				Field::Number(number) => write!(w, "{}", number.value)?,
+				Field::Toggle(on, when_on, when_off) => self::segments(if *on { when_on } else { when_off }, w)?,
+				Field::Repeat(repeat) => for (i, body) in repeat.repetitions.iter().enumerate() {
+					if i > 0 {
+						w.write_str(&repeat.separator)?;
+					}
+					self::segments(body, w)?;
+				}
+			},
+			Segment::Text(_) | Segment::Variable(_) | Segment::Transformation(_) => segment.render_to(w)?
+		}
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::parse::SnippetSyntax;
+
+	#[test]
+	fn preview_without_running() {
+		let mut snippet = Snippet::parse_with(SnippetSyntax::UltiSnips, "${1:name} = `rm -rf ~`; ${2:`!p snip.rv = 1`}").unwrap();
+		assert_eq!(snippet.preview(), "name = ⟨`rm -rf ~`⟩; ⟨`snip.rv = 1`⟩");
+		snippet.set_field_text(1, "x").unwrap();
+		assert_eq!(snippet.preview(), "x = ⟨`rm -rf ~`⟩; ⟨`snip.rv = 1`⟩");
+		let variables = Snippet::parse("${TM_FILENAME:file} ${2|a,b|}").unwrap();
+		assert_eq!(variables.preview(), variables.to_string());
+	}
+}
@@ -0,0 +1,698 @@
+//! Small regular expression engine for conditions and transformations, keeping the crate free of dependencies.
+//!
+//! Supports the commonly used subset of JavaScript (and TextMate) regular expressions: literals, `.`, classes (`[a-z]`, `[^,]`),
+//! `\d \w \s` and their negations, anchors (`^ $ \b \B`), capturing and non capturing (`(?:)`) groups, alternation
+//! and greedy or lazy quantifiers (`* + ? {n} {n,} {n,m}`). Backreferences and lookaround are not supported.
+//! Matching backtracks but never visits the same state twice, so it takes time proportional to the pattern times the text.
+
+use std::fmt;
+use std::collections::HashSet;
+use crate::parse::MAX_NESTING;
+use std::ops::Range;
+
+/// Why a pattern is not a valid regular expression.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegexError {
+	/// Byte offset within the pattern.
+	pub offset: usize,
+	pub message: &'static str
+}
+
+impl fmt::Display for RegexError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "offset {}: {}", self.offset, self.message)
+	}
+}
+
+impl std::error::Error for RegexError {}
+
+/// Largest count allowed in `{n,m}`, as each repetition is compiled separately.
+const MAX_REPEAT: u32 = 1000;
+
+/// Most instructions a pattern may compile into, as nested repetitions multiply
+/// (and matching keeps a bit for every instruction at every position of the text).
+const MAX_PROGRAM: usize = 10_000;
+
+#[derive(Debug, Clone, Copy)]
+enum Perl {
+	Digit,
+	Word,
+	Space
+}
+
+impl Perl {
+	fn matches(&self, c: char) -> bool {
+		match self {
+			Perl::Digit => c.is_ascii_digit(),
+			Perl::Word => c.is_ascii_alphanumeric() || c == '_',
+			Perl::Space => c.is_whitespace()
+		}
+	}
+}
+
+#[derive(Debug, Clone)]
+enum ClassItem {
+	Range(char, char),
+	/// Shorthand class, negated when set.
+	Perl(Perl, bool)
+}
+
+#[derive(Debug, Clone)]
+struct Class {
+	items: Vec<ClassItem>,
+	negated: bool
+}
+
+impl Class {
+	fn matches(&self, c: char, ignore_case: bool) -> bool {
+		let within = |c: char| self.items.iter().any(|item| match item {
+			ClassItem::Range(from, to) => (*from..=*to).contains(&c),
+			ClassItem::Perl(perl, negated) => perl.matches(c) != *negated
+		});
+		let found = within(c) || ignore_case && (c.to_lowercase().any(within) || c.to_uppercase().any(within));
+		found != self.negated
+	}
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Assertion {
+	Start,
+	End,
+	WordBoundary,
+	NotWordBoundary
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+	Empty,
+	Char(char),
+	Any,
+	Class(Class),
+	Assert(Assertion),
+	/// Capture group number (from 1) unless non capturing.
+	Group(Option<usize>, Box<Node>),
+	Concat(Vec<Node>),
+	Alternate(Vec<Node>),
+	Repeat(Box<Node>, u32, Option<u32>, bool)
+}
+
+#[derive(Debug, Clone)]
+enum Inst {
+	Char(char),
+	Any,
+	Class(Class),
+	Assert(Assertion),
+	/// Tries the first then the second.
+	Split(usize, usize),
+	Jump(usize),
+	/// Records the position into a capture slot.
+	Save(usize),
+	Match
+}
+
+/// States (instruction and position) the matcher has been in, as one bit each when there are few enough states
+/// for that to be small, otherwise as the set of those visited, which grows only as matching goes on.
+enum Visited {
+	Dense(Vec<u64>),
+	Sparse(HashSet<usize>)
+}
+
+/// Most states kept as bits, taking 1 MiB.
+const DENSE_STATES: usize = 1 << 23;
+
+impl Visited {
+	fn new(states: usize) -> Self {
+		if states <= DENSE_STATES {
+			Visited::Dense(vec![0; states.div_ceil(64)])
+		} else {
+			Visited::Sparse(HashSet::new())
+		}
+	}
+
+	/// Marks the state as visited, false when it was already.
+	fn insert(&mut self, state: usize) -> bool {
+		match self {
+			Visited::Dense(bits) => {
+				let bit = 1 << (state % 64);
+				let new = bits[state / 64] & bit == 0;
+				bits[state / 64] |= bit;
+				new
+			},
+			Visited::Sparse(states) => states.insert(state)
+		}
+	}
+}
+
+/// A compiled regular expression.
+#[derive(Debug, Clone)]
+pub struct Regex {
+	pattern: String,
+	program: Vec<Inst>,
+	/// Capture groups, not counting the whole match.
+	groups: usize,
+	/// The flags honoured, out of those given.
+	flags: String,
+	ignore_case: bool,
+	multi_line: bool,
+	dot_all: bool
+}
+
+/// Text matched by a regular expression and its capture groups.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Captures<'t> {
+	text: &'t str,
+	/// Start and end of each group, the whole match first.
+	slots: Vec<Option<usize>>
+}
+
+impl<'t> Captures<'t> {
+	/// Byte range of the group (0 for the whole match), if it took part in the match.
+	pub fn range(&self, group: usize) -> Option<Range<usize>> {
+		Some(self.slots.get(group * 2).copied()??..self.slots.get(group * 2 + 1).copied()??)
+	}
+
+	/// Text of the group (0 for the whole match), if it took part in the match.
+	pub fn get(&self, group: usize) -> Option<&'t str> {
+		self.range(group).map(|range| &self.text[range])
+	}
+
+	/// Number of groups, counting the whole match.
+	pub fn len(&self) -> usize {
+		self.slots.len() / 2
+	}
+
+	/// Always false, as the whole match is a group.
+	pub fn is_empty(&self) -> bool {
+		false
+	}
+}
+
+impl Regex {
+	/// Compiles the pattern with no flags.
+	pub fn new(pattern: &str) -> Result<Regex, RegexError> {
+		Regex::with_flags(pattern, "")
+	}
+
+	/// Compiles the pattern with the flags of a transformation: `i` ignores case, `m` makes `^` and `$` match at line breaks
+	/// and `s` makes `.` match line breaks. Other flags (such as `g`) concern the caller and are ignored.
+	pub fn with_flags(pattern: &str, flags: &str) -> Result<Regex, RegexError> {
+		let mut parser = Parser { pattern, pos: 0, groups: 0, depth: 0 };
+		let node = parser.alternate()?;
+		if parser.pos < pattern.len() {
+			return Err(RegexError { offset: parser.pos, message: "unmatched )" })
+		}
+		if size(&node) > MAX_PROGRAM {
+			return Err(RegexError { offset: 0, message: "pattern is too large" })
+		}
+		let mut program = vec![Inst::Save(0)];
+		compile(&node, &mut program);
+		program.push(Inst::Save(1));
+		program.push(Inst::Match);
+		Ok(Regex {
+			pattern: pattern.to_string(),
+			program,
+			groups: parser.groups,
+			flags: flags.chars().filter(|flag| matches!(flag, 'i' | 'm' | 's')).collect(),
+			ignore_case: flags.contains('i'),
+			multi_line: flags.contains('m'),
+			dot_all: flags.contains('s')
+		})
+	}
+
+	/// The pattern the regular expression was compiled from.
+	pub fn as_str(&self) -> &str {
+		&self.pattern
+	}
+
+	/// Number of capture groups, not counting the whole match.
+	pub fn groups(&self) -> usize {
+		self.groups
+	}
+
+	/// The flags the regular expression was compiled with that change how it matches.
+	pub fn flags(&self) -> &str {
+		&self.flags
+	}
+
+	/// Whether the regular expression matches anywhere within the text.
+	pub fn is_match(&self, text: &str) -> bool {
+		self.captures(text).is_some()
+	}
+
+	/// First (leftmost) match within the text.
+	pub fn captures<'t>(&self, text: &'t str) -> Option<Captures<'t>> {
+		self.captures_from(text, 0)
+	}
+
+	/// First match within the text starting at or after the byte offset.
+	pub fn captures_from<'t>(&self, text: &'t str, from: usize) -> Option<Captures<'t>> {
+		let mut visited = Visited::new(self.program.len() * (text.len() + 1));
+		let mut slots = vec![None; (self.groups + 1) * 2];
+		for start in (from..=text.len()).filter(|start| text.is_char_boundary(*start)) {
+			if self.backtrack(text, start, &mut visited, &mut slots) {
+				return Some(Captures { text, slots })
+			}
+		}
+		None
+	}
+
+	fn backtrack(&self, text: &str, start: usize, visited: &mut Visited, slots: &mut [Option<usize>]) -> bool {
+		enum Job {
+			Run(usize, usize),
+			Restore(usize, Option<usize>)
+		}
+		let mut stack = vec![Job::Run(0, start)];
+		while let Some(job) = stack.pop() {
+			let (mut pc, mut pos) = match job {
+				Job::Run(pc, pos) => (pc, pos),
+				Job::Restore(slot, old) => {
+					slots[slot] = old;
+					continue
+				}
+			};
+			loop {
+				// A state visited before failed from there (or matching would have stopped), so fails again.
+				if !visited.insert(pc * (text.len() + 1) + pos) {
+					break
+				}
+				let next = text[pos..].chars().next();
+				match &self.program[pc] {
+					Inst::Match => return true,
+					Inst::Char(expected) => match next {
+						Some(c) if c == *expected || self.ignore_case && c.to_lowercase().eq(expected.to_lowercase()) => {
+							pos += c.len_utf8();
+							pc += 1;
+						},
+						_ => break
+					},
+					Inst::Any => match next {
+						Some(c) if self.dot_all || c != '\n' => {
+							pos += c.len_utf8();
+							pc += 1;
+						},
+						_ => break
+					},
+					Inst::Class(class) => match next {
+						Some(c) if class.matches(c, self.ignore_case) => {
+							pos += c.len_utf8();
+							pc += 1;
+						},
+						_ => break
+					},
+					Inst::Assert(assertion) => if self.holds(*assertion, text, pos) {
+						pc += 1;
+					} else {
+						break
+					},
+					Inst::Split(first, second) => {
+						stack.push(Job::Run(*second, pos));
+						pc = *first;
+					},
+					Inst::Jump(to) => pc = *to,
+					Inst::Save(slot) => {
+						stack.push(Job::Restore(*slot, slots[*slot]));
+						slots[*slot] = Some(pos);
+						pc += 1;
+					}
+				}
+			}
+		}
+		false
+	}
+
+	fn holds(&self, assertion: Assertion, text: &str, pos: usize) -> bool {
+		let before = text[..pos].chars().next_back();
+		let after = text[pos..].chars().next();
+		match assertion {
+			Assertion::Start => pos == 0 || self.multi_line && before == Some('\n'),
+			Assertion::End => pos == text.len() || self.multi_line && after == Some('\n'),
+			Assertion::WordBoundary | Assertion::NotWordBoundary => {
+				let word = |c: Option<char>| c.is_some_and(|c| Perl::Word.matches(c));
+				(word(before) != word(after)) == matches!(assertion, Assertion::WordBoundary)
+			}
+		}
+	}
+}
+
+/// Number of instructions the node compiles into.
+fn size(node: &Node) -> usize {
+	match node {
+		Node::Empty => 0,
+		Node::Char(_) | Node::Any | Node::Class(_) | Node::Assert(_) => 1,
+		Node::Group(group, inner) => size(inner).saturating_add(if group.is_some() { 2 } else { 0 }),
+		Node::Concat(nodes) => nodes.iter().map(size).fold(0, usize::saturating_add),
+		Node::Alternate(nodes) => nodes.iter().map(size).fold(2 * nodes.len().saturating_sub(1), usize::saturating_add),
+		Node::Repeat(inner, min, max, _) => {
+			let inner = size(inner);
+			let optional = match max {
+				None => inner.saturating_add(2),
+				Some(max) => inner.saturating_add(1).saturating_mul((max - min) as usize)
+			};
+			inner.saturating_mul(*min as usize).saturating_add(optional)
+		}
+	}
+}
+
+fn compile(node: &Node, program: &mut Vec<Inst>) {
+	match node {
+		Node::Empty => {},
+		Node::Char(c) => program.push(Inst::Char(*c)),
+		Node::Any => program.push(Inst::Any),
+		Node::Class(class) => program.push(Inst::Class(class.clone())),
+		Node::Assert(assertion) => program.push(Inst::Assert(*assertion)),
+		Node::Group(group, inner) => match group {
+			Some(group) => {
+				program.push(Inst::Save(group * 2));
+				compile(inner, program);
+				program.push(Inst::Save(group * 2 + 1));
+			},
+			None => compile(inner, program)
+		},
+		Node::Concat(nodes) => for node in nodes {
+			compile(node, program);
+		},
+		Node::Alternate(nodes) => {
+			let mut jumps = Vec::new();
+			for (i, node) in nodes.iter().enumerate() {
+				if i + 1 < nodes.len() {
+					let split = program.len();
+					program.push(Inst::Split(split + 1, 0));
+					compile(node, program);
+					jumps.push(program.len());
+					program.push(Inst::Jump(0));
+					let next = program.len();
+					program[split] = Inst::Split(split + 1, next);
+				} else {
+					compile(node, program);
+				}
+			}
+			let end = program.len();
+			for jump in jumps {
+				program[jump] = Inst::Jump(end);
+			}
+		},
+		Node::Repeat(inner, min, max, greedy) => {
+			for _ in 0..*min {
+				compile(inner, program);
+			}
+			let split = |program: &mut Vec<Inst>, at: usize, taken: usize, skipped: usize| {
+				program[at] = if *greedy { Inst::Split(taken, skipped) } else { Inst::Split(skipped, taken) };
+			};
+			match max {
+				None => {
+					let start = program.len();
+					program.push(Inst::Match);
+					compile(inner, program);
+					program.push(Inst::Jump(start));
+					let end = program.len();
+					split(program, start, start + 1, end);
+				},
+				Some(max) => {
+					let mut splits = Vec::new();
+					for _ in *min..*max {
+						splits.push(program.len());
+						program.push(Inst::Match);
+						compile(inner, program);
+					}
+					let end = program.len();
+					for at in splits {
+						split(program, at, at + 1, end);
+					}
+				}
+			}
+		}
+	}
+}
+
+struct Parser<'a> {
+	pattern: &'a str,
+	pos: usize,
+	groups: usize,
+	/// Number of groups being read.
+	depth: usize
+}
+
+impl Parser<'_> {
+	fn peek(&self) -> Option<char> {
+		self.pattern[self.pos..].chars().next()
+	}
+
+	fn eat(&mut self, c: char) -> bool {
+		let found = self.peek() == Some(c);
+		if found {
+			self.pos += c.len_utf8();
+		}
+		found
+	}
+
+	fn error(&self, message: &'static str) -> RegexError {
+		RegexError { offset: self.pos, message }
+	}
+
+	fn alternate(&mut self) -> Result<Node, RegexError> {
+		let mut nodes = vec![self.concat()?];
+		while self.eat('|') {
+			nodes.push(self.concat()?);
+		}
+		Ok(if nodes.len() == 1 { nodes.remove(0) } else { Node::Alternate(nodes) })
+	}
+
+	fn concat(&mut self) -> Result<Node, RegexError> {
+		let mut nodes = Vec::new();
+		while let Some(c) = self.peek() {
+			if c == '|' || c == ')' {
+				break
+			}
+			let atom = self.atom()?;
+			nodes.push(self.quantified(atom)?);
+		}
+		Ok(match nodes.len() {
+			0 => Node::Empty,
+			1 => nodes.remove(0),
+			_ => Node::Concat(nodes)
+		})
+	}
+
+	fn quantified(&mut self, atom: Node) -> Result<Node, RegexError> {
+		let start = self.pos;
+		let (min, max) = match self.peek() {
+			Some('*') => (0, None),
+			Some('+') => (1, None),
+			Some('?') => (0, Some(1)),
+			Some('{') => match self.counts() {
+				Some(counts) => counts,
+				None => {
+					self.pos = start;
+					return Ok(atom)
+				}
+			},
+			_ => return Ok(atom)
+		};
+		if self.pos == start {
+			self.pos += 1;
+		}
+		if matches!(atom, Node::Assert(_) | Node::Empty) {
+			return Err(RegexError { offset: start, message: "nothing to repeat" })
+		}
+		if max.is_some_and(|max| max < min) {
+			return Err(RegexError { offset: start, message: "repetition range is backwards" })
+		}
+		if min > MAX_REPEAT || max.is_some_and(|max| max > MAX_REPEAT) {
+			return Err(RegexError { offset: start, message: "repetition count is too large" })
+		}
+		let greedy = !self.eat('?');
+		if matches!(self.peek(), Some('*' | '+' | '?')) {
+			return Err(self.error("nothing to repeat"))
+		}
+		Ok(Node::Repeat(Box::new(atom), min, max, greedy))
+	}
+
+	/// Reads `{n}`, `{n,}` or `{n,m}`, or nothing when the brace does not start one.
+	fn counts(&mut self) -> Option<(u32, Option<u32>)> {
+		let rest = &self.pattern[self.pos + 1..];
+		let end = rest.find('}')?;
+		let inner = &rest[..end];
+		let number = |text: &str| if !text.is_empty() && text.chars().all(|c| c.is_ascii_digit()) {
+			Some(text.parse().unwrap_or(u32::MAX))
+		} else {
+			None
+		};
+		let counts = match inner.split_once(',') {
+			None => {
+				let count = number(inner)?;
+				(count, Some(count))
+			},
+			Some((min, "")) => (number(min)?, None),
+			Some((min, max)) => (number(min)?, Some(number(max)?))
+		};
+		self.pos += end + 2;
+		Some(counts)
+	}
+
+	fn atom(&mut self) -> Result<Node, RegexError> {
+		let start = self.pos;
+		let Some(c) = self.peek() else {
+			return Ok(Node::Empty)
+		};
+		self.pos += c.len_utf8();
+		Ok(match c {
+			'.' => Node::Any,
+			'^' => Node::Assert(Assertion::Start),
+			'$' => Node::Assert(Assertion::End),
+			'*' | '+' | '?' => return Err(RegexError { offset: start, message: "nothing to repeat" }),
+			'(' if self.depth == MAX_NESTING => return Err(RegexError { offset: start, message: "groups are nested too deeply" }),
+			'(' => {
+				let group = if self.eat('?') {
+					if !self.eat(':') {
+						return Err(RegexError { offset: start, message: "unsupported group" })
+					}
+					None
+				} else {
+					self.groups += 1;
+					Some(self.groups)
+				};
+				self.depth += 1;
+				let inner = self.alternate()?;
+				self.depth -= 1;
+				if !self.eat(')') {
+					return Err(RegexError { offset: start, message: "unclosed group" })
+				}
+				Node::Group(group, Box::new(inner))
+			},
+			'[' => Node::Class(self.class(start)?),
+			'\\' => match self.escape()? {
+				Escaped::Char(c) => Node::Char(c),
+				Escaped::Perl(perl, negated) => Node::Class(Class { items: vec![ClassItem::Perl(perl, negated)], negated: false }),
+				Escaped::Assert(assertion) => Node::Assert(assertion)
+			},
+			c => Node::Char(c)
+		})
+	}
+
+	fn class(&mut self, start: usize) -> Result<Class, RegexError> {
+		let negated = self.eat('^');
+		let mut items = Vec::new();
+		let mut first = true;
+		loop {
+			let c = self.peek().ok_or(RegexError { offset: start, message: "unclosed class" })?;
+			if c == ']' && !first {
+				self.pos += 1;
+				return Ok(Class { items, negated })
+			}
+			first = false;
+			let from = self.class_char()?;
+			let Escaped::Char(from) = from else {
+				if let Escaped::Perl(perl, negated) = from {
+					items.push(ClassItem::Perl(perl, negated));
+				}
+				continue
+			};
+			let to = if self.peek() == Some('-') && !self.pattern[self.pos + 1..].starts_with(']') && self.pos + 1 < self.pattern.len() {
+				self.pos += 1;
+				match self.class_char()? {
+					Escaped::Char(to) => to,
+					_ => return Err(self.error("invalid class range"))
+				}
+			} else {
+				from
+			};
+			if to < from {
+				return Err(self.error("class range is backwards"))
+			}
+			items.push(ClassItem::Range(from, to));
+		}
+	}
+
+	fn class_char(&mut self) -> Result<Escaped, RegexError> {
+		let c = self.peek().ok_or(self.error("unclosed class"))?;
+		self.pos += c.len_utf8();
+		if c != '\\' {
+			return Ok(Escaped::Char(c))
+		}
+		Ok(match self.escape()? {
+			// Within a class \b is a backspace.
+			Escaped::Assert(Assertion::WordBoundary) => Escaped::Char('\u{8}'),
+			Escaped::Assert(_) => return Err(self.error("invalid escape within class")),
+			escaped => escaped
+		})
+	}
+
+	/// Reads what follows a `\`.
+	fn escape(&mut self) -> Result<Escaped, RegexError> {
+		let start = self.pos - 1;
+		let c = self.peek().ok_or(RegexError { offset: start, message: "trailing \\" })?;
+		self.pos += c.len_utf8();
+		Ok(match c {
+			'd' => Escaped::Perl(Perl::Digit, false),
+			'D' => Escaped::Perl(Perl::Digit, true),
+			'w' => Escaped::Perl(Perl::Word, false),
+			'W' => Escaped::Perl(Perl::Word, true),
+			's' => Escaped::Perl(Perl::Space, false),
+			'S' => Escaped::Perl(Perl::Space, true),
+			'b' => Escaped::Assert(Assertion::WordBoundary),
+			'B' => Escaped::Assert(Assertion::NotWordBoundary),
+			'n' => Escaped::Char('\n'),
+			't' => Escaped::Char('\t'),
+			'r' => Escaped::Char('\r'),
+			'f' => Escaped::Char('\u{c}'),
+			'v' => Escaped::Char('\u{b}'),
+			'0' => Escaped::Char('\0'),
+			'x' | 'u' => {
+				let len = if c == 'x' { 2 } else { 4 };
+				let digits = self.pattern.get(self.pos..self.pos + len).filter(|digits| digits.chars().all(|c| c.is_ascii_hexdigit()));
+				let c = digits.and_then(|digits| char::from_u32(u32::from_str_radix(digits, 16).ok()?))
+					.ok_or(RegexError { offset: start, message: "invalid character code" })?;
+				self.pos += len;
+				Escaped::Char(c)
+			},
+			'1'..='9' => return Err(RegexError { offset: start, message: "backreferences are not supported" }),
+			c if c.is_ascii_alphanumeric() => return Err(RegexError { offset: start, message: "unknown escape" }),
+			c => Escaped::Char(c)
+		})
+	}
+}
+
+enum Escaped {
+	Char(char),
+	Perl(Perl, bool),
+	Assert(Assertion)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn find(pattern: &str, flags: &str, text: &str) -> Option<Vec<Option<String>>> {
+		let captures = Regex::with_flags(pattern, flags).unwrap().captures(text)?;
+		Some((0..captures.len()).map(|group| captures.get(group).map(str::to_string)).collect())
+	}
+
+	#[test]
+	fn match_patterns() {
+		let some = |groups: &[&str]| Some(groups.iter().map(|group| Some(group.to_string())).collect::<Vec<_>>());
+		assert_eq!(find("(\\w+)\\.(\\w+)$", "", "my file.rs"), some(&["file.rs", "file", "rs"]));
+		assert_eq!(find("^[a-c]+?(b*)", "", "abbbc"), some(&["abbb", "bbb"]));
+		assert_eq!(find("^[a-c]+?", "", "abbbc"), some(&["a"]));
+		assert_eq!(find("x{2,3}|y", "", "axxxxy"), some(&["xxx"]));
+		assert_eq!(find("HELLO", "i", "say hello"), some(&["hello"]));
+		assert_eq!(find("^b", "m", "a\nb"), some(&["b"]));
+		assert_eq!(find("a.b", "", "a\nb"), None);
+		assert_eq!(find("a.b", "s", "a\nb"), some(&["a\nb"]));
+		assert_eq!(find("\\bis\\b", "", "this is"), some(&["is"]));
+		assert_eq!(find("[^,\\s]+", "", " , é-ü"), some(&["é-ü"]));
+		assert_eq!(find("(a)|(b)", "", "b"), Some(vec![Some(String::from("b")), None, Some(String::from("b"))]));
+		assert_eq!(find("a{,2}", "", "a{,2}"), some(&["a{,2}"]));
+		// Would take exponential time with plain backtracking.
+		assert!(!Regex::new("(a*)*b").unwrap().is_match(&"a".repeat(200)));
+		// Too many states to keep a bit for each, so those visited are kept instead.
+		let text = format!("{}{}", "x".repeat(30000), "ab".repeat(150));
+		assert_eq!(Regex::new("(?:ab){150}").unwrap().captures(&text).unwrap().range(0), Some(30000..30300));
+	}
+
+	#[test]
+	fn reject_invalid_patterns() {
+		for (pattern, offset) in [("(a", 0), ("a)", 1), ("*a", 0), ("[a", 0), ("a{3,1}", 1), ("\\1", 0), ("(?=a)", 0), ("\\q", 0), ("[z-a]", 4)] {
+			assert_eq!(Regex::new(pattern).unwrap_err().offset, offset, "{}", pattern);
+		}
+	}
+}
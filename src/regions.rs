@@ -0,0 +1,123 @@
+use std::ops::Range;
+use crate::{Snippet, Segment, Field};
+
+/// What produced a region of a rendered snippet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionKind {
+	/// Normal text of the snippet.
+	Literal,
+	/// Text of a field, typed in or chosen by the user (or the field's default).
+	Field,
+	/// Value of a program variable.
+	Variable,
+	/// Output of an external program.
+	Code,
+	/// Result of a transformation.
+	Transformation
+}
+
+impl RegionKind {
+	/// Whether the text was produced by a program rather than written by the snippet's author or user,
+	/// such as text that spellcheckers and diagnostics should skip.
+	pub fn is_generated(&self) -> bool {
+		matches!(self, RegionKind::Variable | RegionKind::Code | RegionKind::Transformation)
+	}
+}
+
+/// A byte range of the rendered snippet along with what produced it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Region {
+	pub range: Range<usize>,
+	pub kind: RegionKind
+}
+
+impl Snippet {
+	/// Splits the rendered snippet (as Display renders it) into regions by what produced their text, in order.
+	/// Neighbouring regions of the same kind are merged and empty regions are left out.
+	/// Text within a field is a field region, apart from the variables, code and transformations nested in it.
+	pub fn regions(&self) -> Vec<Region> {
+		let mut regions = Vec::new();
+		let mut end = 0;
+		add_regions(&self.body, RegionKind::Literal, &mut end, &mut regions);
+		regions
+	}
+}
+
+fn push(regions: &mut Vec<Region>, end: &mut usize, len: usize, kind: RegionKind) {
+	if len == 0 {
+		return
+	}
+	let start = *end;
+	*end += len;
+	match regions.last_mut() {
+		Some(last) if last.kind == kind => last.range.end = *end,
+		_ => regions.push(Region { range: start..*end, kind })
+	}
+}
+
+fn add_regions(segments: &[Segment], text_kind: RegionKind, end: &mut usize, regions: &mut Vec<Region>) {
+	for segment in segments {
+		match segment {
+			Segment::Text(text) => push(regions, end, text.len(), text_kind),
+			Segment::Variable(variable) => push(regions, end, variable.value.len(), RegionKind::Variable),
+			Segment::Code(code) => push(regions, end, code.output.len(), RegionKind::Code),
+			Segment::Transformation(transformation) => push(regions, end, transformation.result.len(), RegionKind::Transformation),
+			Segment::Snippet(nested) => add_regions(&nested.body, text_kind, end, regions),
+			Segment::Conditional(conditional) => add_regions(conditional.shown(), text_kind, end, regions),
+			Segment::Field(field) => match &**field {
+				Field::Placeholder(child_body) => add_regions(child_body, RegionKind::Field, end, regions),
+				Field::Choice(choice, child_body, _) => if let Some(child_body) = child_body.get(*choice) {
+					add_regions(child_body, RegionKind::Field, end, regions);
+				},
+				Field::Number(number) => push(regions, end, number.value.to_string().len(), RegionKind::Field),
+				Field::Toggle(on, when_on, when_off) => add_regions(if *on { when_on } else { when_off }, RegionKind::Field, end, regions),
+				Field::Repeat(repeat) => for (i, body) in repeat.repetitions.iter().enumerate() {
+					if i > 0 {
+						push(regions, end, repeat.separator.len(), RegionKind::Field);
+					}
+					add_regions(body, RegionKind::Field, end, regions);
+				}
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::shared::Rc;
+	use crate::{Code, Variable, VariableSource};
+
+	#[test]
+	fn split_into_regions() {
+		let user = Rc::new(Variable { name: String::from("USER"), value: String::from("me"), source: VariableSource::Daemon, default: None });
+		let snippet = Snippet {
+			body: vec![
+				Segment::Text(String::from("Date: ")),
+				Segment::Code(Rc::new(Code { code: String::from("date"), output: String::from("today"), shebang: String::from("#!/bin/sh") })),
+				Segment::Text(String::from(" by ")),
+				Segment::Field(Rc::new(Field::Placeholder(vec![Segment::Text(String::from("dear ")), Segment::Variable(user.clone()), Segment::Text(String::new())]))),
+				Segment::Variable(user),
+				Segment::Text(String::from("."))
+			],
+			tabs: Vec::new(),
+			variables: Vec::new(),
+			code_expansions: Vec::new(),
+			named_segments: Vec::new(),
+			current_tab: None,
+			choice_sources: Vec::new(),
+			baseline: Default::default()
+		};
+		let rendered = snippet.to_string();
+		let regions: Vec<(&str, RegionKind)> = snippet.regions().into_iter().map(|region| (&rendered[region.range], region.kind)).collect();
+		assert_eq!(regions, [
+			("Date: ", RegionKind::Literal),
+			("today", RegionKind::Code),
+			(" by ", RegionKind::Literal),
+			("dear ", RegionKind::Field),
+			("meme", RegionKind::Variable),
+			(".", RegionKind::Literal)
+		]);
+		assert!(RegionKind::Code.is_generated() && !RegionKind::Field.is_generated());
+	}
+}
@@ -0,0 +1,231 @@
+//! Rendering a snippet along with where its tabs, variables and nested snippets ended up,
+//! for editors to place cursors and highlights (in bytes, or in UTF-16 code units as LSP positions count).
+
+use std::ops::Range;
+use crate::shared::Rc;
+use crate::{Snippet, Segment, Field};
+
+/// Part of the rendered text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+	/// Byte range within the text.
+	pub bytes: Range<usize>,
+	/// Range within the text in UTF-16 code units.
+	pub utf16: Range<usize>
+}
+
+/// A snippet rendered as Display renders it, along with where its parts ended up.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Rendered {
+	pub text: String,
+	/// Fields of the snippet's tabs by tab number, a span for every occurrence (mirrors included), in order of where they start.
+	pub tabs: Vec<(u8, Span)>,
+	/// Variables by name, in order of where they start.
+	pub variables: Vec<(String, Span)>,
+	/// Nested snippets, in order of where they start. Their own tabs are not among the tabs.
+	pub snippets: Vec<Span>
+}
+
+/// Where cursors go once the snippet is filled in, see [`Snippet::finalize`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CursorBehavior {
+	/// A cursor where the final tab (tab 0) is.
+	#[default]
+	FinalTab,
+	/// The whole text selected.
+	SelectAll,
+	/// A cursor at every occurrence of the selected tab's field (the final tab when none is selected),
+	/// for hosts with many cursors to keep editing mirrors together.
+	Mirrors
+}
+
+struct Renderer {
+	rendered: Rendered,
+	utf16: usize,
+	/// Tab number of each tabbed field.
+	nums: Vec<(*const Field, u8)>
+}
+
+impl Renderer {
+	fn position(&self) -> (usize, usize) {
+		(self.rendered.text.len(), self.utf16)
+	}
+
+	fn span(&self, (byte, utf16): (usize, usize)) -> Span {
+		Span { bytes: byte..self.rendered.text.len(), utf16: utf16..self.utf16 }
+	}
+
+	fn write(&mut self, text: &str) {
+		self.rendered.text.push_str(text);
+		self.utf16 += text.encode_utf16().count();
+	}
+
+	fn segments(&mut self, segments: &[Segment]) {
+		for segment in segments {
+			match segment {
+				Segment::Text(text) => self.write(text),
+				Segment::Code(code) => self.write(&code.output),
+				Segment::Transformation(transformation) => self.write(&transformation.result),
+				Segment::Variable(variable) => {
+					let start = self.position();
+					self.write(&variable.value);
+					self.rendered.variables.push((variable.name.clone(), self.span(start)));
+				},
+				Segment::Conditional(conditional) => self.segments(conditional.shown()),
+				Segment::Snippet(nested) => {
+					let start = self.position();
+					let index = self.rendered.snippets.len();
+					self.segments(&nested.body);
+					let span = self.span(start);
+					self.rendered.snippets.insert(index, span);
+				},
+				Segment::Field(field) => {
+					let start = self.position();
+					// Fields are added once rendered, but ahead of the fields nested in them.
+					let index = self.rendered.tabs.len();
+					self.field(field);
+					if let Some(&(_, num)) = self.nums.iter().find(|(ptr, _)| *ptr == Rc::as_ptr(field)) {
+						let span = self.span(start);
+						self.rendered.tabs.insert(index, (num, span));
+					}
+				}
+			}
+		}
+	}
+
+	fn field(&mut self, field: &Field) {
+		match field {
+			Field::Placeholder(child_body) => self.segments(child_body),
+			Field::Choice(choice, child_body, _) => if let Some(child_body) = child_body.get(*choice) {
+				self.segments(child_body);
+			},
+			Field::Number(number) => self.write(&number.value.to_string()),
+			Field::Toggle(on, when_on, when_off) => self.segments(if *on { when_on } else { when_off }),
+			Field::Repeat(repeat) => for (i, body) in repeat.repetitions.iter().enumerate() {
+				if i > 0 {
+					self.write(&repeat.separator);
+				}
+				self.segments(body);
+			}
+		}
+	}
+}
+
+impl Snippet {
+	/// Renders the snippet, keeping where its tabs, variables and nested snippets ended up.
+	pub fn render(&self) -> Rendered {
+		let mut renderer = Renderer {
+			rendered: Rendered::default(),
+			utf16: 0,
+			nums: self.tabs.iter().map(|tab| (tab.field.as_ptr(), tab.num)).collect()
+		};
+		renderer.segments(&self.body);
+		renderer.rendered
+	}
+
+	/// Ends filling in the snippet, no tab being selected after, giving where the cursors go (the first being the main one)
+	/// within the rendered text. Cursors fall at the end of the text when there is no such tab to put them at.
+	pub fn finalize(&mut self, behavior: CursorBehavior) -> Vec<Span> {
+		let rendered = self.render();
+		let end = rendered.text.len();
+		let whole = Span { bytes: 0..end, utf16: 0..rendered.text.encode_utf16().count() };
+		let num = match behavior {
+			CursorBehavior::SelectAll => None,
+			CursorBehavior::FinalTab => Some(0),
+			CursorBehavior::Mirrors => Some(self.current_tab.unwrap_or(0))
+		};
+		self.current_tab = None;
+		let Some(num) = num else {
+			return vec![whole]
+		};
+		let mut spans: Vec<Span> = rendered.tabs.into_iter().filter(|(tab, _)| *tab == num).map(|(_, span)| span).collect();
+		if behavior == CursorBehavior::FinalTab {
+			spans.truncate(1);
+		}
+		if spans.is_empty() {
+			spans.push(Span { bytes: end..end, utf16: whole.utf16.end..whole.utf16.end });
+		}
+		spans
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn render_with_spans() {
+		let snippet = Snippet::parse("${1:a${2:é}} $1 ${USER:me}").unwrap();
+		let rendered = snippet.render();
+		assert_eq!(rendered.text, snippet.to_string());
+		let tabs: Vec<(u8, Range<usize>, Range<usize>)> = rendered.tabs.into_iter().map(|(num, span)| (num, span.bytes, span.utf16)).collect();
+		assert_eq!(tabs, [(1, 0..3, 0..2), (2, 1..3, 1..2), (1, 4..7, 3..5), (2, 5..7, 4..5)]);
+		assert_eq!(rendered.variables, [(String::from("USER"), Span { bytes: 8..10, utf16: 6..8 })]);
+		assert!(rendered.snippets.is_empty());
+	}
+
+	#[test]
+	fn finalize_cursors() {
+		let source = "${1:ü} $1 ${2:b}$0";
+		let mut snippet = Snippet::parse(source).unwrap();
+		let spans = |spans: Vec<Span>| -> Vec<(Range<usize>, Range<usize>)> { spans.into_iter().map(|span| (span.bytes, span.utf16)).collect() };
+		assert_eq!(spans(snippet.finalize(CursorBehavior::FinalTab)), [(7..7, 5..5)]);
+		assert_eq!(spans(snippet.finalize(CursorBehavior::SelectAll)), [(0..7, 0..5)]);
+		snippet.jump_to(1);
+		assert_eq!(spans(snippet.finalize(CursorBehavior::Mirrors)), [(0..2, 0..1), (3..5, 2..3)]);
+		assert!(snippet.current_tab().is_none());
+		assert_eq!(spans(snippet.finalize(CursorBehavior::Mirrors)), [(7..7, 5..5)]);
+		// Without a final tab, the cursor is left at the end.
+		let mut snippet = Snippet::parse("ü${1:a}").unwrap();
+		assert_eq!(spans(snippet.finalize(CursorBehavior::FinalTab)), [(3..3, 2..2)]);
+		assert_eq!(spans(snippet.finalize(CursorBehavior::Mirrors)), [(3..3, 2..2)]);
+	}
+
+	/// Checks that every span and region of the snippet falls on character boundaries of its text, with UTF-16 offsets that agree.
+	fn check_boundaries(snippet: &Snippet) {
+		let rendered = snippet.render();
+		let text = &rendered.text;
+		assert_eq!(*text, snippet.to_string());
+		let utf16 = |byte: usize| text[..byte].encode_utf16().count();
+		let spans = rendered.tabs.iter().map(|(_, span)| span).chain(rendered.variables.iter().map(|(_, span)| span)).chain(&rendered.snippets);
+		for span in spans {
+			assert!(text.is_char_boundary(span.bytes.start) && text.is_char_boundary(span.bytes.end), "{:?} in {:?}", span, text);
+			assert_eq!(span.utf16, utf16(span.bytes.start)..utf16(span.bytes.end), "{:?} in {:?}", span, text);
+		}
+		for region in snippet.regions() {
+			assert!(region.range.end <= text.len() && text.get(region.range.clone()).is_some(), "{:?} in {:?}", region, text);
+		}
+	}
+
+	#[test]
+	fn multibyte_spans() {
+		let typed = ["字e\u{301}", "😀", "n\u{303}\n", ""];
+		let mut checked = 0;
+		for seed in 0..3000 {
+			let source = crate::testing::arbitrary_source(seed);
+			// Cutting the source anywhere between characters gives text that parses or fails, without slicing it elsewhere.
+			for (cut, _) in source.char_indices() {
+				let _ = Snippet::parse(&source[..cut]).map(|snippet| check_boundaries(&snippet));
+			}
+			let Ok(mut snippet) = Snippet::parse(&source) else {
+				continue
+			};
+			check_boundaries(&snippet);
+			checked += !source.is_ascii() as usize;
+			let before = snippet.to_string();
+			snippet.render_delta();
+			let nums: Vec<u8> = snippet.tabs().iter().map(|tab| tab.num).collect();
+			for (i, num) in nums.into_iter().enumerate() {
+				snippet.set_field_text(num, typed[i % typed.len()]);
+				check_boundaries(&snippet);
+			}
+			let mut text = before;
+			for edit in snippet.render_delta().iter().rev() {
+				assert!(text.is_char_boundary(edit.range.bytes.start) && text.is_char_boundary(edit.range.bytes.end));
+				text.replace_range(edit.range.bytes.clone(), &edit.text);
+			}
+			assert_eq!(text, snippet.to_string(), "{:?}", source);
+		}
+		assert!(checked > 300, "{}", checked);
+	}
+}
@@ -0,0 +1,320 @@
+//! Filling in the values of variables, from the standard TextMate and VSCode variables or any other resolver.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::path::PathBuf;
+use crate::shared::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::{Snippet, Segment, Field, Tab, Variable, VariableSource};
+use crate::compose::{Replacer, shared_body};
+use crate::transform::TransformError;
+
+/// Provides the values of variables by name.
+pub trait VariableResolver {
+	/// Value of the variable. None when the resolver does not know the variable, leaving its value as it is.
+	fn resolve(&self, name: &str) -> Option<String>;
+}
+
+impl<F: Fn(&str) -> Option<String>> VariableResolver for F {
+	fn resolve(&self, name: &str) -> Option<String> {
+		self(name)
+	}
+}
+
+/// Resolves the standard TextMate and VSCode variables, and environment variables for any other name.
+/// Editor state is for the program using this library to fill in, variables depending on it resolving to nothing until then.
+#[derive(Debug, Clone)]
+pub struct StandardVariables {
+	/// File being edited, for `TM_FILENAME`, `TM_FILENAME_BASE`, `TM_FILEPATH` and `TM_DIRECTORY`.
+	pub file: Option<PathBuf>,
+	/// Workspace folder of the file, for `WORKSPACE_NAME`, `WORKSPACE_FOLDER` and `RELATIVE_FILEPATH`.
+	pub workspace: Option<PathBuf>,
+	/// `TM_SELECTED_TEXT`
+	pub selected_text: Option<String>,
+	/// `TM_CURRENT_LINE`
+	pub current_line: Option<String>,
+	/// `TM_CURRENT_WORD`
+	pub current_word: Option<String>,
+	/// Line of the cursor counting from 0, for `TM_LINE_INDEX` and `TM_LINE_NUMBER` (counting from 1).
+	pub line_index: Option<usize>,
+	/// `CLIPBOARD`
+	pub clipboard: Option<String>,
+	/// `LINE_COMMENT` of the file's language.
+	pub line_comment: Option<String>,
+	/// `BLOCK_COMMENT_START` and `BLOCK_COMMENT_END` of the file's language.
+	pub block_comment: Option<(String, String)>,
+	/// Time of the `CURRENT_` variables, which are in UTC. The time of resolving when None.
+	pub time: Option<SystemTime>,
+	/// Whether other names are looked up as environment variables.
+	pub environment: bool
+}
+
+impl Default for StandardVariables {
+	fn default() -> Self {
+		StandardVariables {
+			file: None,
+			workspace: None,
+			selected_text: None,
+			current_line: None,
+			current_word: None,
+			line_index: None,
+			clipboard: None,
+			line_comment: None,
+			block_comment: None,
+			time: None,
+			environment: true
+		}
+	}
+}
+
+const MONTHS: [&str; 12] = ["January", "February", "March", "April", "May", "June", "July", "August", "September", "October", "November", "December"];
+const DAYS: [&str; 7] = ["Sunday", "Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday"];
+
+/// Date and time in UTC.
+struct DateTime {
+	year: i64,
+	/// Counting from 1.
+	month: usize,
+	day: i64,
+	/// Counting from Sunday as 0.
+	weekday: usize,
+	hour: i64,
+	minute: i64,
+	second: i64
+}
+
+impl DateTime {
+	fn from_unix(seconds: i64) -> Self {
+		let days = seconds.div_euclid(86400);
+		let time = seconds.rem_euclid(86400);
+		// Days since 0000-03-01, so leap days come at the end of a year.
+		let days_since = days + 719468;
+		let era = days_since.div_euclid(146097);
+		let day_of_era = days_since.rem_euclid(146097);
+		let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+		let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+		let shifted_month = (5 * day_of_year + 2) / 153;
+		let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+		DateTime {
+			year: era * 400 + year_of_era + if month <= 2 { 1 } else { 0 },
+			month: month as usize,
+			day: day_of_year - (153 * shifted_month + 2) / 5 + 1,
+			// 1970-01-01 was a Thursday.
+			weekday: (days + 4).rem_euclid(7) as usize,
+			hour: time / 3600,
+			minute: time / 60 % 60,
+			second: time % 60
+		}
+	}
+}
+
+fn random() -> u64 {
+	RandomState::new().build_hasher().finish()
+}
+
+fn uuid() -> String {
+	let high = random();
+	let low = random();
+	format!(
+		"{:08x}-{:04x}-4{:03x}-{:04x}-{:012x}",
+		high >> 32,
+		(high >> 16) & 0xffff,
+		high & 0xfff,
+		0x8000 | (low >> 48) & 0x3fff,
+		low & 0xffff_ffff_ffff
+	)
+}
+
+impl StandardVariables {
+	fn unix_time(&self) -> i64 {
+		match self.time.unwrap_or_else(SystemTime::now).duration_since(UNIX_EPOCH) {
+			Ok(since) => since.as_secs() as i64,
+			Err(before) => -(before.duration().as_secs() as i64)
+		}
+	}
+
+	fn current(&self, name: &str) -> Option<String> {
+		let seconds = self.unix_time();
+		let now = DateTime::from_unix(seconds);
+		Some(match name {
+			"CURRENT_YEAR" => now.year.to_string(),
+			"CURRENT_YEAR_SHORT" => format!("{:02}", now.year.rem_euclid(100)),
+			"CURRENT_MONTH" => format!("{:02}", now.month),
+			"CURRENT_MONTH_NAME" => MONTHS[now.month - 1].to_string(),
+			"CURRENT_MONTH_NAME_SHORT" => MONTHS[now.month - 1][..3].to_string(),
+			"CURRENT_DATE" => format!("{:02}", now.day),
+			"CURRENT_DAY_NAME" => DAYS[now.weekday].to_string(),
+			"CURRENT_DAY_NAME_SHORT" => DAYS[now.weekday][..3].to_string(),
+			"CURRENT_HOUR" => format!("{:02}", now.hour),
+			"CURRENT_MINUTE" => format!("{:02}", now.minute),
+			"CURRENT_SECOND" => format!("{:02}", now.second),
+			"CURRENT_SECONDS_UNIX" => seconds.to_string(),
+			"CURRENT_TIMEZONE_OFFSET" => String::from("+00:00"),
+			_ => return None
+		})
+	}
+}
+
+impl VariableResolver for StandardVariables {
+	fn resolve(&self, name: &str) -> Option<String> {
+		let path = |path: &PathBuf| path.to_string_lossy().into_owned();
+		match name {
+			"TM_FILENAME" => self.file.as_ref()?.file_name().map(|name| name.to_string_lossy().into_owned()),
+			"TM_FILENAME_BASE" => self.file.as_ref()?.file_stem().map(|stem| stem.to_string_lossy().into_owned()),
+			"TM_FILEPATH" => self.file.as_ref().map(path),
+			"TM_DIRECTORY" => self.file.as_ref()?.parent().map(|parent| parent.to_string_lossy().into_owned()),
+			"RELATIVE_FILEPATH" => self.file.as_ref()?.strip_prefix(self.workspace.as_ref()?).ok().map(|relative| relative.to_string_lossy().into_owned()),
+			"WORKSPACE_NAME" => self.workspace.as_ref()?.file_name().map(|name| name.to_string_lossy().into_owned()),
+			"WORKSPACE_FOLDER" => self.workspace.as_ref().map(path),
+			"TM_SELECTED_TEXT" => self.selected_text.clone(),
+			"TM_CURRENT_LINE" => self.current_line.clone(),
+			"TM_CURRENT_WORD" => self.current_word.clone(),
+			"TM_LINE_INDEX" => self.line_index.map(|index| index.to_string()),
+			"TM_LINE_NUMBER" => self.line_index.map(|index| (index + 1).to_string()),
+			"CURSOR_INDEX" => Some(String::from("0")),
+			"CURSOR_NUMBER" => Some(String::from("1")),
+			"CLIPBOARD" => self.clipboard.clone(),
+			"LINE_COMMENT" => self.line_comment.clone(),
+			"BLOCK_COMMENT_START" => self.block_comment.as_ref().map(|(start, _)| start.clone()),
+			"BLOCK_COMMENT_END" => self.block_comment.as_ref().map(|(_, end)| end.clone()),
+			"UUID" => Some(uuid()),
+			"RANDOM" => Some(format!("{:06}", random() % 1_000_000)),
+			"RANDOM_HEX" => Some(format!("{:06x}", random() & 0xff_ffff)),
+			_ if name.starts_with("CURRENT_") => self.current(name),
+			_ if self.environment => std::env::var(name).ok(),
+			_ => None
+		}
+	}
+}
+
+impl Snippet {
+	/// Fills in the snippet's variables with the values the resolver has for them, re-running the transformations acting upon them.
+	/// Everything holding a resolved variable is rebuilt around it, as variables can not change once shared.
+	/// Returns why transformations could not be applied, those transformations keeping their previous result.
+	pub fn resolve_variables(&mut self, resolver: &dyn VariableResolver) -> Vec<TransformError> {
+		self.resolve(resolver, false)
+	}
+
+	/// Resolves the snippet's variables as [`Snippet::resolve_variables`] does, those the resolver does not know falling back as TextMate has them:
+	/// a variable given a default (`${NAME:default}`) shows the default, and one without (`$NAME`, `${NAME}`) is replaced by a placeholder
+	/// holding its name, a new tab numbered after the others that the transformations of the variable then act upon.
+	/// Every occurrence of a variable is replaced by the same placeholder, which is left untabbed when tab numbers have run out.
+	/// Meant for when the snippet is expanded, as variables of the client (editor) fall back too.
+	pub fn fall_through_variables(&mut self, resolver: &dyn VariableResolver) -> Vec<TransformError> {
+		self.resolve(resolver, true)
+	}
+
+	fn resolve(&mut self, resolver: &dyn VariableResolver, fall_through: bool) -> Vec<TransformError> {
+		let mut replacer = Replacer::default();
+		let mut errors = Vec::new();
+		// Variables falling back to a placeholder, with the placeholder.
+		let mut placeholders: Vec<(*const Variable, Rc<Field>)> = Vec::new();
+		for expansion in &self.variables {
+			let Some(variable) = expansion.expansion.upgrade() else {
+				continue
+			};
+			let ptr = Rc::as_ptr(&variable);
+			if replacer.variables.iter().any(|(known, _)| *known == ptr) || placeholders.iter().any(|(known, _)| *known == ptr) {
+				continue
+			}
+			let value = match (resolver.resolve(&variable.name), &variable.default) {
+				(Some(value), _) => value,
+				_ if !fall_through => continue,
+				(None, Some(default)) => {
+					let value: String = default.iter().map(Segment::to_string).collect();
+					if value == variable.value {
+						continue
+					}
+					value
+				},
+				(None, None) => {
+					errors.extend(replacer.reapply(&expansion.transformations, &variable.name));
+					placeholders.push((ptr, Rc::new(Field::Placeholder(vec![Segment::Text(variable.name.clone())]))));
+					continue
+				}
+			};
+			errors.extend(replacer.reapply(&expansion.transformations, &value));
+			replacer.variables.push((Rc::as_ptr(&variable), Rc::new(Variable {
+				name: variable.name.clone(),
+				value,
+				source: match variable.source {
+					VariableSource::Daemon => VariableSource::Daemon,
+					VariableSource::Client => VariableSource::Client
+				},
+				default: variable.default.as_deref().map(shared_body)
+			})));
+		}
+		replacer.snippet(self);
+		if placeholders.is_empty() {
+			return errors
+		}
+
+		let mut next = self.tabs.iter().map(|tab| tab.num).max().unwrap_or(0).checked_add(1);
+		for (ptr, field) in &placeholders {
+			let Some(num) = next else {
+				break
+			};
+			next = num.checked_add(1);
+			let transformations = self.variables.iter().find(|expansion| expansion.expansion.as_ptr() == *ptr)
+				.map_or_else(Vec::new, |expansion| expansion.transformations.clone());
+			self.tabs.push(Tab { num, field: Rc::downgrade(field), transformations, label: None });
+		}
+		self.visit(&mut |segment: &mut Segment| {
+			let Segment::Variable(variable) = segment else {
+				return
+			};
+			if let Some((_, field)) = placeholders.iter().find(|(ptr, _)| *ptr == Rc::as_ptr(variable)) {
+				*segment = Segment::Field(field.clone());
+			}
+		});
+		errors
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::time::Duration;
+
+	#[test]
+	fn resolve_standard_variables() {
+		let mut snippet = Snippet::parse("${1:$TM_FILENAME} ${TM_FILENAME/(.*)\\..+$/${1:/upcase}/} $CURRENT_YEAR-$CURRENT_MONTH-$CURRENT_DATE $CURRENT_DAY_NAME_SHORT ${UNSET:none} $TM_FILENAME").unwrap();
+		let resolver = StandardVariables {
+			file: Some(PathBuf::from("/src/main.rs")),
+			// 2000-02-29, a leap day.
+			time: Some(UNIX_EPOCH + Duration::from_secs(951782400)),
+			environment: false,
+			..StandardVariables::default()
+		};
+		assert!(snippet.resolve_variables(&resolver).is_empty());
+		assert_eq!(snippet.to_string(), "main.rs MAIN 2000-02-29 Tue none main.rs");
+		let field = snippet.tabs()[0].field.upgrade().unwrap();
+		assert_eq!(field.to_string(), "main.rs");
+		assert!(snippet.variables().iter().all(|variable| variable.expansion.upgrade().is_some()));
+	}
+
+	#[test]
+	fn resolve_with_closure() {
+		let mut snippet = Snippet::parse("$USER and $USER").unwrap();
+		snippet.resolve_variables(&|name: &str| (name == "USER").then(|| String::from("me")));
+		assert_eq!(snippet.to_string(), "me and me");
+		assert_eq!(StandardVariables::default().resolve("UUID").unwrap().len(), 36);
+	}
+
+	#[test]
+	fn fall_through_unresolved_variables() {
+		let source = "${1:a $user} ${user/(.*)/${1:/upcase}/} ${TITLE:Untitled} ${KNOWN:x} $user";
+		let resolver = |name: &str| (name == "KNOWN").then(|| String::from("k"));
+		let mut snippet = Snippet::parse(source).unwrap();
+		snippet.resolve_variables(&resolver);
+		assert_eq!(snippet.to_string(), "a user  Untitled k user");
+
+		let mut snippet = Snippet::parse(source).unwrap();
+		assert!(snippet.fall_through_variables(&resolver).is_empty());
+		assert_eq!(snippet.to_string(), "a user USER Untitled k user");
+		assert_eq!(snippet.tabs().iter().map(|tab| tab.num).collect::<Vec<_>>(), [1, 2]);
+		assert_eq!(snippet.variables().len(), 2);
+		snippet.set_field_text(2, "me").unwrap();
+		assert_eq!(snippet.to_string(), "a me ME Untitled k me");
+	}
+}
@@ -0,0 +1,45 @@
+use crate::Snippet;
+use crate::compose::Copier;
+
+/// Executable or client provided content removed by [`Snippet::sanitize`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Removed {
+	/// Code along with the shebang of the program that would have run it.
+	Code(String, String),
+	/// Name of a variable coming from the client.
+	Variable(String)
+}
+
+impl Snippet {
+	/// Removes every code segment and variable coming from the client (within fields and nested snippets too),
+	/// along with the references to them, so snippets from untrusted sources run nothing and read nothing from the client.
+	/// Tabs keep their numbers. Returns what was removed, each once in the order it was found.
+	pub fn sanitize(&mut self) -> Vec<Removed> {
+		let mut copier = Copier::stripping();
+		let mut sanitized = copier.snippet(self, &self.body);
+		sanitized.current_tab = self.current_tab;
+		*self = sanitized;
+		copier.stripped
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::parse::SnippetSyntax;
+
+	#[test]
+	fn strip_executable_content() {
+		let mut snippet = Snippet::parse_with(SnippetSyntax::UltiSnips, "${2:`rm -rf ~`} ${VISUAL} `!p snip.rv = 1` ${2} $3").unwrap();
+		let removed = snippet.sanitize();
+		assert_eq!(removed, [
+			Removed::Code(String::from("#!/bin/sh"), String::from("rm -rf ~")),
+			Removed::Variable(String::from("VISUAL")),
+			Removed::Code(String::from("#!/usr/bin/env python3"), String::from("snip.rv = 1"))
+		]);
+		assert!(snippet.code_expansions().is_empty() && snippet.variables().is_empty());
+		assert_eq!(snippet.tabs().iter().map(|tab| tab.num).collect::<Vec<_>>(), [2, 3]);
+		assert_eq!(snippet.to_string(), "    ");
+		assert!(snippet.sanitize().is_empty());
+	}
+}
@@ -0,0 +1,128 @@
+//! Expansion of groups of snippets into files, for generating projects from templates.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf, Component};
+use std::{fs, io};
+use crate::Snippet;
+use crate::template::{CompiledTemplate, SlotKey};
+
+/// A named group of snippets each expanded into a file.
+#[derive(Debug, Clone)]
+pub struct ScaffoldSet {
+	pub name: String,
+	files: Vec<ScaffoldFile>
+}
+
+/// The compiled snippets for the path and contents of a file.
+#[derive(Debug, Clone)]
+struct ScaffoldFile {
+	path: CompiledTemplate,
+	contents: CompiledTemplate
+}
+
+/// A file expanded from a scaffold set.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScaffoldOutput {
+	/// Path relative to where the set is expanded.
+	pub path: PathBuf,
+	pub contents: String
+}
+
+/// Values shared by all snippets of a set.
+/// Variables are filled by name, tabbed fields by their default text (such as the names given
+/// by [`crate::mustache::from_template`]). Anything not given keeps its default.
+pub type Fill = HashMap<String, String>;
+
+fn fill(template: &CompiledTemplate, values: &Fill) -> String {
+	template.fill_with(|_, slot| match &slot.key {
+		SlotKey::Variable(name) => values.get(name),
+		SlotKey::Tab(_) => values.get(&slot.default)
+	}.map(String::as_str))
+}
+
+impl ScaffoldSet {
+	pub fn new(name: impl Into<String>) -> Self {
+		Self {
+			name: name.into(),
+			files: Vec::new()
+		}
+	}
+
+	/// Adds a file whose path is expanded from the first snippet and contents from the second.
+	pub fn add(&mut self, path: &Snippet, contents: &Snippet) {
+		self.files.push(ScaffoldFile {
+			path: path.compile(),
+			contents: contents.compile()
+		});
+	}
+
+	/// Number of files in the set.
+	pub fn len(&self) -> usize {
+		self.files.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.files.is_empty()
+	}
+
+	/// Expands every file of the set with the same values.
+	pub fn expand(&self, values: &Fill) -> Vec<ScaffoldOutput> {
+		self.files.iter().map(|file| ScaffoldOutput {
+			path: PathBuf::from(fill(&file.path, values)),
+			contents: fill(&file.contents, values)
+		}).collect()
+	}
+
+	/// Expands the set and writes the files under the directory, creating directories as needed.
+	/// Nothing is written when an expanded path is empty, absolute or leaves the directory.
+	pub fn write(&self, root: impl AsRef<Path>, values: &Fill) -> io::Result<Vec<PathBuf>> {
+		let outputs = self.expand(values);
+		for output in &outputs {
+			let contained = output.path.components().all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+			if !contained || output.path.as_os_str().is_empty() {
+				return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("scaffold path {} is outside of the target directory", output.path.display())));
+			}
+		}
+		let root = root.as_ref();
+		let mut written = Vec::new();
+		for output in outputs {
+			let path = root.join(&output.path);
+			if let Some(parent) = path.parent() {
+				fs::create_dir_all(parent)?;
+			}
+			fs::write(&path, output.contents)?;
+			written.push(path);
+		}
+		Ok(written)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mustache::from_template;
+	use std::env;
+
+	fn set() -> ScaffoldSet {
+		let mut set = ScaffoldSet::new("crate");
+		set.add(&from_template("{{name}}/Cargo.toml").output, &from_template("[package]\nname = \"{{name}}\"\nversion = \"{{version}}\"\n").output);
+		set.add(&from_template("{{name}}/src/lib.rs").output, &from_template("//! {{description}}\n").output);
+		set
+	}
+
+	#[test]
+	fn expand_with_shared_values() {
+		let values: Fill = [("name", "demo"), ("version", "0.1.0")].into_iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+		let outputs = set().expand(&values);
+		assert_eq!(outputs[0].path, PathBuf::from("demo/Cargo.toml"));
+		assert_eq!(outputs[0].contents, "[package]\nname = \"demo\"\nversion = \"0.1.0\"\n");
+		assert_eq!(outputs[1], ScaffoldOutput { path: PathBuf::from("demo/src/lib.rs"), contents: String::from("//! description\n") });
+
+		let root = env::temp_dir().join(format!("snippet-parse-scaffold-{}", std::process::id()));
+		let written = set().write(&root, &values).unwrap();
+		assert_eq!(fs::read_to_string(&written[1]).unwrap(), "//! description\n");
+		let escaping: Fill = [(String::from("name"), String::from("../x"))].into_iter().collect();
+		assert_eq!(set().write(&root, &escaping).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+		fs::remove_dir_all(&root).unwrap();
+	}
+}
@@ -0,0 +1,26 @@
+//! Pointers to the parts of snippets shared between their segments, tabs and expansions:
+//! those of [`std::rc`], or with the `sync` feature those of [`std::sync`] (as `Rc`), so snippets are Send and Sync
+//! and can be handed between the threads of a language server.
+
+#[cfg(not(feature = "sync"))]
+pub use std::rc::{Rc, Weak};
+#[cfg(feature = "sync")]
+pub use std::sync::{Arc as Rc, Weak};
+
+#[cfg(all(test, feature = "sync"))]
+mod tests {
+	use crate::Snippet;
+	use crate::library::SnippetLibrary;
+	use super::Rc;
+
+	fn shareable<T: Send + Sync>() {}
+
+	#[test]
+	fn share_between_threads() {
+		shareable::<Snippet>();
+		shareable::<SnippetLibrary>();
+		let snippet = Rc::new(Snippet::parse("${1:a} $1").unwrap());
+		let shared = snippet.clone();
+		assert_eq!(std::thread::spawn(move || shared.to_string()).join().unwrap(), "a a");
+	}
+}
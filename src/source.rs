@@ -0,0 +1,305 @@
+//! Writing snippets back out in snippet syntax, such as to normalise or rewrite snippet definitions.
+
+use std::fmt;
+use crate::shared::Rc;
+use crate::{Snippet, Segment, Field, NamedSegment, Transformation, Code};
+use crate::parse::SnippetSyntax;
+
+/// Part of a snippet that the syntax has no way of writing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceError {
+	pub message: &'static str
+}
+
+impl fmt::Display for SourceError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.message)
+	}
+}
+
+impl std::error::Error for SourceError {}
+
+fn unrepresentable<T>(message: &'static str) -> Result<T, SourceError> {
+	Err(SourceError { message })
+}
+
+impl Snippet {
+	/// The snippet in the LSP snippet syntax, see [`Snippet::to_source_with`].
+	pub fn to_source(&self) -> Result<String, SourceError> {
+		self.to_source_with(SnippetSyntax::Lsp)
+	}
+
+	/// The snippet written in the syntax, so that parsing it gives a snippet with the same structure
+	/// (and the same [`Snippet::content_hash`] when this snippet was itself parsed).
+	/// Named segments are defined where they first appear and written as their name after.
+	/// Text filled into fields and the values of variables are written as their defaults. Results of transformations,
+	/// output of code and labels of tabs are not part of the syntax and are left out.
+	/// Fails for what the syntax can not express: conditionals, number, toggle and repeat fields, nested snippets,
+	/// choices with anything other than the first option chosen, code in LSP syntax and variables other than VISUAL in UltiSnips syntax.
+	pub fn to_source_with(&self, syntax: SnippetSyntax) -> Result<String, SourceError> {
+		let mut writer = SourceWriter { snippet: self, syntax, source: String::new(), written: Vec::new(), written_named: Vec::new() };
+		writer.segments(&self.body)?;
+		Ok(writer.source)
+	}
+}
+
+struct SourceWriter<'a> {
+	snippet: &'a Snippet,
+	syntax: SnippetSyntax,
+	source: String,
+	/// Fields already written in full, later occurrences being written as mirrors.
+	written: Vec<*const Field>,
+	/// Named segments already defined, later occurrences being written as their name.
+	written_named: Vec<*const ()>
+}
+
+impl SourceWriter<'_> {
+	fn text(&mut self, text: &str, special: &[char]) {
+		for c in text.chars() {
+			if special.contains(&c) || (c == '`' && self.syntax == SnippetSyntax::UltiSnips) {
+				self.source.push('\\');
+			}
+			self.source.push(c);
+		}
+	}
+
+	fn segments(&mut self, segments: &[Segment]) -> Result<(), SourceError> {
+		for segment in segments {
+			match segment {
+				Segment::Text(text) => self.text(text, &['$', '}', '\\']),
+				Segment::Field(field) => self.field(field)?,
+				Segment::Variable(variable) => {
+					if self.syntax == SnippetSyntax::UltiSnips && variable.name != "VISUAL" {
+						return unrepresentable("UltiSnips syntax has no variables other than VISUAL")
+					}
+					self.source.push_str("${");
+					self.source.push_str(&variable.name);
+					// Variables without a default show their name until resolved.
+					let unresolved = variable.value.is_empty() || variable.value == variable.name;
+					if !unresolved || variable.default.is_some() {
+						self.source.push(':');
+						self.text(&variable.value, &['$', '}', '\\']);
+					}
+					self.source.push('}');
+				},
+				Segment::Transformation(transformation) => {
+					let ptr = Rc::as_ptr(transformation);
+					let names: Vec<&str> = self.snippet.named_segments.iter().filter_map(|named| match named {
+						NamedSegment::Transformation(name, of) if of.as_ptr() == ptr => Some(name.as_str()),
+						_ => None
+					}).collect();
+					// Transformations of variables are also listed under the name of the variable, ahead of any name they were given.
+					let of_tab = self.snippet.tabs.iter().any(|tab| tab.transformations.iter().any(|of| of.as_ptr() == ptr));
+					let name = names.get(if of_tab { 0 } else { 1 }).copied();
+					self.named(ptr as *const (), name, |writer| writer.transformation(transformation))?;
+				},
+				Segment::Code(code) => {
+					let ptr = Rc::as_ptr(code);
+					let name = self.snippet.named_segments.iter().find_map(|named| match named {
+						NamedSegment::Code(name, of) if of.as_ptr() == ptr => Some(name.as_str()),
+						_ => None
+					});
+					self.named(ptr as *const (), name, |writer| writer.code(code))?;
+				},
+				Segment::Conditional(_) => return unrepresentable("snippet syntax has no conditionals"),
+				Segment::Snippet(_) => return unrepresentable("snippet syntax has no nested snippets")
+			}
+		}
+		Ok(())
+	}
+
+	/// Writes the segment as the named segment when it was given a name, defining it where it first appears.
+	fn named(&mut self, ptr: *const (), name: Option<&str>, write: impl FnOnce(&mut Self) -> Result<(), SourceError>) -> Result<(), SourceError> {
+		let Some(name) = name.filter(|name| name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')) else {
+			return write(self)
+		};
+		if self.written_named.contains(&ptr) {
+			self.source.push_str(&format!("${{{}}}", name));
+			return Ok(())
+		}
+		self.written_named.push(ptr);
+		self.source.push_str(&format!("${{{}=", name));
+		write(self)?;
+		self.source.push('}');
+		Ok(())
+	}
+
+	fn code(&mut self, code: &Code) -> Result<(), SourceError> {
+		if self.syntax == SnippetSyntax::Lsp {
+			return unrepresentable("LSP syntax has no code")
+		}
+		let escaped = code.code.replace('`', "\\`");
+		match code.shebang.as_str() {
+			"#!/usr/bin/env python3" => self.source.push_str(&format!("`!p {}`", escaped)),
+			"#!/bin/sh" | "" if !code.code.starts_with("!p") => self.source.push_str(&format!("`{}`", escaped)),
+			_ => return unrepresentable("UltiSnips syntax only has shell and python code")
+		}
+		Ok(())
+	}
+
+	fn field(&mut self, field: &Rc<Field>) -> Result<(), SourceError> {
+		let ptr = Rc::as_ptr(field);
+		let Some(tab) = self.snippet.tabs.iter().find(|tab| tab.field.as_ptr() == ptr) else {
+			return unrepresentable("field has no tab")
+		};
+		if self.written.contains(&ptr) {
+			self.source.push_str(&format!("${{{}}}", tab.num));
+			return Ok(())
+		}
+		self.written.push(ptr);
+		match &**field {
+			Field::Placeholder(body) if body.is_empty() => self.source.push_str(&format!("${{{}}}", tab.num)),
+			Field::Placeholder(body) => {
+				self.source.push_str(&format!("${{{}:", tab.num));
+				self.segments(body)?;
+				self.source.push('}');
+			},
+			Field::Choice(0, options, _) => {
+				self.source.push_str(&format!("${{{}|", tab.num));
+				for (i, option) in options.iter().enumerate() {
+					if i > 0 {
+						self.source.push(',');
+					}
+					match &option[..] {
+						[] => {},
+						[Segment::Text(text)] => self.text(text, &['$', '}', '\\', ',', '|']),
+						_ => return unrepresentable("snippet syntax only has choices of plain text")
+					}
+				}
+				self.source.push_str("|}");
+			},
+			Field::Choice(..) => return unrepresentable("snippet syntax always chooses the first option"),
+			Field::Number(_) | Field::Toggle(..) | Field::Repeat(_) => return unrepresentable("snippet syntax only has placeholders and choices")
+		}
+		Ok(())
+	}
+
+	fn transformation(&mut self, transformation: &Rc<Transformation>) -> Result<(), SourceError> {
+		let ptr = Rc::as_ptr(transformation);
+		let target = match self.snippet.tabs.iter().find(|tab| tab.transformations.iter().any(|of| of.as_ptr() == ptr)) {
+			Some(tab) => tab.num.to_string(),
+			None => match self.snippet.named_segments.iter().find_map(|named| match named {
+				NamedSegment::Transformation(name, of) if of.as_ptr() == ptr => Some(name),
+				_ => None
+			}) {
+				Some(name) => name.clone(),
+				None => return unrepresentable("transformation acts upon nothing")
+			}
+		};
+		self.source.push_str(&format!("${{{}/", target));
+		self.pattern(&transformation.section, false);
+		self.source.push('/');
+		self.pattern(&transformation.format, true);
+		self.source.push('/');
+		self.source.push_str(&transformation.flags);
+		self.source.push('}');
+		Ok(())
+	}
+
+	/// Writes the regex or format of a transformation, which keep their escapes other than that of `/`.
+	/// `${...}` groups within formats are read as a whole, so their `/` are not escaped.
+	fn pattern(&mut self, pattern: &str, format: bool) {
+		let mut chars = pattern.chars();
+		while let Some(c) = chars.next() {
+			match c {
+				'\\' => {
+					self.source.push('\\');
+					if let Some(escaped) = chars.next() {
+						self.source.push(escaped);
+					}
+				},
+				'/' => self.source.push_str("\\/"),
+				'$' if format && chars.as_str().starts_with('{') => {
+					let group = chars.as_str();
+					let len = group.find('}').map_or(group.len(), |end| end + 1);
+					self.source.push('$');
+					self.source.push_str(&group[..len]);
+					chars = group[len..].chars();
+				},
+				c => self.source.push(c)
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Xorshift, so the property test needs nothing else and fails the same way every run.
+	struct Random(u64);
+
+	impl Random {
+		fn below(&mut self, n: u64) -> u64 {
+			self.0 ^= self.0 << 13;
+			self.0 ^= self.0 >> 7;
+			self.0 ^= self.0 << 17;
+			self.0 % n
+		}
+
+		fn pick<'a>(&mut self, options: &[&'a str]) -> &'a str {
+			options[self.below(options.len() as u64) as usize]
+		}
+
+		fn source(&mut self, depth: u32, out: &mut String) {
+			for _ in 0..self.below(6) {
+				match self.below(if depth > 2 { 3 } else { 9 }) {
+					0 | 1 => out.push_str(self.pick(&["a", " ", "$", "}", "\\", "\\$", "\\}", "\\\\", "{", ",", "|", "/", "`", "é", "\n", "$$", "1"])),
+					2 => {
+						let num = self.below(4);
+						out.push_str(&if self.below(2) == 0 { format!("${}", num) } else { format!("${{{}}}", num) });
+					},
+					3 | 4 => {
+						out.push_str(&format!("${{{}:", self.below(4)));
+						self.source(depth + 1, out);
+						out.push('}');
+					},
+					5 => out.push_str(&format!("${{{}|{}|}}", self.below(4), self.pick(&["a,b", "\\,x,\\|", "", "$,}"]))),
+					6 => {
+						out.push_str(&format!("${{{}:", self.pick(&["TM_FILENAME", "A", "NAME"])));
+						self.source(depth + 1, out);
+						out.push('}');
+					},
+					7 => out.push_str(self.pick(&["$A", "${NAME}", "$NAME_1"])),
+					_ => out.push_str(&format!(
+						"${{{}/{}/{}/{}}}",
+						self.pick(&["1", "2", "NAME"]),
+						self.pick(&["(.*)", "a\\/b", "\\\\", "^$"]),
+						self.pick(&["${1:/upcase}", "x\\/y", "${1:+a/b}", "\\n$1", ""]),
+						self.pick(&["", "g", "gi"])
+					))
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn round_trip_source() {
+		let mut random = Random(0x2545_f491_4f6c_dd1d);
+		let mut checked = 0;
+		for _ in 0..2000 {
+			let mut source = String::new();
+			random.source(0, &mut source);
+			let Ok(snippet) = Snippet::parse(&source) else {
+				continue
+			};
+			checked += 1;
+			let written = snippet.to_source().unwrap_or_else(|error| panic!("{:?}: {}", source, error));
+			let reparsed = Snippet::parse(&written).unwrap_or_else(|error| panic!("{:?} written as {:?}: {}", source, written, error));
+			assert_eq!(snippet.content_hash(), reparsed.content_hash(), "{:?} written as {:?}", source, written);
+			assert_eq!(reparsed.to_source().unwrap(), written);
+		}
+		assert!(checked > 1000);
+
+		let ultisnips = Snippet::parse_with(SnippetSyntax::UltiSnips, "`echo \\`x\\``${1:$VISUAL} ${VISUAL} `!p snip.rv = 1`").unwrap();
+		let written = ultisnips.to_source_with(SnippetSyntax::UltiSnips).unwrap();
+		assert_eq!(written, "`echo \\`x\\``${1:${VISUAL}} ${VISUAL} `!p snip.rv = 1`");
+		assert_eq!(Snippet::parse_with(SnippetSyntax::UltiSnips, &written).unwrap().content_hash(), ultisnips.content_hash());
+		assert!(ultisnips.to_source().is_err());
+
+		let named = Snippet::parse("${1:a} ${up=${1/(.*)/${1:/upcase}/}} $up ${file=${TM_FILENAME/(.*)/$1/}}-${file}").unwrap();
+		let written = named.to_source().unwrap();
+		assert_eq!(written, "${1:a} ${up=${1/(.*)/${1:/upcase}/}} ${up} ${file=${TM_FILENAME/(.*)/$1/}}-${file}");
+		assert_eq!(Snippet::parse(&written).unwrap().content_hash(), named.content_hash());
+	}
+}
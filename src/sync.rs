@@ -0,0 +1,118 @@
+//! Comparing the libraries kept on different machines by what their definitions are made of, to plan bringing them together.
+//! Definitions are matched by their triggers (the first with the same triggers in one library with the first in the other and so on)
+//! and compared by [`SnippetDefinition::content_hash`], so where they were loaded from does not matter.
+
+use crate::library::{SnippetLibrary, SnippetDefinition};
+
+/// How to bring the changes of a remote library into a local one.
+#[derive(Debug, Default)]
+pub struct MergePlan<'a> {
+	/// Remote definitions the local library does not have, to add to it.
+	pub added: Vec<&'a SnippetDefinition>,
+	/// Local definitions removed from the remote library since the base (without changes to them locally), to remove.
+	pub removed: Vec<&'a SnippetDefinition>,
+	/// Local definitions changed remotely since the base (without changes to them locally), along with what to replace them with.
+	pub modified: Vec<(&'a SnippetDefinition, &'a SnippetDefinition)>,
+	/// Definitions changed differently in the two libraries, for the user to choose between.
+	pub conflicts: Vec<Conflict<'a>>
+}
+
+/// The two sides of a definition changed in both libraries. A side is None when its library no longer has the definition.
+#[derive(Debug)]
+pub struct Conflict<'a> {
+	pub local: Option<&'a SnippetDefinition>,
+	pub remote: Option<&'a SnippetDefinition>
+}
+
+impl MergePlan<'_> {
+	/// Whether the local library already has every change of the remote one.
+	pub fn is_empty(&self) -> bool {
+		self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty() && self.conflicts.is_empty()
+	}
+}
+
+/// Definitions of a library along with how many definitions with the same triggers come before each, and their hashes.
+fn keyed(library: &SnippetLibrary) -> Vec<(usize, u64, &SnippetDefinition)> {
+	let definitions = library.definitions();
+	definitions.iter().enumerate().map(|(index, definition)| {
+		let occurrence = definitions[..index].iter().filter(|before| before.triggers == definition.triggers).count();
+		(occurrence, definition.content_hash(), definition)
+	}).collect()
+}
+
+fn find<'a>(keyed: &[(usize, u64, &'a SnippetDefinition)], key: (&[String], usize)) -> Option<(u64, &'a SnippetDefinition)> {
+	keyed.iter()
+		.find(|(occurrence, _, definition)| (definition.triggers.as_slice(), *occurrence) == key)
+		.map(|(_, hash, definition)| (*hash, *definition))
+}
+
+/// Plans bringing the changes of the remote library into the local one.
+/// With the base (the library both were last in step with), a definition changed in one library only is taken from it.
+/// Without it, nothing is known to be removed and definitions differing between the libraries are all conflicts.
+pub fn merge_plan<'a>(base: Option<&SnippetLibrary>, local: &'a SnippetLibrary, remote: &'a SnippetLibrary) -> MergePlan<'a> {
+	let base = base.map(keyed);
+	let local = keyed(local);
+	let remote = keyed(remote);
+	let mut plan = MergePlan::default();
+	let keys = local.iter().chain(&remote).map(|(occurrence, _, definition)| (definition.triggers.as_slice(), *occurrence));
+	let mut seen: Vec<(&[String], usize)> = Vec::new();
+	for key in keys {
+		if seen.contains(&key) {
+			continue
+		}
+		seen.push(key);
+		let local = find(&local, key);
+		let remote = find(&remote, key);
+		if local.map(|(hash, _)| hash) == remote.map(|(hash, _)| hash) {
+			continue
+		}
+		let base_hash = base.as_ref().map(|base| find(base, key).map(|(hash, _)| hash));
+		match (base_hash, local, remote) {
+			// Only changed locally.
+			(Some(base_hash), _, _) if base_hash == remote.map(|(hash, _)| hash) => {},
+			(Some(base_hash), None, Some((_, remote))) if base_hash.is_none() => plan.added.push(remote),
+			(Some(base_hash), Some((hash, local)), None) if base_hash == Some(hash) => plan.removed.push(local),
+			(Some(base_hash), Some((hash, local)), Some((_, remote))) if base_hash == Some(hash) => plan.modified.push((local, remote)),
+			(None, None, Some((_, remote))) => plan.added.push(remote),
+			// Without a base, definitions only in the local library are taken to be new there.
+			(None, Some(_), None) => {},
+			(_, local, remote) => plan.conflicts.push(Conflict {
+				local: local.map(|(_, definition)| definition),
+				remote: remote.map(|(_, definition)| definition)
+			})
+		}
+	}
+	plan
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::Snippet;
+
+	fn library(definitions: &[(&str, &str)]) -> SnippetLibrary {
+		definitions.iter()
+			.map(|(trigger, body)| SnippetDefinition::new(vec![trigger.to_string()], None, Snippet::parse(body).unwrap()))
+			.collect()
+	}
+
+	#[test]
+	fn plan_merge() {
+		let base = library(&[("a", "$1"), ("b", "b"), ("c", "c"), ("d", "d"), ("e", "e")]);
+		let local = library(&[("a", "${1}"), ("b", "b"), ("c", "c"), ("d", "local d"), ("e", "local e")]);
+		let remote = library(&[("a", "$1"), ("b", "remote b"), ("d", "d"), ("e", "remote e"), ("f", "f")]);
+		let plan = merge_plan(Some(&base), &local, &remote);
+		let triggers = |definitions: &[&SnippetDefinition]| definitions.iter().map(|definition| definition.triggers[0].clone()).collect::<Vec<_>>();
+		assert_eq!(triggers(&plan.added), ["f"]);
+		assert_eq!(triggers(&plan.removed), ["c"]);
+		assert_eq!(plan.modified.iter().map(|(_, remote)| remote.kind.to_string()).collect::<Vec<_>>(), ["remote b"]);
+		assert_eq!(plan.conflicts.len(), 1);
+		assert_eq!(plan.conflicts[0].local.unwrap().kind.to_string(), "local e");
+
+		let plan = merge_plan(None, &local, &remote);
+		assert_eq!(triggers(&plan.added), ["f"]);
+		assert!(plan.removed.is_empty() && plan.modified.is_empty());
+		assert_eq!(plan.conflicts.len(), 3);
+		assert!(merge_plan(None, &remote, &remote).is_empty());
+	}
+}
@@ -0,0 +1,149 @@
+use crate::shared::Rc;
+use crate::{Snippet, Segment, Field};
+
+/// A snippet flattened into literal text and slots, so that it can be filled in many times over
+/// by looking up slot values and concatenating, without walking the segment tree again.
+#[derive(Debug, Clone)]
+pub struct CompiledTemplate {
+	parts: Vec<Part>,
+	slots: Vec<Slot>,
+	/// Bytes of literal text, used to size the filled in string up front.
+	literal_len: usize
+}
+
+#[derive(Debug, Clone)]
+enum Part {
+	Literal(String),
+	/// Index into the slots.
+	Slot(usize)
+}
+
+/// A part of a compiled template that is filled in on every use.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Slot {
+	/// What fills in the slot.
+	pub key: SlotKey,
+	/// Text used when no value is given for the slot: the field's current text or the variable's value.
+	pub default: String
+}
+
+/// What a slot of a compiled template stands for.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SlotKey {
+	/// The field selected by the tab with this number (every mirror of it).
+	Tab(u8),
+	/// The variable with this name.
+	Variable(String)
+}
+
+impl Snippet {
+	/// Flattens the snippet into a template for bulk filling.
+	/// Tabbed fields and variables become slots (mirrors sharing one), everything else becomes literal text
+	/// as currently rendered, including code output and transformation results.
+	pub fn compile(&self) -> CompiledTemplate {
+		let mut template = CompiledTemplate {
+			parts: Vec::new(),
+			slots: Vec::new(),
+			literal_len: 0
+		};
+		template.add(self, &self.body);
+		template
+	}
+}
+
+impl CompiledTemplate {
+	fn literal(&mut self, text: &str) {
+		self.literal_len += text.len();
+		match self.parts.last_mut() {
+			Some(Part::Literal(literal)) => literal.push_str(text),
+			_ => self.parts.push(Part::Literal(text.to_string()))
+		}
+	}
+
+	fn slot(&mut self, key: SlotKey, default: String) {
+		let index = match self.slots.iter().position(|slot| slot.key == key) {
+			Some(index) => index,
+			None => {
+				self.slots.push(Slot { key, default });
+				self.slots.len() - 1
+			}
+		};
+		self.parts.push(Part::Slot(index));
+	}
+
+	fn add(&mut self, snippet: &Snippet, segments: &[Segment]) {
+		for segment in segments {
+			match segment {
+				Segment::Field(field) => {
+					let tab = snippet.tabs.iter().find(|tab| tab.field.upgrade().is_some_and(|tabbed| Rc::ptr_eq(&tabbed, field)));
+					match (tab, &**field) {
+						(Some(tab), _) => self.slot(SlotKey::Tab(tab.num), field.to_string()),
+						(None, Field::Placeholder(body)) => self.add(snippet, body),
+						(None, Field::Choice(choice, choices, _)) => if let Some(body) = choices.get(*choice) {
+							self.add(snippet, body);
+						},
+						(None, Field::Number(_)) => self.literal(&field.to_string()),
+						(None, Field::Toggle(on, when_on, when_off)) => self.add(snippet, if *on { when_on } else { when_off }),
+						(None, Field::Repeat(repeat)) => for (i, body) in repeat.repetitions.iter().enumerate() {
+							if i > 0 {
+								self.literal(&repeat.separator);
+							}
+							self.add(snippet, body);
+						}
+					}
+				},
+				Segment::Variable(variable) => self.slot(SlotKey::Variable(variable.name.clone()), variable.value.clone()),
+				Segment::Snippet(nested) => self.add(nested, &nested.body),
+				_ => self.literal(&segment.to_string())
+			}
+		}
+	}
+
+	/// Slots of the template, indexed as by [`CompiledTemplate::fill`].
+	pub fn slots(&self) -> &[Slot] {
+		&self.slots
+	}
+
+	/// Fills in the template with values given by slot index.
+	/// Slots beyond the values given keep their default.
+	pub fn fill(&self, values: &[&str]) -> String {
+		self.fill_with(|index, _| values.get(index).copied())
+	}
+
+	/// Fills in the template with values looked up per slot (by index and slot), slots without a value keeping their default.
+	pub fn fill_with<'a, F: FnMut(usize, &Slot) -> Option<&'a str>>(&'a self, mut lookup: F) -> String {
+		let values: Vec<&str> = self.slots.iter().enumerate()
+			.map(|(index, slot)| lookup(index, slot).unwrap_or(&slot.default))
+			.collect();
+		let mut filled = String::with_capacity(self.literal_len + values.iter().map(|value| value.len()).sum::<usize>());
+		for part in &self.parts {
+			match part {
+				Part::Literal(literal) => filled.push_str(literal),
+				Part::Slot(index) => filled.push_str(values[*index])
+			}
+		}
+		filled
+	}
+}
+
+#[cfg(all(test, feature = "formats-jetbrains"))]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn compile_and_fill() {
+		let import = crate::jetbrains::import(r#"<templateSet><template name="t" value="let $NAME$: $TYPE$ = $NAME$; // $USER$">
+<variable name="NAME" expression="&quot;x&quot;" /><variable name="TYPE" expression="" /><variable name="USER" expression="user()" />
+</template></templateSet>"#).unwrap();
+		let template = import.definitions[0].snippet().unwrap().compile();
+		let keys: Vec<_> = template.slots().iter().map(|slot| &slot.key).collect();
+		assert_eq!(keys, [&SlotKey::Tab(1), &SlotKey::Tab(2), &SlotKey::Variable(String::from("USER"))]);
+		assert_eq!(template.fill(&[]), "let x:  = x; // ");
+		assert_eq!(template.fill(&["count", "u32", "me"]), "let count: u32 = count; // me");
+		let filled = template.fill_with(|_, slot| match &slot.key {
+			SlotKey::Tab(2) => Some("i64"),
+			_ => None
+		});
+		assert_eq!(filled, "let x: i64 = x; // ");
+	}
+}
@@ -0,0 +1,570 @@
+//! Golden (snapshot) testing of snippet collections, and exercising snippets on arbitrary input.
+//!
+//! Every definition of a library is expanded with the dynamic parts (variables, code output, typed text)
+//! taken from a [`Fixture`] or replaced by deterministic stubs, so the expansions can be compared against a checked in file.
+//!
+//! Parsing, rendering and the operations of filling in a snippet never panic, whatever the input: malformed text is an error,
+//! and operations upon tabs, choices or ranges that do not exist return None, false or an error rather than indexing out of bounds.
+//! Nor do the loaders of snippet files and configuration files.
+//! [`arbitrary_source`], [`exercise`] and [`exercise_loaders`] check this, and can drive a fuzzer as well.
+
+use std::collections::HashMap;
+use std::path::Path;
+use crate::shared::{Rc, Weak};
+use std::{env, fs};
+use crate::{Snippet, Segment, Field, Condition, NamedSegment, VariableSource};
+use crate::library::{SnippetLibrary, SnippetKind};
+use crate::numbering::DuplicateTabs;
+use crate::parse::SnippetSyntax;
+use crate::config::{self, ConfigFormat};
+#[cfg(any(feature = "formats-ultisnips", feature = "formats-vscode"))]
+use crate::collection::{SnippetCollection, PartialLoad};
+
+/// Environment variable that, when set, makes [`assert_golden`] (re)write the golden file instead of comparing against it.
+pub const BLESS_VAR: &str = "SNIPPET_PARSE_BLESS";
+
+/// Stand ins for the dynamic inputs of snippets.
+#[derive(Debug, Default, Clone)]
+pub struct Fixture {
+	/// Values of variables by name. Variables not given expand to `${NAME}`.
+	pub variables: HashMap<String, String>,
+	/// Output of code blocks by their code. Code not given expands to `` `code` ``.
+	pub code_outputs: HashMap<String, String>,
+	/// Text typed into tabs by number. Tabs not given keep their default text.
+	pub tabs: HashMap<u8, String>
+}
+
+/// The expansion of one trigger of a library.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Golden {
+	pub trigger: String,
+	pub expansion: String
+}
+
+/// Expands every trigger of every definition, in library order.
+pub fn golden(library: &SnippetLibrary, fixture: &Fixture) -> Vec<Golden> {
+	let mut goldens = Vec::new();
+	for definition in library.definitions() {
+		let expansion = match &definition.kind {
+			SnippetKind::Static(text) => text.clone(),
+			SnippetKind::Dynamic(snippet) => {
+				let mut expansion = String::new();
+				expand(snippet, &snippet.body, fixture, &mut expansion);
+				expansion
+			}
+		};
+		for trigger in &definition.triggers {
+			goldens.push(Golden {
+				trigger: trigger.clone(),
+				expansion: expansion.clone()
+			});
+		}
+	}
+	goldens
+}
+
+/// All expansions as one diff friendly text: a `=== trigger` line followed by the expansion, for every trigger.
+pub fn golden_text(library: &SnippetLibrary, fixture: &Fixture) -> String {
+	let mut text = String::new();
+	for golden in golden(library, fixture) {
+		text.push_str("=== ");
+		text.push_str(&golden.trigger);
+		text.push('\n');
+		text.push_str(&golden.expansion);
+		text.push('\n');
+	}
+	text
+}
+
+/// Compares the library's golden text against the file, panicking with both texts when they differ.
+/// Writes the file instead when it does not exist yet or when the [`BLESS_VAR`] environment variable is set.
+pub fn assert_golden(library: &SnippetLibrary, fixture: &Fixture, path: impl AsRef<Path>) {
+	let path = path.as_ref();
+	let actual = golden_text(library, fixture);
+	if env::var_os(BLESS_VAR).is_some() || !path.exists() {
+		fs::write(path, &actual).unwrap_or_else(|error| panic!("could not write golden file {}: {}", path.display(), error));
+		return
+	}
+	let expected = fs::read_to_string(path).unwrap_or_else(|error| panic!("could not read golden file {}: {}", path.display(), error));
+	if expected != actual {
+		panic!("expansions differ from golden file {} (set {} to update it)\n--- expected\n{}\n--- actual\n{}", path.display(), BLESS_VAR, expected, actual);
+	}
+}
+
+fn expand(snippet: &Snippet, segments: &[Segment], fixture: &Fixture, out: &mut String) {
+	for segment in segments {
+		match segment {
+			Segment::Text(text) => out.push_str(text),
+			Segment::Variable(variable) => match fixture.variables.get(&variable.name) {
+				Some(value) => out.push_str(value),
+				None => {
+					out.push_str("${");
+					out.push_str(&variable.name);
+					out.push('}');
+				}
+			},
+			Segment::Code(code) => match fixture.code_outputs.get(&code.code) {
+				Some(output) => out.push_str(output),
+				None => {
+					out.push('`');
+					out.push_str(&code.code);
+					out.push('`');
+				}
+			},
+			Segment::Field(field) => {
+				let num = snippet.tabs.iter()
+					.find(|tab| tab.field.upgrade().is_some_and(|tabbed| Rc::ptr_eq(&tabbed, field)))
+					.map(|tab| tab.num);
+				match (num.and_then(|num| fixture.tabs.get(&num)), &**field) {
+					(Some(typed), _) => out.push_str(typed),
+					(None, Field::Placeholder(body)) => expand(snippet, body, fixture, out),
+					(None, Field::Choice(choice, choices, _)) => if let Some(body) = choices.get(*choice) {
+						expand(snippet, body, fixture, out);
+					},
+					(None, Field::Number(number)) => out.push_str(&number.value.to_string()),
+					(None, Field::Toggle(on, when_on, when_off)) => expand(snippet, if *on { when_on } else { when_off }, fixture, out),
+					(None, Field::Repeat(repeat)) => for (i, body) in repeat.repetitions.iter().enumerate() {
+						if i > 0 {
+							out.push_str(&repeat.separator);
+						}
+						expand(snippet, body, fixture, out);
+					}
+				}
+			},
+			Segment::Transformation(transformation) => out.push_str(&transformation.result),
+			Segment::Conditional(conditional) => {
+				let mut text = String::new();
+				if let Some(field) = conditional.field.upgrade() {
+					expand(snippet, &[Segment::Field(field)], fixture, &mut text);
+				}
+				let shown = if conditional.condition.holds(&text) { &conditional.then } else { &conditional.otherwise };
+				expand(snippet, shown, fixture, out);
+			},
+			Segment::Snippet(nested) => expand(nested, &nested.body, fixture, out)
+		}
+	}
+}
+
+impl Snippet {
+	/// The structure of the snippet as text for snapshot tests: a line per segment, indented by nesting,
+	/// followed by the snippet's tabs, expansions and named segments. Shared parts are given ids in the order they first appear
+	/// (fields by their tab number), later occurrences and references naming the id, so the text only changes with the structure.
+	pub fn to_test_fixture(&self) -> String {
+		let mut writer = FixtureWriter::default();
+		writer.snippet(self, 0);
+		writer.out
+	}
+}
+
+#[derive(Default)]
+struct FixtureWriter {
+	out: String,
+	ids: Vec<(*const (), String)>,
+	/// Parts given ids so far, counting the fields without a tab.
+	count: usize
+}
+
+impl FixtureWriter {
+	fn line(&mut self, depth: usize, line: &str) {
+		for _ in 0..depth {
+			self.out.push_str("  ");
+		}
+		self.out.push_str(line);
+		self.out.push('\n');
+	}
+
+	/// Id of the part, and whether it is given one now (being its first occurrence).
+	fn id<T>(&mut self, rc: &Rc<T>, name: impl FnOnce(usize) -> String) -> (String, bool) {
+		let ptr = Rc::as_ptr(rc) as *const ();
+		if let Some((_, id)) = self.ids.iter().find(|(known, _)| *known == ptr) {
+			return (id.clone(), false)
+		}
+		self.count += 1;
+		let id = name(self.count);
+		self.ids.push((ptr, id.clone()));
+		(id, true)
+	}
+
+	fn reference<T>(&self, weak: &Weak<T>) -> String {
+		let ptr = weak.as_ptr() as *const ();
+		match self.ids.iter().find(|(known, _)| *known == ptr) {
+			Some((_, id)) => id.clone(),
+			None if weak.upgrade().is_some() => String::from("outside"),
+			None => String::from("dangling")
+		}
+	}
+
+	fn references<T>(&self, weaks: &[Weak<T>]) -> String {
+		let ids: Vec<String> = weaks.iter().map(|weak| self.reference(weak)).collect();
+		format!("[{}]", ids.join(", "))
+	}
+
+	fn snippet(&mut self, snippet: &Snippet, depth: usize) {
+		self.segments(snippet, &snippet.body, depth);
+		for tab in &snippet.tabs {
+			let mut line = format!("tab {} field {} transformations {}", tab.num, self.reference(&tab.field), self.references(&tab.transformations));
+			if let Some(label) = &tab.label {
+				line.push_str(&format!(" label {:?}", label));
+			}
+			self.line(depth, &line);
+		}
+		for expansion in &snippet.variables {
+			let line = format!("variable expansion {} transformations {}", self.reference(&expansion.expansion), self.references(&expansion.transformations));
+			self.line(depth, &line);
+		}
+		for expansion in &snippet.code_expansions {
+			let line = format!("code expansion {} transformations {}", self.reference(&expansion.expansion), self.references(&expansion.transformations));
+			self.line(depth, &line);
+		}
+		for named in &snippet.named_segments {
+			let line = match named {
+				NamedSegment::Transformation(name, transformation) => format!("named transformation {:?} {}", name, self.reference(transformation)),
+				NamedSegment::Code(name, code) => format!("named code {:?} {}", name, self.reference(code))
+			};
+			self.line(depth, &line);
+		}
+		if let Some(num) = snippet.current_tab {
+			self.line(depth, &format!("current tab {}", num));
+		}
+	}
+
+	fn segments(&mut self, snippet: &Snippet, segments: &[Segment], depth: usize) {
+		for segment in segments {
+			match segment {
+				Segment::Text(text) => self.line(depth, &format!("text {:?}", text)),
+				Segment::Variable(variable) => {
+					let (id, new) = self.id(variable, |n| format!("variable{}", n));
+					if new {
+						let source = match variable.source {
+							VariableSource::Daemon => "daemon",
+							VariableSource::Client => "client"
+						};
+						self.line(depth, &format!("{} {:?} = {:?} from {}", id, variable.name, variable.value, source));
+					} else {
+						self.line(depth, &format!("{} again", id));
+					}
+				},
+				Segment::Code(code) => {
+					let (id, new) = self.id(code, |n| format!("code{}", n));
+					if new {
+						self.line(depth, &format!("{} {:?} {:?} output {:?}", id, code.shebang, code.code, code.output));
+					} else {
+						self.line(depth, &format!("{} again", id));
+					}
+				},
+				Segment::Transformation(transformation) => {
+					let (id, new) = self.id(transformation, |n| format!("transformation{}", n));
+					if new {
+						let line = format!("{} /{}/{}/{} result {:?}", id, transformation.section, transformation.format, transformation.flags, transformation.result);
+						self.line(depth, &line);
+					} else {
+						self.line(depth, &format!("{} again", id));
+					}
+				},
+				Segment::Conditional(conditional) => {
+					let (id, new) = self.id(conditional, |n| format!("conditional{}", n));
+					if !new {
+						self.line(depth, &format!("{} again", id));
+						continue
+					}
+					let condition = match &conditional.condition {
+						Condition::Empty => String::from("empty"),
+						Condition::NonEmpty => String::from("non-empty"),
+						Condition::Matches(regex) => format!("matches /{}/{}", regex.as_str(), regex.flags())
+					};
+					let line = format!("{} when {} {}", id, self.reference(&conditional.field), condition);
+					self.line(depth, &line);
+					self.line(depth + 1, "then");
+					self.segments(snippet, &conditional.then, depth + 2);
+					self.line(depth + 1, "otherwise");
+					self.segments(snippet, &conditional.otherwise, depth + 2);
+				},
+				Segment::Snippet(nested) => {
+					let (id, new) = self.id(nested, |n| format!("snippet{}", n));
+					if new {
+						self.line(depth, &id);
+						self.snippet(nested, depth + 1);
+					} else {
+						self.line(depth, &format!("{} again", id));
+					}
+				},
+				Segment::Field(field) => {
+					let num = snippet.tabs.iter().find(|tab| tab.field.as_ptr() == Rc::as_ptr(field)).map(|tab| tab.num);
+					let (id, new) = self.id(field, |n| match num {
+						Some(num) => format!("${}", num),
+						None => format!("field{}", n)
+					});
+					if !new {
+						self.line(depth, &format!("{} again", id));
+						continue
+					}
+					match &**field {
+						Field::Placeholder(body) => {
+							self.line(depth, &format!("{} placeholder", id));
+							self.segments(snippet, body, depth + 1);
+						},
+						Field::Choice(choice, choices, labels) => {
+							self.line(depth, &format!("{} choice {} labels {:?}", id, choice, labels));
+							for body in choices {
+								self.line(depth + 1, "option");
+								self.segments(snippet, body, depth + 2);
+							}
+						},
+						Field::Number(number) => {
+							let line = format!("{} number {} min {:?} max {:?} step {}", id, number.value, number.min, number.max, number.step);
+							self.line(depth, &line);
+						},
+						Field::Toggle(on, when_on, when_off) => {
+							self.line(depth, &format!("{} toggle {}", id, if *on { "on" } else { "off" }));
+							self.line(depth + 1, "on");
+							self.segments(snippet, when_on, depth + 2);
+							self.line(depth + 1, "off");
+							self.segments(snippet, when_off, depth + 2);
+						},
+						Field::Repeat(repeat) => {
+							self.line(depth, &format!("{} repeat separator {:?}", id, repeat.separator));
+							self.line(depth + 1, "template");
+							self.snippet(&repeat.template, depth + 2);
+							for body in &repeat.repetitions {
+								self.line(depth + 1, "repetition");
+								self.segments(snippet, body, depth + 2);
+							}
+						}
+					}
+				}
+			}
+		}
+	}
+}
+
+/// Pieces of snippet syntax that arbitrary sources are made of, more of them being malformed than not.
+const PIECES: &[&str] = &[
+	"$", "{", "}", ":", "|", ",", "/", "\\", "`", "0", "1", "2", "255", "256", "a", " ", "\n", "é", "(", ")", "?", "=", "[", "]", "*", "+", "^", "\u{202e}",
+	"$0", "${1:", "${2|", "|}", "${1/", "/g}", "(.*)", "${1:/upcase}", "${1:?x:y}", "$TM_FILENAME", "${TM_SELECTED_TEXT:", "${VISUAL}",
+	"${name=", "$name", "`!p ", "snip.rv = 1", "`date`", "${3/(a)|(b)/$2/}", "\\u", "${1:a${2:b}}", "${2|x,y|}",
+	"😀", "漢字", "e\u{301}", "${1:🎉}", "${2|字,n\u{303}|}", "${3/(.)/$1\u{301}/g}"
+];
+
+/// Source text made of pieces of snippet syntax chosen by the seed (the same seed always giving the same text),
+/// for checking that parsing and everything done with the snippets parsed handle whatever they are given.
+pub fn arbitrary_source(seed: u64) -> String {
+	// xorshift, so that sources are reproducible without depending on a random number generator.
+	let mut state = seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1;
+	let mut next = move || {
+		state ^= state << 13;
+		state ^= state >> 7;
+		state ^= state << 17;
+		state
+	};
+	let len = next() % 24;
+	(0..len).map(|_| PIECES[(next() % PIECES.len() as u64) as usize]).collect()
+}
+
+/// Renders the snippet in every way there is and runs every operation of filling it in (upon every tab, with choices and ranges
+/// in and out of bounds) on copies of it, panicking only when the parts of the snippet are found to disagree (see [`Snippet::verify`])
+/// or writing and reading back its state changes it.
+pub fn exercise(snippet: &Snippet) {
+	let check = |snippet: &Snippet| if let Err(inconsistency) = snippet.verify() {
+		panic!("{}: {}", inconsistency, snippet.to_test_fixture())
+	};
+	check(snippet);
+	let _ = (snippet.render(), snippet.regions(), snippet.preview(), snippet.validate(), snippet.try_render(), snippet.shared_segments());
+	let _ = (snippet.to_source_with(SnippetSyntax::Lsp), snippet.to_source_with(SnippetSyntax::UltiSnips), snippet.segments().count());
+	let mut state = Vec::new();
+	let _ = snippet.write_state(&mut state);
+	match Snippet::read_state(&state[..]) {
+		Ok(read) => assert_eq!(read.to_test_fixture(), snippet.to_test_fixture(), "state read back differs"),
+		Err(error) => panic!("state could not be read back: {}", error)
+	}
+
+	let nums: Vec<u8> = snippet.tabs().iter().map(|tab| tab.num).chain([0, 1, 255]).collect();
+	let len = snippet.body().len();
+	let mut copy = snippet.deep_clone();
+	while copy.next_tab().is_some() {}
+	while copy.prev_tab().is_some() {}
+	for &num in &nums {
+		copy.jump_to(num);
+		copy.set_choice(num, 1);
+		copy.set_choice(num, usize::MAX);
+		copy.select_next_choice(num);
+		copy.select_prev_choice(num);
+		copy.add_repetition(num);
+		copy.label_tab(num, "label");
+		let _ = copy.extract(num);
+		check(&copy);
+		let _ = copy.apply_fill_patch(&copy.fill_patch(snippet));
+		copy.set_field_text(num, "é\n$1");
+		check(&copy);
+	}
+	for range in [0..0, 0..len, len..len, len / 2..len, len..len + 1, len + 1..len] {
+		let _ = snippet.extract(range.clone());
+		let mut spliced = snippet.deep_clone();
+		spliced.try_splice(range, snippet.deep_clone());
+		check(&spliced);
+	}
+	let mut copy = snippet.deep_clone();
+	copy.concat(snippet.deep_clone());
+	copy.visit(&mut |_: &mut Segment| {});
+	for repeated in copy.repeated_text(1) {
+		let _ = copy.mirror_text(&repeated.text);
+	}
+	for duplicates in [DuplicateTabs::Keep, DuplicateTabs::Separate, DuplicateTabs::Drop] {
+		copy.deep_clone().renumber_tabs(duplicates);
+	}
+	copy.sanitize();
+	check(&copy);
+}
+
+/// Loads the text with every loader built (those of the snippet file formats and of configuration files), both as it is
+/// and as the body of a snippet of each format, exercising every snippet loaded (see [`exercise`]).
+/// Loaders refuse or leave out what they can not load, so this panics only when [`exercise`] does.
+pub fn exercise_loaders(text: &str) {
+	let quoted = crate::json::quote(text);
+	let bodies = [
+		(ConfigFormat::Yaml, text.to_string()),
+		(ConfigFormat::Yaml, format!("snippets:\n  - body: {}\n", quoted)),
+		(ConfigFormat::Toml, text.to_string()),
+		(ConfigFormat::Toml, format!("[[snippets]]\nbody = {}\n", quoted))
+	];
+	for (format, config) in bodies {
+		for body in config::embedded_bodies(&config, format, "body").into_iter().flatten() {
+			if let Ok(snippet) = Snippet::parse(&body.body) {
+				exercise(&snippet);
+			}
+		}
+	}
+	#[cfg(feature = "formats-jetbrains")]
+	{
+		let escaped = text.replace('&', "&amp;").replace('"', "&quot;").replace('<', "&lt;");
+		let xml = format!("<templateSet><template name=\"t\" value=\"{0} $A$\"><variable name=\"A\" expression=\"{0}\" defaultValue=\"{0}\"/></template></templateSet>", escaped);
+		for import in [crate::jetbrains::import(text), crate::jetbrains::import(&xml)].into_iter().flatten() {
+			import.definitions.iter().filter_map(|definition| definition.snippet()).for_each(exercise);
+		}
+	}
+	#[cfg(feature = "formats-espanso")]
+	{
+		let yaml = format!("matches:\n  - trigger: a\n    replace: {}\n", quoted);
+		for import in [crate::espanso::import(text), crate::espanso::import(&yaml)].into_iter().flatten() {
+			import.definitions.iter().filter_map(|definition| definition.snippet()).for_each(exercise);
+		}
+	}
+	#[cfg(any(feature = "formats-ultisnips", feature = "formats-vscode"))]
+	{
+		let mut loaded: Vec<PartialLoad> = Vec::new();
+		#[cfg(feature = "formats-ultisnips")]
+		{
+			let file = format!("snippet a \"d\"\n{}\nendsnippet\nsnippet b\n\t{}\n", text, text.replace('\n', "\n\t"));
+			for file in [text, &file] {
+				loaded.extend(SnippetCollection::from_snippets(file.as_bytes()));
+				loaded.extend(SnippetCollection::lazy_snippets(file.as_bytes()));
+			}
+		}
+		#[cfg(feature = "formats-vscode")]
+		{
+			let json = format!("{{\"a\": {{\"prefix\": \"a\", \"body\": {}}}}}", quoted);
+			for file in [text, &json] {
+				loaded.extend(SnippetCollection::from_vscode_json(file.as_bytes()));
+				loaded.extend(SnippetCollection::lazy_vscode_json(file.as_bytes()));
+			}
+		}
+		for load in loaded {
+			load.collection.entries().iter().filter_map(|entry| entry.snippet.get().ok()).for_each(exercise);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[cfg(feature = "formats-espanso")]
+	fn library() -> SnippetLibrary {
+		crate::espanso::import("matches:\n  - triggers: [\":a\", \":b\"]\n    replace: \"{{x}} {{c}} [{{p}}]\"\n    vars:\n      - name: c\n        type: shell\n        params:\n          cmd: date\n      - name: p\n        type: choice\n        params:\n          values: [one, two]\n  - trigger: \":s\"\n    replace: plain\n")
+			.unwrap().definitions.into_iter().collect()
+	}
+
+	#[test]
+	#[cfg(feature = "formats-espanso")]
+	fn stub_dynamic_inputs() {
+		assert_eq!(golden_text(&library(), &Fixture::default()), "=== :a\n${x} `date` [one]\n=== :b\n${x} `date` [one]\n=== :s\nplain\n");
+		let mut fixture = Fixture::default();
+		fixture.variables.insert(String::from("x"), String::from("X"));
+		fixture.code_outputs.insert(String::from("date"), String::from("today"));
+		fixture.tabs.insert(1, String::from("three"));
+		assert_eq!(golden(&library(), &fixture)[0].expansion, "X today [three]");
+	}
+
+	#[test]
+	#[cfg(feature = "formats-espanso")]
+	fn compare_against_file() {
+		let path = env::temp_dir().join(format!("snippet-parse-golden-{}.txt", std::process::id()));
+		let _ = fs::remove_file(&path);
+		assert_golden(&library(), &Fixture::default(), &path);
+		assert_golden(&library(), &Fixture::default(), &path);
+		let mut fixture = Fixture::default();
+		fixture.tabs.insert(1, String::from("changed"));
+		let mismatch = std::panic::catch_unwind(|| assert_golden(&library(), &fixture, &path));
+		fs::remove_file(&path).unwrap();
+		assert!(mismatch.is_err());
+	}
+
+	#[test]
+	fn describe_structure() {
+		let snippet = Snippet::parse("${1:a $TM_FILENAME} $1 ${1/(.*)/${1:/upcase}/} ${2|x,y|}").unwrap();
+		assert_eq!(snippet.to_test_fixture(), "\
+$1 placeholder
+  text \"a \"
+  variable2 \"TM_FILENAME\" = \"\" from client
+text \" \"
+$1 again
+text \" \"
+transformation3 /(.*)/${1:/upcase}/ result \"\"
+text \" \"
+$2 choice 0 labels []
+  option
+    text \"x\"
+  option
+    text \"y\"
+tab 1 field $1 transformations [transformation3]
+tab 2 field $2 transformations []
+variable expansion variable2 transformations []
+");
+		assert_eq!(snippet.to_test_fixture(), Snippet::parse("${1:a ${TM_FILENAME}} ${1} ${1/(.*)/${1:/upcase}/} ${2|x,y|}").unwrap().to_test_fixture());
+	}
+
+	#[test]
+	fn arbitrary_input_does_not_panic() {
+		for seed in 0..3000 {
+			let source = arbitrary_source(seed);
+			for syntax in [SnippetSyntax::Lsp, SnippetSyntax::UltiSnips] {
+				if let Ok(snippet) = Snippet::parse_with(syntax, &source) {
+					exercise(&snippet);
+				}
+			}
+			let mut state = Vec::new();
+			Snippet::parse("${1:a} ${2|b,c|}").unwrap().write_state(&mut state).unwrap();
+			let cut = seed as usize % (state.len() + 1);
+			state.truncate(cut);
+			state.extend(source.bytes());
+			if let Ok(read) = Snippet::read_state(&state[..]) {
+				exercise(&read);
+			}
+			let mut snippet = Snippet::parse("${1:a} ${2|b,c|} $1").unwrap();
+			let _ = snippet.apply_fill_patch(&source);
+			// Loading takes a while, each loader exercising each snippet loaded.
+			if seed % 4 == 0 {
+				exercise_loaders(&source);
+			}
+		}
+	}
+
+	#[test]
+	fn deep_nesting_is_limited() {
+		let error = Snippet::parse(&"${1:".repeat(5000)).unwrap_err();
+		assert!(matches!(error, crate::parse::ParseError::TooDeeplyNested(_)));
+		let nested = format!("{}x{}", "${1:".repeat(crate::parse::MAX_NESTING), "}".repeat(crate::parse::MAX_NESTING));
+		exercise(&Snippet::parse(&nested).unwrap());
+		assert!(crate::regex::Regex::new(&"(".repeat(5000)).is_err());
+		assert!(crate::regex::Regex::new("(a{1000}){1000}").is_err());
+	}
+}
@@ -0,0 +1,337 @@
+//! Reader for the string values of TOML documents, as needed to find snippet bodies embedded in configuration files.
+//! Other values are checked just enough to be skipped over.
+
+/// A string value along with the key it belongs to and the lines (starting at 1) it begins and ends on.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Entry {
+	/// Dotted path of the key, with `[index]` for each entry of an array of tables.
+	pub(crate) path: String,
+	/// Last part of the key.
+	pub(crate) key: String,
+	pub(crate) line: usize,
+	pub(crate) end: usize,
+	pub(crate) value: String
+}
+
+/// Why a document could not be read, and the line (starting at 1) where that was noticed.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Error {
+	pub(crate) line: usize,
+	pub(crate) message: &'static str
+}
+
+/// Reads every string value assigned to a key (not those inside arrays or inline tables), in document order.
+pub(crate) fn strings(text: &str) -> Result<Vec<Entry>, Error> {
+	let mut reader = Reader { text, pos: 0 };
+	let mut entries = Vec::new();
+	let mut table = String::new();
+	let mut array_counts: Vec<(String, usize)> = Vec::new();
+	loop {
+		reader.skip_trivia(true);
+		let Some(c) = reader.peek() else {
+			break
+		};
+		if c == '[' {
+			let array = reader.rest().starts_with("[[");
+			reader.pos += if array { 2 } else { 1 };
+			let name = reader.key()?.join(".");
+			reader.skip_trivia(false);
+			if !reader.rest().starts_with(if array { "]]" } else { "]" }) {
+				return Err(reader.error("unterminated table header"))
+			}
+			reader.pos += if array { 2 } else { 1 };
+			table = if array {
+				let index = match array_counts.iter_mut().find(|(counted, _)| *counted == name) {
+					Some((_, count)) => {
+						*count += 1;
+						*count - 1
+					},
+					None => {
+						array_counts.push((name.clone(), 1));
+						0
+					}
+				};
+				format!("{}[{}]", name, index)
+			} else {
+				name
+			};
+		} else {
+			let line = reader.line();
+			let key = reader.key()?;
+			reader.skip_trivia(false);
+			if reader.peek() != Some('=') {
+				return Err(reader.error("expected = after key"))
+			}
+			reader.pos += 1;
+			reader.skip_trivia(false);
+			if let Some(value) = reader.value()? {
+				let path = if table.is_empty() { key.join(".") } else { format!("{}.{}", table, key.join(".")) };
+				entries.push(Entry {
+					path,
+					key: key.last().cloned().unwrap_or_default(),
+					line,
+					end: reader.line(),
+					value
+				});
+			}
+		}
+		reader.skip_trivia(false);
+		match reader.peek() {
+			None | Some('\n') => {},
+			Some('\r') if reader.rest().starts_with("\r\n") => {},
+			_ => return Err(reader.error("expected the end of the line"))
+		}
+	}
+	Ok(entries)
+}
+
+struct Reader<'a> {
+	text: &'a str,
+	pos: usize
+}
+
+impl Reader<'_> {
+	fn rest(&self) -> &str {
+		&self.text[self.pos..]
+	}
+
+	fn peek(&self) -> Option<char> {
+		self.rest().chars().next()
+	}
+
+	fn line(&self) -> usize {
+		self.text[..self.pos].matches('\n').count() + 1
+	}
+
+	fn error(&self, message: &'static str) -> Error {
+		Error {
+			line: self.line(),
+			message
+		}
+	}
+
+	/// Skips spaces and comments, along with line breaks when `newlines` is set.
+	fn skip_trivia(&mut self, newlines: bool) {
+		while let Some(c) = self.peek() {
+			match c {
+				' ' | '\t' => self.pos += 1,
+				'\n' | '\r' if newlines => self.pos += 1,
+				'#' => self.pos += self.rest().find('\n').unwrap_or(self.rest().len()),
+				_ => break
+			}
+		}
+	}
+
+	/// Reads a possibly dotted key made up of bare and quoted parts.
+	fn key(&mut self) -> Result<Vec<String>, Error> {
+		let mut parts = Vec::new();
+		loop {
+			self.skip_trivia(false);
+			let part = match self.peek() {
+				Some('"') => self.basic_string()?,
+				Some('\'') => self.literal_string()?,
+				_ => {
+					let len = self.rest().find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-')).unwrap_or(self.rest().len());
+					if len == 0 {
+						return Err(self.error("expected a key"))
+					}
+					self.pos += len;
+					self.text[self.pos - len..self.pos].to_string()
+				}
+			};
+			parts.push(part);
+			self.skip_trivia(false);
+			if self.peek() == Some('.') {
+				self.pos += 1;
+			} else {
+				return Ok(parts)
+			}
+		}
+	}
+
+	/// Reads a value, returning it when it is a string.
+	fn value(&mut self) -> Result<Option<String>, Error> {
+		let rest = self.rest();
+		if rest.starts_with("\"\"\"") {
+			self.multiline(true).map(Some)
+		} else if rest.starts_with("'''") {
+			self.multiline(false).map(Some)
+		} else if rest.starts_with('"') {
+			self.basic_string().map(Some)
+		} else if rest.starts_with('\'') {
+			self.literal_string().map(Some)
+		} else if rest.starts_with('[') || rest.starts_with('{') {
+			self.skip_collection()?;
+			Ok(None)
+		} else {
+			let len = rest.find(['\n', '\r', '#', ',', ']', '}']).unwrap_or(rest.len());
+			if rest[..len].trim().is_empty() {
+				return Err(self.error("expected a value"))
+			}
+			self.pos += len;
+			Ok(None)
+		}
+	}
+
+	/// Skips an array or inline table, along with everything nested in it.
+	fn skip_collection(&mut self) -> Result<(), Error> {
+		let line = self.line();
+		let mut depth = 0usize;
+		while let Some(c) = self.peek() {
+			match c {
+				'[' | '{' => {
+					depth += 1;
+					self.pos += 1;
+				},
+				']' | '}' => {
+					depth -= 1;
+					self.pos += 1;
+					if depth == 0 {
+						return Ok(())
+					}
+				},
+				'"' | '\'' => {
+					self.value()?;
+				},
+				'#' => self.skip_trivia(false),
+				_ => self.pos += c.len_utf8()
+			}
+		}
+		Err(Error { line, message: "unterminated array or inline table" })
+	}
+
+	fn literal_string(&mut self) -> Result<String, Error> {
+		let rest = &self.rest()[1..];
+		let Some(len) = rest.find(['\'', '\n']).filter(|&len| rest[len..].starts_with('\'')) else {
+			return Err(self.error("unterminated literal string"))
+		};
+		let value = rest[..len].to_string();
+		self.pos += len + 2;
+		Ok(value)
+	}
+
+	fn basic_string(&mut self) -> Result<String, Error> {
+		self.pos += 1;
+		let mut value = String::new();
+		loop {
+			match self.peek() {
+				Some('"') => {
+					self.pos += 1;
+					return Ok(value)
+				},
+				Some('\\') => self.escape(&mut value)?,
+				Some('\n') | None => return Err(self.error("unterminated basic string")),
+				Some(c) => {
+					value.push(c);
+					self.pos += c.len_utf8();
+				}
+			}
+		}
+	}
+
+	/// Reads a `"""` (basic) or `'''` (literal) multi-line string, leaving out a line break right after the opening quotes.
+	fn multiline(&mut self, basic: bool) -> Result<String, Error> {
+		let line = self.line();
+		let quotes = if basic { "\"\"\"" } else { "'''" };
+		self.pos += 3;
+		if self.rest().starts_with("\r\n") {
+			self.pos += 2;
+		} else if self.rest().starts_with('\n') {
+			self.pos += 1;
+		}
+		let mut value = String::new();
+		loop {
+			if self.rest().starts_with(quotes) {
+				// Up to two quotes right before the closing ones belong to the string.
+				let extra = self.rest()[3..].chars().take(2).take_while(|&c| c == quotes.as_bytes()[0] as char).count();
+				value.push_str(&quotes[..extra]);
+				self.pos += 3 + extra;
+				return Ok(value)
+			}
+			match self.peek() {
+				Some('\\') if basic => {
+					let after = self.rest()[1..].trim_start_matches([' ', '\t']);
+					if after.starts_with('\n') || after.starts_with("\r\n") {
+						// A backslash ending a line leaves out the line break and the whitespace starting the next lines.
+						self.pos = self.text.len() - after.len();
+						self.pos += self.rest().len() - self.rest().trim_start().len();
+					} else {
+						self.escape(&mut value)?;
+					}
+				},
+				Some(c) => {
+					value.push(c);
+					self.pos += c.len_utf8();
+				},
+				None => return Err(Error { line, message: "unterminated multi-line string" })
+			}
+		}
+	}
+
+	fn escape(&mut self, value: &mut String) -> Result<(), Error> {
+		self.pos += 1;
+		let c = self.peek().ok_or_else(|| self.error("unterminated basic string"))?;
+		self.pos += c.len_utf8();
+		match c {
+			'b' => value.push('\u{8}'),
+			't' => value.push('\t'),
+			'n' => value.push('\n'),
+			'f' => value.push('\u{c}'),
+			'r' => value.push('\r'),
+			'e' => value.push('\u{1b}'),
+			'"' => value.push('"'),
+			'\\' => value.push('\\'),
+			'u' | 'U' => {
+				let len = if c == 'u' { 4 } else { 8 };
+				let digits = self.rest().get(..len).ok_or_else(|| self.error("invalid escape in basic string"))?;
+				let code = u32::from_str_radix(digits, 16).ok().and_then(char::from_u32).ok_or_else(|| self.error("invalid escape in basic string"))?;
+				value.push(code);
+				self.pos += len;
+			},
+			_ => return Err(self.error("invalid escape in basic string"))
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn read_strings() {
+		let entries = strings("title = \"a\\tb\" # comment\ncount = 3\nlist = [\"x\", [1, 2],\n  'y']\n\n[[snippet]]\nname = 'lit\\'\nbody = \"\"\"\n  indented\n    more \\\n    joined\"\"\"\"\n[[snippet]]\n\"quoted key\".raw = '''\nC:\\path'''\n").unwrap();
+		let found: Vec<_> = entries.iter().map(|entry| (entry.path.as_str(), entry.line, entry.end, entry.value.as_str())).collect();
+		assert_eq!(found, [
+			("title", 1, 1, "a\tb"),
+			("snippet[0].name", 7, 7, "lit\\"),
+			("snippet[0].body", 8, 11, "  indented\n    more joined\""),
+			("snippet[1].quoted key.raw", 13, 14, "C:\\path")
+		]);
+		assert_eq!(entries[3].key, "raw");
+	}
+
+	#[test]
+	fn report_errors() {
+		assert_eq!(strings("a = \"open\nb = 1\n").unwrap_err(), Error { line: 1, message: "unterminated basic string" });
+		assert_eq!(strings("a = 1\nb = '''\nnever closed\n").unwrap_err(), Error { line: 2, message: "unterminated multi-line string" });
+		assert_eq!(strings("a =\n").unwrap_err(), Error { line: 1, message: "expected a value" });
+		assert_eq!(strings("[table\n").unwrap_err().message, "unterminated table header");
+	}
+
+	#[test]
+	fn handle_multi_byte_text() {
+		let text = "[\"表\"]\n\"キー\" = \"π \\u00e9 🎉\"\nbody = '''\n名前 ✓'''\nemoji = \"\\U0001F389\"\n";
+		let values: Vec<_> = strings(text).unwrap().into_iter().map(|entry| (entry.path, entry.value)).collect();
+		assert_eq!(values, [
+			(String::from("表.キー"), String::from("π é 🎉")),
+			(String::from("表.body"), String::from("名前 ✓")),
+			(String::from("表.emoji"), String::from("🎉"))
+		]);
+		for (i, _) in text.char_indices() {
+			let _ = strings(&text[..i]);
+		}
+		assert!(strings("a = \"\\u00é\"\n").is_err());
+		assert_eq!(strings("[表]\n").unwrap_err().message, "expected a key");
+	}
+}
@@ -0,0 +1,450 @@
+//! Computing the results of transformations: replacing matches of the section (a regular expression) with the format.
+//!
+//! Formats follow TextMate, as extended by VSCode: `$1` or `${1}` inserts a group (0 being the whole match),
+//! `${1:/upcase}`, `/downcase`, `/capitalize`, `/camelcase` and `/pascalcase` change a group's case,
+//! `${1:+if}`, `${1:-else}` (or `${1:else}`), `${1:?if:else}` and `(?1:if:else)` insert text depending on whether the group took part,
+//! `\u` and `\l` change the case of the next character, `\U` and `\L` of everything up to `\E`, and `\n`, `\t` and `\r` are
+//! line breaks and tabs. A `\` before any other character inserts that character as it is.
+
+use std::fmt;
+use std::sync::Mutex;
+use crate::shared::Rc;
+use crate::Transformation;
+use crate::parse::MAX_NESTING;
+use crate::regex::{Regex, RegexError, Captures};
+
+/// Why a transformation could not be applied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransformError {
+	/// The section is not a valid regular expression.
+	Pattern(RegexError),
+	/// The format is not valid. Carries the byte offset within the format and a description of the problem.
+	Format(usize, &'static str),
+	/// The format inserts a group the section does not have (inserted as nothing when transforming), see [`check`].
+	/// Carries the group and the number of groups of the section.
+	UnknownGroup(usize, usize)
+}
+
+impl fmt::Display for TransformError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			TransformError::Pattern(error) => write!(f, "invalid pattern: {}", error),
+			TransformError::Format(offset, message) => write!(f, "invalid format: offset {}: {}", offset, message),
+			TransformError::UnknownGroup(group, groups) => write!(f, "format inserts group {}, which the pattern does not have (it has {})", group, groups)
+		}
+	}
+}
+
+impl std::error::Error for TransformError {}
+
+impl From<RegexError> for TransformError {
+	fn from(error: RegexError) -> Self {
+		TransformError::Pattern(error)
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Case {
+	/// `\u`
+	UpperNext,
+	/// `\l`
+	LowerNext,
+	/// `\U`
+	Upper,
+	/// `\L`
+	Lower,
+	/// `\E`
+	End
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Function {
+	Upcase,
+	Downcase,
+	Capitalize,
+	Camelcase,
+	Pascalcase
+}
+
+#[derive(Debug)]
+enum Part {
+	Text(String),
+	Group(usize),
+	Case(Case),
+	Function(usize, Function),
+	/// Group, inserted when it took part, inserted when it did not.
+	Conditional(usize, Vec<Part>, Vec<Part>)
+}
+
+/// Regex of the section of a transformation, compiled when the transformation is first applied
+/// and kept while its section and flags stay the same. Copies of a transformation share it.
+#[derive(Debug, Default)]
+pub struct CompiledSection(Mutex<Option<(String, String, Rc<Regex>)>>);
+
+impl CompiledSection {
+	/// The regex of the section with the flags, compiled unless it already was.
+	fn regex(&self, section: &str, flags: &str) -> Result<Rc<Regex>, RegexError> {
+		let mut compiled = self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+		if let Some((compiled_section, compiled_flags, regex)) = &*compiled {
+			if compiled_section == section && compiled_flags == flags {
+				return Ok(regex.clone())
+			}
+		}
+		let regex = Rc::new(Regex::with_flags(section, flags)?);
+		*compiled = Some((section.to_string(), flags.to_string(), regex.clone()));
+		Ok(regex)
+	}
+}
+
+impl Clone for CompiledSection {
+	fn clone(&self) -> Self {
+		CompiledSection(Mutex::new(self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()))
+	}
+}
+
+impl Transformation {
+	/// Computes the result of transforming the input, keeping it as the transformation's result.
+	pub fn apply(&mut self, input: &str) -> Result<&str, TransformError> {
+		self.result = self.transform(input)?;
+		Ok(&self.result)
+	}
+
+	/// Transforms the input as [`transform`] does with the transformation's section, format and flags,
+	/// compiling the section only when it or the flags changed since last transforming.
+	pub fn transform(&self, input: &str) -> Result<String, TransformError> {
+		let regex = self.compiled.regex(&self.section, &self.flags)?;
+		transform_with(input, &regex, &self.format, &self.flags)
+	}
+}
+
+/// Transforms the input, replacing the first match of the section (every match with the `g` flag) with the format,
+/// for uses outside of snippets with the same format as transformations.
+/// The `i`, `m` and `s` flags change how the section matches, see [`Regex::with_flags`].
+pub fn transform(input: &str, section: &str, format: &str, flags: &str) -> Result<String, TransformError> {
+	transform_with(input, &Regex::with_flags(section, flags)?, format, flags)
+}
+
+fn transform_with(input: &str, regex: &Regex, format: &str, flags: &str) -> Result<String, TransformError> {
+	let format = FormatParser { format, pos: 0, depth: 0 }.parts(&[])?;
+	let global = flags.contains('g');
+	let mut output = String::new();
+	let mut copied = 0;
+	let mut from = 0;
+	while let Some(captures) = regex.captures_from(input, from) {
+		let Some(found) = captures.range(0) else {
+			break
+		};
+		output.push_str(&input[copied..found.start]);
+		let mut writer = CaseWriter { output: &mut output, next: None, ongoing: None };
+		writer.parts(&format, &captures);
+		copied = found.end;
+		if !global {
+			break
+		}
+		// An empty match is followed by trying again one character along, so matching ends.
+		from = if found.is_empty() {
+			match input[found.end..].chars().next() {
+				Some(c) => found.end + c.len_utf8(),
+				None => break
+			}
+		} else {
+			found.end
+		};
+	}
+	output.push_str(&input[copied..]);
+	Ok(output)
+}
+
+/// Checks that the section and format are valid and that the format only inserts groups the section has,
+/// for catching mistakes in transformations ahead of applying them.
+pub fn check(section: &str, format: &str, flags: &str) -> Result<(), TransformError> {
+	let groups = Regex::with_flags(section, flags)?.groups();
+	let format = FormatParser { format, pos: 0, depth: 0 }.parts(&[])?;
+	match highest_group(&format) {
+		Some(group) if group > groups => Err(TransformError::UnknownGroup(group, groups)),
+		_ => Ok(())
+	}
+}
+
+fn highest_group(parts: &[Part]) -> Option<usize> {
+	parts.iter().filter_map(|part| match part {
+		Part::Group(group) | Part::Function(group, _) => Some(*group),
+		Part::Conditional(group, when, otherwise) => Some(*group).max(highest_group(when)).max(highest_group(otherwise)),
+		Part::Text(_) | Part::Case(_) => None
+	}).max()
+}
+
+struct FormatParser<'a> {
+	format: &'a str,
+	pos: usize,
+	/// Number of conditionals being read.
+	depth: usize
+}
+
+impl FormatParser<'_> {
+	fn peek(&self) -> Option<char> {
+		self.format[self.pos..].chars().next()
+	}
+
+	fn error(&self, message: &'static str) -> TransformError {
+		TransformError::Format(self.pos, message)
+	}
+
+	/// Reads parts up to one of the characters ending them (not consuming it) or the end of the format.
+	fn parts(&mut self, ends: &[char]) -> Result<Vec<Part>, TransformError> {
+		if self.depth == MAX_NESTING {
+			return Err(self.error("conditionals are nested too deeply"))
+		}
+		self.depth += 1;
+		let parts = self.parts_within(ends);
+		self.depth -= 1;
+		parts
+	}
+
+	fn parts_within(&mut self, ends: &[char]) -> Result<Vec<Part>, TransformError> {
+		let mut parts = Vec::new();
+		let mut text = String::new();
+		while let Some(c) = self.peek() {
+			if ends.contains(&c) {
+				break
+			}
+			let start = self.pos;
+			self.pos += c.len_utf8();
+			let part = match c {
+				'\\' => {
+					let Some(escaped) = self.peek() else {
+						text.push('\\');
+						continue
+					};
+					self.pos += escaped.len_utf8();
+					match escaped {
+						'u' => Part::Case(Case::UpperNext),
+						'l' => Part::Case(Case::LowerNext),
+						'U' => Part::Case(Case::Upper),
+						'L' => Part::Case(Case::Lower),
+						'E' => Part::Case(Case::End),
+						'n' => {
+							text.push('\n');
+							continue
+						},
+						't' => {
+							text.push('\t');
+							continue
+						},
+						'r' => {
+							text.push('\r');
+							continue
+						},
+						escaped => {
+							text.push(escaped);
+							continue
+						}
+					}
+				},
+				'$' => match self.dollar()? {
+					Some(part) => part,
+					None => {
+						text.push('$');
+						continue
+					}
+				},
+				'(' if self.format[self.pos..].starts_with('?') => {
+					self.pos += 1;
+					let group = self.number().ok_or(TransformError::Format(start, "expected a group number after (?"))?;
+					if self.peek() != Some(':') {
+						return Err(self.error("expected : after the group of a conditional"))
+					}
+					self.pos += 1;
+					let then = self.parts(&[':', ')'])?;
+					let otherwise = if self.peek() == Some(':') {
+						self.pos += 1;
+						self.parts(&[')'])?
+					} else {
+						Vec::new()
+					};
+					if self.peek() != Some(')') {
+						return Err(TransformError::Format(start, "conditional is not closed by )"))
+					}
+					self.pos += 1;
+					Part::Conditional(group, then, otherwise)
+				},
+				c => {
+					text.push(c);
+					continue
+				}
+			};
+			if !text.is_empty() {
+				parts.push(Part::Text(std::mem::take(&mut text)));
+			}
+			parts.push(part);
+		}
+		if !text.is_empty() {
+			parts.push(Part::Text(text));
+		}
+		Ok(parts)
+	}
+
+	fn number(&mut self) -> Option<usize> {
+		let rest = &self.format[self.pos..];
+		let len = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+		let number = rest[..len].parse().ok()?;
+		self.pos += len;
+		Some(number)
+	}
+
+	/// Reads what follows a `$`, which is text when not followed by a group.
+	fn dollar(&mut self) -> Result<Option<Part>, TransformError> {
+		let start = self.pos - 1;
+		if let Some(group) = self.number() {
+			return Ok(Some(Part::Group(group)))
+		}
+		if self.peek() != Some('{') {
+			return Ok(None)
+		}
+		self.pos += 1;
+		let group = self.number().ok_or(TransformError::Format(start, "expected a group number after ${"))?;
+		let part = match self.peek() {
+			Some('}') => Part::Group(group),
+			Some(':') => {
+				self.pos += 1;
+				match self.peek() {
+					Some('/') => {
+						let rest = &self.format[self.pos + 1..];
+						let len = rest.find('}').ok_or(TransformError::Format(start, "group is not closed by }"))?;
+						let function = match &rest[..len] {
+							"upcase" => Function::Upcase,
+							"downcase" => Function::Downcase,
+							"capitalize" => Function::Capitalize,
+							"camelcase" => Function::Camelcase,
+							"pascalcase" => Function::Pascalcase,
+							_ => return Err(self.error("unknown case function"))
+						};
+						self.pos += 1 + len;
+						Part::Function(group, function)
+					},
+					Some('+') => {
+						self.pos += 1;
+						Part::Conditional(group, self.parts(&['}'])?, Vec::new())
+					},
+					Some('?') => {
+						self.pos += 1;
+						let then = self.parts(&[':', '}'])?;
+						if self.peek() == Some(':') {
+							self.pos += 1;
+						}
+						Part::Conditional(group, then, self.parts(&['}'])?)
+					},
+					Some('-') => {
+						self.pos += 1;
+						Part::Conditional(group, vec![Part::Group(group)], self.parts(&['}'])?)
+					},
+					_ => Part::Conditional(group, vec![Part::Group(group)], self.parts(&['}'])?)
+				}
+			},
+			None => return Err(TransformError::Format(start, "group is not closed by }")),
+			_ => return Err(TransformError::Format(start, "expected } or : after the group"))
+		};
+		if self.peek() != Some('}') {
+			return Err(TransformError::Format(start, "group is not closed by }"))
+		}
+		self.pos += 1;
+		Ok(Some(part))
+	}
+}
+
+/// Writes text, changing its case as told by `\u`, `\l`, `\U`, `\L` and `\E`.
+struct CaseWriter<'a> {
+	output: &'a mut String,
+	next: Option<Case>,
+	ongoing: Option<Case>
+}
+
+impl CaseWriter<'_> {
+	fn write(&mut self, text: &str) {
+		for c in text.chars() {
+			match self.next.take().or(self.ongoing) {
+				Some(Case::UpperNext | Case::Upper) => self.output.extend(c.to_uppercase()),
+				Some(Case::LowerNext | Case::Lower) => self.output.extend(c.to_lowercase()),
+				_ => self.output.push(c)
+			}
+		}
+	}
+
+	fn parts(&mut self, parts: &[Part], captures: &Captures) {
+		for part in parts {
+			match part {
+				Part::Text(text) => self.write(text),
+				Part::Group(group) => self.write(captures.get(*group).unwrap_or("")),
+				Part::Case(case @ (Case::UpperNext | Case::LowerNext)) => self.next = Some(*case),
+				Part::Case(Case::End) => self.ongoing = None,
+				Part::Case(case) => self.ongoing = Some(*case),
+				Part::Function(group, function) => {
+					let text = captures.get(*group).unwrap_or("");
+					self.write(&apply_function(*function, text));
+				},
+				Part::Conditional(group, then, otherwise) => {
+					let took_part = captures.get(*group).is_some_and(|text| !text.is_empty());
+					self.parts(if took_part { then } else { otherwise }, captures);
+				}
+			}
+		}
+	}
+}
+
+fn apply_function(function: Function, text: &str) -> String {
+	let capitalized = |word: &str| {
+		let mut chars = word.chars();
+		chars.next().map_or_else(String::new, |first| first.to_uppercase().chain(chars).collect())
+	};
+	let words = || text.split(|c: char| !c.is_alphanumeric()).filter(|word| !word.is_empty());
+	match function {
+		Function::Upcase => text.to_uppercase(),
+		Function::Downcase => text.to_lowercase(),
+		Function::Capitalize => capitalized(text),
+		Function::Pascalcase => words().map(capitalized).collect(),
+		Function::Camelcase => words().enumerate()
+			.map(|(i, word)| if i == 0 { word.to_lowercase() } else { capitalized(word) })
+			.collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn apply_formats() {
+		assert_eq!(transform("main.rs", "(\\w+)\\.(\\w+)", "$2 of ${1}", "").unwrap(), "rs of main");
+		assert_eq!(transform("foo", "o", "0", "").unwrap(), "f0o");
+		assert_eq!(transform("foo", "O", "0", "gi").unwrap(), "f00");
+		assert_eq!(transform("a\nb", "^", "> ", "gm").unwrap(), "> a\n> b");
+		assert_eq!(transform("ab", "x*", "-", "g").unwrap(), "-a-b-");
+		assert_eq!(transform("élan", "(.*)", "\\u$1 \\Uloud\\E quiet \\L$1X\\E", "").unwrap(), "Élan LOUD quiet élanx");
+		assert_eq!(transform("my_file name", "(.*)", "${1:/upcase} ${1:/capitalize} ${1:/camelcase} ${1:/pascalcase}", "").unwrap(),
+			"MY_FILE NAME My_file name myFileName MyFileName");
+		assert_eq!(transform("b", "(a)?b", "(?1:yes:no) ${1:+plus} ${1:-dflt} ${1:?if:else} ${1:other}", "").unwrap(), "no  dflt else other");
+		assert_eq!(transform("ab", "(a)?b", "(?1:yes:no) ${1:+plus} ${1:-dflt} ${1:?if:else}", "").unwrap(), "yes plus a if");
+		assert_eq!(transform("z", "(.)", "\\$1 \\(x\\) $ \\n", "").unwrap(), "$1 (x) $ \n");
+
+		let mut transformation = Transformation::new("-", "_", "g");
+		assert_eq!(transformation.apply("a-b-c").unwrap(), "a_b_c");
+		assert_eq!(transformation.result, "a_b_c");
+		let compiled = transformation.compiled.regex("-", "g").unwrap();
+		let copy = transformation.compiled.clone();
+		assert!(Rc::ptr_eq(&compiled, &copy.regex("-", "g").unwrap()));
+		transformation.section = String::from("b");
+		assert_eq!(transformation.apply("a-b-c").unwrap(), "a-_-c");
+		assert!(!Rc::ptr_eq(&compiled, &transformation.compiled.regex("b", "g").unwrap()));
+	}
+
+	#[test]
+	fn reject_invalid_transformations() {
+		assert!(matches!(transform("x", "(", "", ""), Err(TransformError::Pattern(_))));
+		assert_eq!(transform("x", "x", "${1:/shout}", ""), Err(TransformError::Format(4, "unknown case function")));
+		assert_eq!(transform("x", "x", "a ${1", ""), Err(TransformError::Format(2, "group is not closed by }")));
+		assert_eq!(transform("x", "x", "(?1:a", ""), Err(TransformError::Format(0, "conditional is not closed by )")));
+		assert_eq!(transform("x", "x", "${x}", ""), Err(TransformError::Format(0, "expected a group number after ${")));
+		assert_eq!(check("(a)(?:b)", "$1 ${2:/upcase}", ""), Err(TransformError::UnknownGroup(2, 1)));
+		assert_eq!(check("(a)", "(?1:$0:x)", ""), Ok(()));
+	}
+}
@@ -0,0 +1,109 @@
+//! Reading UltiSnips `.snippets` files.
+
+use std::{fmt, fs, io};
+use std::path::Path;
+use crate::library::{GlobalCode, SourceLocation};
+
+/// Reasons a `.snippets` file could not be read.
+#[derive(Debug)]
+pub enum ImportError {
+	/// The file could not be read.
+	Io(io::Error),
+	/// The file is not a valid `.snippets` file. Carries the line (starting at 1) and a description of the problem.
+	Syntax(usize, &'static str)
+}
+
+impl fmt::Display for ImportError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			ImportError::Io(error) => write!(f, "{}", error),
+			ImportError::Syntax(line, message) => write!(f, "line {}: {}", line, message)
+		}
+	}
+}
+
+impl std::error::Error for ImportError {}
+
+impl From<io::Error> for ImportError {
+	fn from(error: io::Error) -> Self {
+		ImportError::Io(error)
+	}
+}
+
+/// Reads the global blocks of the `.snippets` file at the path, recording the path in their source locations.
+pub fn globals_file(path: impl AsRef<Path>) -> Result<Vec<GlobalCode>, ImportError> {
+	let path = path.as_ref();
+	let mut globals = globals(&fs::read_to_string(path)?)?;
+	for global in &mut globals {
+		if let Some(source) = &mut global.source {
+			source.path = Some(path.to_path_buf());
+		}
+	}
+	Ok(globals)
+}
+
+/// Reads the `global !p` ... `endglobal` blocks of a `.snippets` file: Python code (the lines in between) shared by the file's snippets.
+/// Lines within `snippet` ... `endsnippet` blocks are bodies rather than blocks of the file, so they are skipped.
+pub fn globals(text: &str) -> Result<Vec<GlobalCode>, ImportError> {
+	let mut globals = Vec::new();
+	let mut lines = text.lines().enumerate().map(|(index, line)| (index + 1, line.trim_end()));
+	while let Some((start_line, line)) = lines.next() {
+		let mut words = line.split_whitespace();
+		match words.next() {
+			Some("snippet") if !lines.any(|(_, line)| line == "endsnippet") => {
+				return Err(ImportError::Syntax(start_line, "snippet is not closed by endsnippet"))
+			},
+			Some("global") => {
+				if words.next() != Some("!p") {
+					return Err(ImportError::Syntax(start_line, "expected !p after global"))
+				}
+				let mut code = Vec::new();
+				let end_line = loop {
+					match lines.next() {
+						Some((end_line, "endglobal")) => break end_line,
+						Some((_, line)) => code.push(line),
+						None => return Err(ImportError::Syntax(start_line, "global block is not closed by endglobal"))
+					}
+				};
+				globals.push(GlobalCode {
+					code: code.join("\n"),
+					shebang: String::from("#!/usr/bin/env python3"),
+					source: Some(SourceLocation { path: None, start_line, end_line })
+				});
+			},
+			_ => {}
+		}
+	}
+	Ok(globals)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn read_globals() {
+		let file = "priority 1\nglobal !p\ndef greet():\n    return 'hi'\nendglobal\n\nsnippet g \"greet\"\nglobal !p\nendsnippet\n";
+		let globals = globals(file).unwrap();
+		assert_eq!(globals.len(), 1);
+		assert_eq!(globals[0].code, "def greet():\n    return 'hi'");
+		assert_eq!(globals[0].source, Some(SourceLocation { path: None, start_line: 2, end_line: 5 }));
+		assert!(matches!(super::globals("global !p\nx = 1"), Err(ImportError::Syntax(1, _))));
+		assert!(matches!(super::globals("global !v\nendglobal"), Err(ImportError::Syntax(1, _))));
+	}
+
+	#[test]
+	#[cfg(feature = "exec")]
+	fn run_globals() {
+		use crate::exec::CodeRunner;
+		use crate::library::{SnippetLibrary, SnippetDefinition};
+		use crate::{Snippet, Code};
+
+		let mut library = SnippetLibrary::new();
+		library.add(SnippetDefinition::new(vec![String::from("g")], None, Snippet::parse("x").unwrap()));
+		library.add_global(GlobalCode { code: String::from("greet() { echo hi; }"), shebang: String::from("#!/bin/sh"), source: None });
+		let runner = library.code_runner(&library.definitions()[0], &CodeRunner::default());
+		let mut code = Code { code: String::from("greet"), output: String::new(), shebang: String::from("#!/bin/sh") };
+		assert_eq!(runner.run(&mut code).unwrap(), "hi");
+	}
+}
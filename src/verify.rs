@@ -0,0 +1,122 @@
+//! Checking that the ways of locating the parts of a rendered snippet agree with each other and with its text,
+//! so that coordinate bugs show as a failed check rather than as cursors misplaced by editors.
+//! Mutations of snippets check this themselves in debug builds.
+
+use std::fmt;
+use crate::Snippet;
+
+/// The first disagreement found by [`Snippet::verify`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Inconsistency {
+	pub message: &'static str
+}
+
+impl fmt::Display for Inconsistency {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.message)
+	}
+}
+
+impl std::error::Error for Inconsistency {}
+
+fn inconsistent(message: &'static str) -> Result<(), Inconsistency> {
+	Err(Inconsistency { message })
+}
+
+impl Snippet {
+	/// Checks that the selected tab exists, and that [`Snippet::render`], [`Snippet::regions`]
+	/// and the ranges of tab stops (see [`Snippet::current_tab`]) agree with each other and with the text Display renders:
+	/// every span lies within the text on character boundaries with its UTF-16 range counted from the same text,
+	/// spans of tabs hold the text of their field, regions cover the text in order, and tab stops are the first span of their tab.
+	/// Tabs and expansions left referring to dropped parts (such as by replacing the field they were nested in) are not inconsistent.
+	pub fn verify(&self) -> Result<(), Inconsistency> {
+		if self.current_tab.is_some_and(|num| !self.tabs.iter().any(|tab| tab.num == num)) {
+			return inconsistent("selected tab does not exist")
+		}
+
+		let rendered = self.render();
+		let text = &rendered.text;
+		if *text != self.to_string() {
+			return inconsistent("rendered text differs from Display")
+		}
+		let spans = rendered.tabs.iter().map(|(_, span)| span)
+			.chain(rendered.variables.iter().map(|(_, span)| span))
+			.chain(&rendered.snippets);
+		for span in spans {
+			let Some(spanned) = text.get(span.bytes.clone()) else {
+				return inconsistent("span is not within the text on character boundaries")
+			};
+			let utf16_start = text[..span.bytes.start].encode_utf16().count();
+			if span.utf16 != (utf16_start..utf16_start + spanned.encode_utf16().count()) {
+				return inconsistent("UTF-16 range of span differs from its byte range")
+			}
+		}
+		if rendered.tabs.windows(2).any(|pair| pair[0].1.bytes.start > pair[1].1.bytes.start) {
+			return inconsistent("spans of tabs are out of order")
+		}
+		for (num, span) in &rendered.tabs {
+			let field = self.tabs.iter().find(|tab| tab.num == *num).and_then(|tab| tab.field.upgrade());
+			if field.is_none_or(|field| text[span.bytes.clone()] != field.to_string()) {
+				return inconsistent("span of tab does not hold the text of its field")
+			}
+		}
+
+		let mut end = 0;
+		for region in self.regions() {
+			if region.range.start != end || region.range.is_empty() || text.get(region.range.clone()).is_none() {
+				return inconsistent("regions do not cover the text in order")
+			}
+			end = region.range.end;
+		}
+		if end != text.len() {
+			return inconsistent("regions do not cover the text in order")
+		}
+
+		for tab in &self.tabs {
+			let first = rendered.tabs.iter().find(|(num, _)| *num == tab.num).map(|(_, span)| span.bytes.clone());
+			if self.tab_stop(tab.num).and_then(|stop| stop.range) != first {
+				return inconsistent("range of tab stop is not the first span of its tab")
+			}
+		}
+		Ok(())
+	}
+
+	/// Panics when [`Snippet::verify`] fails, in debug builds only. Called after mutations.
+	pub(crate) fn debug_verify(&self) {
+		#[cfg(debug_assertions)]
+		if let Err(inconsistency) = self.verify() {
+			panic!("snippet is inconsistent after mutation: {}", inconsistency)
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::Segment;
+
+	#[test]
+	fn verify_consistency() {
+		let mut snippet = Snippet::parse("${1:é${2:ü}} $1 ${USER:me} ${1/(.*)/${1:/upcase}/} ${3|a,b|}").unwrap();
+		assert_eq!(snippet.verify(), Ok(()));
+		snippet.set_field_text(2, "x").unwrap();
+		snippet.set_choice(3, 1).unwrap();
+		snippet.next_tab();
+		assert_eq!(snippet.verify(), Ok(()));
+
+		snippet.set_field_text(1, "outer").unwrap();
+		assert_eq!(snippet.verify(), Ok(()));
+
+		let unselectable = Snippet {
+			body: vec![Segment::Text(String::from("a"))],
+			tabs: Vec::new(),
+			variables: Vec::new(),
+			code_expansions: Vec::new(),
+			named_segments: Vec::new(),
+			current_tab: Some(2),
+			choice_sources: Vec::new(),
+			baseline: Default::default()
+		};
+		assert_eq!(unselectable.verify().unwrap_err().message, "selected tab does not exist");
+	}
+}
@@ -0,0 +1,205 @@
+//! Walking the segments of a snippet depth first, to read them without matching on every kind of segment that holds others,
+//! or to change them in a pass over the whole snippet.
+
+use std::slice;
+use crate::shared::Rc;
+use crate::{Snippet, Segment, Field, RepeatField, Conditional};
+use crate::compose::{Replacer, shared_body};
+
+/// Changes segments one at a time, see [`Snippet::visit`].
+pub trait SegmentVisitor {
+	/// Called with each segment before the segments within it, which are those of the segment it is changed into.
+	fn visit(&mut self, segment: &mut Segment);
+}
+
+impl<F: FnMut(&mut Segment)> SegmentVisitor for F {
+	fn visit(&mut self, segment: &mut Segment) {
+		self(segment)
+	}
+}
+
+/// Segments within the segment, in the order they are shown: those of every choice of a choice field,
+/// both blocks of toggles and conditionals, and the repetitions (not the template) of repeated groups.
+fn within(segment: &Segment) -> Vec<&[Segment]> {
+	match segment {
+		Segment::Field(field) => match &**field {
+			Field::Placeholder(body) => vec![body],
+			Field::Choice(_, choices, _) => choices.iter().map(Vec::as_slice).collect(),
+			Field::Number(_) => Vec::new(),
+			Field::Toggle(_, on, off) => vec![on, off],
+			Field::Repeat(repeat) => repeat.repetitions.iter().map(Vec::as_slice).collect()
+		},
+		Segment::Conditional(conditional) => vec![&conditional.then, &conditional.otherwise],
+		Segment::Snippet(nested) => vec![&nested.body],
+		_ => Vec::new()
+	}
+}
+
+/// Iterator over the segments of a snippet depth first, see [`Snippet::segments`].
+pub struct Segments<'a> {
+	stack: Vec<slice::Iter<'a, Segment>>
+}
+
+impl<'a> Segments<'a> {
+	/// The segments depth first, each before the segments within it.
+	pub(crate) fn of(segments: &'a [Segment]) -> Self {
+		Segments { stack: vec![segments.iter()] }
+	}
+}
+
+impl<'a> Iterator for Segments<'a> {
+	type Item = &'a Segment;
+
+	fn next(&mut self) -> Option<&'a Segment> {
+		loop {
+			let Some(segment) = self.stack.last_mut()?.next() else {
+				self.stack.pop();
+				continue
+			};
+			self.stack.extend(within(segment).into_iter().rev().map(|segments| segments.iter()));
+			return Some(segment)
+		}
+	}
+}
+
+impl Snippet {
+	/// Every segment of the snippet depth first, each before the segments within it (see [`SegmentVisitor::visit`] for which those are).
+	/// Segments shared by several occurrences, such as mirrored fields, come up at each of them.
+	pub fn segments(&self) -> Segments<'_> {
+		Segments { stack: vec![self.body.iter()] }
+	}
+
+	/// The segments of the body itself, the only ones the snippet owns. Those within fields, conditionals and nested snippets are shared
+	/// (see [`crate::shared`]) and are changed through [`Snippet::visit`]. Tabs and expansions of segments replaced through this are left dangling.
+	pub fn segments_mut(&mut self) -> impl Iterator<Item = &mut Segment> {
+		self.body.iter_mut()
+	}
+
+	/// Lets the visitor change every segment of the snippet depth first, as [`Snippet::segments`] lists them,
+	/// except that segments within shared parts are visited once rather than at every occurrence.
+	/// Whatever holds a segment is rebuilt around it, tabs and expansions following, and references to the parts replaced are dropped.
+	/// Transformations are not applied again to changed text.
+	pub fn visit<V: SegmentVisitor + ?Sized>(&mut self, visitor: &mut V) {
+		let mut visiting = Visiting { visitor, replacer: Replacer::default(), snippets: Vec::new() };
+		let mut body = shared_body(&self.body);
+		visiting.segments(&mut body);
+		self.body = body;
+		// Conditionals built before the field they test was rebuilt still test the original.
+		visiting.replacer.snippet(self);
+		drop(visiting);
+		self.prune();
+		self.debug_verify();
+	}
+}
+
+struct Visiting<'v, V: ?Sized> {
+	visitor: &'v mut V,
+	/// Fields rebuilt so far, by the original.
+	replacer: Replacer,
+	snippets: Vec<(*const Snippet, Rc<Snippet>)>
+}
+
+impl<V: SegmentVisitor + ?Sized> Visiting<'_, V> {
+	fn segments(&mut self, segments: &mut [Segment]) {
+		for segment in segments {
+			self.visitor.visit(segment);
+			match segment {
+				Segment::Field(field) => *field = self.field(field),
+				Segment::Conditional(conditional) => *conditional = self.conditional(conditional),
+				Segment::Snippet(nested) => *nested = self.snippet(nested),
+				_ => {}
+			}
+		}
+	}
+
+	fn body(&mut self, segments: &[Segment]) -> Vec<Segment> {
+		let mut body = shared_body(segments);
+		self.segments(&mut body);
+		body
+	}
+
+	fn field(&mut self, field: &Rc<Field>) -> Rc<Field> {
+		if let Some((_, rebuilt)) = self.replacer.fields.iter().find(|(ptr, _)| *ptr == Rc::as_ptr(field)) {
+			return rebuilt.clone()
+		}
+		let rebuilt = Rc::new(match &**field {
+			Field::Placeholder(body) => Field::Placeholder(self.body(body)),
+			Field::Choice(choice, choices, labels) => Field::Choice(*choice, choices.iter().map(|body| self.body(body)).collect(), labels.clone()),
+			Field::Number(number) => Field::Number(number.clone()),
+			Field::Toggle(on, when_on, when_off) => Field::Toggle(*on, self.body(when_on), self.body(when_off)),
+			Field::Repeat(repeat) => Field::Repeat(RepeatField {
+				template: repeat.template.clone(),
+				separator: repeat.separator.clone(),
+				repetitions: repeat.repetitions.iter().map(|body| self.body(body)).collect()
+			})
+		});
+		self.replacer.fields.push((Rc::as_ptr(field), rebuilt.clone()));
+		rebuilt
+	}
+
+	fn conditional(&mut self, conditional: &Rc<Conditional>) -> Rc<Conditional> {
+		let field = conditional.field.upgrade()
+			.and_then(|field| self.replacer.fields.iter().find(|(ptr, _)| *ptr == Rc::as_ptr(&field)).map(|(_, rebuilt)| Rc::downgrade(rebuilt)));
+		Rc::new(Conditional {
+			field: field.unwrap_or_else(|| conditional.field.clone()),
+			condition: conditional.condition.clone(),
+			then: self.body(&conditional.then),
+			otherwise: self.body(&conditional.otherwise)
+		})
+	}
+
+	fn snippet(&mut self, nested: &Rc<Snippet>) -> Rc<Snippet> {
+		if let Some((_, rebuilt)) = self.snippets.iter().find(|(ptr, _)| *ptr == Rc::as_ptr(nested)) {
+			return rebuilt.clone()
+		}
+		let mut rebuilt = nested.deep_clone();
+		rebuilt.visit(&mut *self.visitor);
+		let rebuilt = Rc::new(rebuilt);
+		self.snippets.push((Rc::as_ptr(nested), rebuilt.clone()));
+		rebuilt
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn walk_segments() {
+		let snippet = Snippet::parse("a${1:b${2|c,d|}} $1 ${3:e}").unwrap();
+		let texts: Vec<&str> = snippet.segments().filter_map(|segment| match segment {
+			Segment::Text(text) => Some(text.as_str()),
+			_ => None
+		}).collect();
+		assert_eq!(texts, ["a", "b", "c", "d", " ", "b", "c", "d", " ", "e"]);
+		assert_eq!(snippet.segments().filter(|segment| matches!(segment, Segment::Field(_))).count(), 5);
+	}
+
+	#[test]
+	fn visit_segments() {
+		let mut snippet = Snippet::parse("a ${1:b ${2:c}} $1 ${3:d}").unwrap();
+		let mut visited = 0;
+		snippet.visit(&mut |segment: &mut Segment| {
+			visited += 1;
+			if let Segment::Text(text) = segment {
+				*text = text.to_uppercase();
+			}
+		});
+		assert_eq!(visited, 10);
+		assert_eq!(snippet.to_string(), "A B C B C D");
+		snippet.set_field_text(2, "x").unwrap();
+		assert_eq!(snippet.to_string(), "A B x B x D");
+
+		snippet.visit(&mut |segment: &mut Segment| if matches!(segment, Segment::Field(field) if field.to_string() == "D") {
+			*segment = Segment::Text(String::from("gone"));
+		});
+		assert_eq!(snippet.to_string(), "A B x B x gone");
+		assert_eq!(snippet.tabs().iter().map(|tab| tab.num).collect::<Vec<_>>(), [2, 1]);
+		for segment in snippet.segments_mut() {
+			if let Segment::Text(text) = segment {
+				text.push('.');
+			}
+		}
+		assert_eq!(snippet.to_string(), "A .B x .B x .gone.");
+	}
+}
@@ -0,0 +1,49 @@
+use std::fmt;
+use crate::Snippet;
+
+/// A quality issue found while loading a snippet that does not prevent it from being used.
+#[derive(Debug, PartialEq)]
+pub struct Warning {
+	/// First trigger of the definition the issue was found in.
+	pub trigger: String,
+	/// Line (starting at 1) of the loaded source the definition starts on.
+	pub line: usize,
+	/// What the issue is.
+	pub kind: WarningKind
+}
+
+/// Kinds of issues reported by warnings.
+#[derive(Debug, PartialEq)]
+pub enum WarningKind {
+	/// Syntax that is still understood but has been superseded. Carries the deprecated construct.
+	DeprecatedSyntax(String),
+	/// A variable is referenced that is neither declared nor known. Carries the variable name.
+	UnknownVariable(String),
+	/// Text that looks like the start of a construct but is taken literally. Carries that text.
+	SuspiciousEscape(String),
+	/// Tab numbers skip over a number. Carries the first number skipped.
+	TabNumberGap(u8),
+	/// Every tab number is taken, so a field is not a tab. Carries the name of the field.
+	TooManyTabs(String)
+}
+
+impl fmt::Display for Warning {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "line {} ({}): ", self.line, self.trigger)?;
+		match &self.kind {
+			WarningKind::DeprecatedSyntax(syntax) => write!(f, "{} is deprecated", syntax),
+			WarningKind::UnknownVariable(name) => write!(f, "unknown variable {}", name),
+			WarningKind::SuspiciousEscape(text) => write!(f, "{} is taken literally", text),
+			WarningKind::TabNumberGap(num) => write!(f, "tab number {} is skipped", num),
+			WarningKind::TooManyTabs(name) => write!(f, "{} is not a tab, every tab number being taken", name)
+		}
+	}
+}
+
+/// First tab number (counting from 1, tab 0 being the final one) that the snippet's tabs skip over.
+pub(crate) fn tab_number_gap(snippet: &Snippet) -> Option<u8> {
+	let mut nums: Vec<u8> = snippet.tabs.iter().map(|tab| tab.num).filter(|num| *num != 0).collect();
+	nums.sort_unstable();
+	nums.dedup();
+	nums.iter().zip(1..=u8::MAX).find(|(num, expected)| **num != *expected).map(|(_, expected)| expected)
+}
@@ -0,0 +1,416 @@
+//! Reader for the subset of YAML used by snippet and text expander configuration files:
+//! block mappings and sequences, plain, quoted and block (`|`, `>`) scalars and single line flow collections.
+
+use crate::parse::MAX_NESTING;
+
+/// A value read from a YAML document along with the lines (starting at 1) it begins and ends on.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Node {
+	pub(crate) line: usize,
+	pub(crate) end: usize,
+	pub(crate) value: Value
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum Value {
+	Scalar(String),
+	Sequence(Vec<Node>),
+	Mapping(Vec<(String, Node)>)
+}
+
+/// Why a document could not be read, and the line (starting at 1) where that was noticed.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Error {
+	pub(crate) line: usize,
+	pub(crate) message: &'static str
+}
+
+// Reading the nodes is only needed for espanso files, configuration files being read as a whole.
+#[cfg(any(test, feature = "formats-espanso"))]
+impl Node {
+	/// Value of the key if this node is a mapping containing it.
+	pub(crate) fn get(&self, key: &str) -> Option<&Node> {
+		match &self.value {
+			Value::Mapping(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, node)| node),
+			_ => None
+		}
+	}
+
+	pub(crate) fn as_str(&self) -> Option<&str> {
+		match &self.value {
+			Value::Scalar(text) => Some(text),
+			_ => None
+		}
+	}
+
+	/// Items of this node if it is a sequence, nothing otherwise.
+	pub(crate) fn items(&self) -> &[Node] {
+		match &self.value {
+			Value::Sequence(items) => items,
+			_ => &[]
+		}
+	}
+}
+
+pub(crate) fn parse(text: &str) -> Result<Node, Error> {
+	let mut parser = Parser {
+		lines: text.lines().map(str::to_string).collect(),
+		pos: 0,
+		last: 0,
+		depth: 0
+	};
+	let node = parser.node(0)?;
+	parser.skip_blank();
+	if parser.pos < parser.lines.len() {
+		return Err(parser.error("unexpected indentation"))
+	}
+	Ok(node)
+}
+
+struct Parser {
+	lines: Vec<String>,
+	pos: usize,
+	/// Last line that content was read from.
+	last: usize,
+	/// Number of nodes being read.
+	depth: usize
+}
+
+fn indent(line: &str) -> usize {
+	line.len() - line.trim_start_matches(' ').len()
+}
+
+fn is_dash(content: &str) -> bool {
+	content == "-" || content.starts_with("- ")
+}
+
+impl Parser {
+	fn error(&self, message: &'static str) -> Error {
+		Error {
+			line: self.pos + 1,
+			message
+		}
+	}
+
+	/// Skips empty lines, comment lines and document markers.
+	fn skip_blank(&mut self) {
+		while let Some(line) = self.lines.get(self.pos) {
+			let content = line.trim();
+			if content.is_empty() || content.starts_with('#') || line.starts_with("---") || line.starts_with("...") {
+				self.pos += 1;
+			} else {
+				break
+			}
+		}
+	}
+
+	/// Reads the node starting at the next non blank line, if that line is indented by at least `min_indent`.
+	fn node(&mut self, min_indent: usize) -> Result<Node, Error> {
+		if self.depth == MAX_NESTING {
+			return Err(self.error("mappings and sequences are nested too deeply"))
+		}
+		self.depth += 1;
+		let node = self.node_within(min_indent);
+		self.depth -= 1;
+		node
+	}
+
+	fn node_within(&mut self, min_indent: usize) -> Result<Node, Error> {
+		self.skip_blank();
+		let Some(line) = self.lines.get(self.pos) else {
+			return Ok(Node { line: self.pos + 1, end: self.pos + 1, value: Value::Scalar(String::new()) })
+		};
+		let ind = indent(line);
+		if ind < min_indent {
+			return Ok(Node { line: self.pos + 1, end: self.pos + 1, value: Value::Scalar(String::new()) })
+		}
+		let content = &line[ind..];
+		if is_dash(content) {
+			self.sequence(ind)
+		} else if split_key(content)?.is_some() {
+			self.mapping(ind)
+		} else {
+			let node = Node { line: self.pos + 1, end: self.pos + 1, value: inline(content).map_err(|message| self.error(message))? };
+			self.pos += 1;
+			self.last = self.pos;
+			Ok(node)
+		}
+	}
+
+	fn sequence(&mut self, ind: usize) -> Result<Node, Error> {
+		let line = self.pos + 1;
+		let mut items = Vec::new();
+		loop {
+			self.skip_blank();
+			let Some(current) = self.lines.get(self.pos) else {
+				break
+			};
+			if indent(current) != ind || !is_dash(&current[ind..]) {
+				break
+			}
+			let after = current[ind + 1..].trim_start();
+			if after.is_empty() || after.starts_with('#') {
+				self.pos += 1;
+				self.last = self.pos;
+				items.push(self.node(ind + 1)?);
+			} else {
+				// Reread the item's content as if the dash were indentation, so `- key: value` lines start a mapping.
+				let content_indent = current.len() - after.len();
+				let item_line = self.pos + 1;
+				self.lines[self.pos] = format!("{}{}", " ".repeat(content_indent), after);
+				let mut item = self.node(ind + 1)?;
+				item.line = item_line;
+				items.push(item);
+			}
+		}
+		Ok(Node { line, end: self.last, value: Value::Sequence(items) })
+	}
+
+	fn mapping(&mut self, ind: usize) -> Result<Node, Error> {
+		let line = self.pos + 1;
+		let mut entries = Vec::new();
+		loop {
+			self.skip_blank();
+			let Some(current) = self.lines.get(self.pos) else {
+				break
+			};
+			if indent(current) != ind {
+				break
+			}
+			let Some((key, rest)) = split_key(&current[ind..])? else {
+				return Err(self.error("expected a mapping key"))
+			};
+			let rest = rest.trim().to_string();
+			let key_line = self.pos + 1;
+			self.pos += 1;
+			self.last = self.pos;
+			let value = if rest.is_empty() || rest.starts_with('#') {
+				self.skip_blank();
+				match self.lines.get(self.pos) {
+					Some(next) if indent(next) > ind => self.node(ind + 1)?,
+					Some(next) if indent(next) == ind && is_dash(&next[ind..]) => self.sequence(ind)?,
+					_ => Node { line: key_line, end: key_line, value: Value::Scalar(String::new()) }
+				}
+			} else if rest.starts_with('|') || rest.starts_with('>') {
+				let value = Value::Scalar(self.block_scalar(ind, &rest));
+				Node { line: key_line, end: self.last, value }
+			} else {
+				Node { line: key_line, end: key_line, value: inline(&rest).map_err(|message| Error { line: key_line, message })? }
+			};
+			entries.push((key, value));
+		}
+		Ok(Node { line, end: self.last, value: Value::Mapping(entries) })
+	}
+
+	/// Reads the lines of a `|` (literal) or `>` (folded) scalar belonging to a key indented by `ind`.
+	fn block_scalar(&mut self, ind: usize, header: &str) -> String {
+		let folded = header.starts_with('>');
+		let mut lines = Vec::new();
+		let mut content_indent = None;
+		while let Some(line) = self.lines.get(self.pos) {
+			if line.trim().is_empty() {
+				lines.push("");
+			} else {
+				let line_indent = indent(line);
+				if line_indent <= ind || content_indent.is_some_and(|content_indent| line_indent < content_indent) {
+					break
+				}
+				let content_indent = *content_indent.get_or_insert(line_indent);
+				lines.push(&line[content_indent..]);
+				self.last = self.pos + 1;
+			}
+			self.pos += 1;
+		}
+		let trailing = lines.iter().rev().take_while(|line| line.is_empty()).count();
+		let content = &lines[..lines.len() - trailing];
+		let mut text = String::new();
+		for (i, line) in content.iter().enumerate() {
+			if i > 0 {
+				let previous = content[i - 1];
+				if !folded || previous.is_empty() || line.is_empty() || line.starts_with(' ') || previous.starts_with(' ') {
+					if !(folded && previous.is_empty()) {
+						text.push('\n');
+					}
+				} else {
+					text.push(' ');
+				}
+			}
+			text.push_str(line);
+		}
+		if header.contains('-') {
+			text
+		} else if header.contains('+') {
+			text + &"\n".repeat(trailing + usize::from(!content.is_empty()))
+		} else if content.is_empty() {
+			text
+		} else {
+			text + "\n"
+		}
+	}
+}
+
+/// Splits `key: rest` outside of quotes, returning nothing when the content is not a mapping entry.
+fn split_key(content: &str) -> Result<Option<(String, &str)>, Error> {
+	if content.starts_with('"') || content.starts_with('\'') {
+		let (key, len) = quoted(content).map_err(|message| Error { line: 0, message })?;
+		let after = &content[len..];
+		return Ok(after.strip_prefix(':').filter(|rest| rest.is_empty() || rest.starts_with(' ')).map(|rest| (key, rest)))
+	}
+	if content.starts_with('[') || content.starts_with('{') || content.starts_with('#') {
+		return Ok(None)
+	}
+	let bytes = content.as_bytes();
+	for (i, b) in bytes.iter().enumerate() {
+		match b {
+			b':' if i + 1 == bytes.len() || bytes[i + 1] == b' ' => return Ok(Some((content[..i].trim_end().to_string(), &content[i + 1..]))),
+			b'#' if i > 0 && bytes[i - 1] == b' ' => return Ok(None),
+			_ => {}
+		}
+	}
+	Ok(None)
+}
+
+/// Reads a quoted scalar at the start of the text, returning it along with the number of bytes it took up.
+fn quoted(text: &str) -> Result<(String, usize), &'static str> {
+	let mut result = String::new();
+	let double = text.starts_with('"');
+	let mut chars = text.char_indices().skip(1).peekable();
+	while let Some((i, c)) = chars.next() {
+		match c {
+			'"' if double => return Ok((result, i + 1)),
+			'\'' if !double => if let Some((_, '\'')) = chars.peek() {
+				chars.next();
+				result.push('\'');
+			} else {
+				return Ok((result, i + 1))
+			},
+			'\\' if double => match chars.next().map(|(_, c)| c) {
+				Some('n') => result.push('\n'),
+				Some('t') => result.push('\t'),
+				Some('r') => result.push('\r'),
+				Some('0') => result.push('\0'),
+				Some(hex @ ('x' | 'u' | 'U')) => {
+					let len = match hex { 'x' => 2, 'u' => 4, _ => 8 };
+					let digits: String = (0..len).filter_map(|_| chars.next().map(|(_, c)| c)).collect();
+					result.push(u32::from_str_radix(&digits, 16).ok().and_then(char::from_u32).ok_or("invalid escape in double quoted scalar")?);
+				},
+				Some(c) => result.push(c),
+				None => return Err("unterminated double quoted scalar")
+			},
+			_ => result.push(c)
+		}
+	}
+	Err("unterminated quoted scalar")
+}
+
+/// Reads a scalar or flow collection written on the rest of a line.
+fn inline(text: &str) -> Result<Value, &'static str> {
+	let text = text.trim();
+	if text.starts_with('"') || text.starts_with('\'') {
+		let (value, len) = quoted(text)?;
+		let rest = text[len..].trim_start();
+		if !rest.is_empty() && !rest.starts_with('#') {
+			return Err("unexpected text after quoted scalar")
+		}
+		return Ok(Value::Scalar(value))
+	}
+	if let Some(inner) = text.strip_prefix('[') {
+		let inner = inner.trim_end().strip_suffix(']').ok_or("unterminated flow sequence")?;
+		let items = flow_items(inner)?.into_iter()
+			.map(|item| inline(item).map(|value| Node { line: 0, end: 0, value }))
+			.collect::<Result<_, _>>()?;
+		return Ok(Value::Sequence(items))
+	}
+	if let Some(inner) = text.strip_prefix('{') {
+		let inner = inner.trim_end().strip_suffix('}').ok_or("unterminated flow mapping")?;
+		let mut entries = Vec::new();
+		for item in flow_items(inner)? {
+			let (key, value) = match split_key(item).map_err(|error| error.message)? {
+				Some((key, value)) => (key, inline(value)?),
+				None => (item.to_string(), Value::Scalar(String::new()))
+			};
+			entries.push((key, Node { line: 0, end: 0, value }));
+		}
+		return Ok(Value::Mapping(entries))
+	}
+	let plain = match text.find(" #") {
+		Some(comment) => text[..comment].trim_end(),
+		None => text
+	};
+	Ok(Value::Scalar(if plain == "~" || plain == "null" { String::new() } else { plain.to_string() }))
+}
+
+/// Splits the inside of a flow collection at the commas that are not quoted or nested.
+fn flow_items(inner: &str) -> Result<Vec<&str>, &'static str> {
+	let mut items = Vec::new();
+	let mut depth = 0;
+	let mut quote = None;
+	let mut start = 0;
+	for (i, c) in inner.char_indices() {
+		match (quote, c) {
+			(None, '"' | '\'') => quote = Some(c),
+			(Some(q), _) if q == c => quote = None,
+			(None, '[' | '{') => depth += 1,
+			(None, ']' | '}') => depth -= 1,
+			(None, ',') if depth == 0 => {
+				items.push(inner[start..i].trim());
+				start = i + 1;
+			},
+			_ => {}
+		}
+	}
+	if quote.is_some() {
+		return Err("unterminated quoted scalar")
+	}
+	let last = inner[start..].trim();
+	if !last.is_empty() {
+		items.push(last);
+	}
+	Ok(items)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn read_nested_collections() {
+		let node = parse("# comment\nmatches:\n  - trigger: \":hi\" # greet\n    replace: hello\n  - triggers: [a, 'b''s']\n    vars:\n    - name: x\n      params: {cmd: ls}\n").unwrap();
+		let matches = node.get("matches").unwrap().items();
+		assert_eq!(matches.len(), 2);
+		assert_eq!((matches[0].line, matches[0].end), (3, 4));
+		assert_eq!((matches[1].line, matches[1].end), (5, 8));
+		assert_eq!(matches[0].get("trigger").unwrap().as_str(), Some(":hi"));
+		assert_eq!(matches[0].get("replace").unwrap().as_str(), Some("hello"));
+		let triggers: Vec<_> = matches[1].get("triggers").unwrap().items().iter().filter_map(Node::as_str).collect();
+		assert_eq!(triggers, ["a", "b's"]);
+		let var = &matches[1].get("vars").unwrap().items()[0];
+		assert_eq!(var.get("params").unwrap().get("cmd").unwrap().as_str(), Some("ls"));
+	}
+
+	#[test]
+	fn read_block_scalars() {
+		let node = parse("a: |\n  one\n    two\n\nb: >-\n  folded\n  text\nc: \"tab\\there\"\n").unwrap();
+		assert_eq!(node.get("a").unwrap().as_str(), Some("one\n  two\n"));
+		assert_eq!(node.get("b").unwrap().as_str(), Some("folded text"));
+		assert_eq!(node.get("c").unwrap().as_str(), Some("tab\there"));
+	}
+
+	#[test]
+	fn report_errors() {
+		assert_eq!(parse("a: \"open\n").unwrap_err(), Error { line: 1, message: "unterminated quoted scalar" });
+		assert_eq!(parse("a: b\n    c: d\n").unwrap_err().line, 2);
+	}
+
+	#[test]
+	fn handle_multi_byte_text() {
+		let text = "名前: |\n  π ✓\n    🎉\nキー: [é, \"\\u00fc\"]\n";
+		let node = parse(text).unwrap();
+		assert_eq!(node.get("名前").unwrap().as_str(), Some("π ✓\n  🎉\n"));
+		let items: Vec<_> = node.get("キー").unwrap().items().iter().filter_map(Node::as_str).collect();
+		assert_eq!(items, ["é", "ü"]);
+		for (i, _) in text.char_indices() {
+			let _ = parse(&text[..i]);
+		}
+	}
+}